@@ -51,6 +51,9 @@ fn main() {
             "EGL_KHR_stream_fifo",
             "EGL_NV_output_drm_flip_event",
             "EGL_NV_stream_attrib",
+            "EGL_KHR_fence_sync",
+            "EGL_KHR_wait_sync",
+            "EGL_ANDROID_native_fence_sync",
         ],
     )
     .write_bindings(gl_generator::GlobalGenerator, &mut file)
@@ -60,6 +63,8 @@ fn main() {
     let drm_protocol_file = "resources/wayland-drm.xml";
     let eglstream_protocol_file = "resources/wayland-eglstream.xml";
     let eglstream_controller_protocol_file = "resources/wayland-eglstream-controller.xml";
+    let syncobj_protocol_file = "resources/linux-drm-syncobj-v1.xml";
+    let session_lock_protocol_file = "resources/ext-session-lock-v1.xml";
 
     // Target directory for the generate files
     generate_code(
@@ -77,4 +82,14 @@ fn main() {
         &dest.join("wl_eglstream_controller.rs"),
         Side::Server,
     );
+    generate_code(
+        syncobj_protocol_file,
+        &dest.join("wp_linux_drm_syncobj.rs"),
+        Side::Server,
+    );
+    generate_code(
+        session_lock_protocol_file,
+        &dest.join("ext_session_lock.rs"),
+        Side::Server,
+    );
 }
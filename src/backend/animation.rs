@@ -0,0 +1,281 @@
+use smithay::utils::{Logical, Point, Rectangle};
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    config::{Animations as AnimationConfig, Easing},
+    shell::window::Kind,
+};
+
+/// Spaces not rendered for this long are considered to appear anew, without animating their windows
+const STALE_SPACE: Duration = Duration::from_secs(1);
+
+/// Smallest size windows are scaled to while opening or closing
+const MIN_ZOOM: f32 = 0.8;
+
+/// Texture of a surface drawn at an offset from the window location
+pub struct Snapshot<T> {
+    pub texture: T,
+    pub offset: Point<i32, Logical>,
+    pub buffer_scale: i32,
+}
+
+/// How a window is drawn in the current frame
+#[derive(Debug, Clone, Copy)]
+pub struct WindowAnimation {
+    pub location: Point<i32, Logical>,
+    pub alpha: f32,
+    /// Scale around the center of the window
+    pub zoom: f32,
+}
+
+impl WindowAnimation {
+    /// Location to draw the window at, so that it is zoomed around its center.
+    ///
+    /// Has to be drawn with the output scale multiplied by `zoom`.
+    pub fn zoomed_location(&self, bounding_box: Rectangle<i32, Logical>) -> Point<i32, Logical> {
+        let location = self.location.to_f64();
+        let size = bounding_box.size.to_f64();
+        let zoom = self.zoom as f64;
+        (
+            ((location.x + size.w * (1.0 - zoom) / 2.0) / zoom).round() as i32,
+            ((location.y + size.h * (1.0 - zoom) / 2.0) / zoom).round() as i32,
+        )
+            .into()
+    }
+}
+
+struct WindowState<T> {
+    window: Kind,
+    /// Location the layout placed the window at
+    target: Point<i32, Logical>,
+    bounding_box: Rectangle<i32, Logical>,
+    opened: Option<Instant>,
+    /// Location the window started moving from
+    moving: Option<(Point<f64, Logical>, Instant)>,
+    /// Last drawn textures, faded out once the window is closed
+    snapshot: Vec<Snapshot<T>>,
+}
+
+/// A closed window, drawn from its last textures until it has faded out
+pub struct Closing<T> {
+    pub snapshot: Vec<Snapshot<T>>,
+    pub bounding_box: Rectangle<i32, Logical>,
+    started: Instant,
+}
+
+struct SpaceState<T> {
+    last_frame: Instant,
+    windows: Vec<WindowState<T>>,
+    closing: Vec<Closing<T>>,
+}
+
+/// Progress of window animations, tracked per renderer and driven by its frames
+pub struct Animations<T> {
+    spaces: HashMap<usize, SpaceState<T>>,
+}
+
+impl<T> Animations<T> {
+    pub fn new() -> Animations<T> {
+        Animations { spaces: HashMap::new() }
+    }
+
+    /// Compares the windows of a space to the last frame, starting animations for any changes
+    ///
+    /// `windows` are the windows of the space with their location and bounding box.
+    pub fn update(
+        &mut self,
+        config: &AnimationConfig,
+        space: usize,
+        animate_moves: bool,
+        windows: &[(Kind, Point<i32, Logical>, Rectangle<i32, Logical>)],
+    ) {
+        let now = Instant::now();
+        let fresh = self
+            .spaces
+            .get(&space)
+            .map(|state| now.duration_since(state.last_frame) > STALE_SPACE)
+            .unwrap_or(true);
+        let state = self.spaces.entry(space).or_insert_with(|| SpaceState {
+            last_frame: now,
+            windows: Vec::new(),
+            closing: Vec::new(),
+        });
+        state.last_frame = now;
+
+        if fresh || !config.enabled {
+            state.closing.clear();
+            state.windows = windows
+                .iter()
+                .map(|(window, location, bounding_box)| WindowState {
+                    window: window.clone(),
+                    target: *location,
+                    bounding_box: *bounding_box,
+                    opened: None,
+                    moving: None,
+                    snapshot: Vec::new(),
+                })
+                .collect();
+            return;
+        }
+
+        // closed windows fade out, windows moved to other spaces just vanish
+        let (kept, gone): (Vec<_>, Vec<_>) = state
+            .windows
+            .drain(..)
+            .partition(|known| windows.iter().any(|(window, _, _)| window == &known.window));
+        for known in gone {
+            if !known.window.alive() && config.close > 0 && !known.snapshot.is_empty() {
+                state.closing.push(Closing {
+                    snapshot: known.snapshot,
+                    bounding_box: Rectangle {
+                        loc: known.target,
+                        size: known.bounding_box.size,
+                    },
+                    started: now,
+                });
+            }
+        }
+        state.windows = kept;
+        let move_duration = Duration::from_millis(config.moves);
+        state
+            .closing
+            .retain(|closing| now.duration_since(closing.started) < Duration::from_millis(config.close));
+
+        for (window, location, bounding_box) in windows {
+            match state.windows.iter_mut().find(|known| &known.window == window) {
+                Some(known) => {
+                    if known.target != *location && animate_moves && config.moves > 0 {
+                        // continue from wherever the window is drawn right now
+                        let from = current_location(known, now, move_duration, config);
+                        known.moving = Some((from, now));
+                    }
+                    known.target = *location;
+                    known.bounding_box = *bounding_box;
+                }
+                None => state.windows.push(WindowState {
+                    window: window.clone(),
+                    target: *location,
+                    bounding_box: *bounding_box,
+                    opened: if config.open > 0 { Some(now) } else { None },
+                    moving: None,
+                    snapshot: Vec::new(),
+                }),
+            }
+        }
+    }
+
+    /// How to draw a window of the space in the current frame
+    pub fn window(&mut self, config: &AnimationConfig, space: usize, window: &Kind) -> Option<WindowAnimation> {
+        let now = Instant::now();
+        let known = self
+            .spaces
+            .get_mut(&space)?
+            .windows
+            .iter_mut()
+            .find(|known| &known.window == window)?;
+
+        let move_duration = Duration::from_millis(config.moves);
+        let location = current_location(known, now, move_duration, config);
+        if known
+            .moving
+            .map(|(_, started)| now.duration_since(started) >= move_duration)
+            .unwrap_or(false)
+        {
+            known.moving = None;
+        }
+
+        let progress = match known.opened {
+            Some(opened) => {
+                let progress = progress(now.duration_since(opened), config.open, config.easing);
+                if progress >= 1.0 {
+                    known.opened = None;
+                }
+                progress
+            }
+            None => 1.0,
+        };
+
+        Some(WindowAnimation {
+            location: (location.x.round() as i32, location.y.round() as i32).into(),
+            alpha: progress,
+            zoom: MIN_ZOOM + (1.0 - MIN_ZOOM) * progress,
+        })
+    }
+
+    /// Keeps the textures of a window, to fade it out once it is closed
+    pub fn record(&mut self, space: usize, window: &Kind, snapshot: Vec<Snapshot<T>>) {
+        if let Some(known) = self
+            .spaces
+            .get_mut(&space)
+            .and_then(|state| state.windows.iter_mut().find(|known| &known.window == window))
+        {
+            known.snapshot = snapshot;
+        }
+    }
+
+    /// Closed windows of the space still fading out, with their animation
+    pub fn closing<'a>(
+        &'a self,
+        config: &AnimationConfig,
+        space: usize,
+    ) -> impl Iterator<Item = (&'a Closing<T>, WindowAnimation)> + 'a {
+        let now = Instant::now();
+        let (close, easing) = (config.close, config.easing);
+        self.spaces
+            .get(&space)
+            .into_iter()
+            .flat_map(|state| state.closing.iter())
+            .map(move |closing| {
+                let progress = 1.0 - progress(now.duration_since(closing.started), close, easing);
+                (
+                    closing,
+                    WindowAnimation {
+                        location: closing.bounding_box.loc,
+                        alpha: progress,
+                        zoom: MIN_ZOOM + (1.0 - MIN_ZOOM) * progress,
+                    },
+                )
+            })
+    }
+
+    /// Any window of the space is currently animated, so it needs to be composited
+    pub fn is_animating(&self, space: usize) -> bool {
+        self.spaces
+            .get(&space)
+            .map(|state| {
+                !state.closing.is_empty()
+                    || state
+                        .windows
+                        .iter()
+                        .any(|known| known.opened.is_some() || known.moving.is_some())
+            })
+            .unwrap_or(false)
+    }
+}
+
+/// Eased progress of an animation of `duration` milliseconds
+fn progress(elapsed: Duration, duration: u64, easing: Easing) -> f32 {
+    if duration == 0 {
+        return 1.0;
+    }
+    easing.apply(elapsed.as_millis() as f32 / duration as f32)
+}
+
+fn current_location<T>(
+    known: &WindowState<T>,
+    now: Instant,
+    duration: Duration,
+    config: &AnimationConfig,
+) -> Point<f64, Logical> {
+    let target = known.target.to_f64();
+    match known.moving {
+        Some((from, started)) => {
+            let progress = progress(now.duration_since(started), duration.as_millis() as u64, config.easing) as f64;
+            (from.x + (target.x - from.x) * progress, from.y + (target.y - from.y) * progress).into()
+        }
+        None => target,
+    }
+}
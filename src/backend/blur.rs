@@ -0,0 +1,305 @@
+use smithay::{
+    backend::renderer::gles2::{ffi, Gles2Error, Gles2Renderer, Gles2Texture},
+    utils::{Physical, Rectangle},
+};
+use std::ffi::CString;
+
+use crate::config::BlurConfig;
+
+/// Every pass halves the size, more make no difference for any sane window size
+const MAX_PASSES: u32 = 8;
+
+pub(super) const VERTEX_SHADER: &str = r#"
+#version 100
+attribute vec2 position;
+uniform float flip;
+varying vec2 v_coords;
+
+void main() {
+    v_coords = vec2(position.x, mix(position.y, 1.0 - position.y, flip));
+    gl_Position = vec4(position * 2.0 - 1.0, 0.0, 1.0);
+}
+"#;
+
+/// Downsampling step of the dual kawase blur
+const DOWN_SHADER: &str = r#"
+#version 100
+precision mediump float;
+uniform sampler2D tex;
+uniform vec2 half_pixel;
+uniform float offset;
+varying vec2 v_coords;
+
+void main() {
+    vec2 o = half_pixel * offset;
+    vec4 sum = texture2D(tex, v_coords) * 4.0;
+    sum += texture2D(tex, v_coords - o);
+    sum += texture2D(tex, v_coords + o);
+    sum += texture2D(tex, v_coords + vec2(o.x, -o.y));
+    sum += texture2D(tex, v_coords - vec2(o.x, -o.y));
+    gl_FragColor = sum / 8.0;
+}
+"#;
+
+/// Upsampling step of the dual kawase blur
+const UP_SHADER: &str = r#"
+#version 100
+precision mediump float;
+uniform sampler2D tex;
+uniform vec2 half_pixel;
+uniform float offset;
+varying vec2 v_coords;
+
+void main() {
+    vec2 o = half_pixel * offset;
+    vec4 sum = texture2D(tex, v_coords + vec2(-o.x * 2.0, 0.0));
+    sum += texture2D(tex, v_coords + vec2(-o.x, o.y)) * 2.0;
+    sum += texture2D(tex, v_coords + vec2(0.0, o.y * 2.0));
+    sum += texture2D(tex, v_coords + vec2(o.x, o.y)) * 2.0;
+    sum += texture2D(tex, v_coords + vec2(o.x * 2.0, 0.0));
+    sum += texture2D(tex, v_coords + vec2(o.x, -o.y)) * 2.0;
+    sum += texture2D(tex, v_coords + vec2(0.0, -o.y * 2.0));
+    sum += texture2D(tex, v_coords + vec2(-o.x, -o.y)) * 2.0;
+    gl_FragColor = sum / 12.0;
+}
+"#;
+
+pub(super) static QUAD: [f32; 8] = [0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 1.0, 1.0];
+
+struct Program {
+    id: ffi::types::GLuint,
+    position: ffi::types::GLuint,
+    tex: ffi::types::GLint,
+    half_pixel: ffi::types::GLint,
+    offset: ffi::types::GLint,
+    flip: ffi::types::GLint,
+}
+
+/// Shaders of the blur, compiled on first use by every renderer
+pub struct BlurState {
+    programs: Option<Option<(Program, Program)>>,
+    /// The framebuffer starts with the bottom row, like the default framebuffer of egl windows.
+    ///
+    /// Set by the backend before rendering, see `screenshot::read_pixels`.
+    pub flipped: bool,
+}
+
+impl BlurState {
+    pub fn new() -> BlurState {
+        BlurState {
+            programs: None,
+            flipped: false,
+        }
+    }
+}
+
+/// Renderers able to blur what they have drawn so far
+pub trait Blur {
+    type Error: std::error::Error;
+    type Texture;
+
+    /// Returns a blurred copy of an area of the frame currently rendered, if there is anything to blur
+    fn blur(
+        &mut self,
+        state: &mut BlurState,
+        config: &BlurConfig,
+        area: Rectangle<i32, Physical>,
+    ) -> Result<Option<Self::Texture>, Self::Error>;
+}
+
+impl Blur for Gles2Renderer {
+    type Error = Gles2Error;
+    type Texture = Gles2Texture;
+
+    fn blur(
+        &mut self,
+        state: &mut BlurState,
+        config: &BlurConfig,
+        area: Rectangle<i32, Physical>,
+    ) -> Result<Option<Self::Texture>, Self::Error> {
+        let passes = config.passes.min(MAX_PASSES);
+        if passes == 0 {
+            return Ok(None);
+        }
+        let flipped = state.flipped;
+        let offset = config.strength;
+
+        self.with_context(|renderer, gl| unsafe {
+            let programs = match state.programs.get_or_insert_with(|| compile(gl)) {
+                Some(programs) => programs,
+                None => return None,
+            };
+
+            let mut viewport = [0; 4];
+            gl.GetIntegerv(ffi::VIEWPORT, viewport.as_mut_ptr());
+            let mut framebuffer = 0;
+            gl.GetIntegerv(ffi::FRAMEBUFFER_BINDING, &mut framebuffer);
+            let blend = gl.IsEnabled(ffi::BLEND) == ffi::TRUE;
+            // partially redrawn frames must not clip the passes, see `damage::Scissor`
+            let scissor = gl.IsEnabled(ffi::SCISSOR_TEST) == ffi::TRUE;
+
+            // clip to the frame, windows may be partially off-screen
+            let x1 = area.loc.x.max(0);
+            let y1 = area.loc.y.max(0);
+            let x2 = (area.loc.x + area.size.w).min(viewport[2]);
+            let y2 = (area.loc.y + area.size.h).min(viewport[3]);
+            if x2 <= x1 || y2 <= y1 {
+                return None;
+            }
+            let (width, height) = (x2 - x1, y2 - y1);
+            let y = if flipped { viewport[3] - y2 } else { y1 };
+
+            // every level is half the size of the previous one
+            let mut sizes = vec![(width, height)];
+            for _ in 0..passes {
+                let (w, h) = *sizes.last().unwrap();
+                if w <= 1 && h <= 1 {
+                    break;
+                }
+                sizes.push(((w / 2).max(1), (h / 2).max(1)));
+            }
+            let mut textures = vec![0; sizes.len()];
+            gl.GenTextures(textures.len() as i32, textures.as_mut_ptr());
+            for (i, (tex, (w, h))) in textures.iter().zip(sizes.iter()).enumerate() {
+                gl.BindTexture(ffi::TEXTURE_2D, *tex);
+                gl.TexParameteri(ffi::TEXTURE_2D, ffi::TEXTURE_MIN_FILTER, ffi::LINEAR as i32);
+                gl.TexParameteri(ffi::TEXTURE_2D, ffi::TEXTURE_MAG_FILTER, ffi::LINEAR as i32);
+                gl.TexParameteri(ffi::TEXTURE_2D, ffi::TEXTURE_WRAP_S, ffi::CLAMP_TO_EDGE as i32);
+                gl.TexParameteri(ffi::TEXTURE_2D, ffi::TEXTURE_WRAP_T, ffi::CLAMP_TO_EDGE as i32);
+                if i == 0 {
+                    // the framebuffer might have no alpha channel, which rgb textures do not need
+                    gl.CopyTexImage2D(ffi::TEXTURE_2D, 0, ffi::RGB, x1, y, *w, *h, 0);
+                } else {
+                    gl.TexImage2D(
+                        ffi::TEXTURE_2D,
+                        0,
+                        ffi::RGBA as i32,
+                        *w,
+                        *h,
+                        0,
+                        ffi::RGBA,
+                        ffi::UNSIGNED_BYTE,
+                        std::ptr::null(),
+                    );
+                }
+            }
+
+            let mut target = 0;
+            gl.GenFramebuffers(1, &mut target);
+            gl.BindFramebuffer(ffi::FRAMEBUFFER, target);
+            gl.Disable(ffi::BLEND);
+            gl.Disable(ffi::SCISSOR_TEST);
+            gl.BindBuffer(ffi::ARRAY_BUFFER, 0);
+            gl.ActiveTexture(ffi::TEXTURE0);
+
+            let (down, up) = programs;
+            let levels = textures.len();
+            for i in 1..levels {
+                pass(gl, down, textures[i - 1], textures[i], sizes[i], offset, false);
+            }
+            for i in (1..levels).rev() {
+                // the last pass turns the image upright, if the framebuffer is upside down
+                pass(gl, up, textures[i], textures[i - 1], sizes[i - 1], offset, flipped && i == 1);
+            }
+
+            gl.BindFramebuffer(ffi::FRAMEBUFFER, framebuffer as u32);
+            gl.DeleteFramebuffers(1, &target);
+            gl.DeleteTextures(levels as i32 - 1, textures[1..].as_ptr());
+            gl.BindTexture(ffi::TEXTURE_2D, 0);
+            gl.Viewport(viewport[0], viewport[1], viewport[2], viewport[3]);
+            if blend {
+                gl.Enable(ffi::BLEND);
+            }
+            if scissor {
+                gl.Enable(ffi::SCISSOR_TEST);
+            }
+
+            Some(Gles2Texture::from_raw(renderer, textures[0], (width, height).into()))
+        })
+    }
+}
+
+/// Renders `source` into `target` through one of the blur programs
+unsafe fn pass(
+    gl: &ffi::Gles2,
+    program: &Program,
+    source: ffi::types::GLuint,
+    target: ffi::types::GLuint,
+    (width, height): (i32, i32),
+    offset: f32,
+    flip: bool,
+) {
+    gl.FramebufferTexture2D(ffi::FRAMEBUFFER, ffi::COLOR_ATTACHMENT0, ffi::TEXTURE_2D, target, 0);
+    gl.Viewport(0, 0, width, height);
+    gl.UseProgram(program.id);
+    gl.BindTexture(ffi::TEXTURE_2D, source);
+    gl.Uniform1i(program.tex, 0);
+    gl.Uniform2f(program.half_pixel, 0.5 / width as f32, 0.5 / height as f32);
+    gl.Uniform1f(program.offset, offset);
+    gl.Uniform1f(program.flip, if flip { 1.0 } else { 0.0 });
+    gl.EnableVertexAttribArray(program.position);
+    gl.VertexAttribPointer(program.position, 2, ffi::FLOAT, ffi::FALSE, 0, QUAD.as_ptr() as *const _);
+    gl.DrawArrays(ffi::TRIANGLE_STRIP, 0, 4);
+    gl.DisableVertexAttribArray(program.position);
+}
+
+unsafe fn compile(gl: &ffi::Gles2) -> Option<(Program, Program)> {
+    let down = link(gl, DOWN_SHADER);
+    let up = link(gl, UP_SHADER);
+    if down.is_none() || up.is_none() {
+        slog_scope::error!("Failed to compile the blur shaders, windows are not blurred");
+    }
+    Some((down?, up?))
+}
+
+unsafe fn link(gl: &ffi::Gles2, fragment: &str) -> Option<Program> {
+    let vertex = shader(gl, ffi::VERTEX_SHADER, VERTEX_SHADER)?;
+    let fragment = shader(gl, ffi::FRAGMENT_SHADER, fragment)?;
+    let id = gl.CreateProgram();
+    gl.AttachShader(id, vertex);
+    gl.AttachShader(id, fragment);
+    gl.LinkProgram(id);
+    gl.DetachShader(id, vertex);
+    gl.DetachShader(id, fragment);
+    gl.DeleteShader(vertex);
+    gl.DeleteShader(fragment);
+
+    let mut status = ffi::FALSE as i32;
+    gl.GetProgramiv(id, ffi::LINK_STATUS, &mut status);
+    if status == ffi::FALSE as i32 {
+        gl.DeleteProgram(id);
+        return None;
+    }
+
+    let uniform = |name: &str| {
+        let name = CString::new(name).unwrap();
+        gl.GetUniformLocation(id, name.as_ptr() as *const _)
+    };
+    let position = CString::new("position").unwrap();
+    Some(Program {
+        id,
+        position: gl.GetAttribLocation(id, position.as_ptr() as *const _) as ffi::types::GLuint,
+        tex: uniform("tex"),
+        half_pixel: uniform("half_pixel"),
+        offset: uniform("offset"),
+        flip: uniform("flip"),
+    })
+}
+
+pub(super) unsafe fn shader(gl: &ffi::Gles2, kind: ffi::types::GLenum, source: &str) -> Option<ffi::types::GLuint> {
+    let shader = gl.CreateShader(kind);
+    gl.ShaderSource(
+        shader,
+        1,
+        &source.as_ptr() as *const *const u8 as *const *const _,
+        &(source.len() as i32) as *const _,
+    );
+    gl.CompileShader(shader);
+    let mut status = ffi::FALSE as i32;
+    gl.GetShaderiv(shader, ffi::COMPILE_STATUS, &mut status);
+    if status == ffi::FALSE as i32 {
+        gl.DeleteShader(shader);
+        return None;
+    }
+    Some(shader)
+}
@@ -0,0 +1,233 @@
+use smithay::backend::renderer::gles2::{ffi, Gles2Error, Gles2Renderer};
+use std::ffi::CString;
+
+use super::blur::{shader, QUAD, VERTEX_SHADER};
+use crate::config::ColorFilter;
+
+/// Applies an affine transformation to the colors of every pixel
+const FILTER_SHADER: &str = r#"
+#version 100
+precision mediump float;
+uniform sampler2D tex;
+uniform mat3 matrix;
+uniform vec3 offset;
+varying vec2 v_coords;
+
+void main() {
+    vec3 color = texture2D(tex, v_coords).rgb;
+    gl_FragColor = vec4(clamp(matrix * color + offset, 0.0, 1.0), 1.0);
+}
+"#;
+
+type Matrix = [[f32; 3]; 3];
+
+const IDENTITY: Matrix = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+
+/// Converts rgb to the responses of the long, medium and short cones
+const RGB_TO_LMS: Matrix = [
+    [17.8824, 43.5161, 4.11935],
+    [3.45565, 27.1554, 3.86714],
+    [0.0299566, 0.184309, 1.46709],
+];
+const LMS_TO_RGB: Matrix = [
+    [0.0809444479, -0.130504409, 0.116721066],
+    [-0.0102485335, 0.0540193266, -0.113614708],
+    [-0.000365296938, -0.00412161469, 0.693511405],
+];
+/// What is seen without long cones
+const PROTANOPIA: Matrix = [[0.0, 2.02344, -2.52581], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+/// What is seen without medium cones
+const DEUTERANOPIA: Matrix = [[1.0, 0.0, 0.0], [0.494207, 0.0, 1.24827], [0.0, 0.0, 1.0]];
+/// Moves the colors lost by a deficiency into the green and blue channels
+const ERROR_SHIFT: Matrix = [[0.0, 0.0, 0.0], [0.7, 1.0, 0.0], [0.7, 0.0, 1.0]];
+
+struct Program {
+    id: ffi::types::GLuint,
+    position: ffi::types::GLuint,
+    tex: ffi::types::GLint,
+    matrix: ffi::types::GLint,
+    offset: ffi::types::GLint,
+    flip: ffi::types::GLint,
+}
+
+/// Shader of the color filters, compiled on first use by every renderer
+pub struct ColorFilterState {
+    program: Option<Option<Program>>,
+}
+
+impl ColorFilterState {
+    pub fn new() -> ColorFilterState {
+        ColorFilterState { program: None }
+    }
+}
+
+/// Renderers able to filter the colors of what they have drawn so far
+pub trait ApplyColorFilter {
+    type Error: std::error::Error;
+
+    /// Replaces the whole frame with a filtered copy of it
+    fn apply_color_filter(&mut self, state: &mut ColorFilterState, filter: ColorFilter) -> Result<(), Self::Error>;
+}
+
+impl ApplyColorFilter for Gles2Renderer {
+    type Error = Gles2Error;
+
+    fn apply_color_filter(&mut self, state: &mut ColorFilterState, filter: ColorFilter) -> Result<(), Self::Error> {
+        let (matrix, offset) = transformation(filter);
+        // glsl matrices are column major
+        let mut columns = [0.0; 9];
+        for (i, row) in matrix.iter().enumerate() {
+            for (j, value) in row.iter().enumerate() {
+                columns[j * 3 + i] = *value;
+            }
+        }
+
+        self.with_context(|_, gl| unsafe {
+            let program = match state.program.get_or_insert_with(|| compile(gl)) {
+                Some(program) => program,
+                None => return,
+            };
+
+            let mut viewport = [0; 4];
+            gl.GetIntegerv(ffi::VIEWPORT, viewport.as_mut_ptr());
+            let blend = gl.IsEnabled(ffi::BLEND) == ffi::TRUE;
+            let scissor = gl.IsEnabled(ffi::SCISSOR_TEST) == ffi::TRUE;
+
+            // copied and drawn back with the same orientation, flipped framebuffers need no special care
+            let mut texture = 0;
+            gl.GenTextures(1, &mut texture);
+            gl.ActiveTexture(ffi::TEXTURE0);
+            gl.BindTexture(ffi::TEXTURE_2D, texture);
+            gl.TexParameteri(ffi::TEXTURE_2D, ffi::TEXTURE_MIN_FILTER, ffi::NEAREST as i32);
+            gl.TexParameteri(ffi::TEXTURE_2D, ffi::TEXTURE_MAG_FILTER, ffi::NEAREST as i32);
+            gl.TexParameteri(ffi::TEXTURE_2D, ffi::TEXTURE_WRAP_S, ffi::CLAMP_TO_EDGE as i32);
+            gl.TexParameteri(ffi::TEXTURE_2D, ffi::TEXTURE_WRAP_T, ffi::CLAMP_TO_EDGE as i32);
+            gl.CopyTexImage2D(
+                ffi::TEXTURE_2D,
+                0,
+                ffi::RGB,
+                viewport[0],
+                viewport[1],
+                viewport[2],
+                viewport[3],
+                0,
+            );
+
+            gl.Disable(ffi::BLEND);
+            gl.Disable(ffi::SCISSOR_TEST);
+            gl.BindBuffer(ffi::ARRAY_BUFFER, 0);
+            gl.UseProgram(program.id);
+            gl.Uniform1i(program.tex, 0);
+            gl.UniformMatrix3fv(program.matrix, 1, ffi::FALSE, columns.as_ptr());
+            gl.Uniform3f(program.offset, offset[0], offset[1], offset[2]);
+            gl.Uniform1f(program.flip, 0.0);
+            gl.EnableVertexAttribArray(program.position);
+            gl.VertexAttribPointer(program.position, 2, ffi::FLOAT, ffi::FALSE, 0, QUAD.as_ptr() as *const _);
+            gl.DrawArrays(ffi::TRIANGLE_STRIP, 0, 4);
+            gl.DisableVertexAttribArray(program.position);
+
+            gl.DeleteTextures(1, &texture);
+            gl.BindTexture(ffi::TEXTURE_2D, 0);
+            if blend {
+                gl.Enable(ffi::BLEND);
+            }
+            if scissor {
+                gl.Enable(ffi::SCISSOR_TEST);
+            }
+        })
+    }
+}
+
+/// Matrix and offset applied to the rgb values of a filter
+fn transformation(filter: ColorFilter) -> (Matrix, [f32; 3]) {
+    match filter {
+        ColorFilter::Grayscale => {
+            // relative luminance of the sRGB primaries
+            let luminance = [0.2126, 0.7152, 0.0722];
+            ([luminance; 3], [0.0; 3])
+        }
+        ColorFilter::Invert => (
+            [[-1.0, 0.0, 0.0], [0.0, -1.0, 0.0], [0.0, 0.0, -1.0]],
+            [1.0; 3],
+        ),
+        ColorFilter::Protanopia => (daltonize(PROTANOPIA), [0.0; 3]),
+        ColorFilter::Deuteranopia => (daltonize(DEUTERANOPIA), [0.0; 3]),
+    }
+}
+
+/// Adds the difference between the colors and how they are seen with a deficiency back into visible channels
+fn daltonize(deficiency: Matrix) -> Matrix {
+    let simulation = multiply(LMS_TO_RGB, multiply(deficiency, RGB_TO_LMS));
+    let error = subtract(IDENTITY, simulation);
+    add(IDENTITY, multiply(ERROR_SHIFT, error))
+}
+
+fn multiply(a: Matrix, b: Matrix) -> Matrix {
+    let mut result = [[0.0; 3]; 3];
+    for (i, row) in result.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    result
+}
+
+fn add(a: Matrix, b: Matrix) -> Matrix {
+    let mut result = a;
+    for (row, other) in result.iter_mut().zip(b.iter()) {
+        for (value, other) in row.iter_mut().zip(other.iter()) {
+            *value += other;
+        }
+    }
+    result
+}
+
+fn subtract(a: Matrix, b: Matrix) -> Matrix {
+    let mut negated = b;
+    for value in negated.iter_mut().flat_map(|row| row.iter_mut()) {
+        *value = -*value;
+    }
+    add(a, negated)
+}
+
+unsafe fn compile(gl: &ffi::Gles2) -> Option<Program> {
+    let vertex = shader(gl, ffi::VERTEX_SHADER, VERTEX_SHADER);
+    let fragment = shader(gl, ffi::FRAGMENT_SHADER, FILTER_SHADER);
+    let (vertex, fragment) = match (vertex, fragment) {
+        (Some(vertex), Some(fragment)) => (vertex, fragment),
+        _ => {
+            slog_scope::error!("Failed to compile the color filter shader, colors are not filtered");
+            return None;
+        }
+    };
+    let id = gl.CreateProgram();
+    gl.AttachShader(id, vertex);
+    gl.AttachShader(id, fragment);
+    gl.LinkProgram(id);
+    gl.DetachShader(id, vertex);
+    gl.DetachShader(id, fragment);
+    gl.DeleteShader(vertex);
+    gl.DeleteShader(fragment);
+
+    let mut status = ffi::FALSE as i32;
+    gl.GetProgramiv(id, ffi::LINK_STATUS, &mut status);
+    if status == ffi::FALSE as i32 {
+        slog_scope::error!("Failed to link the color filter shader, colors are not filtered");
+        gl.DeleteProgram(id);
+        return None;
+    }
+
+    let uniform = |name: &str| {
+        let name = CString::new(name).unwrap();
+        gl.GetUniformLocation(id, name.as_ptr() as *const _)
+    };
+    let position = CString::new("position").unwrap();
+    Some(Program {
+        id,
+        position: gl.GetAttribLocation(id, position.as_ptr() as *const _) as ffi::types::GLuint,
+        tex: uniform("tex"),
+        matrix: uniform("matrix"),
+        offset: uniform("offset"),
+        flip: uniform("flip"),
+    })
+}
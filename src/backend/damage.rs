@@ -0,0 +1,363 @@
+use smithay::{
+    backend::renderer::gles2::{ffi, Gles2Error, Gles2Renderer},
+    reexports::wayland_server::protocol::wl_surface,
+    utils::{Logical, Physical, Point, Rectangle, Size},
+    wayland::compositor::{with_surface_tree_downward, SubsurfaceCachedState, TraversalAction},
+};
+use std::{
+    cell::RefCell,
+    collections::{hash_map::DefaultHasher, VecDeque},
+    hash::{Hash, Hasher},
+};
+
+use crate::{
+    config::Config,
+    shell::{child_popups, decoration, layout::Layout, window::PopupKind, SurfaceData},
+};
+
+/// Damage of older frames is forgotten, buffers older than this are redrawn completely
+const MAX_AGE: usize = 4;
+
+/// Identifies something drawn across frames
+#[derive(Debug, Clone, PartialEq)]
+pub enum Id {
+    Surface(wl_surface::WlSurface),
+    /// Default cursor image of the seat with this index
+    Cursor(usize),
+    /// Title row of a stacked container with this index
+    TitleRow(usize),
+}
+
+/// Something drawn into a frame, compared to the last frame to find what changed
+#[derive(Debug, Clone, PartialEq)]
+pub struct Element {
+    id: Id,
+    area: Rectangle<i32, Physical>,
+    /// Hash of everything influencing what is drawn inside `area`
+    state: u64,
+    /// Draws a blurred copy of everything below it
+    blur: bool,
+}
+
+impl Element {
+    pub fn new(id: Id, area: Rectangle<i32, Physical>, state: impl Hash) -> Element {
+        let mut hasher = DefaultHasher::new();
+        state.hash(&mut hasher);
+        Element {
+            id,
+            area,
+            state: hasher.finish(),
+            blur: false,
+        }
+    }
+
+    /// A surface tree drawn at `location`, like cursors or drag-and-drop icons
+    pub fn surface(root: &wl_surface::WlSurface, location: Point<i32, Logical>, scale: f32) -> Element {
+        let mut hasher = DefaultHasher::new();
+        let bounding_box = surface_tree(root, location, &mut hasher);
+        Element {
+            id: Id::Surface(root.clone()),
+            area: to_physical(bounding_box, scale),
+            state: hasher.finish(),
+            blur: false,
+        }
+    }
+}
+
+/// Part of a buffer to redraw for the next frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Redraw {
+    Everything,
+    Area(Rectangle<i32, Physical>),
+    /// The buffer already shows the next frame
+    Nothing,
+}
+
+/// Changes of the frames of an output, used to only redraw the parts of a buffer that are out of date
+pub struct DamageTracker {
+    size: Size<i32, Physical>,
+    elements: Vec<Element>,
+    /// The last frame was not described by its elements
+    full: bool,
+    /// Areas changed by the last frame, `None` if unknown
+    changed: Option<Vec<Rectangle<i32, Physical>>>,
+    /// Changes since the last queued frame, `None` if everything changed
+    pending: Option<Vec<Rectangle<i32, Physical>>>,
+    /// Changes drawn by the last queued frames, newest first
+    history: VecDeque<Option<Vec<Rectangle<i32, Physical>>>>,
+}
+
+impl DamageTracker {
+    pub fn new() -> DamageTracker {
+        DamageTracker {
+            size: (0, 0).into(),
+            elements: Vec::new(),
+            full: true,
+            changed: None,
+            pending: None,
+            history: VecDeque::new(),
+        }
+    }
+
+    /// Compares the elements of the next frame to the last one and returns what to redraw of a buffer
+    ///
+    /// `age` is the number of frames since the buffer was last queued, 0 if its contents are unknown.
+    /// Frames not fully described by their elements, like animations, need to be `full`y redrawn.
+    pub fn damage(&mut self, elements: Vec<Element>, size: Size<i32, Physical>, age: usize, full: bool) -> Redraw {
+        profile_span!("damage", elements = elements.len());
+        let diff = if size != self.size {
+            None
+        } else {
+            Some(diff(&self.elements, &elements))
+        };
+        self.changed = diff.as_ref().map(|changes| {
+            changes
+                .iter()
+                .map(|rect| clip(*rect, size))
+                .filter(|rect| rect.size.w > 0 && rect.size.h > 0)
+                .collect()
+        });
+        // frames following a full one differ from it in ways the elements do not describe either
+        let changes = if full || self.full { None } else { diff };
+        self.full = full;
+        self.size = size;
+        self.elements = elements;
+        // frames rendered but never queued still have to be drawn
+        self.pending = match (self.pending.take(), changes) {
+            (Some(mut pending), Some(changes)) => {
+                pending.extend(changes);
+                Some(pending)
+            }
+            _ => None,
+        };
+
+        if age == 0 || age > self.history.len() + 1 {
+            return Redraw::Everything;
+        }
+        let mut area: Option<Rectangle<i32, Physical>> = None;
+        for changes in std::iter::once(&self.pending).chain(self.history.iter().take(age - 1)) {
+            let changes = match changes {
+                Some(changes) => changes,
+                None => return Redraw::Everything,
+            };
+            for rect in changes {
+                area = Some(area.map(|area| area.merge(*rect)).unwrap_or(*rect));
+            }
+        }
+        let area = match area {
+            Some(area) => self.blurred(clip(area, size)),
+            None => return Redraw::Nothing,
+        };
+        if area.size.w <= 0 || area.size.h <= 0 {
+            Redraw::Nothing
+        } else if area.size == size {
+            Redraw::Everything
+        } else {
+            Redraw::Area(area)
+        }
+    }
+
+    /// Areas the elements given to the last call of `damage` changed, `None` if they might all have
+    ///
+    /// Unlike the result of `damage`, this does not depend on the buffer age or full redraws.
+    pub fn changes(&self) -> Option<&[Rectangle<i32, Physical>]> {
+        self.changed.as_deref()
+    }
+
+    /// The frame returned by the last call to `damage` was queued
+    pub fn queued(&mut self) {
+        self.history.push_front(self.pending.replace(Vec::new()));
+        self.history.truncate(MAX_AGE);
+    }
+
+    /// Extends the area to blurred elements it touches, their blur depends on everything below them
+    fn blurred(&self, mut area: Rectangle<i32, Physical>) -> Rectangle<i32, Physical> {
+        loop {
+            let extended = self
+                .elements
+                .iter()
+                .filter(|element| element.blur && element.area.overlaps(area))
+                .fold(area, |area, element| area.merge(element.area));
+            if extended == area {
+                return clip(area, self.size);
+            }
+            area = extended;
+        }
+    }
+}
+
+/// Areas that differ between two lists of elements, ordered from bottom to top
+fn diff(old: &[Element], new: &[Element]) -> Vec<Rectangle<i32, Physical>> {
+    let mut damage = Vec::new();
+    let common = |from: &[Element], other: &[Element]| {
+        from.iter()
+            .filter(|element| other.iter().any(|e| e.id == element.id))
+            .map(|element| element.id.clone())
+            .collect::<Vec<_>>()
+    };
+    // elements raised or lowered change what is on top
+    let restacked = common(old, new) != common(new, old);
+
+    for element in new {
+        match old.iter().find(|e| e.id == element.id) {
+            Some(previous) if previous.area == element.area && previous.state == element.state && !restacked => {}
+            Some(previous) => {
+                damage.push(previous.area);
+                damage.push(element.area);
+            }
+            None => damage.push(element.area),
+        }
+    }
+    for element in old.iter().filter(|element| !new.iter().any(|e| e.id == element.id)) {
+        damage.push(element.area);
+    }
+    damage
+}
+
+/// Elements for the windows of a workspace and their popups, except the surface `skip` presented by a plane
+///
+/// Animated frames are not described by their elements, they are always drawn completely.
+pub fn space_elements(
+    space: &dyn Layout,
+    popups: &[PopupKind],
+    config: &Config,
+    skip: Option<&wl_surface::WlSurface>,
+    scale: f32,
+) -> Vec<Element> {
+    let decoration = &config.decoration;
+    let focused = space.focused_window();
+    let border = decoration.border.width as i32;
+    let title_height = decoration.title_bar.height();
+
+    let mut elements = space
+        .stacked_titles()
+        .into_iter()
+        .enumerate()
+        .map(|(idx, (toplevel, row))| {
+            let is_focused = focused.as_ref() == Some(&toplevel);
+            Element::new(Id::TitleRow(idx), to_physical(row, scale), (toplevel.title(), is_focused))
+        })
+        .collect::<Vec<_>>();
+    for (toplevel, location, bounding_box) in space.windows_from_bottom_to_top() {
+        let wl_surface = match toplevel.get_surface() {
+            Some(wl_surface) => wl_surface,
+            None => continue,
+        };
+        let mut hasher = DefaultHasher::new();
+        let mut area = surface_tree(wl_surface, location, &mut hasher);
+        let decorated = space.is_decorated(&toplevel);
+        if !toplevel.is_fullscreen() && decorated {
+            let mut geometry = decoration::window_geometry(&toplevel, location, bounding_box);
+            geometry.loc.y -= title_height;
+            geometry.size.h += title_height;
+            geometry.loc -= Point::from((border, border));
+            geometry.size += Size::from((2 * border, 2 * border));
+            area = area.merge(geometry);
+        }
+
+        let is_focused = focused.as_ref() == Some(&toplevel);
+        is_focused.hash(&mut hasher);
+        space.is_urgent(&toplevel).hash(&mut hasher);
+        space.opacity(&toplevel).to_bits().hash(&mut hasher);
+        space.corner_radius(&toplevel).hash(&mut hasher);
+        toplevel.is_fullscreen().hash(&mut hasher);
+        decorated.hash(&mut hasher);
+        toplevel.title().hash(&mut hasher);
+        (skip == Some(wl_surface)).hash(&mut hasher);
+        elements.push(Element {
+            id: Id::Surface(wl_surface.clone()),
+            area: to_physical(area, scale),
+            state: hasher.finish(),
+            blur: space.blur(&toplevel),
+        });
+
+        for popup in child_popups(popups.iter(), wl_surface) {
+            if let Some(popup_surface) = popup.get_surface() {
+                elements.push(Element::surface(popup_surface, location + popup.location(), scale));
+            }
+        }
+    }
+    elements
+}
+
+/// Hashes the contents of a surface tree and returns its bounding box
+fn surface_tree(root: &wl_surface::WlSurface, location: Point<i32, Logical>, hasher: &mut DefaultHasher) -> Rectangle<i32, Logical> {
+    let mut bounding_box = Rectangle::from_loc_and_size(location, (0, 0));
+    with_surface_tree_downward(
+        root,
+        location,
+        |_, states, location| {
+            let mut location = *location;
+            let data = match states.data_map.get::<RefCell<SurfaceData>>() {
+                Some(data) => data.borrow(),
+                None => return TraversalAction::SkipChildren,
+            };
+            match data.size() {
+                Some(size) => {
+                    if states.role == Some("subsurface") {
+                        let current = states.cached_state.current::<SubsurfaceCachedState>();
+                        location += current.location;
+                    }
+                    (location.x, location.y, data.contents).hash(hasher);
+                    bounding_box = bounding_box.merge(Rectangle::from_loc_and_size(location, size));
+                    TraversalAction::DoChildren(location)
+                }
+                // unmapped surfaces hide their children as well
+                None => TraversalAction::SkipChildren,
+            }
+        },
+        |_, _, _| {},
+        |_, _, _| true,
+    );
+    bounding_box
+}
+
+/// Smallest physical rectangle covering a logical one
+fn to_physical(rect: Rectangle<i32, Logical>, scale: f32) -> Rectangle<i32, Physical> {
+    let scale = scale as f64;
+    let loc = rect.loc.to_f64().to_physical(scale);
+    let end = (rect.loc + Point::from((rect.size.w, rect.size.h))).to_f64().to_physical(scale);
+    let (x, y) = (loc.x.floor() as i32, loc.y.floor() as i32);
+    Rectangle::from_loc_and_size((x, y), (end.x.ceil() as i32 - x, end.y.ceil() as i32 - y))
+}
+
+fn clip(rect: Rectangle<i32, Physical>, size: Size<i32, Physical>) -> Rectangle<i32, Physical> {
+    let x1 = rect.loc.x.max(0);
+    let y1 = rect.loc.y.max(0);
+    let x2 = (rect.loc.x + rect.size.w).min(size.w);
+    let y2 = (rect.loc.y + rect.size.h).min(size.h);
+    Rectangle::from_loc_and_size((x1, y1), ((x2 - x1).max(0), (y2 - y1).max(0)))
+}
+
+/// Renderers able to restrict drawing to a part of the frame
+pub trait Scissor {
+    type Error: std::error::Error;
+
+    /// Limits drawing to `area` until called again with `None`
+    ///
+    /// `flipped` framebuffers start with the bottom row, see `BlurState::flipped`.
+    fn scissor(&mut self, area: Option<Rectangle<i32, Physical>>, flipped: bool) -> Result<(), Self::Error>;
+}
+
+impl Scissor for Gles2Renderer {
+    type Error = Gles2Error;
+
+    fn scissor(&mut self, area: Option<Rectangle<i32, Physical>>, flipped: bool) -> Result<(), Self::Error> {
+        self.with_context(|_, gl| unsafe {
+            match area {
+                Some(area) => {
+                    let y = if flipped {
+                        let mut viewport = [0; 4];
+                        gl.GetIntegerv(ffi::VIEWPORT, viewport.as_mut_ptr());
+                        viewport[3] - area.loc.y - area.size.h
+                    } else {
+                        area.loc.y
+                    };
+                    gl.Enable(ffi::SCISSOR_TEST);
+                    gl.Scissor(area.loc.x, y, area.size.w, area.size.h);
+                }
+                None => gl.Disable(ffi::SCISSOR_TEST),
+            }
+        })
+    }
+}
@@ -0,0 +1,184 @@
+//! Backend with a single virtual output and without input devices
+//!
+//! Clients may connect and the ipc is served, but nothing is ever shown.
+//! The output is rendered on the cpu, if mesa's software renderer is available,
+//! so screenshots and screencasts of it work. Otherwise frame callbacks are sent
+//! at a fixed rate, as if the output was rendered.
+//! Meant for testing clients and scripts against fireplace, e.g. in CI.
+
+use anyhow::Result;
+use smithay::{
+    backend::{
+        egl::{EGLContext, EGLDisplay, EGLSurface},
+        renderer::{gles2::{Gles2Renderer, Gles2Texture}, Bind, Renderer, Transform},
+    },
+    reexports::{
+        calloop::{timer::Timer, EventLoop},
+        wayland_server::protocol::wl_output::Subpixel,
+    },
+    utils::{Physical, Size},
+    wayland::output::{Mode, PhysicalProperties},
+};
+use std::{cell::RefCell, rc::Rc, time::Duration};
+
+use crate::{
+    backend::{
+        render::{draw_dnd_icons, render_lock, render_space, RenderCache},
+        screenshot::{read_pixels, save, take_pending},
+        software::{Pbuffer, SoftwareDevice},
+    },
+    handler::ActiveOutput,
+    state::Fireplace,
+};
+
+const OUTPUT_NAME: &str = "HEADLESS-1";
+const OUTPUT_SIZE: (i32, i32) = (1920, 1080);
+/// Interval frame callbacks are sent in
+const FRAME_INTERVAL: Duration = Duration::from_millis(16);
+
+/// Software renderer of the virtual output
+struct HeadlessRenderer {
+    renderer: Gles2Renderer,
+    surface: Rc<EGLSurface>,
+    cache: RenderCache<Gles2Texture>,
+}
+
+impl HeadlessRenderer {
+    fn new() -> Result<HeadlessRenderer> {
+        let display = EGLDisplay::new(&SoftwareDevice::new()?, None)?;
+        let context = EGLContext::new(&display, None)?;
+        let surface = Rc::new(EGLSurface::new(
+            &display,
+            context.pixel_format().unwrap(),
+            context.config_id(),
+            Pbuffer::new(OUTPUT_SIZE),
+            None,
+        )?);
+        let renderer = unsafe { Gles2Renderer::new(context, None)? };
+        Ok(HeadlessRenderer {
+            renderer,
+            surface,
+            cache: RenderCache::new(),
+        })
+    }
+}
+
+pub fn init_headless(event_loop: &mut EventLoop<'static, Fireplace>, state: &mut Fireplace) -> Result<()> {
+    state.workspaces.borrow_mut().add_output(
+        OUTPUT_NAME,
+        PhysicalProperties {
+            size: (0, 0).into(),
+            subpixel: Subpixel::Unknown,
+            make: String::from("Fireplace"),
+            model: String::from("Headless"),
+        },
+        Mode {
+            size: OUTPUT_SIZE.into(),
+            refresh: (1000 * 1000 / FRAME_INTERVAL.as_millis()) as i32,
+        },
+    );
+    // seats get their active output with their first input device otherwise, there are none
+    for seat in state.seats.iter() {
+        seat.user_data()
+            .insert_if_missing(|| ActiveOutput(RefCell::new(String::from(OUTPUT_NAME))));
+    }
+
+    let mut renderer = match HeadlessRenderer::new() {
+        Ok(renderer) => Some(renderer),
+        Err(err) => {
+            slog_scope::warn!("Unable to render the headless output, only frame callbacks are sent: {}", err);
+            None
+        }
+    };
+
+    let timer = Timer::new()?;
+    timer.handle().add_timeout(FRAME_INTERVAL, ());
+    let token = event_loop
+        .handle()
+        .insert_source(timer, move |(), handle, state| {
+            state.guard("render", |state| {
+                if let Some(renderer) = renderer.as_mut() {
+                    if let Err(err) = state.render_headless(renderer) {
+                        slog_scope::error!("Failed to render the headless output: {}", err);
+                    }
+                }
+                state.send_headless_frames();
+            });
+            handle.add_timeout(FRAME_INTERVAL, ());
+        })
+        .map_err(|_| anyhow::anyhow!("Failed to init eventloop timer for the headless output"))?;
+    state.tokens.push(token);
+
+    slog_scope::info!("Running headless on the virtual output {}, there are no input devices", OUTPUT_NAME);
+    Ok(())
+}
+
+impl Fireplace {
+    /// Renders the virtual output and takes the screenshots and screencast frames of it
+    fn render_headless(&mut self, output: &mut HeadlessRenderer) -> Result<()> {
+        let mut workspaces = self.workspaces.borrow_mut();
+        let scale = workspaces.output_by_name(OUTPUT_NAME).unwrap().scale();
+        let (space, switch) = match workspaces.visible_spaces(OUTPUT_NAME, &self.config.animations) {
+            Some(spaces) => spaces,
+            None => return Ok(()),
+        };
+        let popups = self.popups.borrow();
+        let screenshots = take_pending(&mut self.screenshots, OUTPUT_NAME);
+        let mut images = Vec::with_capacity(screenshots.len());
+        let size: Size<i32, Physical> = OUTPUT_SIZE.into();
+        let config = &self.config;
+        let (lock, seats, screencast) = (self.lock.as_ref(), &self.seats, self.screencast.as_ref());
+        let cache = &mut output.cache;
+        // the pbuffer starts at the bottom row like window surfaces
+        cache.blur.flipped = true;
+
+        output.renderer.bind(output.surface.clone())?;
+        output
+            .renderer
+            .render(size, Transform::Normal, |renderer, frame| {
+                match lock {
+                    Some(lock) => render_lock(lock, OUTPUT_NAME, scale, size, config, None, renderer, frame, cache, &mut [])?,
+                    None => {
+                        render_space(space, switch.as_ref(), scale, size, &**popups, config, None, None, renderer, frame, cache, &mut [])?;
+                        draw_dnd_icons(seats, OUTPUT_NAME, None, renderer, frame, scale, &mut [])?;
+                    }
+                }
+                for screenshot in screenshots.iter() {
+                    let area = screenshot.area(size, scale);
+                    match read_pixels(renderer, size, area, true) {
+                        Ok(image) => images.push((screenshot.clone(), image)),
+                        Err(err) => {
+                            slog_scope::error!("Failed to read back screenshot: {}", err);
+                            screenshot.failed(err.to_string());
+                        }
+                    }
+                }
+                if let Some(screencast) = screencast {
+                    screencast.capture(space, OUTPUT_NAME, size, scale, |area| {
+                        read_pixels(renderer, size, area, true)
+                            .map_err(|err| slog_scope::error!("Failed to read back screencast frame: {}", err))
+                            .ok()
+                    });
+                }
+                Ok(())
+            })
+            .and_then(|x| x.map_err(Into::into))?;
+
+        for (screenshot, image) in images {
+            save(&config.screenshot, screenshot, image);
+        }
+        Ok(())
+    }
+
+    /// Lets clients draw their next frame, as if the output was shown
+    fn send_headless_frames(&mut self) {
+        let time = self.start_time.elapsed().as_millis() as u32;
+        let workspaces = self.workspaces.borrow();
+        if let Some((space, _)) = workspaces.visible_spaces(OUTPUT_NAME, &self.config.animations) {
+            space.send_frames(time);
+        }
+        if let Some(lock) = self.lock.as_ref() {
+            lock.send_frames(OUTPUT_NAME, time);
+        }
+    }
+}
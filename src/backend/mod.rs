@@ -2,11 +2,55 @@ use anyhow::Result;
 use smithay::reexports::calloop::EventLoop;
 
 use crate::state::Fireplace;
+pub mod animation;
+pub mod blur;
+pub mod color_filter;
+pub mod damage;
+pub mod headless;
 pub mod render;
+pub mod rounded;
+pub mod screencast;
+pub mod screenshot;
+pub mod software;
+pub mod stats;
 pub mod udev;
+pub mod vnc;
 pub mod winit;
 pub mod egl;
 
+/// Backend forced on the command line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Winit,
+    Udev,
+    Headless,
+}
+
+impl std::str::FromStr for Backend {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Backend, String> {
+        match name {
+            "winit" => Ok(Backend::Winit),
+            "udev" => Ok(Backend::Udev),
+            "headless" => Ok(Backend::Headless),
+            _ => Err(format!("Unknown backend {}, expected winit, udev or headless", name)),
+        }
+    }
+}
+
+pub fn initial_backend(
+    event_loop: &mut EventLoop<'static, Fireplace>,
+    state: &mut Fireplace,
+    backend: Backend,
+) -> Result<()> {
+    match backend {
+        Backend::Winit => winit::init_winit(event_loop, state),
+        Backend::Udev => udev::init_udev(event_loop, state),
+        Backend::Headless => headless::init_headless(event_loop, state),
+    }
+}
+
 pub fn initial_backend_auto(
     event_loop: &mut EventLoop<'static, Fireplace>,
     state: &mut Fireplace,
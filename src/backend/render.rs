@@ -1,7 +1,9 @@
+use ab_glyph::{point, Font, FontVec, GlyphId, PxScale, ScaleFont};
+use cgmath::{Matrix3, SquareMatrix, Vector2};
 use image::{ImageBuffer, Rgba};
 use smithay::{
     backend::{
-        allocator::{Buffer, dmabuf::Dmabuf},
+        allocator::{Buffer, Modifier, dmabuf::Dmabuf},
         renderer::{
             buffer_type, Bind, BufferType, Frame, ImportAll, ImportDma, Renderer, Texture, Transform, Unbind,
             gles2::{Gles2Renderer, Gles2Texture, Gles2Error}
@@ -11,64 +13,526 @@ use smithay::{
         nix::sys::stat::dev_t,
         wayland_server::protocol::{wl_buffer, wl_surface},
     },
-    utils::{Logical, Point, Buffer as BufferCoords, Rectangle},
+    utils::{Logical, Physical, Point, Buffer as BufferCoords, Rectangle, Size},
     wayland::{
         compositor::{
-            with_surface_tree_upward, with_states, Damage, SubsurfaceCachedState, SurfaceAttributes, TraversalAction,
+            get_role, with_surface_tree_downward, with_surface_tree_upward, with_states, Damage, SubsurfaceCachedState, SurfaceAttributes, TraversalAction,
         },
-        seat::CursorImageAttributes,
+        seat::{CursorImageAttributes, Seat},
     },
 };
 
 use std::{
     cell::RefCell,
     collections::HashMap,
+    path::{Path, PathBuf},
+    rc::Rc,
     sync::Mutex,
 };
 
 use crate::{
-    backend::udev::DevId,
-    shell::{child_popups, SurfaceData, layout::Layout, window::PopupKind},
+    backend::{
+        animation::{Animations, Snapshot, WindowAnimation},
+        blur::{Blur, BlurState},
+        color_filter::ColorFilterState,
+        rounded::{RoundedClip, RoundedCorners, RoundedState},
+        screenshot::expand_home,
+        udev::DevId,
+    },
+    config::{Background, BlurConfig, Color, Config, Decoration, FillMode, WorkspaceTransition},
+    handler::{ActiveOutput, DndIcon},
+    ipc::{Notification, Urgency},
+    lock::Lock,
+    shell::{child_popups, decoration, SurfaceData, layout::Layout, window::{Kind, PopupKind}, workspace::Switch},
     state::BackendData,
-    wayland::handle_eglstream_events,
+    wayland::{handle_eglstream_events, SyncPoint},
 };
 
 static PLACEHOLDER: &[u8] = &[255, 0, 255, 255];
 
+/// Fonts tried for window titles, if none is configured
+static FONT_LOCATIONS: &[&str] = &[
+    "/usr/share/fonts/TTF/DejaVuSans.ttf",
+    "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf",
+    "/usr/share/fonts/dejavu/DejaVuSans.ttf",
+    "/usr/share/fonts/noto/NotoSans-Regular.ttf",
+    "/usr/share/fonts/truetype/noto/NotoSans-Regular.ttf",
+    "/usr/share/fonts/google-noto/NotoSans-Regular.ttf",
+];
+
+/// Upper bound of cached text textures, titles tend to change a lot
+const MAX_CACHED_TEXTS: usize = 64;
+
 pub struct BufferTextures {
     buffer: wl_buffer::WlBuffer,
     damage: Vec<Rectangle<i32, BufferCoords>>,
     textures: HashMap<Option<DevId>, Box<dyn std::any::Any>>,
+    release: Rc<BufferRelease>,
+    /// Has to be reached before the gpu may read the buffer
+    acquire: Option<SyncPoint>,
 }
 
-impl Drop for BufferTextures {
+/// Releases a client buffer, once neither its textures nor any plane scanning it out use it anymore
+///
+/// Buffers with a release point are released by signalling it instead.
+pub struct BufferRelease(wl_buffer::WlBuffer, Option<SyncPoint>);
+
+impl Drop for BufferRelease {
     fn drop(&mut self) {
-        self.buffer.release();
+        match self.1.take() {
+            Some(release) => release.release(self.0.clone()),
+            None => self.0.release(),
+        }
     }
 }
 
+/// Textures created by the compositor itself, cached per renderer
+pub struct RenderCache<T> {
+    colors: HashMap<Color, T>,
+    texts: HashMap<(String, i32, i32, Color), T>,
+    /// Font used for texts, loaded on first use
+    font: Option<Option<FontVec>>,
+    /// Background image by its path, reloaded once the path changes
+    background: Option<(String, Option<T>)>,
+    pub animations: Animations<T>,
+    pub blur: BlurState,
+    pub color_filter: ColorFilterState,
+    pub rounded: RoundedState,
+}
+
+impl<T> RenderCache<T> {
+    pub fn new() -> RenderCache<T> {
+        RenderCache {
+            colors: HashMap::new(),
+            texts: HashMap::new(),
+            font: None,
+            background: None,
+            animations: Animations::new(),
+            blur: BlurState::new(),
+            color_filter: ColorFilterState::new(),
+            rounded: RoundedState::new(),
+        }
+    }
+
+    /// Returns a texture filled with the given color
+    pub fn color<R>(&mut self, renderer: &mut R, color: Color) -> Result<&T, R::Error>
+    where
+        R: CpuAccess<Texture = T>,
+    {
+        if !self.colors.contains_key(&color) {
+            let image = ImageBuffer::<Rgba<u8>, _>::from_raw(1, 1, &color.0[..]).unwrap();
+            let texture = renderer.import_bitmap(&image)?;
+            self.colors.insert(color, texture);
+        }
+        Ok(&self.colors[&color])
+    }
+
+    /// Returns the texture of the background image, if it could be loaded
+    pub fn background<R>(&mut self, renderer: &mut R, path: &str) -> Result<Option<&T>, R::Error>
+    where
+        R: CpuAccess<Texture = T>,
+    {
+        if self.background.as_ref().map(|(loaded, _)| loaded != path).unwrap_or(true) {
+            let texture = match image::open(expand_home(path)) {
+                Ok(image) => Some(renderer.import_bitmap(&image.to_rgba8())?),
+                Err(err) => {
+                    slog_scope::warn!("Failed to load background image {}: {}", path, err);
+                    None
+                }
+            };
+            self.background = Some((String::from(path), texture));
+        }
+        Ok(self.background.as_ref().and_then(|(_, texture)| texture.as_ref()))
+    }
+
+    /// Returns a texture containing `text`, `height` physical pixels high
+    /// and cut off after `max_width` pixels, if the text is visible at all
+    pub fn text<R>(
+        &mut self,
+        renderer: &mut R,
+        font: Option<&Path>,
+        text: &str,
+        height: i32,
+        max_width: i32,
+        color: Color,
+    ) -> Result<Option<&T>, R::Error>
+    where
+        R: CpuAccess<Texture = T>,
+    {
+        let font = match self.font.get_or_insert_with(|| load_font(font)) {
+            Some(font) => font,
+            None => return Ok(None),
+        };
+        let key = (String::from(text), height, max_width, color);
+        if !self.texts.contains_key(&key) {
+            let image = match rasterize(font, text, height, max_width, color) {
+                Some(image) => image,
+                None => return Ok(None),
+            };
+            let texture = renderer.import_bitmap(&image)?;
+            if self.texts.len() >= MAX_CACHED_TEXTS {
+                self.texts.clear();
+            }
+            self.texts.insert(key.clone(), texture);
+        }
+        Ok(self.texts.get(&key))
+    }
+}
+
+fn load_font(path: Option<&Path>) -> Option<FontVec> {
+    let candidates = match path {
+        Some(path) => vec![path.to_path_buf()],
+        None => FONT_LOCATIONS.iter().map(PathBuf::from).collect(),
+    };
+    for path in candidates {
+        match std::fs::read(&path)
+            .map_err(|err| err.to_string())
+            .and_then(|data| FontVec::try_from_vec(data).map_err(|err| err.to_string()))
+        {
+            Ok(font) => {
+                slog_scope::info!("Using font {}", path.display());
+                return Some(font);
+            }
+            Err(err) => slog_scope::debug!("Unable to load font {}: {}", path.display(), err),
+        }
+    }
+    slog_scope::warn!("No usable font found, titles will not be drawn");
+    None
+}
+
+/// Renders a single line of text into a premultiplied RGBA image
+fn rasterize(
+    font: &FontVec,
+    text: &str,
+    height: i32,
+    max_width: i32,
+    color: Color,
+) -> Option<ImageBuffer<Rgba<u8>, Vec<u8>>> {
+    let scale = PxScale::from(height as f32 * 0.75);
+    let scaled = font.as_scaled(scale);
+    let baseline = (height as f32 - scaled.height()) / 2.0 + scaled.ascent();
+
+    let mut glyphs = Vec::new();
+    let mut caret = 0.0;
+    let mut previous: Option<GlyphId> = None;
+    for c in text.chars() {
+        let id = scaled.glyph_id(c);
+        if let Some(previous) = previous {
+            caret += scaled.kern(previous, id);
+        }
+        let advance = scaled.h_advance(id);
+        if caret + advance > max_width as f32 {
+            break;
+        }
+        glyphs.push(id.with_scale_and_position(scale, point(caret, baseline)));
+        caret += advance;
+        previous = Some(id);
+    }
+
+    let width = caret.ceil() as u32;
+    if width == 0 || height <= 0 {
+        return None;
+    }
+    let mut image = ImageBuffer::from_pixel(width, height as u32, Rgba([0, 0, 0, 0]));
+    for glyph in glyphs {
+        if let Some(outline) = font.outline_glyph(glyph) {
+            let bounds = outline.px_bounds();
+            outline.draw(|x, y, coverage| {
+                let x = x as i32 + bounds.min.x as i32;
+                let y = y as i32 + bounds.min.y as i32;
+                if x < 0 || y < 0 || x >= width as i32 || y >= height {
+                    return;
+                }
+                let alpha = coverage.min(1.0) * color.0[3] as f32 / 255.0;
+                let premultiply = |channel: u8| (channel as f32 * alpha).round() as u8;
+                image.put_pixel(
+                    x as u32,
+                    y as u32,
+                    Rgba([
+                        premultiply(color.0[0]),
+                        premultiply(color.0[1]),
+                        premultiply(color.0[2]),
+                        (alpha * 255.0).round() as u8,
+                    ]),
+                );
+            });
+        }
+    }
+    Some(image)
+}
+
+/// A client buffer, that can be put onto a plane instead of being composited
+pub struct ScanoutCandidate {
+    pub surface: wl_surface::WlSurface,
+    pub dmabuf: Dmabuf,
+    /// Keeps the buffer from being released while it is scanned out
+    pub release: Rc<BufferRelease>,
+    /// Area covered on the output
+    pub geometry: Rectangle<i32, Physical>,
+    pub fullscreen: bool,
+}
+
+/// Finds the topmost window, if it is a single dmabuf usable by the given gpu
+///
+/// Windows with subsurfaces, popups, rounded corners, blur or any transparency applied by us need to be composited.
+pub fn scanout_candidate(
+    space: &dyn Layout,
+    popups: &[PopupKind],
+    config: &Config,
+    device: Option<DevId>,
+    scale: f32,
+) -> Option<ScanoutCandidate> {
+    let (toplevel, location, _) = space.windows_from_bottom_to_top().last()?;
+    let surface = toplevel.get_surface()?.clone();
+    let mut alpha = space.opacity(&toplevel);
+    if space.focused_window().as_ref() != Some(&toplevel) {
+        alpha *= config.view.inactive_opacity;
+    }
+    if alpha < 1.0 || child_popups(popups.iter(), &surface).next().is_some() {
+        return None;
+    }
+    let radius = space
+        .corner_radius(&toplevel)
+        .unwrap_or(config.decoration.corner_radius);
+    if (radius > 0 && !toplevel.is_fullscreen()) || space.blur(&toplevel) {
+        return None;
+    }
+
+    let mut surfaces = 0;
+    with_surface_tree_downward(
+        &surface,
+        (),
+        |_, _, _| TraversalAction::DoChildren(()),
+        |_, _, _| surfaces += 1,
+        |_, _, _| true,
+    );
+    if surfaces != 1 {
+        return None;
+    }
+
+    with_states(&surface, |states| {
+        let mut data = states.data_map.get::<RefCell<SurfaceData>>()?.borrow_mut();
+        // the surface might not be rendered, so take over new buffers here as well
+        pull_buffer(&mut data, &*states.cached_state.current::<SurfaceAttributes>());
+        let texture = data.texture.as_ref()?;
+        // planes cannot wait for the client, the buffer is composited until it is ready
+        if texture.acquire.as_ref().map(|acquire| !acquire.is_signaled()).unwrap_or(false) {
+            return None;
+        }
+        let dmabuf = texture.buffer.as_ref().user_data().get::<Dmabuf>()?.clone();
+        let client_id = texture
+            .buffer
+            .as_ref()
+            .client()
+            .and_then(|client| client.data_map().get::<DevId>().cloned());
+        if client_id.is_some() && client_id != device {
+            return None;
+        }
+        let size = data.buffer_dimensions?.to_logical(data.buffer_scale);
+        Some(ScanoutCandidate {
+            surface: surface.clone(),
+            dmabuf,
+            release: texture.release.clone(),
+            geometry: Rectangle {
+                loc: location.to_f64().to_physical(scale as f64).to_i32_round(),
+                size: size.to_f64().to_physical(scale as f64).to_i32_round(),
+            },
+            fullscreen: toplevel.is_fullscreen(),
+        })
+    })
+    .ok()
+    .flatten()
+}
+
+/// Draws the windows of a workspace, except for the surface `skip` presented by a plane
+///
+/// While the output switches workspaces, the previous one is drawn as well.
+#[allow(clippy::too_many_arguments)]
 pub fn render_space<'a, R, E, F, T>(
     space: &dyn Layout,
+    switch: Option<&Switch<'_>>,
     scale: f32,
+    size: Size<i32, Physical>,
     popups: &[PopupKind],
+    config: &Config,
     device: Option<DevId>,
+    skip: Option<&wl_surface::WlSurface>,
     renderer: &mut R,
     frame: &mut F,
+    cache: &mut RenderCache<T>,
     other_backends: &mut [(&dev_t, &mut BackendData)],
 ) -> Result<(), E>
 where
-    R: Renderer<Error = E, TextureId = T, Frame = F> + ImportDma + ImportAll + CpuAccess,
+    R: Renderer<Error = E, TextureId = T, Frame = F>
+        + ImportDma
+        + ImportAll
+        + CpuAccess<Error = E, Texture = T>
+        + Blur<Error = E, Texture = T>
+        + RoundedCorners<Error = E>,
     F: Frame<Error = E, TextureId = T>,
-    T: Texture + 'static,
+    T: Texture + Clone + 'static,
+    E: std::error::Error,
+{
+    profile_span!("render_space");
+    draw_background(renderer, frame, cache, &config.background, size)?;
+
+    let switch = match switch {
+        Some(switch) => switch,
+        None => {
+            return draw_space(space, (0, 0).into(), 1.0, scale, popups, config, device, skip, renderer, frame, cache, other_backends);
+        }
+    };
+    let progress = switch.progress;
+    let (previous, current) = match config.animations.workspace_transition {
+        WorkspaceTransition::Slide => {
+            let width = (size.w as f32 / scale).round() as i32;
+            let direction = if switch.forward { 1 } else { -1 };
+            let current = (width as f32 * (1.0 - progress)).round() as i32 * direction;
+            (((current - width * direction, 0).into(), 1.0), ((current, 0).into(), 1.0))
+        }
+        WorkspaceTransition::Crossfade => (((0, 0).into(), 1.0 - progress), ((0, 0).into(), progress)),
+    };
+    draw_space(switch.previous, previous.0, previous.1, scale, popups, config, device, None, renderer, frame, cache, other_backends)?;
+    draw_space(space, current.0, current.1, scale, popups, config, device, skip, renderer, frame, cache, other_backends)
+}
+
+/// Draws the windows of a workspace moved by `shift` and faded by `fade`
+#[allow(clippy::too_many_arguments)]
+fn draw_space<'a, R, E, F, T>(
+    space: &dyn Layout,
+    shift: Point<i32, Logical>,
+    fade: f32,
+    scale: f32,
+    popups: &[PopupKind],
+    config: &Config,
+    device: Option<DevId>,
+    skip: Option<&wl_surface::WlSurface>,
+    renderer: &mut R,
+    frame: &mut F,
+    cache: &mut RenderCache<T>,
+    other_backends: &mut [(&dev_t, &mut BackendData)],
+) -> Result<(), E>
+where
+    R: Renderer<Error = E, TextureId = T, Frame = F>
+        + ImportDma
+        + ImportAll
+        + CpuAccess<Error = E, Texture = T>
+        + Blur<Error = E, Texture = T>
+        + RoundedCorners<Error = E>,
+    F: Frame<Error = E, TextureId = T>,
+    T: Texture + Clone + 'static,
     E: std::error::Error,
 {
-    frame.clear([0.8, 0.8, 0.8, 1.0])?;
+    let decoration = &config.decoration;
+    let focused = space.focused_window();
+    let border = decoration.border.width as i32;
+    let title_height = decoration.title_bar.height();
+
+    let windows = space.windows_from_bottom_to_top().collect::<Vec<_>>();
+    let animations = &config.animations;
+    let record = animations.enabled && animations.close > 0;
+    cache.animations.update(animations, space.id(), space.animates_moves(), &windows);
+
+    for (toplevel, row) in space.stacked_titles() {
+        let row = Rectangle::from_loc_and_size(row.loc + shift, row.size);
+        let is_focused = focused.as_ref() == Some(&toplevel);
+        draw_title_row(renderer, frame, cache, &toplevel, row, is_focused, decoration, scale)?;
+    }
 
     // redraw the frame, in a simple but inneficient way
-    for (toplevel_surface, location, _bounding_box) in space.windows_from_bottom_to_top() {
+    for (toplevel_surface, location, bounding_box) in windows {
         if let Some(wl_surface) = toplevel_surface.get_surface() {
+            let is_focused = focused.as_ref() == Some(&toplevel_surface);
+            let mut alpha = space.opacity(&toplevel_surface);
+            if !is_focused {
+                alpha *= config.view.inactive_opacity;
+            }
+            let animation = cache
+                .animations
+                .window(animations, space.id(), &toplevel_surface)
+                .unwrap_or(WindowAnimation {
+                    location,
+                    alpha: 1.0,
+                    zoom: 1.0,
+                });
+            let animation = WindowAnimation {
+                location: animation.location + shift,
+                alpha: animation.alpha * fade,
+                ..animation
+            };
+            alpha *= animation.alpha;
+            let offset = animation.location - location;
+
+            // decorations only follow moves, they appear once the window is fully opened
+            if !toplevel_surface.is_fullscreen() && space.is_decorated(&toplevel_surface) && animation.zoom >= 1.0 {
+                let mut geometry = decoration::window_geometry(&toplevel_surface, location, bounding_box);
+                geometry.loc += offset;
+                if title_height > 0 {
+                    draw_title_bar(renderer, frame, cache, &toplevel_surface, geometry, is_focused, decoration, scale)?;
+                    // the border surrounds the title bar as well
+                    geometry.loc.y -= title_height;
+                    geometry.size.h += title_height;
+                }
+                if border > 0 {
+                    let color = if is_focused {
+                        decoration.border.focused
+                    } else if space.is_urgent(&toplevel_surface) {
+                        decoration.border.urgent
+                    } else {
+                        decoration.border.unfocused
+                    };
+                    let texture = cache.color(renderer, color)?;
+                    draw_border(frame, texture, geometry, border, scale)?;
+                }
+            }
+
+
             // this surface is a root of a subsurface tree that needs to be drawn
-            draw_surface_tree(device, renderer, frame, wl_surface, location, scale, other_backends)?;
+            if skip != Some(wl_surface) {
+                let (draw_location, draw_scale) = if animation.zoom < 1.0 {
+                    (animation.zoomed_location(bounding_box), scale * animation.zoom)
+                } else {
+                    (animation.location, scale)
+                };
+                // round and blur the window geometry, not client side shadows
+                let geometry = decoration::window_geometry(&toplevel_surface, location, bounding_box);
+                let geometry_loc = draw_location + (geometry.loc - location);
+                let area = Rectangle::<f64, Physical> {
+                    loc: geometry_loc.to_f64().to_physical(draw_scale as f64),
+                    size: geometry.size.to_f64().to_physical(draw_scale as f64),
+                };
+                let radius = space
+                    .corner_radius(&toplevel_surface)
+                    .unwrap_or(decoration.corner_radius);
+                let clip = if radius > 0 && !toplevel_surface.is_fullscreen() {
+                    Some(RoundedClip {
+                        area,
+                        radius: radius as f64 * draw_scale as f64,
+                    })
+                } else {
+                    None
+                };
+                // the blur copies the frame, which is out of reach once drawing is redirected
+                let blurred = if space.blur(&toplevel_surface) {
+                    blur_behind(renderer, cache, &decoration.blur, area)?
+                } else {
+                    None
+                };
+                let flipped = cache.blur.flipped;
+                if let Some(clip) = clip.as_ref() {
+                    renderer.begin_rounded(&mut cache.rounded, clip, flipped)?;
+                }
+                if let Some((blurred, position)) = blurred {
+                    frame.render_texture_at(&blurred, position, 1, 1.0, Transform::Normal, alpha)?;
+                }
+                draw_surface_tree(device, renderer, frame, wl_surface, draw_location, draw_scale, alpha, other_backends)?;
+                if let Some(clip) = clip.as_ref() {
+                    renderer.end_rounded(&mut cache.rounded, clip, flipped)?;
+                }
+            }
+            if record {
+                cache.animations.record(space.id(), &toplevel_surface, snapshot_surface_tree(device, wl_surface));
+            }
 
             // furthermore, draw its popups
             let toplevel_geometry_offset: Point<i32, Logical> = (0, 0).into(); // TODO
@@ -81,17 +545,259 @@ where
 
             for popup in child_popups(popups.iter(), &wl_surface) {
                 let popup_location = popup.location();
-                let draw_location = location + popup_location + toplevel_geometry_offset;
+                let draw_location = animation.location + popup_location + toplevel_geometry_offset;
                 if let Some(wl_surface) = popup.get_surface() {
-                    draw_surface_tree(device, renderer, frame, wl_surface, draw_location, scale, other_backends)?;
+                    draw_surface_tree(device, renderer, frame, wl_surface, draw_location, scale, alpha, other_backends)?;
                 }
             }
         }
     }
 
+    // closed windows fade out above the remaining ones
+    for (closing, animation) in cache.animations.closing(animations, space.id()) {
+        let animation = WindowAnimation {
+            location: animation.location + shift,
+            alpha: animation.alpha * fade,
+            ..animation
+        };
+        let location = animation.zoomed_location(closing.bounding_box);
+        let zoomed_scale = scale * animation.zoom;
+        for snapshot in closing.snapshot.iter() {
+            frame.render_texture_at(
+                &snapshot.texture,
+                (location + snapshot.offset).to_f64().to_physical(zoomed_scale as f64).to_i32_round(),
+                snapshot.buffer_scale,
+                zoomed_scale as f64,
+                Transform::Normal,
+                animation.alpha,
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Collects the current textures of a surface tree, relative to its root
+fn snapshot_surface_tree<T: Clone + 'static>(device: Option<DevId>, root: &wl_surface::WlSurface) -> Vec<Snapshot<T>> {
+    let mut snapshot = Vec::new();
+    with_surface_tree_upward(
+        root,
+        Point::<i32, Logical>::from((0, 0)),
+        |_surface, states, location| {
+            let mut location = *location;
+            if states.role == Some("subsurface") {
+                let current = states.cached_state.current::<SubsurfaceCachedState>();
+                location += current.location;
+            }
+            TraversalAction::DoChildren(location)
+        },
+        |_surface, states, location| {
+            let mut location = *location;
+            if let Some(data) = states.data_map.get::<RefCell<SurfaceData>>() {
+                let data = data.borrow();
+                if let Some(texture) = data
+                    .texture
+                    .as_ref()
+                    .and_then(|x| x.textures.get(&device))
+                    .and_then(|x| <dyn std::any::Any>::downcast_ref::<T>(&**x))
+                {
+                    if states.role == Some("subsurface") {
+                        let current = states.cached_state.current::<SubsurfaceCachedState>();
+                        location += current.location;
+                    }
+                    snapshot.push(Snapshot {
+                        texture: texture.clone(),
+                        offset: location,
+                        buffer_scale: data.buffer_scale,
+                    });
+                }
+            }
+        },
+        |_, _, _| true,
+    );
+    snapshot
+}
+
+/// Draws the title bar including its buttons above `geometry`
+#[allow(clippy::too_many_arguments)]
+fn draw_title_bar<R, E, F, T>(
+    renderer: &mut R,
+    frame: &mut F,
+    cache: &mut RenderCache<T>,
+    toplevel: &Kind,
+    geometry: Rectangle<i32, Logical>,
+    focused: bool,
+    decoration: &Decoration,
+    output_scale: f32,
+) -> Result<(), E>
+where
+    R: CpuAccess<Error = E, Texture = T>,
+    F: Frame<Error = E, TextureId = T>,
+    T: Texture,
+{
+    let config = &decoration.title_bar;
+    let to_physical = |rect: Rectangle<i32, Logical>| Rectangle {
+        loc: rect.loc.to_f64().to_physical(output_scale as f64).to_i32_round(),
+        size: rect.size.to_f64().to_physical(output_scale as f64).to_i32_round(),
+    };
+
+    let bar = decoration::title_bar(geometry, config.height());
+    let background = if focused { config.focused } else { config.unfocused };
+    draw_solid(frame, cache.color(renderer, background)?, to_physical(bar), 1.0)?;
+
+    let buttons = decoration::buttons(bar);
+    for &(button, area) in buttons.iter() {
+        let color = match button {
+            decoration::Button::Close => config.close,
+            decoration::Button::Maximize => config.maximize,
+        };
+        // leave some space around the button
+        let inset = area.size.h / 4;
+        let area = Rectangle::from_loc_and_size(
+            (area.loc.x + inset, area.loc.y + inset),
+            (area.size.w - 2 * inset, area.size.h - 2 * inset),
+        );
+        draw_solid(frame, cache.color(renderer, color)?, to_physical(area), 1.0)?;
+    }
+
+    let text_area = Rectangle::from_loc_and_size(
+        bar.loc,
+        (bar.size.w - buttons.len() as i32 * bar.size.h, bar.size.h),
+    );
+    draw_title(renderer, frame, cache, toplevel, text_area, decoration, output_scale)
+}
+
+/// Draws the title row of a window in a stacked or tabbed container, without buttons
+#[allow(clippy::too_many_arguments)]
+fn draw_title_row<R, E, F, T>(
+    renderer: &mut R,
+    frame: &mut F,
+    cache: &mut RenderCache<T>,
+    toplevel: &Kind,
+    row: Rectangle<i32, Logical>,
+    focused: bool,
+    decoration: &Decoration,
+    output_scale: f32,
+) -> Result<(), E>
+where
+    R: CpuAccess<Error = E, Texture = T>,
+    F: Frame<Error = E, TextureId = T>,
+    T: Texture,
+{
+    let area = Rectangle {
+        loc: row.loc.to_f64().to_physical(output_scale as f64).to_i32_round(),
+        size: row.size.to_f64().to_physical(output_scale as f64).to_i32_round(),
+    };
+    let color = if focused {
+        decoration.title_bar.focused
+    } else {
+        decoration.title_bar.unfocused
+    };
+    let background = cache.color(renderer, color)?;
+    draw_solid(frame, background, area, 1.0)?;
+    draw_title(renderer, frame, cache, toplevel, row, decoration, output_scale)
+}
+
+/// Draws the title of a window into an area of a title bar or row, padded on both sides
+#[allow(clippy::too_many_arguments)]
+fn draw_title<R, E, F, T>(
+    renderer: &mut R,
+    frame: &mut F,
+    cache: &mut RenderCache<T>,
+    toplevel: &Kind,
+    area: Rectangle<i32, Logical>,
+    decoration: &Decoration,
+    output_scale: f32,
+) -> Result<(), E>
+where
+    R: CpuAccess<Error = E, Texture = T>,
+    F: Frame<Error = E, TextureId = T>,
+    T: Texture,
+{
+    let config = &decoration.title_bar;
+    let title = match toplevel.title() {
+        Some(title) => title,
+        None => return Ok(()),
+    };
+    let padding = area.size.h / 4;
+    let text_area = Rectangle::from_loc_and_size(
+        (area.loc.x + padding, area.loc.y),
+        (area.size.w - 2 * padding, area.size.h),
+    );
+    let text_area = Rectangle {
+        loc: text_area.loc.to_f64().to_physical(output_scale as f64).to_i32_round(),
+        size: text_area.size.to_f64().to_physical(output_scale as f64).to_i32_round(),
+    };
+    if let Some(texture) = cache.text(
+        renderer,
+        config.font.as_deref(),
+        &title,
+        text_area.size.h,
+        text_area.size.w,
+        config.text,
+    )? {
+        frame.render_texture_at(texture, text_area.loc, 1, 1.0, Transform::Normal, 1.0)?;
+    }
     Ok(())
 }
 
+/// Draws a border of `width` around the outside of `geometry`
+fn draw_border<F, E, T>(
+    frame: &mut F,
+    texture: &T,
+    geometry: Rectangle<i32, Logical>,
+    width: i32,
+    output_scale: f32,
+) -> Result<(), E>
+where
+    F: Frame<Error = E, TextureId = T>,
+    T: Texture,
+{
+    let (x, y) = geometry.loc.into();
+    let (w, h) = geometry.size.into();
+    let rects: [Rectangle<i32, Logical>; 4] = [
+        Rectangle::from_loc_and_size((x - width, y - width), (w + 2 * width, width)),
+        Rectangle::from_loc_and_size((x - width, y + h), (w + 2 * width, width)),
+        Rectangle::from_loc_and_size((x - width, y), (width, h)),
+        Rectangle::from_loc_and_size((x + w, y), (width, h)),
+    ];
+    for rect in rects.iter() {
+        let area = Rectangle {
+            loc: rect.loc.to_f64().to_physical(output_scale as f64).to_i32_round(),
+            size: rect.size.to_f64().to_physical(output_scale as f64).to_i32_round(),
+        };
+        draw_solid(frame, texture, area, 1.0)?;
+    }
+    Ok(())
+}
+
+/// Stretches a (solid) texture over the given area
+pub fn draw_solid<F, E, T>(
+    frame: &mut F,
+    texture: &T,
+    area: Rectangle<i32, Physical>,
+    alpha: f32,
+) -> Result<(), E>
+where
+    F: Frame<Error = E, TextureId = T>,
+    T: Texture,
+{
+    let mut matrix = Matrix3::<f32>::identity();
+    matrix = matrix * Matrix3::from_translation(Vector2::new(area.loc.x as f32, area.loc.y as f32));
+    matrix = matrix * Matrix3::from_nonuniform_scale(area.size.w as f32, area.size.h as f32);
+    frame.render_texture(
+        texture,
+        matrix,
+        [
+            Vector2::new(0.0, 0.0),
+            Vector2::new(1.0, 0.0),
+            Vector2::new(0.0, 1.0),
+            Vector2::new(1.0, 1.0),
+        ],
+        alpha,
+    )
+}
+
 pub fn draw_cursor<R, E, F, T>(
     device: Option<DevId>,
     renderer: &mut R,
@@ -107,6 +813,12 @@ where
     E: std::error::Error,
     T: Texture + 'static,
 {
+    let delta = cursor_hotspot(surface);
+    draw_surface_tree(device, renderer, frame, surface, location - delta, output_scale, 1.0, other_backends)
+}
+
+/// Hotspot of a client provided cursor surface
+pub fn cursor_hotspot(surface: &wl_surface::WlSurface) -> Point<i32, Logical> {
     let ret = with_states(surface, |states| {
         Some(
             states
@@ -119,7 +831,7 @@ where
         )
     })
     .unwrap_or(None);
-    let delta = match ret {
+    match ret {
         Some(h) => h,
         None => {
             slog_scope::warn!(
@@ -127,10 +839,47 @@ where
             );
             (0, 0).into()
         }
-    };
-    draw_surface_tree(device, renderer, frame, surface, location - delta, output_scale, other_backends)
+    }
+}
+
+/// Draws the drag-and-drop icons of all seats with their pointer on this output
+pub fn draw_dnd_icons<R, E, F, T>(
+    seats: &[Seat],
+    output: &str,
+    device: Option<DevId>,
+    renderer: &mut R,
+    frame: &mut F,
+    output_scale: f32,
+    other_backends: &mut [(&dev_t, &mut BackendData)],
+) -> Result<(), E>
+where
+    R: Renderer<Error = E, TextureId = T, Frame = F> + ImportDma + ImportAll + CpuAccess,
+    F: Frame<Error = E, TextureId = T>,
+    E: std::error::Error,
+    T: Texture + 'static,
+{
+    for seat in seats.iter().filter(|seat| {
+        seat.user_data()
+            .get::<ActiveOutput>()
+            .map(|name| &*name.0.borrow() == output)
+            .unwrap_or(false)
+    }) {
+        let icon = seat.user_data().get::<DndIcon>().and_then(|icon| icon.0.borrow().clone());
+        if let (Some(icon), Some(pointer)) = (icon, seat.get_pointer()) {
+            if !icon.as_ref().is_alive() {
+                continue;
+            }
+            if get_role(&icon) != Some("dnd_icon") {
+                slog_scope::warn!("Trying to display as a dnd icon a surface that does not have the DndIcon role.");
+            }
+            let location = pointer.current_location().to_i32_round();
+            draw_surface_tree(device, renderer, frame, &icon, location, output_scale, 1.0, other_backends)?;
+        }
+    }
+    Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 fn draw_surface_tree<R, E, F, T>(
     device: Option<DevId>,
     renderer: &mut R,
@@ -138,6 +887,7 @@ fn draw_surface_tree<R, E, F, T>(
     root: &wl_surface::WlSurface,
     location: Point<i32, Logical>,
     output_scale: f32,
+    alpha: f32,
     other_backends: &mut [(&dev_t, &mut BackendData)],
 ) -> Result<(), E>
 where
@@ -156,36 +906,28 @@ where
             // Pull a new buffer if available
             if let Some(data) = states.data_map.get::<RefCell<SurfaceData>>() {
                 let mut data = data.borrow_mut();
-                let attributes = states.cached_state.current::<SurfaceAttributes>();
-                if data.texture.is_none() {
-                    if let Some(buffer) = data.buffer.take() {
-                        let damage = attributes
-                            .damage
-                            .iter()
-                            .map(|dmg| match dmg {
-                                Damage::Buffer(rect) => *rect,
-                                // TODO also apply transformations
-                                Damage::Surface(rect) => rect.to_buffer(attributes.buffer_scale),
-                            })
-                            .collect::<Vec<_>>();
-                        
-                        data.texture = Some(BufferTextures {
-                            buffer,
-                            damage,
-                            textures: HashMap::new(),
-                        });
-                    }
-                }
+                pull_buffer(&mut data, &*states.cached_state.current::<SurfaceAttributes>());
 
                 if let Some(texture) = data.texture.as_mut() {
                     let maybe_dma = handle_eglstream_events(&texture.buffer);
                     if !texture.textures.contains_key(&device) {
+                        if let Some(acquire) = texture.acquire.as_ref() {
+                            acquire.gpu_wait();
+                        }
                         let client_id = texture.buffer.as_ref().client().and_then(|client| client.data_map().get::<DevId>().cloned());
                         match buffer_type(&texture.buffer) {
                             Some(BufferType::Dma) | None => {
                                 // Not device local
                                 let dma = texture.buffer.as_ref().user_data().get::<Dmabuf>().cloned().unwrap_or_else(|| maybe_dma.unwrap().1);
-                                match renderer.import_dmabuf(&dma) {
+                                // Importing buffers of other gpus may succeed and still produce garbage
+                                // because of vendor specific tiling, only linear buffers are safe to share.
+                                let same_device = client_id.is_none() || client_id == device;
+                                let direct = if same_device || dma.format().modifier == Modifier::Linear {
+                                    renderer.import_dmabuf(&dma).map_err(|err| err.to_string())
+                                } else {
+                                    Err(String::from("buffer is not linear"))
+                                };
+                                match direct {
                                     Ok(m) => {
                                         // hardware-accelerated copy, yeah!
                                         slog_scope::trace!("Imported dmabuf");
@@ -250,16 +992,17 @@ where
                         let current = states.cached_state.current::<SubsurfaceCachedState>();
                         location += current.location;
                     }
+                    let position = location
+                        .to_f64()
+                        .to_physical(output_scale as f64)
+                        .to_i32_round();
                     if let Err(err) = frame.render_texture_at(
                         texture,
-                        location
-                            .to_f64()
-                            .to_physical(output_scale as f64)
-                            .to_i32_round(),
+                        position,
                         buffer_scale,
                         output_scale as f64,
                         Transform::Normal, /* TODO */
-                        1.0,
+                        alpha,
                     ) {
                         result = Err(err);
                     }
@@ -272,30 +1015,485 @@ where
     result
 }
 
+/// Height of a line of the debug overlay in logical pixels
+const OVERLAY_LINE: i32 = 16;
+const OVERLAY_WIDTH: i32 = 280;
+
+/// Draws lines of debug information into the top left corner of the frame
+pub fn draw_debug_overlay<R, E, F, T>(
+    renderer: &mut R,
+    frame: &mut F,
+    cache: &mut RenderCache<T>,
+    config: &Config,
+    lines: &[String],
+    output_scale: f32,
+) -> Result<(), E>
+where
+    R: CpuAccess<Error = E, Texture = T>,
+    F: Frame<Error = E, TextureId = T>,
+    T: Texture,
+{
+    let to_physical = |rect: Rectangle<i32, Logical>| Rectangle {
+        loc: rect.loc.to_f64().to_physical(output_scale as f64).to_i32_round(),
+        size: rect.size.to_f64().to_physical(output_scale as f64).to_i32_round(),
+    };
+    let padding = OVERLAY_LINE / 2;
+    let background = Rectangle::from_loc_and_size(
+        (padding, padding),
+        (OVERLAY_WIDTH, lines.len() as i32 * OVERLAY_LINE + 2 * padding),
+    );
+    draw_solid(frame, cache.color(renderer, Color([0, 0, 0, 0xc0]))?, to_physical(background), 1.0)?;
+
+    for (i, line) in lines.iter().enumerate() {
+        let area = to_physical(Rectangle::from_loc_and_size(
+            (2 * padding, 2 * padding + i as i32 * OVERLAY_LINE),
+            (OVERLAY_WIDTH - 2 * padding, OVERLAY_LINE),
+        ));
+        if let Some(texture) = cache.text(
+            renderer,
+            config.decoration.title_bar.font.as_deref(),
+            line,
+            area.size.h,
+            area.size.w,
+            Color([0xff, 0xff, 0xff, 0xff]),
+        )? {
+            frame.render_texture_at(texture, area.loc, 1, 1.0, Transform::Normal, 1.0)?;
+        }
+    }
+    Ok(())
+}
+
+/// Width of messages in logical pixels, unless the output is narrower
+const MESSAGE_WIDTH: i32 = 720;
+
+/// Draws a message centered at the top of the frame on a red background
+pub fn draw_message<R, E, F, T>(
+    renderer: &mut R,
+    frame: &mut F,
+    cache: &mut RenderCache<T>,
+    config: &Config,
+    lines: &[String],
+    size: Size<i32, Physical>,
+    output_scale: f32,
+) -> Result<(), E>
+where
+    R: CpuAccess<Error = E, Texture = T>,
+    F: Frame<Error = E, TextureId = T>,
+    T: Texture,
+{
+    let to_physical = |rect: Rectangle<i32, Logical>| Rectangle {
+        loc: rect.loc.to_f64().to_physical(output_scale as f64).to_i32_round(),
+        size: rect.size.to_f64().to_physical(output_scale as f64).to_i32_round(),
+    };
+    let padding = OVERLAY_LINE / 2;
+    let output_width = (size.w as f64 / output_scale as f64).round() as i32;
+    let width = MESSAGE_WIDTH.min(output_width - 2 * padding);
+    let x = (output_width - width) / 2;
+    let background = Rectangle::from_loc_and_size(
+        (x, padding),
+        (width, lines.len() as i32 * OVERLAY_LINE + 2 * padding),
+    );
+    draw_solid(frame, cache.color(renderer, Color([0xa0, 0x10, 0x10, 0xe0]))?, to_physical(background), 1.0)?;
+
+    for (i, line) in lines.iter().enumerate() {
+        let area = to_physical(Rectangle::from_loc_and_size(
+            (x + padding, 2 * padding + i as i32 * OVERLAY_LINE),
+            (width - 2 * padding, OVERLAY_LINE),
+        ));
+        if let Some(texture) = cache.text(
+            renderer,
+            config.decoration.title_bar.font.as_deref(),
+            line,
+            area.size.h,
+            area.size.w,
+            Color([0xff, 0xff, 0xff, 0xff]),
+        )? {
+            frame.render_texture_at(texture, area.loc, 1, 1.0, Transform::Normal, 1.0)?;
+        }
+    }
+    Ok(())
+}
+
+/// Width of the on-screen display in logical pixels
+const OSD_WIDTH: i32 = 240;
+/// Height of the level bar of the on-screen display in logical pixels
+const OSD_BAR: i32 = 6;
+
+/// Draws a labeled level, like the volume, centered near the bottom of the frame
+#[allow(clippy::too_many_arguments)]
+pub fn draw_osd<R, E, F, T>(
+    renderer: &mut R,
+    frame: &mut F,
+    cache: &mut RenderCache<T>,
+    config: &Config,
+    label: &str,
+    level: f64,
+    size: Size<i32, Physical>,
+    output_scale: f32,
+) -> Result<(), E>
+where
+    R: CpuAccess<Error = E, Texture = T>,
+    F: Frame<Error = E, TextureId = T>,
+    T: Texture,
+{
+    let to_physical = |rect: Rectangle<i32, Logical>| Rectangle {
+        loc: rect.loc.to_f64().to_physical(output_scale as f64).to_i32_round(),
+        size: rect.size.to_f64().to_physical(output_scale as f64).to_i32_round(),
+    };
+    let padding = OVERLAY_LINE / 2;
+    let output_width = (size.w as f64 / output_scale as f64).round() as i32;
+    let output_height = (size.h as f64 / output_scale as f64).round() as i32;
+    let width = OSD_WIDTH.min(output_width - 2 * padding);
+    let height = OVERLAY_LINE + OSD_BAR + 3 * padding;
+    let x = (output_width - width) / 2;
+    let y = output_height - height - 8 * padding;
+    let background = Rectangle::from_loc_and_size((x, y), (width, height));
+    draw_solid(frame, cache.color(renderer, Color([0, 0, 0, 0xc0]))?, to_physical(background), 1.0)?;
+
+    let area = to_physical(Rectangle::from_loc_and_size(
+        (x + padding, y + padding),
+        (width - 2 * padding, OVERLAY_LINE),
+    ));
+    if let Some(texture) = cache.text(
+        renderer,
+        config.decoration.title_bar.font.as_deref(),
+        label,
+        area.size.h,
+        area.size.w,
+        Color([0xff, 0xff, 0xff, 0xff]),
+    )? {
+        frame.render_texture_at(texture, area.loc, 1, 1.0, Transform::Normal, 1.0)?;
+    }
+
+    let bar = Rectangle::from_loc_and_size(
+        (x + padding, y + 2 * padding + OVERLAY_LINE),
+        (width - 2 * padding, OSD_BAR),
+    );
+    draw_solid(frame, cache.color(renderer, Color([0x60, 0x60, 0x60, 0xff]))?, to_physical(bar), 1.0)?;
+    let filled = Rectangle::from_loc_and_size(bar.loc, ((bar.size.w as f64 * level).round() as i32, OSD_BAR));
+    draw_solid(frame, cache.color(renderer, Color([0xff, 0xff, 0xff, 0xff]))?, to_physical(filled), 1.0)
+}
+
+/// Width of notification popups in logical pixels, unless the output is narrower
+const NOTIFICATION_WIDTH: i32 = 400;
+
+/// Stacks notifications in the top right corner of the frame, the newest at the top
+///
+/// Critical notifications get a red border, low ones are dimmed.
+pub fn draw_notifications<R, E, F, T>(
+    renderer: &mut R,
+    frame: &mut F,
+    cache: &mut RenderCache<T>,
+    config: &Config,
+    notifications: &[Notification],
+    size: Size<i32, Physical>,
+    output_scale: f32,
+) -> Result<(), E>
+where
+    R: CpuAccess<Error = E, Texture = T>,
+    F: Frame<Error = E, TextureId = T>,
+    T: Texture,
+{
+    let to_physical = |rect: Rectangle<i32, Logical>| Rectangle {
+        loc: rect.loc.to_f64().to_physical(output_scale as f64).to_i32_round(),
+        size: rect.size.to_f64().to_physical(output_scale as f64).to_i32_round(),
+    };
+    let padding = OVERLAY_LINE / 2;
+    let output_width = (size.w as f64 / output_scale as f64).round() as i32;
+    let width = NOTIFICATION_WIDTH.min(output_width - 2 * padding);
+    let x = output_width - width - padding;
+    let mut y = padding;
+
+    for notification in notifications.iter().rev() {
+        let summary = if notification.summary.is_empty() {
+            &notification.app_name
+        } else {
+            &notification.summary
+        };
+        let lines = std::iter::once(summary).chain(notification.body.iter()).collect::<Vec<_>>();
+        let height = lines.len() as i32 * OVERLAY_LINE + 2 * padding;
+        let background = Rectangle::from_loc_and_size((x, y), (width, height));
+        if notification.urgency == Urgency::Critical {
+            let border = Rectangle::from_loc_and_size((x - 2, y - 2), (width + 4, height + 4));
+            draw_solid(frame, cache.color(renderer, Color([0xa0, 0x10, 0x10, 0xff]))?, to_physical(border), 1.0)?;
+        }
+        draw_solid(frame, cache.color(renderer, Color([0x20, 0x20, 0x20, 0xe0]))?, to_physical(background), 1.0)?;
+
+        let text = match notification.urgency {
+            Urgency::Low => Color([0xa0, 0xa0, 0xa0, 0xff]),
+            _ => Color([0xff, 0xff, 0xff, 0xff]),
+        };
+        for (i, line) in lines.iter().enumerate() {
+            let area = to_physical(Rectangle::from_loc_and_size(
+                (x + padding, y + padding + i as i32 * OVERLAY_LINE),
+                (width - 2 * padding, OVERLAY_LINE),
+            ));
+            if let Some(texture) = cache.text(
+                renderer,
+                config.decoration.title_bar.font.as_deref(),
+                line,
+                area.size.h,
+                area.size.w,
+                text,
+            )? {
+                frame.render_texture_at(texture, area.loc, 1, 1.0, Transform::Normal, 1.0)?;
+            }
+        }
+        y += height + padding;
+    }
+    Ok(())
+}
+
+/// Width of the built-in lock prompt in logical pixels
+const PROMPT_WIDTH: i32 = 480;
+
+/// Draws an output of the locked session instead of its workspace
+///
+/// Lockers show their own surfaces, otherwise the blurred wallpaper is covered by the built-in prompt.
+#[allow(clippy::too_many_arguments)]
+pub fn render_lock<R, E, F, T>(
+    lock: &Lock,
+    output: &str,
+    scale: f32,
+    size: Size<i32, Physical>,
+    config: &Config,
+    device: Option<DevId>,
+    renderer: &mut R,
+    frame: &mut F,
+    cache: &mut RenderCache<T>,
+    other_backends: &mut [(&dev_t, &mut BackendData)],
+) -> Result<(), E>
+where
+    R: Renderer<Error = E, TextureId = T, Frame = F>
+        + ImportDma
+        + ImportAll
+        + CpuAccess<Error = E, Texture = T>
+        + Blur<Error = E, Texture = T>,
+    F: Frame<Error = E, TextureId = T>,
+    T: Texture + Clone + 'static,
+    E: std::error::Error,
+{
+    draw_background(renderer, frame, cache, &config.background, size)?;
+    if lock.has_client() {
+        // outputs without a lock surface stay blank
+        if let Some(surface) = lock.surface(output) {
+            draw_surface_tree(device, renderer, frame, surface, (0, 0).into(), scale, 1.0, other_backends)?;
+        }
+        return Ok(());
+    }
+    let everything = Rectangle::from_loc_and_size((0.0, 0.0), size.to_f64());
+    if let Some((blurred, position)) = blur_behind(renderer, cache, &config.decoration.blur, everything)? {
+        frame.render_texture_at(&blurred, position, 1, 1.0, Transform::Normal, 1.0)?;
+    }
+
+    let to_physical = |rect: Rectangle<i32, Logical>| Rectangle {
+        loc: rect.loc.to_f64().to_physical(scale as f64).to_i32_round(),
+        size: rect.size.to_f64().to_physical(scale as f64).to_i32_round(),
+    };
+    let lines = lock.prompt_lines();
+    let padding = OVERLAY_LINE;
+    let output_width = (size.w as f64 / scale as f64).round() as i32;
+    let output_height = (size.h as f64 / scale as f64).round() as i32;
+    let width = PROMPT_WIDTH.min(output_width - 2 * padding);
+    let height = lines.len() as i32 * OVERLAY_LINE + 2 * padding;
+    let x = (output_width - width) / 2;
+    let y = (output_height - height) / 2;
+    let background = Rectangle::from_loc_and_size((x, y), (width, height));
+    draw_solid(frame, cache.color(renderer, Color([0, 0, 0, 0xc0]))?, to_physical(background), 1.0)?;
+
+    for (i, line) in lines.iter().enumerate() {
+        let area = to_physical(Rectangle::from_loc_and_size(
+            (x + padding, y + padding + i as i32 * OVERLAY_LINE),
+            (width - 2 * padding, OVERLAY_LINE),
+        ));
+        if let Some(texture) = cache.text(
+            renderer,
+            config.decoration.title_bar.font.as_deref(),
+            line,
+            area.size.h,
+            area.size.w,
+            Color([0xff, 0xff, 0xff, 0xff]),
+        )? {
+            frame.render_texture_at(texture, area.loc, 1, 1.0, Transform::Normal, 1.0)?;
+        }
+    }
+    Ok(())
+}
+
+/// Tints the areas changed since the last frame and outlines the windows of the space
+///
+/// Without known `changes` the whole frame is tinted.
+pub fn draw_debug_damage<R, E, F, T>(
+    renderer: &mut R,
+    frame: &mut F,
+    cache: &mut RenderCache<T>,
+    space: &dyn Layout,
+    changes: Option<&[Rectangle<i32, Physical>]>,
+    size: Size<i32, Physical>,
+    output_scale: f32,
+) -> Result<(), E>
+where
+    R: CpuAccess<Error = E, Texture = T>,
+    F: Frame<Error = E, TextureId = T>,
+    T: Texture,
+{
+    let everything = [Rectangle::from_loc_and_size((0, 0), size)];
+    let tint = cache.color(renderer, Color([0xff, 0x00, 0x80, 0xff]))?;
+    for area in changes.unwrap_or(&everything) {
+        draw_solid(frame, tint, *area, 0.25)?;
+    }
+
+    for (toplevel, location, bounding_box) in space.windows_from_bottom_to_top() {
+        // everything the window draws, including subsurfaces and shadows
+        let outline = cache.color(renderer, Color([0x00, 0xff, 0x00, 0xff]))?;
+        draw_border(frame, outline, bounding_box, 1, output_scale)?;
+        let geometry = decoration::window_geometry(&toplevel, location, bounding_box);
+        let outline = cache.color(renderer, Color([0x00, 0x80, 0xff, 0xff]))?;
+        draw_border(frame, outline, geometry, 1, output_scale)?;
+    }
+    Ok(())
+}
+
+/// Draws the wallpaper over a whole frame of the given size
+fn draw_background<R, E, F, T>(
+    renderer: &mut R,
+    frame: &mut F,
+    cache: &mut RenderCache<T>,
+    config: &Background,
+    size: Size<i32, Physical>,
+) -> Result<(), E>
+where
+    R: CpuAccess<Error = E, Texture = T>,
+    F: Frame<Error = E, TextureId = T>,
+    T: Texture,
+{
+    let [r, g, b, _] = config.color.0;
+    frame.clear([r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0, 1.0])?;
+
+    let texture = match config.image.as_deref() {
+        Some(path) => match cache.background(renderer, path)? {
+            Some(texture) => texture,
+            None => return Ok(()),
+        },
+        None => return Ok(()),
+    };
+    let (width, height) = (texture.width() as i32, texture.height() as i32);
+    let centered = |w: i32, h: i32| Rectangle::from_loc_and_size(((size.w - w) / 2, (size.h - h) / 2), (w, h));
+    match config.mode {
+        FillMode::Fill | FillMode::Fit => {
+            let scale_x = size.w as f64 / width as f64;
+            let scale_y = size.h as f64 / height as f64;
+            let scale = if config.mode == FillMode::Fill {
+                scale_x.max(scale_y)
+            } else {
+                scale_x.min(scale_y)
+            };
+            let area = centered(
+                (width as f64 * scale).round() as i32,
+                (height as f64 * scale).round() as i32,
+            );
+            draw_solid(frame, texture, area, 1.0)
+        }
+        FillMode::Stretch => draw_solid(frame, texture, Rectangle::from_loc_and_size((0, 0), size), 1.0),
+        FillMode::Center => draw_solid(frame, texture, centered(width, height), 1.0),
+        FillMode::Tile => {
+            for y in (0..size.h).step_by(height.max(1) as usize) {
+                for x in (0..size.w).step_by(width.max(1) as usize) {
+                    draw_solid(frame, texture, Rectangle::from_loc_and_size((x, y), (width, height)), 1.0)?;
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Returns a blurred copy of what is behind an area of the frame and where to draw it
+fn blur_behind<R, E, T>(
+    renderer: &mut R,
+    cache: &mut RenderCache<T>,
+    config: &BlurConfig,
+    area: Rectangle<f64, Physical>,
+) -> Result<Option<(T, Point<i32, Physical>)>, E>
+where
+    R: Blur<Error = E, Texture = T>,
+{
+    let x1 = area.loc.x.round() as i32;
+    let y1 = area.loc.y.round() as i32;
+    let x2 = (area.loc.x + area.size.w).round() as i32;
+    let y2 = (area.loc.y + area.size.h).round() as i32;
+    let area = Rectangle::<i32, Physical>::from_loc_and_size((x1, y1), (x2 - x1, y2 - y1));
+    // the copy is clipped to the frame
+    let position = (x1.max(0), y1.max(0)).into();
+    Ok(renderer
+        .blur(&mut cache.blur, config, area)?
+        .map(|blurred| (blurred, position)))
+}
+
+/// Takes over a newly committed buffer, replacing the textures of the previous one
+fn pull_buffer(data: &mut SurfaceData, attributes: &SurfaceAttributes) {
+    if data.texture.is_none() {
+        if let Some(buffer) = data.buffer.take() {
+            let (acquire, release) = match data.sync_points.take() {
+                Some((acquire, release)) => (Some(acquire), Some(release)),
+                None => (None, None),
+            };
+            let damage = attributes
+                .damage
+                .iter()
+                .map(|dmg| match dmg {
+                    Damage::Buffer(rect) => *rect,
+                    // TODO also apply transformations
+                    Damage::Surface(rect) => rect.to_buffer(attributes.buffer_scale),
+                })
+                .collect::<Vec<_>>();
+
+            data.texture = Some(BufferTextures {
+                release: Rc::new(BufferRelease(buffer.clone(), release)),
+                buffer,
+                damage,
+                textures: HashMap::new(),
+                acquire,
+            });
+        }
+    }
+}
+
+/// Copies a dmabuf through system memory
+///
+/// The buffer is read out on the gpu of the client first and on any other gpu,
+/// if the client is unknown or its gpu is unable to read it.
 pub fn cross_device_cpu_copy<R: CpuAccess>(
     other_backends: &mut [(&dev_t, &mut BackendData)],
     client_id: Option<DevId>,
     renderer: &mut R,
     dma: &Dmabuf
 ) -> R::Texture {
-    let tex = if let Some(src_backend) = other_backends.iter_mut().find(|&&mut (k, _)| client_id.map(|id| *k == id.0).unwrap_or(false)) {
-        let src_renderer = &mut src_backend.1;
-        match src_renderer.renderer.export_bitmap(&dma) {
+    let is_client = |k: &dev_t| client_id.map(|id| *k == id.0).unwrap_or(false);
+    other_backends.sort_by_key(|&(k, _)| !is_client(k));
+
+    let mut tex = None;
+    for (dev_id, src_backend) in other_backends.iter_mut() {
+        match src_backend.renderer.export_bitmap(&dma) {
             Ok(image_buffer) => match renderer.import_bitmap(
                 &image_buffer,
             ) {
-                Ok(m) => Some(m),
+                Ok(m) => {
+                    tex = Some(m);
+                    break;
+                },
                 Err(x) => {
                     slog_scope::error!("Failed to import bitmap: {}", x);
-                    None
+                    break;
                 }
             },
             Err(x) => {
-                slog_scope::error!("Failed to read out app buffer: {}", x);
-                None
+                slog_scope::debug!("Failed to read out app buffer on {:?}: {}", dev_id, x);
             }
         }
-    } else { None };
+    }
+    if tex.is_none() {
+        slog_scope::error!("Failed to copy app buffer from any gpu");
+    }
         
     tex.unwrap_or_else(|| {
         let fallback_buffer = ImageBuffer::from_raw(1, 1, PLACEHOLDER).unwrap();
@@ -0,0 +1,320 @@
+use smithay::{
+    backend::renderer::gles2::{ffi, Gles2Error, Gles2Renderer},
+    utils::{Physical, Rectangle},
+};
+use std::ffi::CString;
+
+use super::blur::{shader, QUAD};
+
+/// Places the quad over an area of the framebuffer, sampling the same area of the offscreen texture
+const VERTEX_SHADER: &str = r#"
+#version 100
+attribute vec2 position;
+uniform vec2 viewport;
+uniform vec4 quad;
+varying vec2 v_coords;
+varying vec2 v_pixel;
+
+void main() {
+    v_pixel = quad.xy + position * quad.zw;
+    v_coords = v_pixel / viewport;
+    gl_Position = vec4(v_coords * 2.0 - 1.0, 0.0, 1.0);
+}
+"#;
+
+/// Keeps the part of the texture inside a rounded rectangle, antialiased by the distance to its edge
+const MASK_SHADER: &str = r#"
+#version 100
+#ifdef GL_FRAGMENT_PRECISION_HIGH
+precision highp float;
+#else
+precision mediump float;
+#endif
+uniform sampler2D tex;
+uniform vec4 area;
+uniform float radius;
+varying vec2 v_coords;
+varying vec2 v_pixel;
+
+void main() {
+    vec2 half_size = area.zw * 0.5;
+    vec2 corner = abs(v_pixel - area.xy - half_size) - half_size + radius;
+    float distance = length(max(corner, 0.0)) + min(max(corner.x, corner.y), 0.0) - radius;
+    gl_FragColor = texture2D(tex, v_coords) * clamp(0.5 - distance, 0.0, 1.0);
+}
+"#;
+
+/// Rectangle with rounded corners windows are cut off at
+#[derive(Debug, Clone, Copy)]
+pub struct RoundedClip {
+    pub area: Rectangle<f64, Physical>,
+    pub radius: f64,
+}
+
+impl RoundedClip {
+    /// Radius of the corners, limited to half the size of the area
+    fn radius(&self) -> f64 {
+        self.radius.min(self.area.size.w / 2.0).min(self.area.size.h / 2.0)
+    }
+
+    /// The area in framebuffer coordinates, starting with the bottom row if `flipped`
+    fn framebuffer_area(&self, height: i32, flipped: bool) -> Rectangle<f64, Physical> {
+        let mut area = self.area;
+        if flipped {
+            area.loc.y = height as f64 - (area.loc.y + area.size.h);
+        }
+        area
+    }
+}
+
+struct Program {
+    id: ffi::types::GLuint,
+    position: ffi::types::GLuint,
+    tex: ffi::types::GLint,
+    viewport: ffi::types::GLint,
+    quad: ffi::types::GLint,
+    area: ffi::types::GLint,
+    radius: ffi::types::GLint,
+}
+
+/// Texture of the size of the framebuffer, windows are drawn into before being cut
+struct Target {
+    texture: ffi::types::GLuint,
+    framebuffer: ffi::types::GLuint,
+    size: (i32, i32),
+}
+
+/// Shader and offscreen buffer of rounded windows, created on first use by every renderer
+pub struct RoundedState {
+    program: Option<Option<Program>>,
+    target: Option<Target>,
+    /// Framebuffer bound before the window was redirected, `None` while drawing directly
+    previous: Option<ffi::types::GLuint>,
+}
+
+impl RoundedState {
+    pub fn new() -> RoundedState {
+        RoundedState {
+            program: None,
+            target: None,
+            previous: None,
+        }
+    }
+}
+
+/// Renderers able to cut what they draw to a rectangle with rounded corners
+///
+/// Everything drawn between `begin_rounded` and `end_rounded` goes into an offscreen buffer first,
+/// so a window with all its subsurfaces is cut at once. If the shader is not available,
+/// windows are drawn directly with square corners.
+pub trait RoundedCorners {
+    type Error: std::error::Error;
+
+    /// Redirects drawing into the offscreen buffer, cleared inside of the clip area.
+    /// `flipped` framebuffers start with the bottom row, see `BlurState::flipped`.
+    fn begin_rounded(&mut self, state: &mut RoundedState, clip: &RoundedClip, flipped: bool) -> Result<(), Self::Error>;
+
+    /// Draws what was drawn since `begin_rounded` into the framebuffer, cut to the clip area
+    fn end_rounded(&mut self, state: &mut RoundedState, clip: &RoundedClip, flipped: bool) -> Result<(), Self::Error>;
+}
+
+impl RoundedCorners for Gles2Renderer {
+    type Error = Gles2Error;
+
+    fn begin_rounded(&mut self, state: &mut RoundedState, clip: &RoundedClip, flipped: bool) -> Result<(), Self::Error> {
+        self.with_context(|_, gl| unsafe {
+            if state.program.get_or_insert_with(|| compile(gl)).is_none() {
+                return;
+            }
+
+            let mut viewport = [0; 4];
+            gl.GetIntegerv(ffi::VIEWPORT, viewport.as_mut_ptr());
+            let mut framebuffer = 0;
+            gl.GetIntegerv(ffi::FRAMEBUFFER_BINDING, &mut framebuffer);
+            let size = (viewport[2], viewport[3]);
+            if state.target.as_ref().map(|target| target.size) != Some(size) {
+                if let Some(target) = state.target.take() {
+                    delete(gl, target);
+                }
+                state.target = Some(create(gl, size));
+            }
+            let target = state.target.as_ref().unwrap();
+            gl.BindFramebuffer(ffi::FRAMEBUFFER, target.framebuffer);
+            state.previous = Some(framebuffer as ffi::types::GLuint);
+
+            // only the window is cut out of the buffer, clearing the rest would be wasted
+            let (x1, y1, x2, y2) = match quad(clip.framebuffer_area(size.1, flipped), size) {
+                Some(quad) => quad,
+                None => return,
+            };
+            let scissor = gl.IsEnabled(ffi::SCISSOR_TEST) == ffi::TRUE;
+            let mut scissor_box = [0; 4];
+            gl.GetIntegerv(ffi::SCISSOR_BOX, scissor_box.as_mut_ptr());
+            let (x1, y1, x2, y2) = if scissor {
+                // partially redrawn frames are only drawn inside of the damage, see `damage::Scissor`
+                (
+                    x1.max(scissor_box[0]),
+                    y1.max(scissor_box[1]),
+                    x2.min(scissor_box[0] + scissor_box[2]),
+                    y2.min(scissor_box[1] + scissor_box[3]),
+                )
+            } else {
+                (x1, y1, x2, y2)
+            };
+            if x2 > x1 && y2 > y1 {
+                gl.Enable(ffi::SCISSOR_TEST);
+                gl.Scissor(x1, y1, x2 - x1, y2 - y1);
+                gl.ClearColor(0.0, 0.0, 0.0, 0.0);
+                gl.Clear(ffi::COLOR_BUFFER_BIT);
+                gl.Scissor(scissor_box[0], scissor_box[1], scissor_box[2], scissor_box[3]);
+                if !scissor {
+                    gl.Disable(ffi::SCISSOR_TEST);
+                }
+            }
+        })
+    }
+
+    fn end_rounded(&mut self, state: &mut RoundedState, clip: &RoundedClip, flipped: bool) -> Result<(), Self::Error> {
+        self.with_context(|_, gl| unsafe {
+            let previous = match state.previous.take() {
+                Some(previous) => previous,
+                None => return,
+            };
+            gl.BindFramebuffer(ffi::FRAMEBUFFER, previous);
+            let (program, target) = match (&state.program, &state.target) {
+                (Some(Some(program)), Some(target)) => (program, target),
+                _ => return,
+            };
+            let area = clip.framebuffer_area(target.size.1, flipped);
+            let (x1, y1, x2, y2) = match quad(area, target.size) {
+                Some(quad) => quad,
+                None => return,
+            };
+
+            let blend = gl.IsEnabled(ffi::BLEND) == ffi::TRUE;
+            gl.Enable(ffi::BLEND);
+            // the buffer holds premultiplied colors, like the textures of the renderer
+            gl.BlendFunc(ffi::ONE, ffi::ONE_MINUS_SRC_ALPHA);
+            gl.BindBuffer(ffi::ARRAY_BUFFER, 0);
+            gl.ActiveTexture(ffi::TEXTURE0);
+            gl.BindTexture(ffi::TEXTURE_2D, target.texture);
+            gl.UseProgram(program.id);
+            gl.Uniform1i(program.tex, 0);
+            gl.Uniform2f(program.viewport, target.size.0 as f32, target.size.1 as f32);
+            gl.Uniform4f(program.quad, x1 as f32, y1 as f32, (x2 - x1) as f32, (y2 - y1) as f32);
+            gl.Uniform4f(
+                program.area,
+                area.loc.x as f32,
+                area.loc.y as f32,
+                area.size.w as f32,
+                area.size.h as f32,
+            );
+            gl.Uniform1f(program.radius, clip.radius() as f32);
+            gl.EnableVertexAttribArray(program.position);
+            gl.VertexAttribPointer(program.position, 2, ffi::FLOAT, ffi::FALSE, 0, QUAD.as_ptr() as *const _);
+            gl.DrawArrays(ffi::TRIANGLE_STRIP, 0, 4);
+            gl.DisableVertexAttribArray(program.position);
+            gl.BindTexture(ffi::TEXTURE_2D, 0);
+            if !blend {
+                gl.Disable(ffi::BLEND);
+            }
+        })
+    }
+}
+
+/// Whole pixels covering an area within a framebuffer of the given size, as left, bottom, right and top
+fn quad(area: Rectangle<f64, Physical>, (width, height): (i32, i32)) -> Option<(i32, i32, i32, i32)> {
+    let x1 = (area.loc.x.floor() as i32).max(0);
+    let y1 = (area.loc.y.floor() as i32).max(0);
+    let x2 = ((area.loc.x + area.size.w).ceil() as i32).min(width);
+    let y2 = ((area.loc.y + area.size.h).ceil() as i32).min(height);
+    if x2 <= x1 || y2 <= y1 {
+        None
+    } else {
+        Some((x1, y1, x2, y2))
+    }
+}
+
+unsafe fn create(gl: &ffi::Gles2, (width, height): (i32, i32)) -> Target {
+    let mut texture = 0;
+    gl.GenTextures(1, &mut texture);
+    gl.BindTexture(ffi::TEXTURE_2D, texture);
+    gl.TexParameteri(ffi::TEXTURE_2D, ffi::TEXTURE_MIN_FILTER, ffi::NEAREST as i32);
+    gl.TexParameteri(ffi::TEXTURE_2D, ffi::TEXTURE_MAG_FILTER, ffi::NEAREST as i32);
+    gl.TexParameteri(ffi::TEXTURE_2D, ffi::TEXTURE_WRAP_S, ffi::CLAMP_TO_EDGE as i32);
+    gl.TexParameteri(ffi::TEXTURE_2D, ffi::TEXTURE_WRAP_T, ffi::CLAMP_TO_EDGE as i32);
+    gl.TexImage2D(
+        ffi::TEXTURE_2D,
+        0,
+        ffi::RGBA as i32,
+        width,
+        height,
+        0,
+        ffi::RGBA,
+        ffi::UNSIGNED_BYTE,
+        std::ptr::null(),
+    );
+    gl.BindTexture(ffi::TEXTURE_2D, 0);
+
+    let mut framebuffer = 0;
+    gl.GenFramebuffers(1, &mut framebuffer);
+    let mut previous = 0;
+    gl.GetIntegerv(ffi::FRAMEBUFFER_BINDING, &mut previous);
+    gl.BindFramebuffer(ffi::FRAMEBUFFER, framebuffer);
+    gl.FramebufferTexture2D(ffi::FRAMEBUFFER, ffi::COLOR_ATTACHMENT0, ffi::TEXTURE_2D, texture, 0);
+    gl.BindFramebuffer(ffi::FRAMEBUFFER, previous as ffi::types::GLuint);
+
+    Target {
+        texture,
+        framebuffer,
+        size: (width, height),
+    }
+}
+
+unsafe fn delete(gl: &ffi::Gles2, target: Target) {
+    gl.DeleteFramebuffers(1, &target.framebuffer);
+    gl.DeleteTextures(1, &target.texture);
+}
+
+unsafe fn compile(gl: &ffi::Gles2) -> Option<Program> {
+    let program = link(gl);
+    if program.is_none() {
+        slog_scope::error!("Failed to compile the rounded corner shader, corners are not rounded");
+    }
+    program
+}
+
+unsafe fn link(gl: &ffi::Gles2) -> Option<Program> {
+    let vertex = shader(gl, ffi::VERTEX_SHADER, VERTEX_SHADER)?;
+    let fragment = shader(gl, ffi::FRAGMENT_SHADER, MASK_SHADER)?;
+    let id = gl.CreateProgram();
+    gl.AttachShader(id, vertex);
+    gl.AttachShader(id, fragment);
+    gl.LinkProgram(id);
+    gl.DetachShader(id, vertex);
+    gl.DetachShader(id, fragment);
+    gl.DeleteShader(vertex);
+    gl.DeleteShader(fragment);
+
+    let mut status = ffi::FALSE as i32;
+    gl.GetProgramiv(id, ffi::LINK_STATUS, &mut status);
+    if status == ffi::FALSE as i32 {
+        gl.DeleteProgram(id);
+        return None;
+    }
+
+    let uniform = |name: &str| {
+        let name = CString::new(name).unwrap();
+        gl.GetUniformLocation(id, name.as_ptr() as *const _)
+    };
+    let position = CString::new("position").unwrap();
+    Some(Program {
+        id,
+        position: gl.GetAttribLocation(id, position.as_ptr() as *const _) as ffi::types::GLuint,
+        tex: uniform("tex"),
+        viewport: uniform("viewport"),
+        quad: uniform("quad"),
+        area: uniform("area"),
+        radius: uniform("radius"),
+    })
+}
@@ -0,0 +1,162 @@
+use anyhow::Result;
+use image::RgbaImage;
+use smithay::utils::{Logical, Physical, Rectangle, Size};
+use std::sync::mpsc;
+
+use crate::{
+    backend::screenshot::frame_area,
+    handler::ActiveOutput,
+    ipc::protocol::{Response, StreamInfo},
+    shell::{decoration, layout::Layout, window::Kind},
+    state::Fireplace,
+};
+
+mod stream;
+
+/// Source types of the screencast portal
+pub const SOURCE_MONITOR: u32 = 1;
+pub const SOURCE_WINDOW: u32 = 2;
+
+/// Output or window currently exported as a pipewire stream
+pub struct Screencast {
+    session: String,
+    output: String,
+    window: Option<Kind>,
+    /// Size of the stream, fixed when it is negotiated
+    size: Size<i32, Physical>,
+}
+
+/// Streams of all portal sessions, fed with every frame rendered for their output
+pub struct Screencasting {
+    casts: Vec<Screencast>,
+    pipewire: stream::Handle,
+}
+
+impl Screencasting {
+    /// Frames of the output are read back, so it has to be composited
+    pub fn is_casting(&self, output: &str) -> bool {
+        self.casts.iter().any(|cast| cast.output == output)
+    }
+
+    /// Hands the current frame of an output to all of its streams
+    ///
+    /// `read` returns the given area of the composited frame.
+    pub fn capture<F>(&self, space: &dyn Layout, output: &str, size: Size<i32, Physical>, scale: f32, mut read: F)
+    where
+        F: FnMut(Rectangle<i32, Physical>) -> Option<RgbaImage>,
+    {
+        for cast in self.casts.iter().filter(|cast| cast.output == output) {
+            let window = match cast.window.as_ref() {
+                Some(window) => match window_geometry(space, window) {
+                    Some(geometry) => Some(geometry),
+                    // the window is not shown right now
+                    None => continue,
+                },
+                None => None,
+            };
+            let area = frame_area(window, size, scale);
+            if let Some(image) = read(area) {
+                self.pipewire.frame(&cast.session, image);
+            }
+        }
+    }
+}
+
+/// Connects to pipewire to serve the streams requested by `xdg-desktop-portal-fireplace` over ipc
+pub fn init_screencast(state: &mut Fireplace) -> Result<()> {
+    if !state.config.screencast {
+        return Ok(());
+    }
+
+    let pipewire = stream::spawn()?;
+    state.screencast = Some(Screencasting {
+        casts: Vec::new(),
+        pipewire,
+    });
+
+    Ok(())
+}
+
+impl Fireplace {
+    /// Starts a stream of one of the given source types, `reply` gets its description once it is ready
+    pub fn start_screencast(&mut self, session: String, types: u32, reply: mpsc::Sender<Response>) {
+        let error = |message: String| Response::Error { message };
+        let screencast = match self.screencast.as_mut() {
+            Some(screencast) => screencast,
+            None => {
+                let _ = reply.send(error(String::from("Screencasting is disabled")));
+                return;
+            }
+        };
+
+        // there is no source picker, the client gets what the last active seat looks at
+        let seat = &self.last_active_seat;
+        let output = seat.user_data().get::<ActiveOutput>().unwrap().0.borrow().clone();
+        let mut workspaces = self.workspaces.borrow_mut();
+        let (location, scale, output_size) = match workspaces.output_by_name(&output) {
+            Some(o) => (o.location(), o.scale(), o.geometry().size),
+            None => {
+                let _ = reply.send(error(format!("Output {} does not exist", output)));
+                return;
+            }
+        };
+        let space = workspaces.space_by_output_name(&output).unwrap();
+
+        let window = if types & SOURCE_MONITOR == 0 && types & SOURCE_WINDOW != 0 {
+            space.focused_window().and_then(|window| {
+                window_geometry(&**space, &window).map(|geometry| (window, geometry))
+            })
+            .ok_or(())
+            .map(Some)
+        } else {
+            Ok(None)
+        };
+        let (window, geometry) = match window {
+            Ok(Some((window, geometry))) => (Some(window), geometry),
+            Ok(None) => (None, Rectangle::from_loc_and_size((0, 0), output_size)),
+            Err(()) => {
+                let _ = reply.send(error(String::from("No window is focused")));
+                return;
+            }
+        };
+        let size = geometry.size.to_f64().to_physical(scale as f64).to_i32_round();
+
+        slog_scope::info!(
+            "Starting screencast of {}{}",
+            output,
+            if window.is_some() { " (focused window)" } else { "" }
+        );
+        let info = StreamInfo {
+            node_id: 0,
+            position: (location.x + geometry.loc.x, location.y + geometry.loc.y),
+            size: (geometry.size.w, geometry.size.h),
+            source_type: if window.is_some() { SOURCE_WINDOW } else { SOURCE_MONITOR },
+        };
+        screencast.pipewire.start(&session, size, info, reply);
+        screencast.casts.push(Screencast {
+            session,
+            output,
+            window,
+            size,
+        });
+        drop(workspaces);
+        // idle outputs need a frame to start the stream with
+        self.damage();
+    }
+
+    pub fn stop_screencast(&mut self, session: &str) {
+        if let Some(screencast) = self.screencast.as_mut() {
+            screencast.casts.retain(|cast| cast.session != session);
+            screencast.pipewire.stop(session);
+            slog_scope::info!("Stopped screencast {}", session);
+        }
+    }
+}
+
+/// Output local window geometry of a toplevel of this space
+fn window_geometry(space: &dyn Layout, window: &Kind) -> Option<Rectangle<i32, Logical>> {
+    space
+        .windows_from_bottom_to_top()
+        .find(|(toplevel, _, _)| toplevel == window)
+        .map(|(toplevel, location, bounding_box)| decoration::window_geometry(&toplevel, location, bounding_box))
+}
@@ -0,0 +1,315 @@
+use anyhow::{Context as _, Result};
+use image::RgbaImage;
+use pipewire::{
+    self as pw,
+    properties,
+    spa::{
+        self,
+        param::{
+            format::{FormatProperties, MediaSubtype, MediaType},
+            video::VideoFormat,
+            ParamType,
+        },
+        pod::{self, serialize::PodSerializer, Pod},
+        utils::{Direction, Fraction, Rectangle, SpaTypes},
+    },
+    stream::{Stream, StreamFlags, StreamListener, StreamState},
+};
+use smithay::utils::{Physical, Size};
+use std::{cell::RefCell, collections::HashMap, rc::Rc, sync::mpsc};
+
+use crate::ipc::protocol::{Response, StreamInfo};
+
+/// Number of buffers frames are queued in
+const BUFFERS: i32 = 4;
+
+type Reply = mpsc::Sender<Response>;
+
+enum Command {
+    Start {
+        session: String,
+        size: Size<i32, Physical>,
+        info: StreamInfo,
+        reply: Reply,
+    },
+    Frame {
+        session: String,
+        image: RgbaImage,
+    },
+    Stop {
+        session: String,
+    },
+}
+
+/// Controls the streams of the pipewire thread
+pub struct Handle(pw::channel::Sender<Command>);
+
+impl Handle {
+    /// Creates a new stream, the node id is sent once it is ready to be connected to
+    pub fn start(&self, session: &str, size: Size<i32, Physical>, info: StreamInfo, reply: Reply) {
+        self.send(Command::Start {
+            session: String::from(session),
+            size,
+            info,
+            reply,
+        });
+    }
+
+    pub fn frame(&self, session: &str, image: RgbaImage) {
+        self.send(Command::Frame {
+            session: String::from(session),
+            image,
+        });
+    }
+
+    pub fn stop(&self, session: &str) {
+        self.send(Command::Stop {
+            session: String::from(session),
+        });
+    }
+
+    fn send(&self, command: Command) {
+        if self.0.send(command).is_err() {
+            slog_scope::error!("The pipewire thread is gone");
+        }
+    }
+}
+
+/// Connects to pipewire on a thread of its own, its main loop is not integrated into ours
+pub fn spawn() -> Result<Handle> {
+    let (sender, receiver) = pw::channel::channel();
+    let (ready, connected) = mpsc::sync_channel(1);
+    std::thread::Builder::new()
+        .name(String::from("pipewire"))
+        .spawn(move || {
+            pw::init();
+            let mainloop = match connect() {
+                Ok(mainloop) => {
+                    let _ = ready.send(Ok(()));
+                    mainloop
+                }
+                Err(err) => {
+                    let _ = ready.send(Err(err));
+                    return;
+                }
+            };
+            run(mainloop, receiver);
+        })?;
+    connected.recv().context("The pipewire thread exited")??;
+    Ok(Handle(sender))
+}
+
+struct MainLoop {
+    mainloop: pw::main_loop::MainLoop,
+    _context: pw::context::Context,
+    core: pw::core::Core,
+}
+
+fn connect() -> Result<MainLoop> {
+    let mainloop = pw::main_loop::MainLoop::new(None)?;
+    let context = pw::context::Context::new(&mainloop)?;
+    let core = context.connect(None).context("Failed to connect to pipewire")?;
+    Ok(MainLoop {
+        mainloop,
+        _context: context,
+        core,
+    })
+}
+
+fn run(mainloop: MainLoop, receiver: pw::channel::Receiver<Command>) {
+    let core = mainloop.core.clone();
+    let casts = Rc::new(RefCell::new(HashMap::<String, Cast>::new()));
+    let _receiver = receiver.attach(mainloop.mainloop.loop_(), move |command| match command {
+        Command::Start {
+            session,
+            size,
+            info,
+            reply,
+        } => match Cast::new(&core, &session, size, info, reply.clone()) {
+            Ok(cast) => {
+                casts.borrow_mut().insert(session, cast);
+            }
+            Err(err) => {
+                slog_scope::warn!("Failed to create screencast stream: {}", err);
+                let _ = reply.send(Response::Error {
+                    message: err.to_string(),
+                });
+            }
+        },
+        Command::Frame { session, image } => {
+            if let Some(cast) = casts.borrow_mut().get_mut(&session) {
+                cast.frame(&image);
+            }
+        }
+        Command::Stop { session } => {
+            if let Some(cast) = casts.borrow_mut().remove(&session) {
+                if let Err(err) = cast.stream.disconnect() {
+                    slog_scope::debug!("Failed to disconnect screencast stream: {}", err);
+                }
+            }
+        }
+    });
+    mainloop.mainloop.run();
+}
+
+/// Stream of a single screencast session
+struct Cast {
+    stream: Stream,
+    _listener: StreamListener<Option<(StreamInfo, Reply)>>,
+    size: Size<i32, Physical>,
+}
+
+impl Cast {
+    fn new(
+        core: &pw::core::Core,
+        session: &str,
+        size: Size<i32, Physical>,
+        info: StreamInfo,
+        reply: Reply,
+    ) -> Result<Cast, pw::Error> {
+        let stream = Stream::new(
+            core,
+            "fireplace-screencast",
+            properties! {
+                *pw::keys::MEDIA_CLASS => "Video/Source",
+                *pw::keys::MEDIA_ROLE => "Screen",
+                *pw::keys::MEDIA_NAME => session,
+            },
+        )?;
+        let listener = stream
+            .add_local_listener_with_user_data(Some((info, reply)))
+            .state_changed(|stream, pending, _old, new| match new {
+                // the node exists from now on, the client may connect to it
+                StreamState::Paused => {
+                    if let Some((mut info, reply)) = pending.take() {
+                        info.node_id = stream.node_id();
+                        let _ = reply.send(Response::Screencast { stream: info });
+                    }
+                }
+                StreamState::Error(err) => {
+                    slog_scope::warn!("Screencast stream failed: {}", err);
+                    if let Some((_, reply)) = pending.take() {
+                        let _ = reply.send(Response::Error { message: err });
+                    }
+                }
+                _ => {}
+            })
+            .param_changed(move |stream, _, id, param| {
+                if id != ParamType::Format.as_raw() || param.is_none() {
+                    return;
+                }
+                // the format is fixed, so are the buffers
+                let buffers = serialize(buffers_param(size));
+                let mut params = [Pod::from_bytes(&buffers).unwrap()];
+                if let Err(err) = stream.update_params(&mut params) {
+                    slog_scope::warn!("Failed to set screencast buffers: {}", err);
+                }
+            })
+            .register()?;
+
+        let format = serialize(format_param(size));
+        let mut params = [Pod::from_bytes(&format).unwrap()];
+        stream.connect(
+            Direction::Output,
+            None,
+            StreamFlags::DRIVER | StreamFlags::ALLOC_BUFFERS | StreamFlags::MAP_BUFFERS,
+            &mut params,
+        )?;
+
+        Ok(Cast {
+            stream,
+            _listener: listener,
+            size,
+        })
+    }
+
+    /// Copies a frame into the next free buffer, frames are dropped if the client falls behind
+    fn frame(&mut self, image: &RgbaImage) {
+        let mut buffer = match self.stream.dequeue_buffer() {
+            Some(buffer) => buffer,
+            None => return,
+        };
+        let width = self.size.w as usize;
+        let height = self.size.h as usize;
+        let stride = width * 4;
+
+        let data = &mut buffer.datas_mut()[0];
+        match data.data() {
+            Some(pixels) if pixels.len() >= stride * height => {
+                // windows shrink at the edges of their output, the rest of the stream stays black
+                let rows = (image.height() as usize).min(height);
+                let row = (image.width() as usize).min(width) * 4;
+                if rows < height || row < stride {
+                    for byte in pixels.iter_mut() {
+                        *byte = 0;
+                    }
+                }
+                for (y, src) in image
+                    .as_raw()
+                    .chunks_exact(image.width() as usize * 4)
+                    .take(rows)
+                    .enumerate()
+                {
+                    pixels[y * stride..y * stride + row].copy_from_slice(&src[..row]);
+                }
+            }
+            _ => return,
+        }
+
+        let chunk = data.chunk_mut();
+        *chunk.offset_mut() = 0;
+        *chunk.stride_mut() = stride as i32;
+        *chunk.size_mut() = (stride * height) as u32;
+    }
+}
+
+/// Offers frames in the byte order glReadPixels produces
+fn format_param(size: Size<i32, Physical>) -> pod::Object {
+    pod::object!(
+        SpaTypes::ObjectParamFormat,
+        ParamType::EnumFormat,
+        pod::property!(FormatProperties::MediaType, Id, MediaType::Video),
+        pod::property!(FormatProperties::MediaSubtype, Id, MediaSubtype::Raw),
+        pod::property!(FormatProperties::VideoFormat, Id, VideoFormat::RGBA),
+        pod::property!(
+            FormatProperties::VideoSize,
+            Rectangle,
+            Rectangle {
+                width: size.w as u32,
+                height: size.h as u32,
+            }
+        ),
+        // frames are pushed whenever the output is rendered
+        pod::property!(FormatProperties::VideoFramerate, Fraction, Fraction { num: 0, denom: 1 }),
+    )
+}
+
+fn buffers_param(size: Size<i32, Physical>) -> pod::Object {
+    let stride = size.w * 4;
+    let property = |key, value| pod::Property {
+        key,
+        flags: pod::PropertyFlags::empty(),
+        value: pod::Value::Int(value),
+    };
+    pod::Object {
+        type_: SpaTypes::ObjectParamBuffers.as_raw(),
+        id: ParamType::Buffers.as_raw(),
+        properties: vec![
+            property(spa::sys::SPA_PARAM_BUFFERS_buffers, BUFFERS),
+            property(spa::sys::SPA_PARAM_BUFFERS_blocks, 1),
+            property(spa::sys::SPA_PARAM_BUFFERS_size, stride * size.h),
+            property(spa::sys::SPA_PARAM_BUFFERS_stride, stride),
+            property(
+                spa::sys::SPA_PARAM_BUFFERS_dataType,
+                (1 << spa::sys::SPA_DATA_MemFd) | (1 << spa::sys::SPA_DATA_MemPtr),
+            ),
+        ],
+    }
+}
+
+fn serialize(object: pod::Object) -> Vec<u8> {
+    PodSerializer::serialize(std::io::Cursor::new(Vec::new()), &pod::Value::Object(object))
+        .unwrap()
+        .0
+        .into_inner()
+}
@@ -0,0 +1,159 @@
+use anyhow::{Context, Result};
+use image::{imageops, RgbaImage};
+use smithay::{
+    backend::renderer::gles2::{ffi, Gles2Error, Gles2Renderer},
+    reexports::nix::libc,
+    utils::{Logical, Physical, Rectangle, Size},
+};
+use std::{path::PathBuf, sync::mpsc};
+
+use crate::{config::ScreenshotConfig, ipc::protocol::Response};
+
+/// Pending screenshot, served by the backend on the next frame of its output
+#[derive(Debug, Clone)]
+pub struct Screenshot {
+    pub output: String,
+    /// Output local window geometry, the whole output if `None`
+    pub window: Option<Rectangle<i32, Logical>>,
+    /// Ipc client waiting for the path of the saved file
+    pub reply: Option<mpsc::Sender<Response>>,
+}
+
+impl Screenshot {
+    /// Area of a frame of the given size covered by this screenshot
+    pub fn area(&self, size: Size<i32, Physical>, scale: f32) -> Rectangle<i32, Physical> {
+        frame_area(self.window, size, scale)
+    }
+
+    /// Tells a waiting client the screenshot could not be taken
+    pub fn failed(&self, message: String) {
+        if let Some(reply) = self.reply.as_ref() {
+            let _ = reply.send(Response::Error { message });
+        }
+    }
+}
+
+/// Area of a frame covered by an output local window geometry, or the whole frame
+pub fn frame_area(
+    window: Option<Rectangle<i32, Logical>>,
+    size: Size<i32, Physical>,
+    scale: f32,
+) -> Rectangle<i32, Physical> {
+    let output = Rectangle::from_loc_and_size((0, 0), size);
+    match window {
+        Some(geometry) => {
+            let area = geometry.to_f64().to_physical(scale as f64).to_i32_round();
+            // clip to the output, windows may be partially off-screen
+            let x1 = area.loc.x.max(0);
+            let y1 = area.loc.y.max(0);
+            let x2 = (area.loc.x + area.size.w).min(size.w);
+            let y2 = (area.loc.y + area.size.h).min(size.h);
+            if x2 <= x1 || y2 <= y1 {
+                output
+            } else {
+                Rectangle::from_loc_and_size((x1, y1), (x2 - x1, y2 - y1))
+            }
+        }
+        None => output,
+    }
+}
+
+/// Reads back an area of the currently bound framebuffer
+///
+/// `flipped` framebuffers start with the bottom row, like the default framebuffer of egl windows.
+/// Those rendered upside down for scanout already start at the top.
+pub fn read_pixels(
+    renderer: &mut Gles2Renderer,
+    size: Size<i32, Physical>,
+    area: Rectangle<i32, Physical>,
+    flipped: bool,
+) -> Result<RgbaImage, Gles2Error> {
+    let y = if flipped {
+        size.h - area.loc.y - area.size.h
+    } else {
+        area.loc.y
+    };
+    let mut pixels = vec![0u8; (area.size.w * area.size.h * 4) as usize];
+    let pixels_ptr = pixels.as_mut_ptr() as *mut _;
+    renderer.with_context(|_renderer, gl| unsafe {
+        gl.ReadPixels(
+            area.loc.x,
+            y,
+            area.size.w,
+            area.size.h,
+            ffi::RGBA,
+            ffi::UNSIGNED_BYTE,
+            pixels_ptr,
+        );
+    })?;
+
+    let mut image = RgbaImage::from_raw(area.size.w as u32, area.size.h as u32, pixels).unwrap();
+    if flipped {
+        imageops::flip_vertical_in_place(&mut image);
+    }
+    // the framebuffer has no meaningful alpha channel
+    for pixel in image.pixels_mut() {
+        pixel.0[3] = 255;
+    }
+    Ok(image)
+}
+
+/// Writes the image to a timestamped png in the configured directory, off the event loop
+pub fn save(config: &ScreenshotConfig, screenshot: Screenshot, image: RgbaImage) {
+    let mut path = PathBuf::from(expand_home(&config.directory));
+    path.push(format!("{}_{}.png", timestamp(), screenshot.output));
+    std::thread::spawn(move || {
+        let result = path
+            .parent()
+            .map(std::fs::create_dir_all)
+            .transpose()
+            .context("Failed to create the screenshot directory")
+            .and_then(|_| image.save(&path).context("Failed to encode screenshot"));
+        match result {
+            Ok(()) => {
+                slog_scope::info!("Saved screenshot to {}", path.display());
+                if let Some(reply) = screenshot.reply {
+                    let _ = reply.send(Response::Screenshot { path });
+                }
+            }
+            Err(err) => {
+                slog_scope::error!("Failed to save screenshot to {}: {:?}", path.display(), err);
+                screenshot.failed(format!("{:#}", err));
+            }
+        }
+    });
+}
+
+/// Expands a leading `~` and `$HOME`
+pub fn expand_home(path: &str) -> String {
+    let home = std::env::var("HOME").unwrap_or_default();
+    match path.strip_prefix('~') {
+        Some(rest) => format!("{}{}", home, rest),
+        None => path.replace("$HOME", &home),
+    }
+}
+
+/// Local time formatted like `2021-08-14_13-37-00`
+fn timestamp() -> String {
+    unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        let mut tm: libc::tm = std::mem::zeroed();
+        libc::localtime_r(&now, &mut tm);
+        format!(
+            "{:04}-{:02}-{:02}_{:02}-{:02}-{:02}",
+            tm.tm_year + 1900,
+            tm.tm_mon + 1,
+            tm.tm_mday,
+            tm.tm_hour,
+            tm.tm_min,
+            tm.tm_sec,
+        )
+    }
+}
+
+/// Takes the pending screenshots of an output out of the queue
+pub fn take_pending(pending: &mut Vec<Screenshot>, output: &str) -> Vec<Screenshot> {
+    let (taken, rest) = pending.drain(..).partition(|shot| shot.output == output);
+    *pending = rest;
+    taken
+}
@@ -0,0 +1,156 @@
+//! Rendering on the cpu, for gpus without usable EGL/GLES drivers and the headless backend
+//!
+//! Mesa exposes llvmpipe as an EGL device of its own, which needs no drm device at all.
+//! It renders into pbuffers, which are read back to be shown or saved.
+
+use anyhow::{Context, Result};
+use smithay::backend::egl::{
+    display::EGLDisplayHandle,
+    native::{EGLNativeDisplay, EGLNativeSurface, EGLPlatform},
+    EGLError,
+};
+use smithay::reexports::nix::libc::{c_char, c_void};
+
+use std::{cell::Cell, ffi::CStr, ptr, sync::Arc};
+
+use crate::backend::egl::{self as ffi, wrap_egl_call};
+
+fn extensions(list: *const c_char) -> Vec<String> {
+    if list.is_null() {
+        return Vec::new();
+    }
+    let list = unsafe { CStr::from_ptr(list) };
+    list.to_string_lossy().split(' ').map(String::from).collect()
+}
+
+/// Mesa's software EGL device
+pub struct SoftwareDevice {
+    device: ffi::types::EGLDeviceEXT,
+}
+
+unsafe impl Send for SoftwareDevice {}
+
+impl SoftwareDevice {
+    pub fn new() -> Result<SoftwareDevice> {
+        smithay::backend::egl::ffi::make_sure_egl_is_loaded()?;
+        ffi::load_with(|sym| unsafe { smithay::backend::egl::get_proc_address(sym) });
+
+        let client_extensions = extensions(wrap_egl_call(|| unsafe {
+            ffi::QueryString(ffi::NO_DISPLAY, ffi::EXTENSIONS as i32)
+        })?);
+        if !client_extensions.iter().any(|x| x == "EGL_EXT_device_enumeration")
+            || !client_extensions.iter().any(|x| x == "EGL_EXT_platform_device")
+        {
+            anyhow::bail!("EGL does not support enumerating devices");
+        }
+
+        let device = unsafe {
+            let mut num_devices = 0;
+            wrap_egl_call(|| ffi::QueryDevicesEXT(0, ptr::null_mut(), &mut num_devices))?;
+            let mut devices = Vec::with_capacity(num_devices as usize);
+            wrap_egl_call(|| ffi::QueryDevicesEXT(num_devices, devices.as_mut_ptr(), &mut num_devices))?;
+            devices.set_len(num_devices as usize);
+            devices
+                .into_iter()
+                .filter(|device| *device != ffi::NO_DEVICE_EXT)
+                .find(|device| {
+                    extensions(ffi::QueryDeviceStringEXT(*device, ffi::EXTENSIONS as i32))
+                        .iter()
+                        .any(|x| x == "EGL_MESA_device_software")
+                })
+                .context("No software EGL device, software rendering needs mesa's llvmpipe")?
+        };
+
+        Ok(SoftwareDevice { device })
+    }
+}
+
+impl EGLNativeDisplay for SoftwareDevice {
+    fn supported_platforms(&self) -> Vec<EGLPlatform<'_>> {
+        vec![
+            // see: https://www.khronos.org/registry/EGL/extensions/EXT/EGL_EXT_platform_device.txt
+            EGLPlatform::new(
+                ffi::PLATFORM_DEVICE_EXT,
+                "PLATFORM_DEVICE_EXT",
+                self.device as *mut _,
+                vec![ffi::NONE as ffi::EGLint],
+                &["EGL_EXT_platform_device"],
+            ),
+        ]
+    }
+
+    fn surface_type(&self) -> smithay::backend::egl::ffi::EGLint {
+        ffi::PBUFFER_BIT as smithay::backend::egl::ffi::EGLint
+    }
+}
+
+/// Creates an offscreen surface of the given size on a display of the software device
+pub fn create_pbuffer(
+    display: &Arc<EGLDisplayHandle>,
+    config_id: ffi::types::EGLConfig,
+    (width, height): (i32, i32),
+) -> Result<*const c_void, EGLError> {
+    let attributes = [
+        ffi::WIDTH as i32,
+        width,
+        ffi::HEIGHT as i32,
+        height,
+        ffi::NONE as i32,
+    ];
+    let surface = wrap_egl_call(|| unsafe { ffi::CreatePbufferSurface(***display, config_id, attributes.as_ptr()) })?;
+    if surface == ffi::NO_SURFACE {
+        return Err(EGLError::BadAlloc);
+    }
+    Ok(surface)
+}
+
+/// Pbuffer without anything showing it, the output of the headless backend
+pub struct Pbuffer {
+    size: Cell<(i32, i32)>,
+    resized: Cell<bool>,
+}
+
+// the event loop and with it all rendering is single threaded
+unsafe impl Send for Pbuffer {}
+unsafe impl Sync for Pbuffer {}
+
+impl Pbuffer {
+    pub fn new(size: (i32, i32)) -> Pbuffer {
+        Pbuffer {
+            size: Cell::new(size),
+            resized: Cell::new(false),
+        }
+    }
+}
+
+unsafe impl EGLNativeSurface for Pbuffer {
+    fn create(
+        &self,
+        display: &Arc<EGLDisplayHandle>,
+        config_id: ffi::types::EGLConfig,
+    ) -> Result<*const c_void, EGLError> {
+        self.resized.set(false);
+        create_pbuffer(display, config_id, self.size.get())
+    }
+
+    fn needs_recreation(&self) -> bool {
+        self.resized.get()
+    }
+
+    fn resize(&self, width: i32, height: i32, _dx: i32, _dy: i32) -> bool {
+        if self.size.get() != (width, height) {
+            self.size.set((width, height));
+            self.resized.set(true);
+        }
+        true
+    }
+
+    fn swap_buffers(
+        &self,
+        _display: &Arc<EGLDisplayHandle>,
+        _surface: ffi::types::EGLSurface,
+    ) -> Result<(), smithay::backend::SwapBuffersError> {
+        // nobody looks at the pbuffer, frames are only read back for screenshots
+        Ok(())
+    }
+}
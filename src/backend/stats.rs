@@ -0,0 +1,171 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use crate::ipc::protocol::OutputMetrics;
+
+/// Frames older than this are dropped from the statistics
+const WINDOW: Duration = Duration::from_secs(1);
+
+struct Sample {
+    finished: Instant,
+    render_time: Duration,
+    /// Changes drawn at once by this frame, if the backend tracks damage
+    damage: Option<u64>,
+    /// Share of the output redrawn by this frame, if the backend tracks damage
+    redrawn: Option<f64>,
+}
+
+/// Timings of the frames an output rendered during the last second, shown by the debug overlay
+/// and reported by the `metrics` ipc request
+#[derive(Default)]
+pub struct FrameStats {
+    frames: VecDeque<Sample>,
+    /// Time from an input event to the first frame showing it was presented, by presentation
+    latencies: VecDeque<(Instant, Duration)>,
+    /// Start of the last frame, kept beyond the window to notice input of idle outputs
+    last_started: Option<Instant>,
+    /// Totals since the output appeared
+    total_frames: u64,
+    missed_vblanks: u64,
+}
+
+impl FrameStats {
+    /// Records a frame, which started rendering at `started`
+    pub fn record(&mut self, started: Instant, damage: Option<u64>, redrawn: Option<f64>) {
+        let now = Instant::now();
+        self.frames.push_back(Sample {
+            finished: now,
+            render_time: now.duration_since(started),
+            damage,
+            redrawn,
+        });
+        self.last_started = Some(started);
+        self.total_frames += 1;
+        self.expire(now);
+    }
+
+    /// The last input event, if it happened since the previous frame started and is shown first by the next one
+    pub fn new_input(&self, last_input: Instant) -> Option<Instant> {
+        self.last_started
+            .filter(|previous| last_input > *previous)
+            .map(|_| last_input)
+    }
+
+    /// Records the presentation of a frame, `missed` vblanks after the one it was rendered for
+    pub fn presented(&mut self, input: Option<Instant>, missed: u64) {
+        let now = Instant::now();
+        if let Some(input) = input {
+            self.latencies.push_back((now, now.duration_since(input)));
+        }
+        self.missed_vblanks += missed;
+        self.expire(now);
+    }
+
+    fn expire(&mut self, now: Instant) {
+        while self
+            .frames
+            .front()
+            .map(|sample| now.duration_since(sample.finished) > WINDOW)
+            .unwrap_or(false)
+        {
+            self.frames.pop_front();
+        }
+        while self
+            .latencies
+            .front()
+            .map(|(presented, _)| now.duration_since(*presented) > WINDOW)
+            .unwrap_or(false)
+        {
+            self.latencies.pop_front();
+        }
+    }
+
+    /// Describes the statistics in a few lines of text
+    pub fn summary(&self) -> Vec<String> {
+        let count = self.frames.len();
+        if count == 0 {
+            return vec![String::from("no frames")];
+        }
+        let total = self.frames.iter().map(|sample| sample.render_time).sum::<Duration>();
+        let max = self.frames.iter().map(|sample| sample.render_time).max().unwrap_or_default();
+        // the longest time nothing new was shown, stutter shows up here first
+        let gap = self
+            .frames
+            .iter()
+            .zip(self.frames.iter().skip(1))
+            .map(|(previous, next)| next.finished.duration_since(previous.finished))
+            .max()
+            .unwrap_or_default();
+
+        let mut lines = vec![
+            format!("{} fps", count),
+            format!(
+                "render {:.1} ms avg, {:.1} ms max",
+                millis(total / count as u32),
+                millis(max)
+            ),
+            format!("longest frame gap {:.1} ms", millis(gap)),
+        ];
+        let damaged = self.frames.iter().filter_map(|sample| sample.damage).collect::<Vec<_>>();
+        if !damaged.is_empty() {
+            lines.push(format!(
+                "{:.1} damage events per frame",
+                damaged.iter().sum::<u64>() as f64 / damaged.len() as f64
+            ));
+        }
+        if let Some(latency) = self.latencies.iter().map(|(_, latency)| *latency).max() {
+            lines.push(format!("input latency {:.1} ms max", millis(latency)));
+        }
+        if self.missed_vblanks > 0 {
+            lines.push(format!("{} missed vblanks", self.missed_vblanks));
+        }
+        lines
+    }
+
+    /// Statistics of the output for the `metrics` ipc request
+    pub fn metrics(&self, output: &str) -> OutputMetrics {
+        let render_times = self.frames.iter().map(|sample| sample.render_time).collect::<Vec<_>>();
+        let latencies = self.latencies.iter().map(|(_, latency)| *latency).collect::<Vec<_>>();
+        let damaged = self
+            .frames
+            .iter()
+            .filter_map(|sample| sample.damage.map(|damage| damage as f64))
+            .collect::<Vec<_>>();
+        let redrawn = self.frames.iter().filter_map(|sample| sample.redrawn).collect::<Vec<_>>();
+        OutputMetrics {
+            output: String::from(output),
+            frames: self.total_frames,
+            missed_vblanks: self.missed_vblanks,
+            fps: self.frames.len() as u32,
+            render_time_avg: average(&render_times),
+            render_time_max: render_times.iter().max().map(|max| millis(*max)),
+            latency_avg: average(&latencies),
+            latency_max: latencies.iter().max().map(|max| millis(*max)),
+            damage_avg: mean(&damaged),
+            redrawn_avg: mean(&redrawn),
+        }
+    }
+}
+
+/// Average in milliseconds, `None` without any durations
+fn average(durations: &[Duration]) -> Option<f64> {
+    if durations.is_empty() {
+        None
+    } else {
+        Some(millis(durations.iter().sum::<Duration>()) / durations.len() as f64)
+    }
+}
+
+fn mean(values: &[f64]) -> Option<f64> {
+    if values.is_empty() {
+        None
+    } else {
+        Some(values.iter().sum::<f64>() / values.len() as f64)
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
@@ -1,4 +1,4 @@
-use std::io::Read;
+use std::{collections::VecDeque, io::Read};
 
 use xcursor::{
     parser::{parse_xcursor, Image},
@@ -7,6 +7,9 @@ use xcursor::{
 
 static FALLBACK_CURSOR_DATA: &[u8] = include_bytes!("../../../assets/cursor.rgba");
 
+/// Textures kept per gpu, enough for the frames of an animated cursor at a few scales
+pub const CURSOR_TEXTURES: usize = 64;
+
 #[derive(Debug, Clone)]
 pub struct Cursor {
     icons: Vec<Image>,
@@ -48,6 +51,44 @@ impl Cursor {
     }
 }
 
+/// Textures of the cursor images drawn last, the least recently used are dropped beyond its capacity
+///
+/// Animated cursors import a texture for every frame and scale, they would pile up otherwise.
+pub struct TextureCache<K, T> {
+    capacity: usize,
+    /// Least recently used first
+    entries: VecDeque<(K, T)>,
+}
+
+impl<K: PartialEq + Clone, T: Clone> TextureCache<K, T> {
+    pub fn new(capacity: usize) -> TextureCache<K, T> {
+        TextureCache {
+            capacity: capacity.max(1),
+            entries: VecDeque::new(),
+        }
+    }
+
+    /// Returns the texture of `key`, importing it if it is not cached
+    pub fn get_or_import<E, F>(&mut self, key: &K, import: F) -> Result<T, E>
+    where
+        F: FnOnce(&K) -> Result<T, E>,
+    {
+        if let Some(idx) = self.entries.iter().position(|(cached, _)| cached == key) {
+            let entry = self.entries.remove(idx).unwrap();
+            let texture = entry.1.clone();
+            self.entries.push_back(entry);
+            return Ok(texture);
+        }
+        let texture = import(key)?;
+        self.entries.push_back((key.clone(), texture.clone()));
+        // textures still in use are only freed once the last frame using them is done
+        while self.entries.len() > self.capacity {
+            self.entries.pop_front();
+        }
+        Ok(texture)
+    }
+}
+
 fn nearest_images(size: u32, images: &[Image]) -> impl Iterator<Item = &Image> {
     // Follow the nominal size of the cursor to choose the nearest
     let nearest_image = images
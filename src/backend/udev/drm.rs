@@ -1,18 +1,23 @@
 use anyhow::Result;
 use smithay::{
     backend::drm::DrmDevice,
-    reexports::drm::control::{
-        AtomicCommitFlags,
-        Device as ControlDevice,
-        ResourceHandle,
-        atomic::AtomicModeReq,
-        crtc,
-        connector::{
-            self,
-            State as ConnectorState,
+    reexports::drm::{
+        ffi,
+        control::{
+            AtomicCommitFlags,
+            Device as ControlDevice,
+            Mode,
+            ResourceHandle,
+            atomic::AtomicModeReq,
+            crtc,
+            connector::{
+                self,
+                State as ConnectorState,
+            },
+            dumbbuffer::DumbBuffer,
+            plane,
+            property,
         },
-        dumbbuffer::DumbBuffer,
-        property,
     },
 };
 use std::{
@@ -121,9 +126,110 @@ pub fn display_configuration<A: AsRawFd>(device: &mut DrmDevice<A>) -> Result<Ha
     Ok(map)
 }
 
-pub fn get_prop<A, T>(device: &DrmDevice<A>, handle: T, name: &str) -> Result<property::Handle>
+/// Powers the output driven by a crtc on or off
+pub fn set_active<A: AsRawFd>(device: &DrmDevice<A>, conn: connector::Handle, crtc: crtc::Handle, active: bool) -> Result<()> {
+    if device.is_atomic() {
+        let mut req = AtomicModeReq::new();
+        let prop = get_prop(device, crtc, "ACTIVE")?;
+        req.add_property(crtc, prop, property::Value::Boolean(active));
+        device.atomic_commit(&[AtomicCommitFlags::AllowModeset], req)?;
+    } else {
+        // DRM_MODE_DPMS_ON and DRM_MODE_DPMS_OFF
+        let prop = get_prop(device, conn, "DPMS")?;
+        device.set_property(conn, prop, if active { 0 } else { 3 })?;
+    }
+    Ok(())
+}
+
+/// Parses a modeline in the format of xorg.conf, e.g.
+/// `173.00 1920 2048 2248 2576 1080 1083 1088 1120 -hsync +vsync`
+/// optionally preceded by a name.
+pub fn parse_modeline(modeline: &str) -> Result<Mode> {
+    let mut tokens = modeline.split_whitespace().peekable();
+    let name = match tokens.peek() {
+        Some(token) if token.parse::<f64>().is_err() => tokens.next().unwrap().trim_matches('"'),
+        _ => "custom",
+    };
+
+    let clock = tokens
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("Modeline is missing the pixel clock"))?
+        .parse::<f64>()?;
+    let mut timings = [0u16; 8];
+    for timing in timings.iter_mut() {
+        *timing = tokens
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("Modeline is missing timings"))?
+            .parse::<u16>()?;
+    }
+
+    let mut flags = 0;
+    for flag in tokens {
+        flags |= match &*flag.to_lowercase() {
+            "+hsync" => ffi::DRM_MODE_FLAG_PHSYNC,
+            "-hsync" => ffi::DRM_MODE_FLAG_NHSYNC,
+            "+vsync" => ffi::DRM_MODE_FLAG_PVSYNC,
+            "-vsync" => ffi::DRM_MODE_FLAG_NVSYNC,
+            "interlace" => ffi::DRM_MODE_FLAG_INTERLACE,
+            "doublescan" => ffi::DRM_MODE_FLAG_DBLSCAN,
+            other => anyhow::bail!("Unknown modeline flag: {}", other),
+        };
+    }
+
+    let [hdisplay, hsync_start, hsync_end, htotal, vdisplay, vsync_start, vsync_end, vtotal] = timings;
+    // the pixel clock is given in MHz but drm expects kHz
+    let clock = (clock * 1000.0).round() as u32;
+    let vrefresh = (clock as f64 * 1000.0 / (htotal as f64 * vtotal as f64)).round() as u32;
+
+    let mut raw_name = [0; 32];
+    for (dst, src) in raw_name.iter_mut().zip(name.bytes().take(31)) {
+        *dst = src as _;
+    }
+
+    Ok(Mode::from(ffi::drm_mode_modeinfo {
+        clock,
+        hdisplay,
+        hsync_start,
+        hsync_end,
+        htotal,
+        hskew: 0,
+        vdisplay,
+        vsync_start,
+        vsync_end,
+        vtotal,
+        vscan: 0,
+        vrefresh,
+        flags,
+        type_: ffi::DRM_MODE_TYPE_USERDEF,
+        name: raw_name,
+    }))
+}
+
+/// Overlay planes usable together with a crtc
+pub fn overlay_planes<A: AsRawFd>(device: &DrmDevice<A>, crtc: crtc::Handle) -> Result<Vec<plane::Handle>> {
+    // DRM_PLANE_TYPE_OVERLAY
+    const OVERLAY: u64 = 0;
+
+    let resources = device.resource_handles()?;
+    let mut planes = Vec::new();
+    for plane in device.plane_handles()?.planes() {
+        let info = device.get_plane(*plane)?;
+        if !resources.filter_crtcs(info.possible_crtcs()).contains(&crtc) {
+            continue;
+        }
+        let type_prop = get_prop(device, *plane, "type")?;
+        let props = device.get_properties(*plane)?;
+        let (ids, vals) = props.as_props_and_values();
+        if ids.iter().zip(vals.iter()).any(|(id, val)| *id == type_prop && *val == OVERLAY) {
+            planes.push(*plane);
+        }
+    }
+    Ok(planes)
+}
+
+pub fn get_prop<D, T>(device: &D, handle: T, name: &str) -> Result<property::Handle>
     where
-        A: AsRawFd,
+        D: ControlDevice,
         T: ResourceHandle
 {
     let props = device.get_properties(handle)?;
@@ -137,6 +243,36 @@ pub fn get_prop<A, T>(device: &DrmDevice<A>, handle: T, name: &str) -> Result<pr
     anyhow::bail!("No prop found")
 }
 
+/// Raw value of the entry of an enum property with the given name
+pub fn get_enum_value<D>(device: &D, prop: property::Handle, name: &str) -> Result<u64>
+    where
+        D: ControlDevice,
+{
+    let info = device.get_property(prop)?;
+    if let property::ValueType::Enum(values) = info.value_type() {
+        let (_, entries) = values.values();
+        if let Some(entry) = entries.iter().find(|entry| Some(name) == entry.name().to_str().ok()) {
+            return Ok(entry.value());
+        }
+    }
+    anyhow::bail!("No enum value {} found", name)
+}
+
+/// Current value of a property
+pub fn get_prop_value<D, T>(device: &D, handle: T, prop: property::Handle) -> Result<u64>
+    where
+        D: ControlDevice,
+        T: ResourceHandle
+{
+    let props = device.get_properties(handle)?;
+    let (ids, vals) = props.as_props_and_values();
+    ids.iter()
+        .zip(vals.iter())
+        .find(|(id, _)| **id == prop)
+        .map(|(_, val)| *val)
+        .ok_or_else(|| anyhow::anyhow!("No prop value found"))
+}
+
 pub fn get_manufacturer(vendor: &[char; 3]) -> &'static str {
 	match vendor {
 	    ['A', 'A', 'A'] => "Avolites Ltd",
@@ -0,0 +1,120 @@
+use anyhow::Result;
+use smithay::{
+    reexports::drm::{
+        buffer::DrmFourcc,
+        control::{crtc, dumbbuffer::DumbBuffer, Device as ControlDevice},
+        DriverCapability,
+    },
+    utils::{Physical, Point},
+};
+use xcursor::parser::Image;
+
+/// Cursor image size every driver supporting cursor planes can handle
+const DEFAULT_CURSOR_SIZE: u64 = 64;
+
+/// Cursor shown on the cursor plane of a crtc, instead of being composited into every frame
+pub struct HardwareCursor {
+    buffer: DumbBuffer,
+    size: (u32, u32),
+    /// Image currently uploaded into the buffer
+    image: Option<Image>,
+    visible: bool,
+    position: Point<i32, Physical>,
+}
+
+impl HardwareCursor {
+    pub fn new<D: ControlDevice>(drm: &D) -> Result<HardwareCursor> {
+        let width = drm
+            .get_driver_capability(DriverCapability::CursorWidth)
+            .unwrap_or(DEFAULT_CURSOR_SIZE) as u32;
+        let height = drm
+            .get_driver_capability(DriverCapability::CursorHeight)
+            .unwrap_or(DEFAULT_CURSOR_SIZE) as u32;
+        let buffer = drm.create_dumb_buffer((width, height), DrmFourcc::Argb8888, 32)?;
+        Ok(HardwareCursor {
+            buffer,
+            size: (width, height),
+            image: None,
+            visible: false,
+            position: (0, 0).into(),
+        })
+    }
+
+    /// The cursor plane currently shows the cursor, it must not be composited
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    /// Forgets the state of the plane, e.g. after the session was inactive
+    pub fn reset(&mut self) {
+        self.image = None;
+        self.visible = false;
+    }
+
+    /// Whether an image fits onto the cursor plane
+    pub fn fits(&self, image: &Image) -> bool {
+        image.width <= self.size.0 && image.height <= self.size.1
+    }
+
+    /// Shows an image with its top left corner at the given crtc local position
+    pub fn show<D: ControlDevice>(
+        &mut self,
+        drm: &D,
+        crtc: crtc::Handle,
+        image: &Image,
+        position: Point<i32, Physical>,
+    ) -> Result<()> {
+        let changed = self.image.as_ref() != Some(image);
+        if changed {
+            self.upload(drm, image)?;
+        }
+        if changed || !self.visible {
+            // the position already accounts for the hotspot
+            #[allow(deprecated)]
+            drm.set_cursor2(crtc, Some(&self.buffer), (0, 0))?;
+            self.visible = true;
+        }
+        if changed || self.position != position {
+            #[allow(deprecated)]
+            drm.move_cursor(crtc, position.into())?;
+            self.position = position;
+        }
+        Ok(())
+    }
+
+    pub fn hide<D: ControlDevice>(&mut self, drm: &D, crtc: crtc::Handle) -> Result<()> {
+        if self.visible {
+            #[allow(deprecated)]
+            drm.set_cursor2(crtc, Option::<&DumbBuffer>::None, (0, 0))?;
+            self.visible = false;
+        }
+        Ok(())
+    }
+
+    pub fn destroy<D: ControlDevice>(self, drm: &D) {
+        if let Err(err) = drm.destroy_dumb_buffer(self.buffer) {
+            slog_scope::debug!("Failed to destroy cursor buffer: {}", err);
+        }
+    }
+
+    fn upload<D: ControlDevice>(&mut self, drm: &D, image: &Image) -> Result<()> {
+        use smithay::reexports::drm::buffer::Buffer;
+
+        let pitch = self.buffer.pitch() as usize;
+        let mut mapping = drm.map_dumb_buffer(&mut self.buffer)?;
+        let pixels = mapping.as_mut();
+        // unused parts of the plane stay transparent
+        for byte in pixels.iter_mut() {
+            *byte = 0;
+        }
+        for (y, row) in image.pixels_rgba.chunks_exact(image.width as usize * 4).enumerate() {
+            for (x, rgba) in row.chunks_exact(4).enumerate() {
+                let offset = y * pitch + x * 4;
+                // little endian ARGB8888
+                pixels[offset..offset + 4].copy_from_slice(&[rgba[2], rgba[1], rgba[0], rgba[3]]);
+            }
+        }
+        self.image = Some(image.clone());
+        Ok(())
+    }
+}
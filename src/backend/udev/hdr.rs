@@ -0,0 +1,230 @@
+use anyhow::Result;
+use smithay::reexports::drm::{
+    ffi,
+    control::{atomic::AtomicModeReq, connector, crtc, property, AtomicCommitFlags, Device as ControlDevice},
+};
+use std::os::unix::io::AsRawFd;
+
+use super::{
+    drm::{get_enum_value, get_prop, get_prop_value},
+    night_light,
+};
+
+/// Luminance in nits the PQ curve is defined up to
+const PQ_MAX_LUMINANCE: f64 = 10000.0;
+/// Peak and frame average luminance announced to the monitor, typical for hdr monitors
+const MAX_LUMINANCE: u16 = 1000;
+const MAX_FRAME_AVERAGE: u16 = 400;
+
+/// Converts linear BT.709 (sRGB) colors to BT.2020, rows are the output channels
+const BT709_TO_BT2020: [[f64; 3]; 3] = [
+    [0.6274, 0.3293, 0.0433],
+    [0.0691, 0.9195, 0.0114],
+    [0.0164, 0.0880, 0.8956],
+];
+
+/// `struct hdr_output_metadata` with the static metadata of HDMI/CTA-861-G
+#[repr(C)]
+struct HdrOutputMetadata {
+    metadata_type: u32,
+    eotf: u8,
+    infoframe_type: u8,
+    /// Chromaticity coordinates of red, green and blue in units of 0.00002
+    display_primaries: [[u16; 2]; 3],
+    white_point: [u16; 2],
+    /// In nits
+    max_display_mastering_luminance: u16,
+    /// In units of 0.0001 nits
+    min_display_mastering_luminance: u16,
+    max_cll: u16,
+    max_fall: u16,
+}
+
+/// `struct drm_color_lut`
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct ColorLut {
+    red: u16,
+    green: u16,
+    blue: u16,
+    reserved: u16,
+}
+
+/// Color pipeline of an output driven in hdr mode
+///
+/// Frames are still rendered in sRGB, the crtc decodes them, converts them to BT.2020 primaries and
+/// encodes them with the PQ curve, white of sdr content ending up at `sdr_brightness` nits.
+/// Client buffers on planes and screenshots thus need no special treatment.
+pub struct Hdr {
+    ctm: property::Handle,
+}
+
+impl Hdr {
+    /// Switches the connector to BT.2020 and PQ and sets up the crtc to convert frames accordingly
+    ///
+    /// Fails without changing anything, if the driver lacks any part of the pipeline.
+    pub fn enable<D: ControlDevice>(
+        drm: &D,
+        conn: connector::Handle,
+        crtc: crtc::Handle,
+        sdr_brightness: u32,
+        temperature: u32,
+    ) -> Result<Hdr> {
+        let colorspace = get_prop(drm, conn, "Colorspace")?;
+        let bt2020 = get_enum_value(drm, colorspace, "BT2020_RGB")?;
+        let metadata_prop = get_prop(drm, conn, "HDR_OUTPUT_METADATA")?;
+        let degamma = get_prop(drm, crtc, "DEGAMMA_LUT")?;
+        let degamma_size = get_prop_value(drm, crtc, get_prop(drm, crtc, "DEGAMMA_LUT_SIZE")?)? as usize;
+        let ctm = get_prop(drm, crtc, "CTM")?;
+        let gamma = get_prop(drm, crtc, "GAMMA_LUT")?;
+        let gamma_size = get_prop_value(drm, crtc, get_prop(drm, crtc, "GAMMA_LUT_SIZE")?)? as usize;
+
+        let mut metadata = [HdrOutputMetadata {
+            // HDMI_STATIC_METADATA_TYPE1
+            metadata_type: 0,
+            // HDMI_EOTF_SMPTE_ST2084
+            eotf: 2,
+            infoframe_type: 0,
+            display_primaries: [[35400, 14600], [8500, 39850], [6550, 2300]],
+            white_point: [15635, 16450],
+            max_display_mastering_luminance: MAX_LUMINANCE,
+            min_display_mastering_luminance: 1,
+            max_cll: MAX_LUMINANCE,
+            max_fall: MAX_FRAME_AVERAGE,
+        }];
+        let mut degamma_lut = lut(degamma_size, srgb_to_linear);
+        // the lut spans the range of sdr content, finer than spreading it over 10000 nits
+        let white = sdr_brightness as f64 / PQ_MAX_LUMINANCE;
+        let mut gamma_lut = lut(gamma_size, |value| linear_to_pq(value * white));
+        let mut matrix = ctm_matrix(temperature);
+
+        let blobs = [
+            create_blob(drm, &mut metadata)?,
+            create_blob(drm, &mut degamma_lut)?,
+            create_blob(drm, &mut matrix)?,
+            create_blob(drm, &mut gamma_lut)?,
+        ];
+        let mut req = AtomicModeReq::new();
+        req.add_property(conn, colorspace, property::Value::UnsignedRange(bt2020));
+        req.add_property(conn, metadata_prop, property::Value::Blob(blobs[0]));
+        req.add_property(crtc, degamma, property::Value::Blob(blobs[1]));
+        req.add_property(crtc, ctm, property::Value::Blob(blobs[2]));
+        req.add_property(crtc, gamma, property::Value::Blob(blobs[3]));
+        let result = drm.atomic_commit(&[AtomicCommitFlags::AllowModeset], req);
+        // the committed state holds its own references
+        for blob in blobs.iter() {
+            let _ = drm.destroy_property_blob(*blob);
+        }
+        result?;
+
+        Ok(Hdr { ctm })
+    }
+
+    /// Applies a color temperature, replacing the gamma ramps used by the night light in sdr mode
+    pub fn set_temperature<D: ControlDevice>(&self, drm: &D, crtc: crtc::Handle, temperature: u32) -> Result<()> {
+        let blob = create_blob(drm, &mut ctm_matrix(temperature))?;
+        let mut req = AtomicModeReq::new();
+        req.add_property(crtc, self.ctm, property::Value::Blob(blob));
+        let result = drm.atomic_commit(&[], req);
+        let _ = drm.destroy_property_blob(blob);
+        Ok(result?)
+    }
+}
+
+/// Reverts hdr settings of a connector and crtc left over by a previous session
+pub fn reset<D: ControlDevice>(drm: &D, conn: connector::Handle, crtc: crtc::Handle) -> Result<()> {
+    let metadata = get_prop(drm, conn, "HDR_OUTPUT_METADATA")?;
+    if get_prop_value(drm, conn, metadata)? == 0 {
+        return Ok(());
+    }
+    slog_scope::debug!("Resetting hdr metadata of {:?}", conn);
+    let mut req = AtomicModeReq::new();
+    req.add_property(conn, metadata, property::Value::Blob(0));
+    if let Ok(colorspace) = get_prop(drm, conn, "Colorspace") {
+        req.add_property(conn, colorspace, property::Value::UnsignedRange(get_enum_value(drm, colorspace, "Default")?));
+    }
+    for name in &["DEGAMMA_LUT", "CTM", "GAMMA_LUT"] {
+        if let Ok(prop) = get_prop(drm, crtc, name) {
+            req.add_property(crtc, prop, property::Value::Blob(0));
+        }
+    }
+    drm.atomic_commit(&[AtomicCommitFlags::AllowModeset], req)?;
+    Ok(())
+}
+
+/// Limits the bits per channel sent to the monitor, drivers default to 8 on many connectors
+pub fn set_max_bpc<D: ControlDevice>(drm: &D, atomic: bool, conn: connector::Handle, bpc: u32) -> Result<()> {
+    let prop = get_prop(drm, conn, "max bpc")?;
+    if atomic {
+        let mut req = AtomicModeReq::new();
+        req.add_property(conn, prop, property::Value::UnsignedRange(bpc as u64));
+        drm.atomic_commit(&[AtomicCommitFlags::AllowModeset], req)?;
+    } else {
+        drm.set_property(conn, prop, bpc as u64)?;
+    }
+    Ok(())
+}
+
+fn create_blob<D: ControlDevice, T>(drm: &D, data: &mut [T]) -> Result<u64> {
+    let bytes = unsafe {
+        std::slice::from_raw_parts_mut(data.as_mut_ptr() as *mut u8, std::mem::size_of_val(data))
+    };
+    Ok(ffi::mode::create_property_blob(drm.as_raw_fd(), bytes)?.blob_id as u64)
+}
+
+/// Lookup table of the given size, applying the same curve to all channels
+fn lut(size: usize, curve: impl Fn(f64) -> f64) -> Vec<ColorLut> {
+    (0..size)
+        .map(|i| {
+            let value = (curve(i as f64 / (size.max(2) - 1) as f64).clamp(0.0, 1.0) * u16::MAX as f64) as u16;
+            ColorLut {
+                red: value,
+                green: value,
+                blue: value,
+                reserved: 0,
+            }
+        })
+        .collect()
+}
+
+/// `struct drm_color_ctm` converting to BT.2020, tinted by the color temperature
+fn ctm_matrix(temperature: u32) -> [u64; 9] {
+    let (r, g, b) = night_light::whitepoint(temperature);
+    let mut matrix = [0; 9];
+    for (i, (row, factor)) in BT709_TO_BT2020.iter().zip([r, g, b].iter()).enumerate() {
+        for (j, coefficient) in row.iter().enumerate() {
+            matrix[i * 3 + j] = fixed_point(coefficient * factor);
+        }
+    }
+    matrix
+}
+
+/// S31.32 sign-magnitude fixed point, as expected by the kernel
+fn fixed_point(value: f64) -> u64 {
+    let magnitude = (value.abs() * (1u64 << 32) as f64) as u64 & !(1 << 63);
+    if value < 0.0 {
+        magnitude | (1 << 63)
+    } else {
+        magnitude
+    }
+}
+
+fn srgb_to_linear(value: f64) -> f64 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Inverse EOTF of SMPTE ST 2084 for a luminance relative to `PQ_MAX_LUMINANCE`
+fn linear_to_pq(value: f64) -> f64 {
+    const M1: f64 = 0.1593017578125;
+    const M2: f64 = 78.84375;
+    const C1: f64 = 0.8359375;
+    const C2: f64 = 18.8515625;
+    const C3: f64 = 18.6875;
+
+    let y = value.max(0.0).powf(M1);
+    ((C1 + C2 * y) / (1.0 + C3 * y)).powf(M2)
+}
@@ -0,0 +1,101 @@
+use smithay::reexports::input::{
+    self,
+    event::{
+        gesture::{GestureEndEvent, GestureEvent, GestureEventCoordinates, GestureEventTrait, GestureSwipeEvent},
+        switch::{Switch, SwitchEvent, SwitchState},
+    },
+    AccelProfile, ClickMethod, ScrollMethod,
+};
+
+use crate::{
+    config::{self, InputConfig},
+    state::Fireplace,
+};
+
+/// Applies the input configuration to a newly added libinput device
+///
+/// Options not supported by the device are skipped.
+pub fn configure_device(device: &mut input::Device, config: &InputConfig) {
+    let name = device.name().to_string();
+    let config = config.device(&name);
+    let mut results = Vec::new();
+
+    if let Some(enabled) = config.tap_to_click {
+        results.push(("tap_to_click", device.config_tap_set_enabled(enabled)));
+    }
+    if let Some(enabled) = config.natural_scroll {
+        results.push((
+            "natural_scroll",
+            device.config_scroll_set_natural_scroll_enabled(enabled),
+        ));
+    }
+    if let Some(method) = config.scroll_method {
+        let method = match method {
+            config::ScrollMethod::None => ScrollMethod::NoScroll,
+            config::ScrollMethod::TwoFinger => ScrollMethod::TwoFinger,
+            config::ScrollMethod::Edge => ScrollMethod::Edge,
+            config::ScrollMethod::OnButtonDown => ScrollMethod::OnButtonDown,
+        };
+        results.push(("scroll_method", device.config_scroll_set_method(method)));
+    }
+    if let Some(method) = config.click_method {
+        let method = match method {
+            config::ClickMethod::ButtonAreas => ClickMethod::ButtonAreas,
+            config::ClickMethod::Clickfinger => ClickMethod::Clickfinger,
+        };
+        results.push(("click_method", device.config_click_set_method(method)));
+    }
+    if let Some(profile) = config.accel_profile {
+        let profile = match profile {
+            config::AccelProfile::Flat => AccelProfile::Flat,
+            config::AccelProfile::Adaptive => AccelProfile::Adaptive,
+        };
+        results.push(("accel_profile", device.config_accel_set_profile(profile)));
+    }
+    if let Some(speed) = config.accel_speed {
+        results.push(("accel_speed", device.config_accel_set_speed(speed)));
+    }
+    if let Some(enabled) = config.left_handed {
+        results.push(("left_handed", device.config_left_handed_set(enabled)));
+    }
+
+    for (option, result) in results {
+        if let Err(err) = result {
+            slog_scope::debug!("Unable to set {} for {}: {:?}", option, name, err);
+        }
+    }
+}
+
+impl Fireplace {
+    /// Applies a changed input configuration to all connected libinput devices
+    pub fn configure_input_devices(&mut self) {
+        for device in self.input_devices.iter_mut() {
+            configure_device(device, &self.config.input);
+        }
+    }
+
+    /// Feeds libinput gestures into the backend independent gesture handling
+    pub fn process_gesture_event(&mut self, event: &GestureEvent) {
+        profile_span!("gesture");
+        if let GestureEvent::Swipe(swipe) = event {
+            match swipe {
+                GestureSwipeEvent::Begin(event) => self.swipe_begin(event.finger_count() as u32),
+                GestureSwipeEvent::Update(event) => self.swipe_update((event.dx(), event.dy()).into()),
+                GestureSwipeEvent::End(event) => self.swipe_end(event.cancelled()),
+                _ => {}
+            }
+        }
+    }
+
+    /// Feeds libinput switch toggles into the backend independent switch handling
+    pub fn process_switch_event(&mut self, event: &SwitchEvent) {
+        if let SwitchEvent::Toggle(toggle) = event {
+            let on = toggle.switch_state() == SwitchState::On;
+            match toggle.switch() {
+                Some(Switch::Lid) => self.lid_switch(on),
+                Some(Switch::TabletMode) => self.tablet_mode_switch(on),
+                _ => {}
+            }
+        }
+    }
+}
@@ -1,8 +1,11 @@
 use crate::{
-    handler::ActiveOutput,
+    config::Config,
+    handler::{ActiveOutput, CursorVisibility, DndIcon, TabletCursor},
+    shell::{inhibits_idle, workspace::Workspaces},
     state::{Fireplace, BackendData, SurfaceData},
     wayland::{
         init_eglstream_globals,
+        init_syncobj_global,
         init_wl_drm_global
     },
 };
@@ -13,24 +16,25 @@ use smithay::{
     backend::{
         drm::{DrmDevice, DrmEvent},
         egl::{EGLDisplay, EGLContext, context::{PixelFormatRequirements, GlAttributes}},
+        input::InputEvent,
         libinput::{LibinputInputBackend, LibinputSessionInterface},
         session::{Session, Signal, auto::AutoSession, AsErrno},
         udev::{UdevBackend, UdevEvent, driver, primary_gpu},
         renderer::{Frame, Renderer, ImportDma, Transform, gles2::Gles2Renderer},
     },
     reexports::{
-        calloop::{EventLoop, LoopHandle, generic::Generic, Interest, Mode, PostAction, timer::Timer},
+        calloop::{Dispatcher, EventLoop, LoopHandle, RegistrationToken, generic::Generic, Interest, Mode, PostAction, timer::Timer},
         drm::control::{crtc, connector, property, Device as ControlDevice},
-        input::Libinput,
+        input::{event::Event as LibinputEvent, Libinput},
         nix::{fcntl::OFlag, sys::stat::dev_t},
         wayland_server::{Client, protocol::wl_output},
     },
     utils::{
-        Point, Logical,
+        Point, Logical, Physical, Rectangle, Size,
         signaling::{Signaler, Linkable}
     },
     wayland::{
-        seat::CursorImageStatus,
+        seat::{CursorImageStatus, Seat},
         output::{Mode as OutputMode, PhysicalProperties},
         dmabuf::init_dmabuf_global_with_filter,
     },
@@ -38,8 +42,9 @@ use smithay::{
 
 use std::{
     cell::RefCell,
-    collections::HashMap,
-    path::PathBuf,
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+    path::{Path, PathBuf},
     os::unix::{
         io::{AsRawFd, IntoRawFd, RawFd},
         net::UnixListener,
@@ -47,19 +52,35 @@ use std::{
 };
 
 mod cursor;
-pub use self::cursor::Cursor;
+pub use self::cursor::{Cursor, TextureCache, CURSOR_TEXTURES};
 
 mod drm;
 use self::drm::*;
 
+mod hardware_cursor;
+pub use self::hardware_cursor::HardwareCursor;
+
+mod hdr;
+pub use self::hdr::Hdr;
+
+mod input;
+mod night_light;
+
+mod schedule;
+pub use self::schedule::{FrameState, QueuedFrame};
+
 mod surface;
 use self::surface::*;
 pub use self::surface::RenderSurface;
 
-use super::render::{render_space, draw_cursor, CpuAccess};
+use super::color_filter::ApplyColorFilter;
+use super::damage::{space_elements, DamageTracker, Element, Id, Redraw, Scissor};
+use super::render::{render_lock, render_space, scanout_candidate, cursor_hotspot, draw_cursor, draw_debug_damage, draw_debug_overlay, draw_dnd_icons, draw_message, draw_notifications, draw_osd, CpuAccess, RenderCache};
+use super::screenshot::{read_pixels, save, take_pending};
+use super::software::SoftwareDevice;
 
 #[derive(Clone)]
-pub struct SessionFd(RawFd);
+pub struct SessionFd(pub RawFd);
 impl AsRawFd for SessionFd {
     fn as_raw_fd(&self) -> RawFd {
         self.0
@@ -69,6 +90,15 @@ impl AsRawFd for SessionFd {
 #[derive(Debug, Hash, PartialEq, Eq, Clone, Copy)]
 pub struct DevId(pub dev_t);
 
+/// How a gpu renders its outputs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderMode {
+    /// EGL/GLES drivers of the gpu itself
+    Hardware,
+    /// Mesa's llvmpipe on the cpu, for gpus without usable drivers, e.g. inside vms
+    Software,
+}
+
 pub fn init_udev(event_loop: &mut EventLoop<'static, Fireplace>, state: &mut Fireplace) -> Result<()> {
     let (mut session, notifier) = AutoSession::new(None).context("Failed to create Session")?;
     let signaler = notifier.signaler();
@@ -81,8 +111,41 @@ pub fn init_udev(event_loop: &mut EventLoop<'static, Fireplace>, state: &mut Fir
 
     let libinput_event_source = event_loop
         .handle()
-        .insert_source(libinput_backend, move |event, _, anvil_state| {
-            anvil_state.process_input_event(event)
+        .insert_source(libinput_backend, move |mut event, _, anvil_state| {
+            anvil_state.guard("input", |anvil_state| {
+                match &mut event {
+                    InputEvent::DeviceAdded { device } => {
+                        input::configure_device(device, &anvil_state.config.input);
+                        anvil_state.input_devices.push(device.clone());
+                    }
+                    InputEvent::DeviceRemoved { device } => {
+                        anvil_state.input_devices.retain(|known| known != device);
+                    }
+                    // libinput gestures and switches are not part of the generic input events
+                    InputEvent::Special(LibinputEvent::Gesture(gesture)) => {
+                        if !anvil_state.is_locked() {
+                            anvil_state.process_gesture_event(gesture);
+                        }
+                        anvil_state.damage();
+                        return;
+                    }
+                    InputEvent::Special(LibinputEvent::Switch(switch)) => {
+                        anvil_state.process_switch_event(switch);
+                        anvil_state.damage();
+                        return;
+                    }
+                    _ => {}
+                }
+                anvil_state.last_input = Instant::now();
+                anvil_state.wake_outputs();
+                anvil_state.process_input_event(event);
+                anvil_state.damage();
+                // cursor planes move without rendering a new frame
+                let devices = anvil_state.udev.keys().copied().collect::<Vec<_>>();
+                for device in devices {
+                    anvil_state.update_hardware_cursors(device);
+                }
+            });
         }).unwrap();
     let session_event_source = event_loop
         .handle()
@@ -90,44 +153,145 @@ pub fn init_udev(event_loop: &mut EventLoop<'static, Fireplace>, state: &mut Fir
 
     let handle = event_loop.handle();
     for (dev, path) in udev_backend.device_list() {
-        state.device_added(handle.clone(), &mut session, signaler.clone(), dev, path.into())?;
+        // a gpu without any usable output should not prevent using the others
+        if let Err(err) = state.device_added(handle.clone(), &mut session, signaler.clone(), dev, path.into()) {
+            slog_scope::error!("Unable to initialize gpu {:?}, skipping it: {}", dev, err);
+        }
+    }
+    if state.udev.is_empty() {
+        anyhow::bail!("No gpu is usable, not even for software rendering");
     }
 
     let handle = event_loop.handle();
     let udev_event_source = event_loop
         .handle()
         .insert_source(udev_backend, move |event, _, state| match match event {
-            UdevEvent::Added { device_id, path } => state.device_added(handle.clone(), &mut session, signaler.clone(), device_id, path),
-            UdevEvent::Changed { device_id } => state.device_changed(&mut session, device_id),
-            UdevEvent::Removed { device_id } => state.device_removed(&mut session, device_id),
+            UdevEvent::Added { device_id, path } => state
+                .guard("udev", |state| {
+                    state
+                        .device_added(handle.clone(), &mut session, signaler.clone(), device_id, path)
+                        .map(|()| state.update_clamshell())
+                }),
+            UdevEvent::Changed { device_id } => state
+                .guard("udev", |state| state.device_changed(device_id).map(|()| state.update_clamshell())),
+            UdevEvent::Removed { device_id } => state.guard("udev", |state| state.device_removed(&mut session, device_id)),
         } {
-            Ok(()) => { slog_scope::info!("Successfully handled udev event") },
-            Err(err) => { slog_scope::error!("Unable to handle udev event : {}", err) },
+            Some(Ok(())) => { slog_scope::info!("Successfully handled udev event") },
+            Some(Err(err)) => { slog_scope::error!("Unable to handle udev event : {}", err) },
+            None => {}
         }).unwrap();
     
+    // check for idle outputs and the night light schedule once a second
+    let idle_timer = Timer::new()?;
+    idle_timer.handle().add_timeout(Duration::from_secs(1), ());
+    let idle_event_source = event_loop
+        .handle()
+        .insert_source(idle_timer, |(), timer, state| {
+            state.guard("dpms", |state| state.power_off_idle_outputs());
+            state.guard("night light", |state| state.update_night_light());
+            state.guard("overlays", |state| {
+                state.expire_message();
+                state.expire_osd();
+            });
+            state.guard("notifications", |state| state.expire_notifications());
+            timer.add_timeout(Duration::from_secs(1), ());
+        }).unwrap();
+
     state.tokens.push(libinput_event_source);
     state.tokens.push(session_event_source);
     state.tokens.push(udev_event_source);
+    state.tokens.push(idle_event_source);
+
+    if let Err(err) = crate::backend::vnc::init_vnc(event_loop, state) {
+        slog_scope::error!("Unable to serve the vnc output: {}", err);
+    }
 
     Ok(())
 }
 
 impl Fireplace {
+    /// Sets up a gpu, falling back to rendering on the cpu if its drivers are unusable
     fn device_added<S, E>(&mut self, handle: LoopHandle<'static, Fireplace>, session: &mut S, signaler: Signaler<Signal>, device_id: dev_t, path: PathBuf) -> Result<()>
+    where
+        S: Session<Error=E>,
+        E: std::error::Error + Send + Sync + AsErrno + 'static,
+    {
+        match self.device_added_with(handle.clone(), session, signaler.clone(), device_id, path.clone(), RenderMode::Hardware) {
+            Ok(()) => Ok(()),
+            Err(err) => {
+                slog_scope::warn!("Unable to render on gpu {:?}, falling back to software rendering: {}", device_id, err);
+                self.device_added_with(handle, session, signaler, device_id, path, RenderMode::Software)
+            }
+        }
+    }
+
+    fn device_added_with<S, E>(&mut self, handle: LoopHandle<'static, Fireplace>, session: &mut S, signaler: Signaler<Signal>, device_id: dev_t, path: PathBuf, mode: RenderMode) -> Result<()>
     where
         S: Session<Error=E>,
         E: std::error::Error + Send + Sync + AsErrno + 'static,
     {
         let fd = SessionFd(session.open(&path, OFlag::O_RDWR | OFlag::O_CLOEXEC | OFlag::O_NOCTTY | OFlag::O_NONBLOCK)?);
+        let mut surfaces = HashMap::new();
+        let mut tokens = Vec::new();
+        match self.init_device(handle.clone(), signaler, device_id, &path, fd.clone(), mode, &mut surfaces, &mut tokens) {
+            Ok(data) => {
+                slog_scope::info!("Initialized gpu {:?} with {:?} rendering", device_id, mode);
+                self.udev.insert(device_id, data);
+                if let Err(err) = self.render(device_id, None) {
+                    slog_scope::error!("Error rendering on {:?}: {}", device_id, err);
+                }
+                Ok(())
+            }
+            Err(err) => {
+                // nothing of the device may be left behind, it is set up again or skipped
+                for (_, surface) in surfaces.drain() {
+                    handle.remove(surface.render_token);
+                    self.workspaces.borrow_mut().remove_output_by_name(&surface.output);
+                    self.output_removed(&surface.output);
+                }
+                for token in tokens {
+                    handle.remove(token);
+                }
+                if let Err(close_err) = session.close(fd.0) {
+                    slog_scope::debug!("Failed to close {:?}: {}", device_id, close_err);
+                }
+                Err(err)
+            }
+        }
+    }
+
+    /// Creates everything necessary to render on a gpu
+    ///
+    /// Outputs and event sources are added to `surfaces` and `tokens` as soon as they exist,
+    /// so the caller is able to remove them again, if a later step fails.
+    #[allow(clippy::too_many_arguments)]
+    fn init_device(
+        &mut self,
+        handle: LoopHandle<'static, Fireplace>,
+        signaler: Signaler<Signal>,
+        device_id: dev_t,
+        path: &Path,
+        fd: SessionFd,
+        mode: RenderMode,
+        surfaces: &mut HashMap<crtc::Handle, SurfaceData>,
+        tokens: &mut Vec<RegistrationToken>,
+    ) -> Result<BackendData> {
         let mut drm = DrmDevice::new(fd.clone(), false, None)?;
 
         let driver = driver(device_id)?.map(|x| x.to_string_lossy().into_owned());
-        let render_node = drm_get_render_node(&fd).context("Device has no render node")?;
-        
+        // software rendered devices do not share buffers with clients, e.g. simpledrm has no render node
+        let render_node = match mode {
+            RenderMode::Hardware => Some(drm_get_render_node(&fd).context("Device has no render node")?),
+            RenderMode::Software => None,
+        };
+        let eglstreams = mode == RenderMode::Hardware && driver.as_deref() == Some("nvidia");
+
         // we do not actually need to use the gbm platform, mesa supports EGLDevice just a well.
-        let egl_device = EGLDeviceEXT::new(fd.clone(), slog_scope::logger())?;
-        let egl_display = EGLDisplay::new(&egl_device, None)?;
-        let egl_context = if driver.as_ref().map(|x| &**x) == Some("nvidia") {
+        let egl_display = match mode {
+            RenderMode::Hardware => EGLDisplay::new(&EGLDeviceEXT::new(fd.clone(), slog_scope::logger())?, None)?,
+            RenderMode::Software => EGLDisplay::new(&SoftwareDevice::new()?, None)?,
+        };
+        let egl_context = if eglstreams {
             EGLContext::new_with_config(
                 &egl_display,
                 GlAttributes {
@@ -147,116 +311,23 @@ impl Fireplace {
         };
 
         // enumerate our outputs
-        let mut surfaces = HashMap::new();
-        for (conn, crtc) in display_configuration(&mut drm)?.iter() {
-            let conn_info = drm.get_connector(*conn)?;
-            let crtc_info = drm.get_crtc(*crtc)?;
-            let mode = crtc_info.mode().unwrap_or(conn_info.modes()[0]);
-            let mut surface = drm.create_surface(*crtc, mode, &[*conn])?;
-            surface.link(signaler.clone());
-
-            let target = match driver.as_ref().map(|x| &**x) {
-                Some("nvidia") => {
-                    RenderSurface::new_eglstream(surface, &egl_display, &egl_context)?
-                },
-                _ => {
-                    RenderSurface::new_gbm(surface, fd.clone(), &egl_context)?
-                },
-            };
-
-            let mode = OutputMode {
-                size: (mode.size().0 as i32, mode.size().1 as i32).into(),
-                refresh: (mode.vrefresh() * 1000) as i32,
-            };
-
-            let other_short_name;
-            let interface_short_name = match conn_info.interface() {
-                connector::Interface::DVII => "DVI-I",
-                connector::Interface::DVID => "DVI-D",
-                connector::Interface::DVIA => "DVI-A",
-                connector::Interface::SVideo => "S-VIDEO",
-                connector::Interface::DisplayPort => "DP",
-                connector::Interface::HDMIA => "HDMI-A",
-                connector::Interface::HDMIB => "HDMI-B",
-                connector::Interface::EmbeddedDisplayPort => "eDP",
-                other => {
-                    other_short_name = format!("{:?}", other);
-                    &other_short_name
-                }
-            };
-            let output_name = format!("{}-{}", interface_short_name, conn_info.interface_id());
-
-            let edid_prop = get_prop(&drm, *conn, "EDID")?;
-            let edid_info = drm.get_property(edid_prop)?;
-            let mut manufacturer = "Unknown".into();
-            let mut model = "Unknown".into();
-            let props = drm.get_properties(*conn)?;
-            let (ids, vals) = props.as_props_and_values();
-            for (&id, &val) in ids.iter().zip(vals.iter()) {
-                if id == edid_prop {
-                    if let property::Value::Blob(edid_blob) =
-                        edid_info.value_type().convert_value(val)
-                    {
-                        let blob = drm.get_property_blob(edid_blob)?;
-                        let mut reader = std::io::Cursor::new(blob);
-                        if let Some(edid) = edid_parse(&mut reader).ok() {
-                            manufacturer = {
-                                let id = edid.product.manufacturer_id;
-                                let code = [id.0, id.1, id.2];
-                                get_manufacturer(&code).into()
-                            };
-                            model = if let Some(MonitorDescriptor::MonitorName(name)) = edid.descriptors.0
-                                .iter()
-                                .find(|x| matches!(x, MonitorDescriptor::MonitorName(_)))
-                            {
-                                name.clone()
-                            } else {
-                                format!("{}", edid.product.product_code)
-                            };
-                        }
-                    }
-                    break;
-                }
-            }            
-
-            let (phys_w, phys_h) = conn_info.size().unwrap_or((0, 0));
-            let mut workspaces = self.workspaces.borrow_mut();
-            workspaces.add_output(
-                &output_name,
-                PhysicalProperties {
-                    size: (phys_w as i32, phys_h as i32).into(),
-                    subpixel: wl_output::Subpixel::Unknown,
-                    make: manufacturer,
-                    model,
-                },
-                mode,
-            );
-
-            let timer = Timer::new()?;
-
-            let data = SurfaceData {
-                output: output_name,
-                size: mode.size,
-                surface: target,
-                render_timer: timer.handle(),
-            };
-
-            // re-render timer
-            handle
-                .insert_source(timer, |(dev_id, crtc), _, state| {
-                    if let Err(err) = state.render(dev_id, Some(crtc)) {
-                        slog_scope::error!("Error rendering: {}", err);
-                    }
-                })
-                .unwrap();
-            surfaces.insert(*crtc, data);
-        }
+        scan_connectors(
+            &self.workspaces,
+            &handle,
+            &mut drm,
+            &fd,
+            driver.as_deref(),
+            mode,
+            &self.config,
+            &self.disabled_outputs,
+            &egl_display,
+            &egl_context,
+            &signaler,
+            surfaces,
+        )?;
 
-        if surfaces.is_empty() {
-            return Ok(());
-        }
-        
-        // create our renderer
+        // create our renderer, the shared context is kept to create surfaces for outputs plugged in later
+        let surface_context = EGLContext::new_shared(&egl_display, &egl_context, None)?;
         let renderer = unsafe { Gles2Renderer::new(egl_context, None)? };
         let pointer = cursor::Cursor::load(&slog_scope::logger());
 
@@ -264,23 +335,42 @@ impl Fireplace {
         let restart_token = signaler.register(move |signal| match signal {
             Signal::ActivateSession | Signal::ActivateDevice { .. } => {
                 restart_handle.insert_idle(move |state| {
-                    if let Err(err) = state.render(device_id, None) {
+                    // the cursor planes were reset while the session was inactive
+                    if let Some(backend) = state.udev.get_mut(&device_id) {
+                        for cursor in backend.surfaces.values_mut().filter_map(|surface| surface.cursor.as_mut()) {
+                            cursor.reset();
+                        }
+                    }
+                    if let Some(Err(err)) = state.guard("render", |state| state.render(device_id, None)) {
                         slog_scope::error!("Error rendering on {:?}: {}", device_id, err);   
                     }
-                    // TODO do re-schedule
                 });
             }
             _ => {}
         });
         drm.link(signaler.clone());
 
-        let drm_token = handle.insert_source(
+        let drm = Dispatcher::new(
             drm,
             move |event, _, state: &mut Fireplace| match event {
-                DrmEvent::VBlank(crtc) => {
+                DrmEvent::VBlank(crtc) => state.guard("render", |state| {
                     {
                         if let Some(backend) = state.udev.get_mut(&device_id) {
+                            // frames queued before the buffers were released are done now
+                            if let Some((_, syncobj)) = backend.syncobj_global.as_ref() {
+                                syncobj.signal_released();
+                            }
                             if let Some(surface) = backend.surfaces.get_mut(&crtc) {
+                                let now = Instant::now();
+                                surface.last_vblank = Some(now);
+                                surface.frame = FrameState::Idle;
+                                if let Some(queued) = surface.queued.take() {
+                                    state
+                                        .frame_stats
+                                        .entry(surface.output.clone())
+                                        .or_default()
+                                        .presented(queued.input, queued.missed_vblanks(surface.refresh, now));
+                                }
                                 if let Err(err) = surface.surface.frame_submitted() {
                                     slog_scope::error!("Error submitting frame on {:?}: {}", device_id, err);
                                     return;
@@ -288,15 +378,16 @@ impl Fireplace {
                             }
                         }
                     }
-                    if let Err(err) = state.render(device_id, Some(crtc)) {
-                        slog_scope::error!("Error rendering on {:?}: {}", device_id, err);
-                    } // TODO re-schedule
-                },
+                    state.schedule_frame(device_id, crtc);
+                }).unwrap_or(()),
                 DrmEvent::Error(error) => {
                     slog_scope::error!("{:?}", error);
                 }
             },
-        ).map_err(|_| anyhow::anyhow!("Failed to register drm device on the event loop"))?;
+        );
+        let drm_token = handle.register_dispatcher(drm.clone())
+            .map_err(|_| anyhow::anyhow!("Failed to register drm device on the event loop"))?;
+        tokens.push(drm_token);
 
         // Add custom gpu socket
         // We would have failed earlier if this is not set
@@ -344,10 +435,11 @@ impl Fireplace {
 
             Ok(PostAction::Continue)
         }).context("Failed to add gpu-wayland socket to the event loop")?;
+        tokens.push(socket_token);
 
         // initialize globals
         let display = self.display.clone();
-        let is_primary = primary_gpu(std::env::var("XDG_SEAT").unwrap_or("seat0".to_string()))? == Some(path);
+        let is_primary = primary_gpu(std::env::var("XDG_SEAT").unwrap_or("seat0".to_string()))?.as_deref() == Some(path);
         let formats = renderer.dmabuf_formats().cloned().collect::<Vec<_>>();
         let filter = move |client: Client| {
             let dev_id = client.data_map().get::<DevId>();
@@ -357,11 +449,21 @@ impl Fireplace {
             dev_id.map(|x| x.0 == device_id).unwrap_or(is_primary)
         };
 
-        if driver.as_ref().map(|x| &**x) == Some("nvidia") {
-            let _ = init_eglstream_globals(&mut *display.borrow_mut(), &egl_display, filter.clone());
-        }
-        let _ = init_wl_drm_global(&mut *display.borrow_mut(), render_node, formats.clone(), filter.clone());
-        let _ = init_dmabuf_global_with_filter(&mut *display.borrow_mut(), formats, move |buf, mut ddata| {
+        let eglstream_globals = if eglstreams {
+            init_eglstream_globals(&mut *display.borrow_mut(), &egl_display, filter.clone()).ok()
+        } else {
+            None
+        };
+        let syncobj_global = match render_node.clone().map(|node| init_syncobj_global(&mut *display.borrow_mut(), node, filter.clone())) {
+            Some(Ok(global)) => Some(global),
+            Some(Err(err)) => {
+                slog_scope::info!("No explicit synchronization on {:?}: {}", device_id, err);
+                None
+            }
+            None => None,
+        };
+        let wl_drm_global = render_node.map(|node| init_wl_drm_global(&mut *display.borrow_mut(), node, formats.clone(), filter.clone()));
+        let dmabuf_global = init_dmabuf_global_with_filter(&mut *display.borrow_mut(), formats, move |buf, mut ddata| {
             let state = ddata.get::<Fireplace>().unwrap();
             state.udev.get_mut(&device_id)
                 .map(|backend| {
@@ -370,42 +472,277 @@ impl Fireplace {
                 .unwrap_or(false)
         }, filter, None);
 
-        let data = BackendData {
+        Ok(BackendData {
+            drm,
             drm_token,
             socket_token,
             _restart_token: restart_token,
-            surfaces,
+            surfaces: std::mem::take(surfaces),
+            fd,
+            mode,
+            egl_display,
+            egl_context: surface_context,
+            wl_drm_global,
+            dmabuf_global,
+            eglstream_globals,
+            syncobj_global,
             renderer,
             driver,
+            handle,
+            signaler,
             pointer,
-            pointer_images: Vec::new(),
+            pointer_images: TextureCache::new(CURSOR_TEXTURES),
+            cache: RenderCache::new(),
+        })
+    }
+
+    fn device_changed(&mut self, device: dev_t) -> Result<()> {
+        let backend = match self.udev.get_mut(&device) {
+            Some(backend) => backend,
+            None => return Ok(()),
         };
-        self.udev.insert(device_id, data);
 
-        if let Err(err) = self.render(device_id, None) {
-            slog_scope::error!("Error rendering on {:?}: {}", device_id, err);
+        let removed = scan_connectors(
+            &self.workspaces,
+            &backend.handle,
+            &mut *backend.drm.as_source_mut(),
+            &backend.fd,
+            backend.driver.as_deref(),
+            backend.mode,
+            &self.config,
+            &self.disabled_outputs,
+            &backend.egl_display,
+            &backend.egl_context,
+            &backend.signaler,
+            &mut backend.surfaces,
+        )?;
+        for output in removed {
+            self.output_removed(&output);
+        }
+
+        if let Err(err) = self.render(device, None) {
+            slog_scope::error!("Error rendering on {:?}: {}", device, err);
         }
 
         Ok(())
     }
 
-    fn device_changed<S, E>(&mut self, session: &mut S, device: dev_t) -> Result<()>
-    where
-        S: Session<Error=E>,
-        E: AsErrno,
-    {
-        Ok(())
+    /// Turns a connected output off or on again without it being unplugged
+    pub fn set_output_enabled(&mut self, name: &str, enabled: bool) {
+        let changed = if enabled {
+            self.disabled_outputs.remove(name)
+        } else {
+            self.disabled_outputs.insert(String::from(name))
+        };
+        if !changed {
+            return;
+        }
+        slog_scope::info!("Output {} {}", name, if enabled { "enabled" } else { "disabled" });
+
+        let devices = self.udev.keys().copied().collect::<Vec<_>>();
+        for device in devices {
+            if let Err(err) = self.device_changed(device) {
+                slog_scope::error!("Unable to rescan outputs of {:?}: {}", device, err);
+            }
+        }
+    }
+
+    /// Disables internal panels while the lid is closed and an external output is connected
+    pub fn update_clamshell(&mut self) {
+        let outputs = self
+            .udev
+            .values()
+            .flat_map(|backend| backend.surfaces.values())
+            .map(|surface| surface.output.clone())
+            .collect::<Vec<_>>();
+        let external = outputs.iter().any(|name| !is_internal(name));
+
+        if self.config.clamshell && self.lid_closed && external {
+            for name in outputs.into_iter().filter(|name| is_internal(name)) {
+                slog_scope::info!("Entering clamshell mode, disabling {}", name);
+                self.clamshell_outputs.push(name.clone());
+                self.set_output_enabled(&name, false);
+            }
+        } else {
+            for name in std::mem::take(&mut self.clamshell_outputs) {
+                slog_scope::info!("Leaving clamshell mode, enabling {}", name);
+                self.set_output_enabled(&name, true);
+            }
+        }
     }
 
     fn device_removed<S, E>(&mut self, session: &mut S, device: dev_t) -> Result<()>
     where
         S: Session<Error=E>,
-        E: AsErrno,
+        E: std::error::Error + Send + Sync + AsErrno + 'static,
     {
+        let backend = match self.udev.remove(&device) {
+            Some(backend) => backend,
+            None => return Ok(()),
+        };
+
+        slog_scope::info!("Removing gpu {:?}", device);
+        let handle = &backend.handle;
+        for (_, surface) in backend.surfaces {
+            handle.remove(surface.render_token);
+            self.workspaces.borrow_mut().remove_output_by_name(&surface.output);
+            self.output_removed(&surface.output);
+        }
+        handle.remove(backend.drm_token);
+        handle.remove(backend.socket_token);
+
+        if let Some(global) = backend.wl_drm_global {
+            global.destroy();
+        }
+        backend.dmabuf_global.destroy();
+        if let Some((display, controller)) = backend.eglstream_globals {
+            display.destroy();
+            controller.destroy();
+        }
+        if let Some((global, _)) = backend.syncobj_global {
+            global.destroy();
+        }
+
+        // the renderer has to go before the device it renders on
+        std::mem::drop(backend.renderer);
+        std::mem::drop(backend.drm);
+        session.close(backend.fd.0)?;
+
         Ok(())
     }
 
+    /// Powers off outputs after their dpms timeout passed without any input
+    fn power_off_idle_outputs(&mut self) {
+        let idle = self.last_input.elapsed();
+        let config = &self.config;
+        let mut workspaces = self.workspaces.borrow_mut();
+        for (dev_id, backend) in self.udev.iter_mut() {
+            let drm = backend.drm.as_source_ref();
+            for (crtc, surface) in backend.surfaces.iter_mut().filter(|(_, surface)| surface.powered) {
+                let timeout = config
+                    .output(&surface.output, &surface.identifier)
+                    .and_then(|output| output.dpms_timeout)
+                    .or(config.dpms_timeout)
+                    .unwrap_or(0);
+                if timeout == 0 || idle < Duration::from_secs(timeout) {
+                    continue;
+                }
+                let inhibited = workspaces
+                    .space_by_output_name(&surface.output)
+                    .map(|space| space.windows_from_bottom_to_top().any(|(toplevel, _, _)| inhibits_idle(&toplevel)))
+                    .unwrap_or(false);
+                if inhibited {
+                    continue;
+                }
+                slog_scope::info!("Powering off idle output {}", surface.output);
+                match set_active(&*drm, surface.connector, *crtc, false) {
+                    Ok(()) => surface.powered = false,
+                    Err(err) => slog_scope::error!("Unable to power off {} on {:?}: {}", surface.output, dev_id, err),
+                }
+            }
+        }
+    }
+
+    /// Applies the color temperature of the night light schedule to all outputs
+    fn update_night_light(&mut self) {
+        let temperature = night_light::target_temperature(&self.config.night_light, self.night_light);
+        for (dev_id, backend) in self.udev.iter_mut() {
+            let drm = backend.drm.as_source_ref();
+            for (crtc, surface) in backend.surfaces.iter_mut() {
+                // avoid flickering through tiny changes
+                if (surface.temperature as i64 - temperature as i64).abs() < 10 {
+                    continue;
+                }
+                // the gamma lut of hdr outputs encodes the pq curve
+                let result = match surface.hdr.as_ref() {
+                    Some(hdr) => hdr.set_temperature(&*drm, *crtc, temperature),
+                    None => drm.get_crtc(*crtc).map_err(anyhow::Error::from).and_then(|info| {
+                        let (red, green, blue) = night_light::gamma_ramps(temperature, info.gamma_length() as usize);
+                        drm.set_gamma(*crtc, &red, &green, &blue).map_err(anyhow::Error::from)
+                    }),
+                };
+                match result {
+                    Ok(()) => {
+                        slog_scope::debug!("Color temperature of {} set to {}K", surface.output, temperature);
+                        surface.temperature = temperature;
+                    }
+                    Err(err) => slog_scope::error!("Unable to set gamma of {} on {:?}: {}", surface.output, dev_id, err),
+                }
+            }
+        }
+    }
+
+    /// Powers on all outputs turned off by `power_off_idle_outputs`
+    fn wake_outputs(&mut self) {
+        let mut woken = Vec::new();
+        for (dev_id, backend) in self.udev.iter_mut() {
+            let drm = backend.drm.as_source_ref();
+            for (crtc, surface) in backend.surfaces.iter_mut().filter(|(_, surface)| !surface.powered) {
+                slog_scope::info!("Waking up output {}", surface.output);
+                match set_active(&*drm, surface.connector, *crtc, true) {
+                    Ok(()) => {
+                        surface.powered = true;
+                        woken.push((*dev_id, *crtc));
+                    }
+                    Err(err) => slog_scope::error!("Unable to power on {} on {:?}: {}", surface.output, dev_id, err),
+                }
+            }
+        }
+        for (dev_id, crtc) in woken {
+            if let Err(err) = self.render(dev_id, Some(crtc)) {
+                slog_scope::error!("Error rendering on {:?}: {}", dev_id, err);
+            }
+        }
+    }
+
+    /// Shows the cursor on the cursor planes of a gpu, where possible
+    ///
+    /// Cursors that cannot be put onto a plane are composited by `render`.
+    pub fn update_hardware_cursors(&mut self, dev_id: dev_t) {
+        let backend = match self.udev.get_mut(&dev_id) {
+            Some(backend) => backend,
+            None => return,
+        };
+        let drm = backend.drm.as_source_ref();
+        let millis = self.start_time.elapsed().as_millis() as u32;
+        let mut workspaces = self.workspaces.borrow_mut();
+
+        for (crtc, surface) in backend.surfaces.iter_mut().filter(|(_, surface)| surface.powered) {
+            let cursor = match surface.cursor.as_mut() {
+                Some(cursor) => cursor,
+                None => continue,
+            };
+            // mirrors scale the cursor together with the rest of the frame, color filters have to cover it
+            let filtered = self
+                .config
+                .color_filter(self.color_filter, &surface.output, &surface.identifier)
+                .is_some();
+            let scale = match workspaces.output_by_name(&surface.output) {
+                Some(output) if surface.mirror.is_none() && !filtered => Some(output.scale()),
+                _ => None,
+            };
+            let image = scale.and_then(|scale| {
+                hardware_cursor_image(&self.seats, &surface.output, &self.config, &backend.pointer, scale, millis)
+            });
+            let result = match image {
+                Some((image, position)) if cursor.fits(&image) => cursor.show(&*drm, *crtc, &image, position),
+                _ => cursor.hide(&*drm, *crtc),
+            };
+            if let Err(err) = result {
+                slog_scope::warn!("Disabling the hardware cursor of {}: {}", surface.output, err);
+                if let Some(mut cursor) = surface.cursor.take() {
+                    let _ = cursor.hide(&*drm, *crtc);
+                    cursor.destroy(&*drm);
+                }
+            }
+        }
+    }
+
     pub fn render(&mut self, dev_id: dev_t, crtc: Option<crtc::Handle>) -> Result<()> {
+        self.update_hardware_cursors(dev_id);
+        let damage = self.damage;
+        let active_output = self.active_output();
+
         let (mut device_backend, mut other_backends): (Vec<(&dev_t, &mut BackendData)>, Vec<_>) = self.udev.iter_mut().partition(|(key, _)| **key == dev_id);
         let device_backend = match device_backend.pop() {
             Some((key, backend)) if *key == dev_id => backend,
@@ -420,77 +757,256 @@ impl Fireplace {
             .iter_mut()
             .filter(|(c, _)| crtc.map(|x| x == **c).unwrap_or(true))
             .map(|(_, surf)| surf)
+            // powered off outputs are woken by the next input event
+            .filter(|surf| surf.powered)
         {
+            profile_span!("render", output = %surface.output);
+            // everything damaged so far is drawn by this frame
+            let damaged = damage.wrapping_sub(surface.rendered);
+            surface.rendered = damage;
+            let started = Instant::now();
+            let target = schedule::next_vblank(surface.last_vblank, surface.refresh, started);
             let mut workspaces = self.workspaces.borrow_mut();
-            let scale = workspaces.output_by_name(&surface.output).unwrap().scale();
-            let space = workspaces.space_by_output_name(&surface.output).unwrap();
+            // mirrors present the content of their source output
+            let output_name = surface.mirror.as_ref().unwrap_or(&surface.output);
+            let output = match workspaces.output_by_name(output_name) {
+                Some(output) => output,
+                None => {
+                    slog_scope::debug!("Output {} mirrors missing output {}", surface.output, output_name);
+                    surface.frame = FrameState::Idle;
+                    continue;
+                }
+            };
+            let scale = if surface.mirror.is_some() {
+                // scaled to fit
+                let size = output.size();
+                f32::min(surface.size.w as f32 / size.w as f32, surface.size.h as f32 / size.h as f32)
+            } else {
+                output.scale()
+            };
+            let (space, switch) = workspaces.visible_spaces(output_name, &self.config.animations).unwrap();
             let popups = self.popups.borrow();
 
             let seats = &self.seats;
-            let output_name = &surface.output;
+            let config = &self.config;
+            let hardware_cursor = surface.cursor.as_ref().map(|cursor| cursor.is_visible()).unwrap_or(false);
+
+            // the topmost window may be put onto an overlay plane, if we draw nothing above it
+            let on_output = |seat: &&Seat| {
+                seat.user_data().get::<ActiveOutput>().map(|name| &*name.0.borrow() == output_name).unwrap_or(false)
+            };
+            let covered = seats.iter().filter(on_output).any(|seat| {
+                !hardware_cursor || seat.user_data().get::<DndIcon>().map(|icon| icon.0.borrow().is_some()).unwrap_or(false)
+            });
+            // screenshots are read back from the composited frame, mirrors leave them to their source
+            let screenshots = match surface.mirror {
+                Some(_) => Vec::new(),
+                None => take_pending(&mut self.screenshots, output_name),
+            };
+            let screencast = self.screencast.as_ref().filter(|_| surface.mirror.is_none());
+            let casting = screencast.map(|screencast| screencast.is_casting(output_name)).unwrap_or(false);
+            // animated windows and workspace switches are blended, planes can not show them
+            let animating = device_backend.cache.animations.is_animating(space.id()) || switch.is_some();
+            let debug_lines = if self.debug_overlay {
+                let mut lines = vec![surface.output.clone()];
+                lines.extend(self.frame_stats.get(&surface.output).map(|stats| stats.summary()).unwrap_or_default());
+                Some(lines)
+            } else {
+                None
+            };
+            let message = self.message.as_ref().map(|message| &message.lines);
+            let osd = self.osd.as_ref();
+            let lock = self.lock.as_ref();
+            // notifications follow the focus and are hidden by the lock
+            let notifications = self
+                .notifications
+                .as_ref()
+                .map(|daemon| &*daemon.notifications)
+                .filter(|notifications| !notifications.is_empty() && lock.is_none() && *output_name == active_output);
+            // planes bypass the filter, so everything is composited while it is active
+            let filter = config.color_filter(self.color_filter, &surface.output, &surface.identifier);
+            let candidate = if surface.mirror.is_none() && !covered && screenshots.is_empty() && !casting && !animating && debug_lines.is_none() && message.is_none() && osd.is_none() && notifications.is_none() && lock.is_none() && filter.is_none() && !self.debug_damage {
+                scanout_candidate(space, &**popups, config, Some(DevId(dev_id)), scale)
+            } else {
+                None
+            };
+            let scanout = candidate
+                .as_ref()
+                .filter(|candidate| candidate.fullscreen)
+                .and_then(|candidate| surface.surface.try_scanout(candidate));
+            let overlay = match scanout {
+                Some(_) => None,
+                None => candidate.as_ref().and_then(|candidate| surface.surface.try_overlay(candidate)),
+            };
+            let skip = overlay.as_ref().and(candidate.as_ref()).map(|candidate| &candidate.surface);
+
             let frame = device_backend
                 .pointer
                 .get_image(scale.ceil() as u32, self.start_time.elapsed().as_millis() as u32);
             let hotspot: Point<i32, Logical> = (frame.xhot as i32, frame.yhot as i32).into();
             let pointer_images = &mut device_backend.pointer_images;
+            let cache = &mut device_backend.cache;
             let renderer = &mut device_backend.renderer;
             let pointer_image = pointer_images
-                .iter()
-                .find_map(|(image, texture)| if image == &frame { Some(texture) } else { None })
-                .cloned()
-                .unwrap_or_else(|| {
+                .get_or_import(&frame, |frame| {
                     let image =
                         ImageBuffer::from_raw(frame.width, frame.height, &*frame.pixels_rgba).unwrap();
-                    let texture = renderer.import_bitmap(&image).expect("Failed to import cursor bitmap");
-                    pointer_images.push((frame, texture.clone()));
-                    texture
-                });
+                    renderer.import_bitmap(&image)
+                })
+                .expect("Failed to import cursor bitmap");
 
-            surface.surface.bind(&mut device_backend.renderer)?;
-            device_backend.renderer.render(surface.size, surface.surface.transform(Transform::Normal), |renderer, frame| {
-                render_space(&**space, scale, &**popups, Some(DevId(dev_id)), renderer, frame, &mut other_backends)?;
-
-                // render the cursors for all seats
-                // TODO tint the cursors by seats
-                for seat in seats.iter().filter(|seat| {
-                    seat.user_data().get::<ActiveOutput>().map(|name| &*name.0.borrow() == output_name).unwrap_or(false)
-                }) {
-                    if let Some(position) = seat.get_pointer()
-                        .map(|ptr| ptr.current_location())
-                    {
-                        let userdata = seat.user_data();
-                        let status_ref = userdata.get::<RefCell<CursorImageStatus>>().unwrap();
-                        let mut status = status_ref.borrow_mut();
-                        let mut reset = false;
-                        if let CursorImageStatus::Image(ref surface) = *status {
-                            reset = !surface.as_ref().is_alive();
+            let size = surface.size;
+            // egl window surfaces are not rendered upside down, so they are read back starting at the bottom
+            let flipped = surface.surface.transform(Transform::Normal) == Transform::Normal;
+            cache.blur.flipped = flipped;
+            let mut images = Vec::with_capacity(screenshots.len());
+            // scanned out frames are not composited at all
+            let mut redrawn = 0.0;
+
+            let result = match scanout {
+                // fullscreen windows covering the whole output skip compositing entirely
+                Some(scanout) => surface.surface.queue_scanout(scanout),
+                None => {
+                    let age = surface.surface.bind(&mut device_backend.renderer)?;
+                    let mut elements = space_elements(space, &**popups, config, skip, scale);
+                    elements.extend(cursor_elements(seats, output_name, config, hardware_cursor, &frame, scale));
+                    // animations and the overlays change every frame in ways the elements do not describe,
+                    // filtered frames can not be partially redrawn on top of their filtered contents
+                    let full = animating || debug_lines.is_some() || message.is_some() || osd.is_some() || notifications.is_some() || lock.is_some() || filter.is_some() || self.debug_damage;
+                    let damage = match surface.tracker.damage(elements, size, age, full) {
+                        Redraw::Nothing if screenshots.is_empty() => {
+                            // the buffer shows the same as the screen already, clients may draw their next frame
+                            surface.frame = FrameState::Idle;
+                            space.send_frames(self.start_time.elapsed().as_millis() as u32);
+                            continue;
+                        }
+                        Redraw::Area(area) => {
+                            redrawn = (area.size.w * area.size.h) as f64 / (size.w * size.h).max(1) as f64;
+                            Some(area)
+                        }
+                        Redraw::Nothing => Some(Rectangle::from_loc_and_size((0, 0), (0, 0))),
+                        Redraw::Everything => {
+                            redrawn = 1.0;
+                            None
+                        }
+                    };
+                    let debug_damage = if self.debug_damage {
+                        Some(surface.tracker.changes().map(<[_]>::to_vec))
+                    } else {
+                        None
+                    };
+                    device_backend.renderer.render(surface.size, surface.surface.transform(Transform::Normal), |renderer, frame| {
+                        renderer.scissor(damage, flipped)?;
+                        match lock {
+                            Some(lock) => render_lock(lock, output_name, scale, size, config, Some(DevId(dev_id)), renderer, frame, cache, &mut other_backends)?,
+                            None => {
+                                render_space(space, switch.as_ref(), scale, size, &**popups, config, Some(DevId(dev_id)), skip, renderer, frame, cache, &mut other_backends)?;
+                                draw_dnd_icons(seats, output_name, Some(DevId(dev_id)), renderer, frame, scale, &mut other_backends)?;
+                            }
+                        }
+                        for screenshot in screenshots.iter() {
+                            let area = screenshot.area(size, scale);
+                            match read_pixels(renderer, size, area, flipped) {
+                                Ok(image) => images.push((screenshot.clone(), image)),
+                                Err(err) => {
+                                    slog_scope::error!("Failed to read back screenshot: {}", err);
+                                    screenshot.failed(err.to_string());
+                                }
+                            }
+                        }
+                        if let Some(screencast) = screencast {
+                            screencast.capture(space, output_name, size, scale, |area| {
+                                read_pixels(renderer, size, area, flipped)
+                                    .map_err(|err| slog_scope::error!("Failed to read back screencast frame: {}", err))
+                                    .ok()
+                            });
+                        }
+                        if let Some(lines) = debug_lines.as_ref() {
+                            draw_debug_overlay(renderer, frame, cache, config, lines, scale)?;
                         }
-                        if reset {
-                            *status = CursorImageStatus::Default;
+                        if let Some(changes) = debug_damage.as_ref() {
+                            draw_debug_damage(renderer, frame, cache, space, changes.as_deref(), size, scale)?;
                         }
-                        match &*status {
-                            &CursorImageStatus::Default => {
-                                frame.render_texture_at(
-                                    &pointer_image,
-                                    (position - hotspot.to_f64()).to_physical(scale as f64).to_i32_round(),
-                                    1, scale as f64,
-                                    Transform::Normal,
-                                    1.0
-                                )?;
-                            },
-                            &CursorImageStatus::Image(ref surface) => {
-                                draw_cursor(Some(DevId(dev_id)), renderer, frame, surface, position.to_i32_round(), scale, &mut other_backends)?;
+                        if let Some(lines) = message {
+                            draw_message(renderer, frame, cache, config, lines, size, scale)?;
+                        }
+                        if let Some(osd) = osd {
+                            draw_osd(renderer, frame, cache, config, &osd.label, osd.level, size, scale)?;
+                        }
+                        if let Some(notifications) = notifications {
+                            draw_notifications(renderer, frame, cache, config, notifications, size, scale)?;
+                        }
+
+                        // render the cursors for all seats, unless the cursor plane shows it
+                        // TODO tint the cursors by seats
+                        for seat in seats.iter().filter(|_| !hardware_cursor).filter(|seat| {
+                            seat.user_data().get::<ActiveOutput>().map(|name| &*name.0.borrow() == output_name).unwrap_or(false)
+                        }) {
+                            if !seat.user_data().get::<CursorVisibility>().map(|v| v.is_visible(&config.cursor)).unwrap_or(true) {
+                                continue;
+                            }
+                            // a tablet tool in proximity takes over the cursor
+                            let tablet = seat.user_data().get::<TabletCursor>().and_then(|cursor| cursor.0.get());
+                            if let Some(position) = tablet.or_else(|| seat.get_pointer()
+                                .map(|ptr| ptr.current_location()))
+                            {
+                                let userdata = seat.user_data();
+                                let status_ref = userdata.get::<RefCell<CursorImageStatus>>().unwrap();
+                                let mut status = status_ref.borrow_mut();
+                                let mut reset = false;
+                                if let CursorImageStatus::Image(ref surface) = *status {
+                                    reset = !surface.as_ref().is_alive();
+                                }
+                                if reset {
+                                    *status = CursorImageStatus::Default;
+                                }
+                                match &*status {
+                                    &CursorImageStatus::Default => {
+                                        frame.render_texture_at(
+                                            &pointer_image,
+                                            (position - hotspot.to_f64()).to_physical(scale as f64).to_i32_round(),
+                                            1, scale as f64,
+                                            Transform::Normal,
+                                            1.0
+                                        )?;
+                                    },
+                                    &CursorImageStatus::Image(ref surface) => {
+                                        draw_cursor(Some(DevId(dev_id)), renderer, frame, surface, position.to_i32_round(), scale, &mut other_backends)?;
+                                    }
+                                    CursorImageStatus::Hidden => {},
+                                }
                             }
-                            CursorImageStatus::Hidden => {},
                         }
+                        if let Some(filter) = filter {
+                            renderer.apply_color_filter(&mut cache.color_filter, filter)?;
+                        }
+                        renderer.scissor(None, flipped)?;
+                        Ok(())
+                    }).and_then(|x| x)?;
+                    let result = surface.surface.queue_buffer(&mut device_backend.renderer, overlay, damage);
+                    if result.is_ok() {
+                        surface.tracker.queued();
                     }
+                    result
                 }
-                Ok(())
-            }).and_then(|x| x)?;
-            match surface.surface.queue_buffer(&mut device_backend.renderer)
+            };
+            for (screenshot, image) in images {
+                save(&config.screenshot, screenshot, image);
+            }
+            match result
             {
                 Ok(_) => {
+                    surface.frame = FrameState::Submitted;
+                    let stats = self.frame_stats.entry(surface.output.clone()).or_default();
+                    let input = stats.new_input(self.last_input);
+                    stats.record(started, Some(damaged), Some(redrawn));
+                    surface.queued = Some(QueuedFrame { target, input });
+                    #[cfg(feature = "profiling")]
+                    crate::profiling::frame_mark();
                     space.send_frames(self.start_time.elapsed().as_millis() as u32);
+                    if let Some(lock) = self.lock.as_ref() {
+                        lock.send_frames(output_name, self.start_time.elapsed().as_millis() as u32);
+                    }
                 },
                 Err(err) => {
                     use smithay::{
@@ -519,11 +1035,12 @@ impl Fireplace {
                     };
 
                     if reschedule {
+                        // try again at the next vblank
                         slog_scope::debug!("Rescheduling frame");
-                        surface.render_timer.add_timeout(
-                            std::time::Duration::from_millis(1000),// /*a second*/ / 60 /*refresh rate*/),
-                            (dev_id, surface.surface.crtc()),
-                        );
+                        surface.render_timer.add_timeout(surface.refresh, (dev_id, surface.surface.crtc()));
+                        surface.frame = FrameState::Scheduled;
+                    } else {
+                        surface.frame = FrameState::Idle;
                     }
                 }
             }
@@ -533,6 +1050,376 @@ impl Fireplace {
     }
 }
 
+/// Matches the surfaces of a gpu against its currently connected connectors
+///
+/// Surfaces of unplugged connectors are dropped together with their outputs,
+/// newly connected ones get a surface and an output. Disabled outputs are treated as unplugged.
+/// Returns the names of the removed outputs.
+#[allow(clippy::too_many_arguments)]
+fn scan_connectors(
+    workspaces: &RefCell<Workspaces>,
+    handle: &LoopHandle<'static, Fireplace>,
+    drm: &mut DrmDevice<SessionFd>,
+    fd: &SessionFd,
+    driver: Option<&str>,
+    render_mode: RenderMode,
+    config: &Config,
+    disabled: &HashSet<String>,
+    egl_display: &EGLDisplay,
+    egl_context: &EGLContext,
+    signaler: &Signaler<Signal>,
+    surfaces: &mut HashMap<crtc::Handle, SurfaceData>,
+) -> Result<Vec<String>> {
+    let mut configuration = display_configuration(drm)?;
+    configuration.retain(|conn, _| {
+        drm.get_connector(*conn)
+            .map(|info| !disabled.contains(&connector_name(&info)))
+            .unwrap_or(true)
+    });
+
+    let mut removed = Vec::new();
+    let stale = surfaces
+        .iter()
+        .filter(|(crtc, data)| configuration.get(&data.connector) != Some(*crtc))
+        .map(|(crtc, _)| *crtc)
+        .collect::<Vec<_>>();
+    for crtc in stale {
+        let data = surfaces.remove(&crtc).unwrap();
+        slog_scope::info!("Output {} was disconnected", data.output);
+        if let Some(cursor) = data.cursor {
+            cursor.destroy(&*drm);
+        }
+        handle.remove(data.render_token);
+        workspaces.borrow_mut().remove_output_by_name(&data.output);
+        removed.push(data.output);
+    }
+
+    for (conn, crtc) in configuration.iter() {
+        if surfaces.contains_key(crtc) {
+            continue;
+        }
+        let conn_info = drm.get_connector(*conn)?;
+        let crtc_info = drm.get_crtc(*crtc)?;
+        let output_name = connector_name(&conn_info);
+        let (manufacturer, model, serial) = monitor_info(drm, *conn)?;
+        let identifier = match serial {
+            Some(serial) => format!("{} {} {}", manufacturer, model, serial),
+            None => format!("{} {}", manufacturer, model),
+        };
+        slog_scope::info!("Found monitor {} on {}", identifier, output_name);
+        let output_config = config.output(&output_name, &identifier);
+        let modeline = output_config
+            .and_then(|config| config.modeline.as_ref())
+            .and_then(|modeline| match parse_modeline(modeline) {
+                Ok(mode) => Some(mode),
+                Err(err) => {
+                    slog_scope::warn!("Ignoring modeline of {}: {}", output_name, err);
+                    None
+                }
+            });
+        let mode = modeline.or_else(|| crtc_info.mode()).unwrap_or(conn_info.modes()[0]);
+        let mut surface = drm.create_surface(*crtc, mode, &[*conn])?;
+        surface.link(signaler.clone());
+
+        let hdr_config = output_config.filter(|config| config.hdr);
+        let bit_depth = match output_config.map(|config| config.bit_depth) {
+            _ if hdr_config.is_some() => 10,
+            Some(depth) if depth != 8 && depth != 10 => {
+                slog_scope::warn!("Unsupported bit depth {} of {}, using 8", depth, output_name);
+                8
+            }
+            depth => depth.unwrap_or(8),
+        };
+        if bit_depth != 8 {
+            if let Err(err) = hdr::set_max_bpc(&*drm, drm.is_atomic(), *conn, bit_depth) {
+                slog_scope::debug!("Unable to raise the bit depth of the link to {}: {}", output_name, err);
+            }
+        }
+        let temperature = night_light::NEUTRAL_TEMPERATURE;
+        let hdr = match hdr_config {
+            Some(config) if drm.is_atomic() && driver != Some("nvidia") => {
+                match Hdr::enable(&*drm, *conn, *crtc, config.sdr_brightness, temperature) {
+                    Ok(hdr) => {
+                        slog_scope::info!("Enabled hdr on {}", output_name);
+                        Some(hdr)
+                    }
+                    Err(err) => {
+                        slog_scope::warn!("Unable to enable hdr on {}: {}", output_name, err);
+                        None
+                    }
+                }
+            }
+            Some(_) => {
+                slog_scope::warn!("Hdr on {} needs an atomic driver", output_name);
+                None
+            }
+            None => {
+                if drm.is_atomic() {
+                    let _ = hdr::reset(&*drm, *conn, *crtc);
+                }
+                None
+            }
+        };
+
+        let target = match driver {
+            _ if render_mode == RenderMode::Software => RenderSurface::new_software(surface, egl_display, egl_context)?,
+            Some("nvidia") => {
+                if bit_depth != 8 {
+                    slog_scope::warn!("Bit depth of {} is not configurable with eglstreams", output_name);
+                }
+                RenderSurface::new_eglstream(surface, egl_display, egl_context)?
+            },
+            _ => {
+                // overlay planes shared between crtcs can only be used by one of them
+                let claimed = surfaces
+                    .values()
+                    .filter_map(|data| data.surface.overlay_plane())
+                    .collect::<HashSet<_>>();
+                let overlay = overlay_planes(drm, *crtc)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .find(|plane| !claimed.contains(plane));
+                RenderSurface::new_gbm(surface, fd.clone(), egl_context, drm.is_atomic(), overlay, bit_depth)?
+            },
+        };
+
+        let cursor = match HardwareCursor::new(&*drm) {
+            Ok(cursor) => Some(cursor),
+            Err(err) => {
+                slog_scope::debug!("No hardware cursor on {}: {}", output_name, err);
+                None
+            }
+        };
+
+        let mode = OutputMode {
+            size: (mode.size().0 as i32, mode.size().1 as i32).into(),
+            refresh: (mode.vrefresh() * 1000) as i32,
+        };
+        let refresh = schedule::refresh_duration(mode.refresh);
+
+        // nothing may fail after the output was added, it would be left without a surface
+        let timer = Timer::new()?;
+        let render_timer = timer.handle();
+
+        // re-render timer
+        let render_token = handle
+            .insert_source(timer, |(dev_id, crtc), _, state| {
+                if let Some(Err(err)) = state.guard("render", |state| state.render(dev_id, Some(crtc))) {
+                    slog_scope::error!("Error rendering: {}", err);
+                }
+            })
+            .unwrap();
+
+        // mirrors do not get workspaces of their own
+        let mirror = output_config.and_then(|config| config.mirror.clone());
+        if let Some(source) = mirror.as_ref() {
+            slog_scope::info!("Output {} mirrors {}", output_name, source);
+        } else {
+            let (phys_w, phys_h) = conn_info.size().unwrap_or((0, 0));
+            let mut workspaces = workspaces.borrow_mut();
+            workspaces.add_output(
+                &output_name,
+                PhysicalProperties {
+                    size: (phys_w as i32, phys_h as i32).into(),
+                    subpixel: wl_output::Subpixel::Unknown,
+                    make: manufacturer,
+                    model,
+                },
+                mode,
+            );
+            let position = output_config
+                .and_then(|config| config.position)
+                .map(|position| (position.x, position.y).into());
+            workspaces.set_preferred_location(&output_name, position);
+        }
+
+        let data = SurfaceData {
+            output: output_name,
+            identifier,
+            mirror,
+            connector: *conn,
+            powered: true,
+            temperature,
+            hdr,
+            size: mode.size,
+            surface: target,
+            cursor,
+            render_timer,
+            render_token,
+            refresh,
+            last_vblank: None,
+            frame: FrameState::Idle,
+            queued: None,
+            rendered: 0,
+            tracker: DamageTracker::new(),
+        };
+        surfaces.insert(*crtc, data);
+    }
+
+    Ok(removed)
+}
+
+/// The cursor image and its crtc local position, if a single seat shows a themed cursor on the output
+///
+/// Client provided cursor surfaces and several cursors on one output are composited instead.
+fn hardware_cursor_image(
+    seats: &[Seat],
+    output: &str,
+    config: &Config,
+    pointer: &Cursor,
+    scale: f32,
+    millis: u32,
+) -> Option<(xcursor::parser::Image, Point<i32, Physical>)> {
+    let mut seats = seats.iter().filter(|seat| {
+        seat.user_data().get::<ActiveOutput>().map(|name| &*name.0.borrow() == output).unwrap_or(false)
+            && seat.user_data().get::<CursorVisibility>().map(|v| v.is_visible(&config.cursor)).unwrap_or(true)
+    });
+    let seat = seats.next()?;
+    if seats.next().is_some() {
+        return None;
+    }
+
+    let status = seat.user_data().get::<RefCell<CursorImageStatus>>()?;
+    if !matches!(*status.borrow(), CursorImageStatus::Default) {
+        return None;
+    }
+    // a tablet tool in proximity takes over the cursor
+    let tablet = seat.user_data().get::<TabletCursor>().and_then(|cursor| cursor.0.get());
+    let position = tablet.or_else(|| seat.get_pointer().map(|ptr| ptr.current_location()))?;
+
+    let image = pointer.get_image(scale.ceil() as u32, millis);
+    let hotspot: Point<i32, Logical> = (image.xhot as i32, image.yhot as i32).into();
+    let position = (position - hotspot.to_f64()).to_physical(scale as f64).to_i32_round();
+    Some((image, position))
+}
+
+/// Elements of the cursors and drag-and-drop icons composited onto an output, see `DamageTracker`
+fn cursor_elements(
+    seats: &[Seat],
+    output: &str,
+    config: &Config,
+    hardware_cursor: bool,
+    pointer_image: &xcursor::parser::Image,
+    scale: f32,
+) -> Vec<Element> {
+    let mut elements = Vec::new();
+    for (i, seat) in seats.iter().enumerate().filter(|(_, seat)| {
+        seat.user_data().get::<ActiveOutput>().map(|name| &*name.0.borrow() == output).unwrap_or(false)
+    }) {
+        let location = match seat.get_pointer() {
+            Some(pointer) => pointer.current_location(),
+            None => continue,
+        };
+        if let Some(icon) = seat.user_data().get::<DndIcon>().and_then(|icon| icon.0.borrow().clone()) {
+            if icon.as_ref().is_alive() {
+                elements.push(Element::surface(&icon, location.to_i32_round(), scale));
+            }
+        }
+
+        if hardware_cursor
+            || !seat.user_data().get::<CursorVisibility>().map(|v| v.is_visible(&config.cursor)).unwrap_or(true)
+        {
+            continue;
+        }
+        let tablet = seat.user_data().get::<TabletCursor>().and_then(|cursor| cursor.0.get());
+        let position = tablet.unwrap_or(location);
+        let status = match seat.user_data().get::<RefCell<CursorImageStatus>>() {
+            Some(status) => status.borrow(),
+            None => continue,
+        };
+        match &*status {
+            CursorImageStatus::Default => {
+                let hotspot: Point<i32, Logical> = (pointer_image.xhot as i32, pointer_image.yhot as i32).into();
+                let size = Size::<i32, Logical>::from((pointer_image.width as i32, pointer_image.height as i32));
+                let area = Rectangle {
+                    loc: (position - hotspot.to_f64()).to_physical(scale as f64).to_i32_round(),
+                    size: size.to_f64().to_physical(scale as f64).to_i32_round(),
+                };
+                elements.push(Element::new(Id::Cursor(i), area, &pointer_image.pixels_rgba));
+            }
+            CursorImageStatus::Image(surface) if surface.as_ref().is_alive() => {
+                let location = position.to_i32_round() - cursor_hotspot(surface);
+                elements.push(Element::surface(surface, location, scale));
+            }
+            _ => {}
+        }
+    }
+    elements
+}
+
+/// Manufacturer, model and serial number of the monitor connected to a connector, read from its EDID
+fn monitor_info(drm: &DrmDevice<SessionFd>, conn: connector::Handle) -> Result<(String, String, Option<String>)> {
+    let edid_prop = get_prop(drm, conn, "EDID")?;
+    let edid_info = drm.get_property(edid_prop)?;
+    let mut manufacturer = "Unknown".into();
+    let mut model = "Unknown".into();
+    let mut serial = None;
+    let props = drm.get_properties(conn)?;
+    let (ids, vals) = props.as_props_and_values();
+    for (&id, &val) in ids.iter().zip(vals.iter()) {
+        if id == edid_prop {
+            if let property::Value::Blob(edid_blob) =
+                edid_info.value_type().convert_value(val)
+            {
+                let blob = drm.get_property_blob(edid_blob)?;
+                let mut reader = std::io::Cursor::new(blob);
+                if let Some(edid) = edid_parse(&mut reader).ok() {
+                    manufacturer = {
+                        let id = edid.product.manufacturer_id;
+                        let code = [id.0, id.1, id.2];
+                        get_manufacturer(&code).into()
+                    };
+                    model = if let Some(MonitorDescriptor::MonitorName(name)) = edid.descriptors.0
+                        .iter()
+                        .find(|x| matches!(x, MonitorDescriptor::MonitorName(_)))
+                    {
+                        name.clone()
+                    } else {
+                        format!("{}", edid.product.product_code)
+                    };
+                    serial = edid.descriptors.0
+                        .iter()
+                        .find_map(|x| match x {
+                            MonitorDescriptor::SerialNumber(serial) => Some(serial.clone()),
+                            _ => None,
+                        })
+                        .or_else(|| Some(edid.product.serial_number)
+                            .filter(|serial| *serial != 0)
+                            .map(|serial| serial.to_string())
+                        );
+                }
+            }
+            break;
+        }
+    }
+    Ok((manufacturer, model, serial))
+}
+
+/// Checks if an output is the built-in panel of a laptop
+fn is_internal(output: &str) -> bool {
+    ["eDP", "LVDS", "DSI"].iter().any(|prefix| output.starts_with(prefix))
+}
+
+/// Name of the output connected to a connector, e.g. `HDMI-A-1`
+fn connector_name(conn_info: &connector::Info) -> String {
+    let other_short_name;
+    let interface_short_name = match conn_info.interface() {
+        connector::Interface::DVII => "DVI-I",
+        connector::Interface::DVID => "DVI-D",
+        connector::Interface::DVIA => "DVI-A",
+        connector::Interface::SVideo => "S-VIDEO",
+        connector::Interface::DisplayPort => "DP",
+        connector::Interface::HDMIA => "HDMI-A",
+        connector::Interface::HDMIB => "HDMI-B",
+        connector::Interface::EmbeddedDisplayPort => "eDP",
+        other => {
+            other_short_name = format!("{:?}", other);
+            &other_short_name
+        }
+    };
+    format!("{}-{}", interface_short_name, conn_info.interface_id())
+}
+
 fn drm_get_render_node<A: AsRawFd>(fd: &A) -> Option<PathBuf> {
     use smithay::reexports::nix::{
         libc::{major, minor},
@@ -0,0 +1,152 @@
+use smithay::reexports::nix::libc;
+
+use crate::config::NightLight;
+
+/// Temperature at which the gamma ramps are left untouched
+pub const NEUTRAL_TEMPERATURE: u32 = 6500;
+
+/// Temperature the outputs should currently be set to
+pub fn target_temperature(config: &NightLight, enabled: bool) -> u32 {
+    if !enabled {
+        return NEUTRAL_TEMPERATURE;
+    }
+
+    let (now, day_of_year, utc_offset) = local_time();
+    let schedule = match (config.latitude, config.longitude) {
+        (Some(lat), Some(lng)) => sun_time(day_of_year, lat, lng, false)
+            .zip(sun_time(day_of_year, lat, lng, true))
+            .map(|(sunset, sunrise)| {
+                let to_local = |utc: f64| ((utc + utc_offset).rem_euclid(24.0) * 60.0) as u32;
+                (to_local(sunset), to_local(sunrise))
+            }),
+        _ => parse_time(&config.start).zip(parse_time(&config.end)),
+    };
+    let (start, end) = match schedule {
+        Some(schedule) => schedule,
+        // polar day or night, or a broken configuration
+        None => return NEUTRAL_TEMPERATURE,
+    };
+
+    let factor = night_factor(now, start, end, config.transition);
+    let day = NEUTRAL_TEMPERATURE as f64;
+    (day + (config.temperature as f64 - day) * factor) as u32
+}
+
+/// Gamma ramps for the red, green and blue channels of a given size
+pub fn gamma_ramps(temperature: u32, size: usize) -> (Vec<u16>, Vec<u16>, Vec<u16>) {
+    let (r, g, b) = whitepoint(temperature);
+    let ramp = |factor: f64| {
+        (0..size)
+            .map(|i| (i as f64 / (size.max(2) - 1) as f64 * factor * u16::MAX as f64) as u16)
+            .collect::<Vec<_>>()
+    };
+    (ramp(r), ramp(g), ramp(b))
+}
+
+/// Approximates the rgb multipliers of a blackbody of the given temperature
+pub fn whitepoint(temperature: u32) -> (f64, f64, f64) {
+    if temperature >= NEUTRAL_TEMPERATURE {
+        return (1.0, 1.0, 1.0);
+    }
+
+    let t = temperature as f64 / 100.0;
+    let r = if t <= 66.0 {
+        1.0
+    } else {
+        329.698727446 * (t - 60.0).powf(-0.1332047592) / 255.0
+    };
+    let g = if t <= 66.0 {
+        (99.4708025861 * t.ln() - 161.1195681661) / 255.0
+    } else {
+        288.1221695283 * (t - 60.0).powf(-0.0755148492) / 255.0
+    };
+    let b = if t >= 66.0 {
+        1.0
+    } else if t <= 19.0 {
+        0.0
+    } else {
+        (138.5177312231 * (t - 10.0).ln() - 305.0447927307) / 255.0
+    };
+    (r.clamp(0.0, 1.0), g.clamp(0.0, 1.0), b.clamp(0.0, 1.0))
+}
+
+/// How far into the night we are, ramping linearly over `transition` minutes
+fn night_factor(now: u32, start: u32, end: u32, transition: u32) -> f64 {
+    let length = (end as i64 - start as i64).rem_euclid(24 * 60) as f64;
+    let elapsed = (now as i64 - start as i64).rem_euclid(24 * 60) as f64;
+    if elapsed >= length {
+        return 0.0;
+    }
+    if transition == 0 {
+        return 1.0;
+    }
+    let transition = transition as f64;
+    f64::min(1.0, f64::min(elapsed / transition, (length - elapsed) / transition))
+}
+
+/// Parses a time of day formatted as `HH:MM` into minutes since midnight
+fn parse_time(time: &str) -> Option<u32> {
+    let mut split = time.trim().splitn(2, ':');
+    let hours = split.next()?.parse::<u32>().ok()?;
+    let minutes = split.next()?.parse::<u32>().ok()?;
+    if hours >= 24 || minutes >= 60 {
+        slog_scope::warn!("Invalid night light time: {}", time);
+        return None;
+    }
+    Some(hours * 60 + minutes)
+}
+
+/// Minutes since midnight, day of the year and utc offset in hours of the local time
+fn local_time() -> (u32, u32, f64) {
+    unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        let mut tm: libc::tm = std::mem::zeroed();
+        libc::localtime_r(&now, &mut tm);
+        (
+            (tm.tm_hour * 60 + tm.tm_min) as u32,
+            tm.tm_yday as u32 + 1,
+            tm.tm_gmtoff as f64 / 3600.0,
+        )
+    }
+}
+
+/// Time of sunrise or sunset in utc hours, `None` if the sun does not rise or set that day
+fn sun_time(day_of_year: u32, latitude: f64, longitude: f64, rising: bool) -> Option<f64> {
+    // zenith of the sun at sunrise/sunset, including refraction
+    const ZENITH: f64 = 90.833;
+
+    let lng_hour = longitude / 15.0;
+    let t = day_of_year as f64 + ((if rising { 6.0 } else { 18.0 }) - lng_hour) / 24.0;
+
+    let mean_anomaly = 0.9856 * t - 3.289;
+    let true_longitude = (mean_anomaly
+        + 1.916 * mean_anomaly.to_radians().sin()
+        + 0.020 * (2.0 * mean_anomaly).to_radians().sin()
+        + 282.634)
+        .rem_euclid(360.0);
+
+    let mut right_ascension = (0.91764 * true_longitude.to_radians().tan())
+        .atan()
+        .to_degrees()
+        .rem_euclid(360.0);
+    // same quadrant as the true longitude
+    right_ascension += (true_longitude / 90.0).floor() * 90.0 - (right_ascension / 90.0).floor() * 90.0;
+    right_ascension /= 15.0;
+
+    let sin_dec = 0.39782 * true_longitude.to_radians().sin();
+    let cos_dec = sin_dec.asin().cos();
+    let cos_hour = (ZENITH.to_radians().cos() - sin_dec * latitude.to_radians().sin())
+        / (cos_dec * latitude.to_radians().cos());
+    if !(-1.0..=1.0).contains(&cos_hour) {
+        return None;
+    }
+
+    let hour_angle = if rising {
+        360.0 - cos_hour.acos().to_degrees()
+    } else {
+        cos_hour.acos().to_degrees()
+    } / 15.0;
+
+    let local_mean_time = hour_angle + right_ascension - 0.06571 * t - 6.622;
+    Some((local_mean_time - lng_hour).rem_euclid(24.0))
+}
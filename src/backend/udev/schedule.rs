@@ -0,0 +1,146 @@
+use smithay::reexports::{drm::control::crtc, nix::sys::stat::dev_t};
+use std::time::{Duration, Instant};
+
+use crate::state::Fireplace;
+
+/// Used for modes not reporting their refresh rate
+const FALLBACK_REFRESH: Duration = Duration::from_micros(16_667);
+
+/// Where the next frame of a surface stands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameState {
+    /// Nothing changed since the last frame, the next damage schedules one
+    Idle,
+    /// Rendering is scheduled shortly before the next vblank
+    Scheduled,
+    /// A frame was queued and waits for its vblank
+    Submitted,
+}
+
+/// A frame waiting for its vblank, to measure its presentation
+#[derive(Debug, Clone, Copy)]
+pub struct QueuedFrame {
+    /// Vblank the frame was rendered for, unknown before the first vblank
+    pub target: Option<Instant>,
+    /// Input event shown first by this frame
+    pub input: Option<Instant>,
+}
+
+impl QueuedFrame {
+    /// Vblanks passed between the target and the presentation of the frame at `presented`
+    pub fn missed_vblanks(&self, refresh: Duration, presented: Instant) -> u64 {
+        let target = match self.target {
+            Some(target) => target,
+            None => return 0,
+        };
+        // vblank events are delivered a little late, anything closer than half a frame is the target
+        let late = presented.saturating_duration_since(target) + refresh / 2;
+        (late.as_nanos() / refresh.as_nanos().max(1)) as u64
+    }
+}
+
+/// The vblank following `now`, estimated from the last one
+pub fn next_vblank(last_vblank: Option<Instant>, refresh: Duration, now: Instant) -> Option<Instant> {
+    last_vblank.map(|_| now + until_next_vblank(last_vblank, refresh, now))
+}
+
+/// Duration of a single frame at a refresh rate in millihertz
+pub fn refresh_duration(refresh: i32) -> Duration {
+    if refresh <= 0 {
+        FALLBACK_REFRESH
+    } else {
+        Duration::from_secs_f64(1000.0 / refresh as f64)
+    }
+}
+
+/// Time left until the next vblank, estimated from the last one
+fn until_next_vblank(last_vblank: Option<Instant>, refresh: Duration, now: Instant) -> Duration {
+    match last_vblank {
+        Some(last) => {
+            let refresh = refresh.as_nanos().max(1);
+            let since = now.saturating_duration_since(last).as_nanos();
+            Duration::from_nanos((refresh - since % refresh) as u64)
+        }
+        // nothing was shown yet, there is nothing to wait for
+        None => Duration::ZERO,
+    }
+}
+
+impl Fireplace {
+    /// Notes that the contents of the outputs might have changed
+    ///
+    /// Damage is coalesced until the next frame, idle outputs get a frame scheduled.
+    pub fn damage(&mut self) {
+        self.damage = self.damage.wrapping_add(1);
+        let idle = self
+            .udev
+            .iter()
+            .flat_map(|(dev_id, backend)| {
+                backend
+                    .surfaces
+                    .iter()
+                    .filter(|(_, surface)| surface.powered && surface.frame == FrameState::Idle)
+                    .map(move |(crtc, _)| (*dev_id, *crtc))
+            })
+            .collect::<Vec<_>>();
+        for (dev_id, crtc) in idle {
+            self.schedule_frame(dev_id, crtc);
+        }
+    }
+
+    /// Schedules rendering of a surface `max_render_time` before its next vblank,
+    /// if anything needs to be redrawn
+    pub fn schedule_frame(&mut self, dev_id: dev_t, crtc: crtc::Handle) {
+        let needed = self.needs_frame(dev_id, crtc);
+        let render_time = Duration::from_millis(self.config.max_render_time);
+        let surface = match self
+            .udev
+            .get_mut(&dev_id)
+            .and_then(|backend| backend.surfaces.get_mut(&crtc))
+        {
+            Some(surface) => surface,
+            None => return,
+        };
+        if !needed {
+            surface.frame = FrameState::Idle;
+            return;
+        }
+
+        let until = until_next_vblank(surface.last_vblank, surface.refresh, Instant::now());
+        surface
+            .render_timer
+            .add_timeout(until.saturating_sub(render_time), (dev_id, crtc));
+        surface.frame = FrameState::Scheduled;
+    }
+
+    /// Checks for damage since the last frame and anything redrawn continuously
+    fn needs_frame(&self, dev_id: dev_t, crtc: crtc::Handle) -> bool {
+        let backend = match self.udev.get(&dev_id) {
+            Some(backend) => backend,
+            None => return false,
+        };
+        let surface = match backend.surfaces.get(&crtc) {
+            Some(surface) => surface,
+            None => return false,
+        };
+        if surface.rendered != self.damage {
+            return true;
+        }
+
+        let output = surface.mirror.as_ref().unwrap_or(&surface.output);
+        if self.screenshots.iter().any(|screenshot| &screenshot.output == output)
+            || self
+                .screencast
+                .as_ref()
+                .map(|screencast| screencast.is_casting(output))
+                .unwrap_or(false)
+        {
+            return true;
+        }
+        let workspaces = self.workspaces.borrow();
+        match workspaces.visible_spaces(output, &self.config.animations) {
+            Some((space, switch)) => switch.is_some() || backend.cache.animations.is_animating(space.id()),
+            None => false,
+        }
+    }
+}
@@ -0,0 +1,584 @@
+use smithay::{
+    backend::{
+        allocator::{
+            dmabuf::{AsDmabuf, Dmabuf},
+            Buffer, Fourcc, Modifier, Slot, Swapchain,
+        },
+        drm::{DrmError, DrmSurface},
+        egl::EGLContext,
+        SwapBuffersError,
+    },
+    reexports::{
+        drm::{
+            self,
+            control::{
+                atomic::AtomicModeReq, crtc, framebuffer, plane, property, AtomicCommitFlags,
+                Device as ControlDevice,
+            },
+            ffi,
+        },
+        gbm::{BufferObject, BufferObjectFlags, DeviceDestroyedError, Device as GbmDevice},
+    },
+    utils::{Physical, Rectangle},
+};
+
+use std::{collections::HashSet, rc::Rc, sync::Arc};
+
+use super::super::{drm::get_prop, SessionFd};
+use crate::backend::render::{BufferRelease, ScanoutCandidate};
+
+/// Imported client buffers kept around, video players usually cycle through a handful
+const MAX_IMPORTED: usize = 4;
+
+/// Formats tried for 10 bits per channel, in order
+const TEN_BIT_FORMATS: &[Fourcc] = &[Fourcc::Xrgb2101010, Fourcc::Argb2101010];
+
+/// Exported buffer, its framebuffer and the number of the frame last rendered into it
+type Userdata = (Dmabuf, Framebuffer, u64);
+
+/// `struct drm_mode_rect`, the layout of `FB_DAMAGE_CLIPS` blobs
+#[repr(C)]
+struct DamageClip {
+    x1: i32,
+    y1: i32,
+    x2: i32,
+    y2: i32,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum GbmSurfaceError {
+    #[error("No free buffers left in the swapchain")]
+    NoFreeSlots,
+    #[error("The gbm device was destroyed")]
+    DeviceDestroyed(#[from] DeviceDestroyedError),
+    #[error("Failed to allocate a buffer: {0}")]
+    Allocation(#[source] Box<dyn std::error::Error + Send + Sync>),
+    #[error("Failed to create a framebuffer: {0}")]
+    Framebuffer(#[source] drm::SystemError),
+    #[error("Failed to commit the frame: {0}")]
+    Commit(#[source] drm::SystemError),
+    #[error(transparent)]
+    Drm(#[from] DrmError),
+}
+
+impl From<GbmSurfaceError> for SwapBuffersError {
+    fn from(err: GbmSurfaceError) -> SwapBuffersError {
+        match err {
+            GbmSurfaceError::Drm(err) => err.into(),
+            err @ GbmSurfaceError::NoFreeSlots | err @ GbmSurfaceError::Commit(_) => {
+                SwapBuffersError::TemporaryFailure(Box::new(err))
+            }
+            err => SwapBuffersError::ContextLost(Box::new(err)),
+        }
+    }
+}
+
+/// Framebuffer of a buffer object, removed from the device once dropped
+struct Framebuffer {
+    drm: Arc<DrmSurface<SessionFd>>,
+    handle: framebuffer::Handle,
+}
+
+impl Drop for Framebuffer {
+    fn drop(&mut self) {
+        let _ = self.drm.destroy_framebuffer(self.handle);
+    }
+}
+
+/// Client buffer imported for scanout
+pub struct ClientFramebuffer {
+    dmabuf: Dmabuf,
+    fb: Framebuffer,
+    _bo: BufferObject<()>,
+}
+
+/// A client buffer assigned to a plane
+pub struct Scanout {
+    framebuffer: Rc<ClientFramebuffer>,
+    /// Keeps the client from reusing the buffer while it is on screen
+    _release: Rc<BufferRelease>,
+    geometry: Rectangle<i32, Physical>,
+}
+
+/// Contents of the primary plane
+enum Contents {
+    Rendered(Slot<BufferObject<()>, Userdata>),
+    Client(Scanout),
+}
+
+struct Frame {
+    primary: Contents,
+    overlay: Option<Scanout>,
+    /// Part of the primary plane changed since the last frame, `None` if unknown
+    damage: Option<Rectangle<i32, Physical>>,
+}
+
+impl Frame {
+    fn primary_framebuffer(&self) -> framebuffer::Handle {
+        match &self.primary {
+            Contents::Rendered(slot) => slot.userdata().as_ref().unwrap().1.handle,
+            Contents::Client(scanout) => scanout.framebuffer.fb.handle,
+        }
+    }
+}
+
+/// Properties of a plane necessary to position a buffer on it
+struct PlaneProps {
+    fb_id: property::Handle,
+    crtc_id: property::Handle,
+    src_x: property::Handle,
+    src_y: property::Handle,
+    src_w: property::Handle,
+    src_h: property::Handle,
+    crtc_x: property::Handle,
+    crtc_y: property::Handle,
+    crtc_w: property::Handle,
+    crtc_h: property::Handle,
+}
+
+impl PlaneProps {
+    fn new(drm: &DrmSurface<SessionFd>, plane: plane::Handle) -> anyhow::Result<PlaneProps> {
+        Ok(PlaneProps {
+            fb_id: get_prop(drm, plane, "FB_ID")?,
+            crtc_id: get_prop(drm, plane, "CRTC_ID")?,
+            src_x: get_prop(drm, plane, "SRC_X")?,
+            src_y: get_prop(drm, plane, "SRC_Y")?,
+            src_w: get_prop(drm, plane, "SRC_W")?,
+            src_h: get_prop(drm, plane, "SRC_H")?,
+            crtc_x: get_prop(drm, plane, "CRTC_X")?,
+            crtc_y: get_prop(drm, plane, "CRTC_Y")?,
+            crtc_w: get_prop(drm, plane, "CRTC_W")?,
+            crtc_h: get_prop(drm, plane, "CRTC_H")?,
+        })
+    }
+}
+
+struct Overlay {
+    plane: plane::Handle,
+    formats: HashSet<u32>,
+    props: PlaneProps,
+}
+
+/// Gbm backed surface, able to present client buffers on the planes of its crtc
+///
+/// Replaces smithay's `GbmBufferedSurface`, which is limited to frames rendered by us.
+pub struct GbmSurface {
+    drm: Arc<DrmSurface<SessionFd>>,
+    gbm: GbmDevice<SessionFd>,
+    swapchain: Swapchain<GbmDevice<SessionFd>, BufferObject<()>, Userdata>,
+    next: Option<Slot<BufferObject<()>, Userdata>>,
+    queued: Option<Frame>,
+    pending: Option<Frame>,
+    current: Option<Frame>,
+    /// Primary plane `FB_ID`, only known to atomic devices
+    primary_fb: Option<property::Handle>,
+    /// Primary plane `FB_DAMAGE_CLIPS`, if the driver makes use of damage
+    damage_clips: Option<property::Handle>,
+    /// Rendered frames queued so far, to derive the age of buffers
+    frames: u64,
+    overlay: Option<Overlay>,
+    imported: Vec<Rc<ClientFramebuffer>>,
+}
+
+impl GbmSurface {
+    pub fn new(
+        drm: DrmSurface<SessionFd>,
+        fd: SessionFd,
+        ctx: &EGLContext,
+        atomic: bool,
+        overlay: Option<plane::Handle>,
+        bit_depth: u32,
+    ) -> anyhow::Result<GbmSurface> {
+        let drm = Arc::new(drm);
+        let plane_formats = drm.supported_formats(drm.plane())?;
+        let modifiers_of = |code: Fourcc| {
+            ctx.dmabuf_render_formats()
+                .iter()
+                .filter(|format| format.code == code && plane_formats.contains(format))
+                .map(|format| format.modifier)
+                .collect::<Vec<_>>()
+        };
+        let code = if bit_depth == 10 {
+            match TEN_BIT_FORMATS.iter().copied().find(|code| !modifiers_of(*code).is_empty()) {
+                Some(code) => code,
+                None => {
+                    slog_scope::warn!("No 10-bit format can be rendered and scanned out, using 8 bits");
+                    Fourcc::Argb8888
+                }
+            }
+        } else {
+            Fourcc::Argb8888
+        };
+        let mut modifiers = modifiers_of(code);
+        if modifiers.is_empty() {
+            modifiers.push(Modifier::Invalid);
+        }
+
+        let (w, h) = drm.pending_mode().size();
+        let swapchain = Swapchain::new(GbmDevice::new(fd.clone())?, w as u32, h as u32, code, modifiers);
+
+        let primary_fb = if atomic {
+            Some(get_prop(&*drm, drm.plane(), "FB_ID")?)
+        } else {
+            None
+        };
+        let damage_clips = if atomic {
+            get_prop(&*drm, drm.plane(), "FB_DAMAGE_CLIPS").ok()
+        } else {
+            None
+        };
+        let overlay = match overlay.filter(|_| atomic) {
+            Some(plane) => {
+                let formats = drm.get_plane(plane)?.formats().iter().copied().collect();
+                match PlaneProps::new(&drm, plane) {
+                    Ok(props) => Some(Overlay { plane, formats, props }),
+                    Err(err) => {
+                        slog_scope::debug!("Unable to use overlay plane {:?}: {}", plane, err);
+                        None
+                    }
+                }
+            }
+            None => None,
+        };
+
+        Ok(GbmSurface {
+            gbm: GbmDevice::new(fd)?,
+            drm,
+            swapchain,
+            next: None,
+            queued: None,
+            pending: None,
+            current: None,
+            primary_fb,
+            damage_clips,
+            frames: 0,
+            overlay,
+            imported: Vec::new(),
+        })
+    }
+
+    pub fn crtc(&self) -> crtc::Handle {
+        self.drm.crtc()
+    }
+
+    pub fn overlay_plane(&self) -> Option<plane::Handle> {
+        self.overlay.as_ref().map(|overlay| overlay.plane)
+    }
+
+    /// Buffer to render the next frame into and its age
+    ///
+    /// The age is the number of frames queued since the buffer was last rendered into, 0 for new buffers.
+    pub fn next_buffer(&mut self) -> Result<(Dmabuf, usize), GbmSurfaceError> {
+        if self.next.is_none() {
+            let slot = self
+                .swapchain
+                .acquire()
+                .map_err(|err| GbmSurfaceError::Allocation(err.into()))?
+                .ok_or(GbmSurfaceError::NoFreeSlots)?;
+            if slot.userdata().is_none() {
+                let dmabuf = slot.export().map_err(|err| GbmSurfaceError::Allocation(err.into()))?;
+                let fb = add_framebuffer(&self.drm, &*slot)?;
+                *slot.userdata() = Some((dmabuf, fb, 0));
+            }
+            self.next = Some(slot);
+        }
+        let userdata = self.next.as_ref().unwrap().userdata();
+        let (dmabuf, _, rendered) = userdata.as_ref().unwrap();
+        let age = if *rendered == 0 { 0 } else { (self.frames + 1 - rendered) as usize };
+        Ok((dmabuf.clone(), age))
+    }
+
+    /// Presents the rendered buffer, optionally together with a client buffer on the overlay plane
+    ///
+    /// `damage` is the part of the buffer redrawn, passed on to drivers making use of it.
+    pub fn queue_buffer(
+        &mut self,
+        overlay: Option<Scanout>,
+        damage: Option<Rectangle<i32, Physical>>,
+    ) -> Result<(), GbmSurfaceError> {
+        if let Some(slot) = self.next.take() {
+            self.frames += 1;
+            slot.userdata().as_mut().unwrap().2 = self.frames;
+            self.queued = Some(Frame {
+                primary: Contents::Rendered(slot),
+                overlay,
+                damage,
+            });
+        }
+        if self.pending.is_none() && self.queued.is_some() {
+            self.submit()?;
+        }
+        Ok(())
+    }
+
+    /// Presents a client buffer on the primary plane instead of a rendered frame
+    pub fn queue_scanout(&mut self, scanout: Scanout) -> Result<(), GbmSurfaceError> {
+        self.queued = Some(Frame {
+            primary: Contents::Client(scanout),
+            overlay: None,
+            damage: None,
+        });
+        if self.pending.is_none() {
+            self.submit()?;
+        }
+        Ok(())
+    }
+
+    /// The last submitted frame is on screen
+    pub fn frame_submitted(&mut self) -> Result<(), GbmSurfaceError> {
+        if let Some(pending) = self.pending.take() {
+            // buffers of the previous frame go back to the swapchain or their clients
+            self.current = Some(pending);
+            if self.queued.is_some() {
+                self.submit()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Prepares presenting a client buffer on the overlay plane, if the driver accepts it
+    pub fn try_overlay(&mut self, candidate: &ScanoutCandidate) -> Option<Scanout> {
+        let overlay = self.overlay.as_ref()?;
+        if self.drm.commit_pending() || !overlay.formats.contains(&(candidate.dmabuf.format().code as u32)) {
+            return None;
+        }
+        let (w, h) = self.drm.pending_mode().size();
+        let geometry = candidate.geometry;
+        if geometry.loc.x < 0
+            || geometry.loc.y < 0
+            || geometry.loc.x + geometry.size.w > w as i32
+            || geometry.loc.y + geometry.size.h > h as i32
+        {
+            return None;
+        }
+        let primary = self.current.as_ref()?.primary_framebuffer();
+
+        let framebuffer = match self.import(&candidate.dmabuf) {
+            Ok(framebuffer) => framebuffer,
+            Err(err) => {
+                slog_scope::debug!("Unable to import client buffer for scanout: {}", err);
+                return None;
+            }
+        };
+        let scanout = Scanout {
+            framebuffer,
+            _release: candidate.release.clone(),
+            geometry,
+        };
+        let req = self.request(primary, Some(&scanout), None)?;
+        match self.drm.atomic_commit(&[AtomicCommitFlags::TestOnly], req) {
+            Ok(()) => Some(scanout),
+            Err(err) => {
+                slog_scope::trace!("Overlay plane rejected client buffer: {}", err);
+                None
+            }
+        }
+    }
+
+    /// Prepares scanning out a client buffer covering the whole crtc, if the driver accepts it
+    pub fn try_scanout(&mut self, candidate: &ScanoutCandidate) -> Option<Scanout> {
+        if self.drm.commit_pending() {
+            return None;
+        }
+        let mode = self.drm.pending_mode();
+        let (w, h) = mode.size();
+        let dmabuf = &candidate.dmabuf;
+        if candidate.geometry != Rectangle::from_loc_and_size((0, 0), (w as i32, h as i32))
+            || dmabuf.width() != w as u32
+            || dmabuf.height() != h as u32
+        {
+            return None;
+        }
+
+        let framebuffer = match self.import(dmabuf) {
+            Ok(framebuffer) => framebuffer,
+            Err(err) => {
+                slog_scope::debug!("Unable to import client buffer for scanout: {}", err);
+                return None;
+            }
+        };
+        match self.drm.test_buffer(framebuffer.fb.handle, &mode, false) {
+            Ok(true) => Some(Scanout {
+                framebuffer,
+                _release: candidate.release.clone(),
+                geometry: candidate.geometry,
+            }),
+            Ok(false) => None,
+            Err(err) => {
+                slog_scope::trace!("Primary plane rejected client buffer: {}", err);
+                None
+            }
+        }
+    }
+
+    fn import(&mut self, dmabuf: &Dmabuf) -> Result<Rc<ClientFramebuffer>, GbmSurfaceError> {
+        if let Some(imported) = self.imported.iter().find(|imported| &imported.dmabuf == dmabuf) {
+            return Ok(imported.clone());
+        }
+
+        let mut fds = [0; 4];
+        let mut strides = [0; 4];
+        let mut offsets = [0; 4];
+        for (i, ((fd, stride), offset)) in dmabuf
+            .handles()
+            .zip(dmabuf.strides())
+            .zip(dmabuf.offsets())
+            .enumerate()
+            .take(4)
+        {
+            fds[i] = fd;
+            strides[i] = stride as i32;
+            offsets[i] = offset as i32;
+        }
+        let format = dmabuf.format();
+        let bo = self
+            .gbm
+            .import_buffer_object_from_dma_buf_with_modifiers::<()>(
+                dmabuf.num_planes() as u32,
+                fds,
+                dmabuf.width(),
+                dmabuf.height(),
+                format.code,
+                BufferObjectFlags::SCANOUT,
+                strides,
+                offsets,
+                format.modifier,
+            )
+            .map_err(|err| GbmSurfaceError::Allocation(err.into()))?;
+        let fb = add_framebuffer(&self.drm, &bo)?;
+
+        let imported = Rc::new(ClientFramebuffer {
+            dmabuf: dmabuf.clone(),
+            fb,
+            _bo: bo,
+        });
+        self.imported.insert(0, imported.clone());
+        self.imported.truncate(MAX_IMPORTED);
+        Ok(imported)
+    }
+
+    /// Atomic request flipping the primary plane and setting up or disabling the overlay plane, if there is one
+    fn request(
+        &self,
+        primary: framebuffer::Handle,
+        overlay: Option<&Scanout>,
+        damage: Option<property::Value<'static>>,
+    ) -> Option<AtomicModeReq> {
+        let mut req = AtomicModeReq::new();
+        req.add_property(
+            self.drm.plane(),
+            self.primary_fb?,
+            property::Value::Framebuffer(Some(primary)),
+        );
+        if let (Some(clips), Some(damage)) = (self.damage_clips, damage) {
+            req.add_property(self.drm.plane(), clips, damage);
+        }
+        let (plane, props) = match self.overlay.as_ref() {
+            Some(Overlay { plane, props, .. }) => (*plane, props),
+            None if overlay.is_some() => return None,
+            None => return Some(req),
+        };
+        match overlay {
+            Some(scanout) => {
+                let dmabuf = &scanout.framebuffer.dmabuf;
+                let geometry = scanout.geometry;
+                req.add_property(plane, props.fb_id, property::Value::Framebuffer(Some(scanout.framebuffer.fb.handle)));
+                req.add_property(plane, props.crtc_id, property::Value::CRTC(Some(self.drm.crtc())));
+                // source coordinates are 16.16 fixed point
+                req.add_property(plane, props.src_x, property::Value::UnsignedRange(0));
+                req.add_property(plane, props.src_y, property::Value::UnsignedRange(0));
+                req.add_property(plane, props.src_w, property::Value::UnsignedRange((dmabuf.width() as u64) << 16));
+                req.add_property(plane, props.src_h, property::Value::UnsignedRange((dmabuf.height() as u64) << 16));
+                req.add_property(plane, props.crtc_x, property::Value::SignedRange(geometry.loc.x as i64));
+                req.add_property(plane, props.crtc_y, property::Value::SignedRange(geometry.loc.y as i64));
+                req.add_property(plane, props.crtc_w, property::Value::UnsignedRange(geometry.size.w as u64));
+                req.add_property(plane, props.crtc_h, property::Value::UnsignedRange(geometry.size.h as u64));
+            }
+            None => {
+                req.add_property(plane, props.fb_id, property::Value::Framebuffer(None));
+                req.add_property(plane, props.crtc_id, property::Value::CRTC(None));
+            }
+        }
+        Some(req)
+    }
+
+    fn submit(&mut self) -> Result<(), GbmSurfaceError> {
+        let frame = self.queued.take().unwrap();
+        let primary = frame.primary_framebuffer();
+        let overlay_shown = self.current.as_ref().map(|frame| frame.overlay.is_some()).unwrap_or(false);
+
+        // smithay only flips the primary plane, the overlay and damage need a request of our own
+        let damage = match frame.damage {
+            Some(area) if self.damage_clips.is_some() && !self.drm.commit_pending() => {
+                let clip = DamageClip {
+                    x1: area.loc.x,
+                    y1: area.loc.y,
+                    x2: area.loc.x + area.size.w,
+                    y2: area.loc.y + area.size.h,
+                };
+                match self.drm.create_property_blob(&clip) {
+                    Ok(blob) => Some(blob),
+                    Err(err) => {
+                        slog_scope::trace!("Failed to create damage clips: {}", err);
+                        None
+                    }
+                }
+            }
+            _ => None,
+        };
+        let req = if frame.overlay.is_some() || overlay_shown || damage.is_some() {
+            self.request(primary, frame.overlay.as_ref(), damage)
+        } else {
+            None
+        };
+        if let Some(req) = req {
+            let result = self
+                .drm
+                .atomic_commit(&[AtomicCommitFlags::PageFlipEvent, AtomicCommitFlags::Nonblock], req);
+            // the commit holds its own reference of the blob
+            if let Some(property::Value::Blob(blob)) = damage {
+                let _ = self.drm.destroy_property_blob(blob);
+            }
+            result.map_err(|err| match err {
+                drm::SystemError::PermissionDenied => GbmSurfaceError::Drm(DrmError::DeviceInactive),
+                err => GbmSurfaceError::Commit(err),
+            })?;
+        } else if self.drm.commit_pending() {
+            self.drm.commit([&(primary, self.drm.plane())].iter().cloned(), true)?;
+        } else {
+            self.drm.page_flip([&(primary, self.drm.plane())].iter().cloned(), true)?;
+        }
+        self.pending = Some(frame);
+        Ok(())
+    }
+}
+
+/// Adds a framebuffer for a buffer object, falling back to the legacy call without modifiers
+fn add_framebuffer(drm: &Arc<DrmSurface<SessionFd>>, bo: &BufferObject<()>) -> Result<Framebuffer, GbmSurfaceError> {
+    let modifier = match bo.modifier()? {
+        Modifier::Invalid => None,
+        modifier => Some(modifier),
+    };
+    let planes = bo.plane_count()? as usize;
+    let mut modifiers = [None; 4];
+    for plane_modifier in modifiers.iter_mut().take(planes) {
+        *plane_modifier = modifier;
+    }
+    let flags = if modifier.is_some() { ffi::DRM_MODE_FB_MODIFIERS } else { 0 };
+
+    let handle = match drm.add_planar_framebuffer(bo, &modifiers, flags) {
+        Ok(handle) => handle,
+        // only possible for single plane rgb formats
+        Err(_) if matches!(bo.format()?, Fourcc::Argb8888 | Fourcc::Xrgb8888) => {
+            drm.add_framebuffer(bo, 24, 32).map_err(GbmSurfaceError::Framebuffer)?
+        }
+        Err(_) if matches!(bo.format()?, Fourcc::Argb2101010 | Fourcc::Xrgb2101010) => {
+            drm.add_framebuffer(bo, 30, 32).map_err(GbmSurfaceError::Framebuffer)?
+        }
+        Err(err) => return Err(GbmSurfaceError::Framebuffer(err)),
+    };
+    Ok(Framebuffer {
+        drm: drm.clone(),
+        handle,
+    })
+}
@@ -1,15 +1,13 @@
 use smithay::{
     backend::{
         allocator::dmabuf::Dmabuf,
-        drm::{DrmError, DrmSurface, GbmBufferedSurface, GbmBufferedSurfaceError::DrmError as GbmDrmError},
+        drm::DrmSurface,
         egl::{EGLDisplay, EGLContext, surface::EGLSurface},
         renderer::{Bind, Renderer, Transform},
         SwapBuffersError
     },
-    reexports::{
-        drm::control::crtc,
-        gbm::{Device as GbmDevice},
-    },
+    reexports::drm::control::{crtc, plane},
+    utils::{Physical, Rectangle},
 };
 
 use std::{
@@ -18,18 +16,30 @@ use std::{
 
 mod eglstream;
 pub use self::eglstream::*;
+mod gbm;
+pub use self::gbm::{GbmSurface, GbmSurfaceError, Scanout};
+mod software;
+pub use self::software::SoftwareSurface;
 use super::SessionFd;
+use crate::backend::render::ScanoutCandidate;
 
 pub enum RenderSurface {
-    Gbm(GbmBufferedSurface<SessionFd>),
+    Gbm(GbmSurface),
+    /// Eglstream or software rendered surface, both are presented by swapping
     Egl(Rc<EGLSurface>, crtc::Handle),
 }
 use RenderSurface::*;
 
 impl RenderSurface {
-    pub fn new_gbm(surf: DrmSurface<SessionFd>, fd: SessionFd, ctx: &EGLContext) -> anyhow::Result<RenderSurface> {
-        let gbm_device = GbmDevice::new(fd)?;
-        let gbm_surface = GbmBufferedSurface::new(surf, gbm_device, ctx.dmabuf_render_formats().clone(), None)?;
+    pub fn new_gbm(
+        surf: DrmSurface<SessionFd>,
+        fd: SessionFd,
+        ctx: &EGLContext,
+        atomic: bool,
+        overlay: Option<plane::Handle>,
+        bit_depth: u32,
+    ) -> anyhow::Result<RenderSurface> {
+        let gbm_surface = GbmSurface::new(surf, fd, ctx, atomic, overlay, bit_depth)?;
         Ok(RenderSurface::Gbm(gbm_surface))
     }
 
@@ -46,25 +56,81 @@ impl RenderSurface {
         Ok(RenderSurface::Egl(egl_surface, crtc))
     }
 
-    pub fn bind<B: Bind<Dmabuf> + Bind<Rc<EGLSurface>>>(&mut self, renderer: &mut B) -> Result<(), B::Error> {
+    pub fn new_software(surf: DrmSurface<SessionFd>, disp: &EGLDisplay, ctx: &EGLContext) -> anyhow::Result<RenderSurface> {
+        let software_surface = SoftwareSurface::new(surf)?;
+        let crtc = software_surface.crtc();
+        let egl_surface = Rc::new(EGLSurface::new(
+                &disp,
+                ctx.pixel_format().unwrap(),
+                ctx.config_id(),
+                software_surface,
+                None,
+            )?);
+        Ok(RenderSurface::Egl(egl_surface, crtc))
+    }
+
+    /// Binds the buffer of the next frame and returns its age, see `GbmSurface::next_buffer`
+    pub fn bind<B: Bind<Dmabuf> + Bind<Rc<EGLSurface>>>(&mut self, renderer: &mut B) -> anyhow::Result<usize>
+    where
+        B::Error: Send + Sync + 'static,
+    {
         match self {
             Gbm(surf) => {
-                let dmabuf = surf.next_buffer().unwrap();
-                renderer.bind(dmabuf)
+                let (dmabuf, age) = surf.next_buffer()?;
+                renderer.bind(dmabuf)?;
+                Ok(age)
             },
             Egl(surf, _) => {
-                renderer.bind(surf.clone())
+                renderer.bind(surf.clone())?;
+                // eglstreams and pbuffers do not report the age of their buffers, every frame is drawn completely
+                Ok(0)
             },
         }
     }
 
-    pub fn queue_buffer<B, E>(&mut self, renderer: &mut B) -> Result<(), SwapBuffersError>
+    /// Prepares presenting a client buffer on an overlay plane, only gbm surfaces have access to planes
+    pub fn try_overlay(&mut self, candidate: &ScanoutCandidate) -> Option<Scanout> {
+        match self {
+            Gbm(surf) => surf.try_overlay(candidate),
+            Egl(_, _) => None,
+        }
+    }
+
+    /// Prepares scanning out a fullscreen client buffer directly
+    pub fn try_scanout(&mut self, candidate: &ScanoutCandidate) -> Option<Scanout> {
+        match self {
+            Gbm(surf) => surf.try_scanout(candidate),
+            Egl(_, _) => None,
+        }
+    }
+
+    pub fn queue_scanout(&mut self, scanout: Scanout) -> Result<(), SwapBuffersError> {
+        match self {
+            Gbm(surf) => surf.queue_scanout(scanout).map_err(Into::into),
+            // eglstream surfaces never hand out scanouts
+            Egl(_, _) => unreachable!(),
+        }
+    }
+
+    pub fn overlay_plane(&self) -> Option<plane::Handle> {
+        match self {
+            Gbm(surf) => surf.overlay_plane(),
+            Egl(_, _) => None,
+        }
+    }
+
+    pub fn queue_buffer<B, E>(
+        &mut self,
+        renderer: &mut B,
+        overlay: Option<Scanout>,
+        damage: Option<Rectangle<i32, Physical>>,
+    ) -> Result<(), SwapBuffersError>
     where
         B: Bind<Rc<EGLSurface>> + Renderer<Error=E>,
         E: Into<SwapBuffersError> + std::error::Error,
     {
         match self {
-            Gbm(surf) => { surf.queue_buffer().map_err(Into::into) },
+            Gbm(surf) => { surf.queue_buffer(overlay, damage).map_err(Into::into) },
             Egl(surf, _) => {
                 renderer.bind(surf.clone()).map_err(Into::into)?;
                 renderer.render((0, 0).into(), smithay::backend::renderer::Transform::Normal, |_,_| {}).map_err(Into::into)?;
@@ -73,10 +139,9 @@ impl RenderSurface {
         }
     }
 
-    pub fn frame_submitted(&mut self) -> Result<(), DrmError> {
+    pub fn frame_submitted(&mut self) -> Result<(), GbmSurfaceError> {
         match self {
-            // yeah, its a hack, i'll fix it later
-            Gbm(surf) => surf.frame_submitted().map_err(|e| match e { GbmDrmError(e) => e, _ => unreachable!() }),
+            Gbm(surf) => surf.frame_submitted(),
             _ => Ok(()), // we do not need to release frames for Eglstreams
         }
     }
@@ -0,0 +1,139 @@
+use smithay::backend::{
+    drm::DrmSurface,
+    egl::{display::EGLDisplayHandle, native::EGLNativeSurface, EGLError, SwapBuffersError},
+    renderer::gles2::ffi as gl,
+};
+use smithay::reexports::{
+    drm::{
+        buffer::{Buffer, DrmFourcc},
+        control::{dumbbuffer::DumbBuffer, framebuffer, Device as ControlDevice},
+    },
+    nix::libc::c_void,
+};
+
+use std::{
+    cell::{Cell, RefCell},
+    sync::Arc,
+};
+
+use super::super::SessionFd as Fd;
+use crate::backend::{egl as ffi, software::create_pbuffer};
+
+/// Output of a gpu rendered on the cpu
+///
+/// Frames are rendered into a pbuffer of the software EGL device and copied into
+/// dumb buffers, which every kms driver is able to scan out.
+pub struct SoftwareSurface {
+    drm: DrmSurface<Fd>,
+    gl: gl::Gles2,
+    size: (i32, i32),
+    /// Two buffers, the one not on screen is written next
+    buffers: RefCell<Vec<(DumbBuffer, framebuffer::Handle)>>,
+    next: Cell<usize>,
+    /// Rows read back from the pbuffer
+    pixels: RefCell<Vec<u8>>,
+}
+
+impl Drop for SoftwareSurface {
+    fn drop(&mut self) {
+        for (buffer, fb) in self.buffers.borrow_mut().drain(..) {
+            let _ = self.drm.destroy_framebuffer(fb);
+            let _ = self.drm.destroy_dumb_buffer(buffer);
+        }
+    }
+}
+
+impl SoftwareSurface {
+    pub fn new(drm: DrmSurface<Fd>) -> anyhow::Result<SoftwareSurface> {
+        let (w, h) = drm.pending_mode().size();
+        let mut buffers = Vec::with_capacity(2);
+        for _ in 0..2 {
+            let buffer = drm.create_dumb_buffer((w as u32, h as u32), DrmFourcc::Xrgb8888, 32)?;
+            let fb = match drm.add_framebuffer(&buffer, 24, 32) {
+                Ok(fb) => fb,
+                Err(err) => {
+                    let _ = drm.destroy_dumb_buffer(buffer);
+                    return Err(err.into());
+                }
+            };
+            buffers.push((buffer, fb));
+        }
+        let gl = gl::Gles2::load_with(|sym| unsafe { smithay::backend::egl::get_proc_address(sym) });
+        Ok(SoftwareSurface {
+            drm,
+            gl,
+            size: (w as i32, h as i32),
+            buffers: RefCell::new(buffers),
+            next: Cell::new(0),
+            pixels: RefCell::new(Vec::new()),
+        })
+    }
+
+    pub fn crtc(&self) -> smithay::reexports::drm::control::crtc::Handle {
+        self.drm.crtc()
+    }
+}
+
+// HACK: like the eglstream surface, we are single threaded anyway.
+unsafe impl Send for SoftwareSurface {}
+unsafe impl Sync for SoftwareSurface {}
+
+unsafe impl EGLNativeSurface for SoftwareSurface {
+    fn create(
+        &self,
+        display: &Arc<EGLDisplayHandle>,
+        config_id: ffi::types::EGLConfig,
+    ) -> Result<*const c_void, EGLError> {
+        create_pbuffer(display, config_id, self.size)
+    }
+
+    fn needs_recreation(&self) -> bool {
+        false
+    }
+
+    fn resize(&self, _width: i32, _height: i32, _dx: i32, _dy: i32) -> bool {
+        // modes are changed by creating a new surface
+        false
+    }
+
+    fn swap_buffers(
+        &self,
+        _display: &Arc<EGLDisplayHandle>,
+        _surface: ffi::types::EGLSurface,
+    ) -> Result<(), SwapBuffersError> {
+        let (w, h) = self.size;
+        let mut pixels = self.pixels.borrow_mut();
+        pixels.resize((w * h * 4) as usize, 0);
+        // the pbuffer is still current from rendering the frame
+        unsafe {
+            self.gl.ReadPixels(0, 0, w, h, gl::RGBA, gl::UNSIGNED_BYTE, pixels.as_mut_ptr() as *mut _);
+        }
+
+        let mut buffers = self.buffers.borrow_mut();
+        let (buffer, fb) = &mut buffers[self.next.get()];
+        let pitch = buffer.pitch() as usize;
+        {
+            let mut mapping = self
+                .drm
+                .map_dumb_buffer(buffer)
+                .map_err(|err| SwapBuffersError::ContextLost(Box::new(err)))?;
+            let target = mapping.as_mut();
+            // gl rows start at the bottom, little endian XRGB8888 is stored as BGRX
+            for (y, row) in pixels.chunks_exact(w as usize * 4).rev().enumerate() {
+                for (x, rgba) in row.chunks_exact(4).enumerate() {
+                    let offset = y * pitch + x * 4;
+                    target[offset..offset + 4].copy_from_slice(&[rgba[2], rgba[1], rgba[0], 0xff]);
+                }
+            }
+        }
+
+        let planes = [(*fb, self.drm.plane())];
+        if self.drm.commit_pending() {
+            self.drm.commit(planes.iter(), true)?;
+        } else {
+            self.drm.page_flip(planes.iter(), true)?;
+        }
+        self.next.set((self.next.get() + 1) % buffers.len());
+        Ok(())
+    }
+}
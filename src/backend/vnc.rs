@@ -0,0 +1,660 @@
+use crate::{
+    backend::{
+        render::{draw_dnd_icons, render_lock, render_space, CpuAccess},
+        screenshot::{save, take_pending},
+        udev::DevId,
+    },
+    config::VncConfig,
+    handler::ActiveOutput,
+    state::Fireplace,
+};
+use anyhow::{Context, Result};
+use image::imageops;
+use smithay::{
+    backend::{
+        allocator::{dmabuf::{AsDmabuf, Dmabuf}, Fourcc},
+        input::KeyState,
+        renderer::{Bind, Renderer, Transform},
+    },
+    reexports::{
+        calloop::{channel, timer::Timer, EventLoop},
+        gbm::{BufferObjectFlags, Device as GbmDevice},
+        nix::sys::stat::dev_t,
+        wayland_server::protocol::wl_output::Subpixel,
+        wayland_server::protocol::wl_pointer,
+    },
+    utils::{Physical, Size},
+    wayland::{
+        output::{Mode, PhysicalProperties},
+        seat::{AxisFrame, FilterResult},
+        SERIAL_COUNTER as SCOUNTER,
+    },
+};
+use std::{
+    collections::HashMap,
+    io::{self, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use xkbcommon::xkb;
+
+/// Interval frames are rendered in for remote clients
+const FRAME_INTERVAL: Duration = Duration::from_millis(33);
+
+/// Input of remote clients
+pub enum VncEvent {
+    Motion { x: u16, y: u16 },
+    Button { button: u32, pressed: bool },
+    Scroll { steps: i32 },
+    Key { keysym: u32, pressed: bool },
+}
+
+/// Latest frame, shared with the threads serving the clients
+#[derive(Default)]
+struct SharedFrame {
+    generation: u64,
+    /// RGBA, top to bottom
+    pixels: Vec<u8>,
+}
+
+struct VncOutput {
+    name: String,
+    size: Size<i32, Physical>,
+    buffer: Dmabuf,
+    frame: Arc<Mutex<SharedFrame>>,
+}
+
+/// Serves a virtual output over the RFB (VNC) protocol, if configured
+///
+/// Frames are rendered on the first gpu, so the udev backend has to be initialized first.
+pub fn init_vnc(event_loop: &mut EventLoop<'static, Fireplace>, state: &mut Fireplace) -> Result<()> {
+    let config = match state.config.vnc.clone() {
+        Some(config) => config,
+        None => return Ok(()),
+    };
+    let name = String::from("VNC-1");
+    let size: Size<i32, Physical> = (config.width as i32, config.height as i32).into();
+
+    let dev_id = *state.udev.keys().next().context("The vnc output requires a gpu")?;
+    let gbm = GbmDevice::new(state.udev[&dev_id].fd.clone())?;
+    let buffer = gbm
+        .create_buffer_object::<()>(config.width, config.height, Fourcc::Abgr8888, BufferObjectFlags::RENDERING)?
+        .export()?;
+
+    state.workspaces.borrow_mut().add_output(
+        &name,
+        PhysicalProperties {
+            size: (0, 0).into(),
+            subpixel: Subpixel::Unknown,
+            make: String::from("VNC"),
+            model: String::from("Remote"),
+        },
+        Mode {
+            size,
+            refresh: (1000 * 1000 / FRAME_INTERVAL.as_millis()) as i32,
+        },
+    );
+
+    let frame = Arc::new(Mutex::new(SharedFrame::default()));
+    let (sender, events) = channel::channel();
+    spawn_server(&config, name.clone(), size, frame.clone(), sender)?;
+
+    let keycodes = keycodes(state);
+    let input_name = name.clone();
+    let input_token = event_loop
+        .handle()
+        .insert_source(events, move |event, _, state| {
+            if let channel::Event::Msg(event) = event {
+                state.guard("vnc", |state| state.process_vnc_event(&input_name, &keycodes, event));
+            }
+        })
+        .map_err(|_| anyhow::anyhow!("Failed to register vnc input on the event loop"))?;
+
+    let timer = Timer::new()?;
+    timer.handle().add_timeout(FRAME_INTERVAL, VncOutput { name, size, buffer, frame });
+    let render_token = event_loop
+        .handle()
+        .insert_source(timer, move |mut output, handle, state| {
+            if let Some(Err(err)) = state.guard("vnc", |state| state.render_vnc(dev_id, &mut output)) {
+                slog_scope::error!("Error rendering {}: {}", output.name, err);
+            }
+            handle.add_timeout(FRAME_INTERVAL, output);
+        })
+        .map_err(|_| anyhow::anyhow!("Failed to init eventloop timer for vnc"))?;
+
+    state.tokens.push(input_token);
+    state.tokens.push(render_token);
+
+    Ok(())
+}
+
+/// Maps keysyms to the keycodes producing them with the configured keymap
+///
+/// Remote clients send keysyms, while the seat expects keycodes.
+fn keycodes(state: &Fireplace) -> HashMap<u32, u32> {
+    let keyboard = &state.config.input.keyboard;
+    let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+    let keymap = match xkb::Keymap::new_from_names(
+        &context,
+        &keyboard.rules,
+        &keyboard.model,
+        &keyboard.layout,
+        &keyboard.variant,
+        keyboard.options.clone(),
+        xkb::KEYMAP_COMPILE_NO_FLAGS,
+    ) {
+        Some(keymap) => keymap,
+        None => {
+            slog_scope::warn!("Failed to compile keymap, remote key presses will be ignored");
+            return HashMap::new();
+        }
+    };
+
+    let mut keycodes = HashMap::new();
+    for keycode in keymap.min_keycode()..=keymap.max_keycode() {
+        for level in 0..keymap.num_levels_for_key(keycode, 0) {
+            for keysym in keymap.key_get_syms_by_level(keycode, 0, level) {
+                // evdev keycodes are offset by 8 in xkb
+                keycodes.entry(*keysym).or_insert(keycode - 8);
+            }
+        }
+    }
+    keycodes
+}
+
+fn spawn_server(
+    config: &VncConfig,
+    name: String,
+    size: Size<i32, Physical>,
+    frame: Arc<Mutex<SharedFrame>>,
+    sender: channel::Sender<VncEvent>,
+) -> Result<()> {
+    let listener = TcpListener::bind(&config.address)
+        .with_context(|| format!("Failed to listen for vnc clients on {}", config.address))?;
+    slog_scope::warn!(
+        "Serving {} unauthenticated over vnc at {}",
+        name,
+        config.address
+    );
+
+    std::thread::Builder::new()
+        .name(String::from("vnc"))
+        .spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(err) => {
+                        slog_scope::warn!("Failed to accept vnc client: {}", err);
+                        continue;
+                    }
+                };
+                let peer = stream.peer_addr().map(|addr| addr.to_string()).unwrap_or_default();
+                slog_scope::info!("Vnc client {} connected", peer);
+                let client = Client::new(name.clone(), size, frame.clone(), sender.clone());
+                let _ = std::thread::Builder::new()
+                    .name(format!("vnc {}", peer))
+                    .spawn(move || {
+                        match client.serve(stream) {
+                            Ok(()) => slog_scope::info!("Vnc client {} disconnected", peer),
+                            Err(err) => slog_scope::info!("Vnc client {} disconnected: {}", peer, err),
+                        }
+                    });
+            }
+        })?;
+
+    Ok(())
+}
+
+/// Pixel layout requested by a client
+struct PixelFormat {
+    bits_per_pixel: u8,
+    depth: u8,
+    big_endian: bool,
+    true_color: bool,
+    max: [u16; 3],
+    shift: [u8; 3],
+}
+
+impl PixelFormat {
+    /// Our native format, RGBA in memory
+    fn native() -> PixelFormat {
+        PixelFormat {
+            bits_per_pixel: 32,
+            depth: 24,
+            big_endian: false,
+            true_color: true,
+            max: [255, 255, 255],
+            shift: [0, 8, 16],
+        }
+    }
+
+    /// Parses a format sent by a client, channels have to fit into the pixels
+    fn parse(data: &[u8; 16]) -> io::Result<PixelFormat> {
+        let format = PixelFormat {
+            bits_per_pixel: data[0],
+            depth: data[1],
+            big_endian: data[2] != 0,
+            true_color: data[3] != 0,
+            max: [
+                u16::from_be_bytes([data[4], data[5]]),
+                u16::from_be_bytes([data[6], data[7]]),
+                u16::from_be_bytes([data[8], data[9]]),
+            ],
+            shift: [data[10], data[11], data[12]],
+        };
+        if !matches!(format.bits_per_pixel, 8 | 16 | 32) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid vnc pixel format with {} bits per pixel", format.bits_per_pixel),
+            ));
+        }
+        for channel in 0..3 {
+            let bits = 16 - format.max[channel].leading_zeros();
+            if format.shift[channel] as u32 + bits > format.bits_per_pixel as u32 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Invalid vnc pixel format, channel {} with max {} shifted by {} exceeds {} bits per pixel",
+                        channel, format.max[channel], format.shift[channel], format.bits_per_pixel
+                    ),
+                ));
+            }
+        }
+        Ok(format)
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&[self.bits_per_pixel, self.depth, self.big_endian as u8, self.true_color as u8]);
+        for max in self.max.iter() {
+            out.extend_from_slice(&max.to_be_bytes());
+        }
+        out.extend_from_slice(&self.shift);
+        out.extend_from_slice(&[0, 0, 0]);
+    }
+
+    /// Converts RGBA pixels into this format
+    fn encode(&self, rgba: &[u8], out: &mut Vec<u8>) {
+        let bytes = (self.bits_per_pixel / 8) as usize;
+        for pixel in rgba.chunks_exact(4) {
+            let mut value = 0u32;
+            for channel in 0..3 {
+                let scaled = pixel[channel] as u32 * self.max[channel] as u32 / 255;
+                value |= scaled << self.shift[channel];
+            }
+            let encoded = if self.big_endian {
+                value.to_be_bytes()
+            } else {
+                value.to_le_bytes()
+            };
+            if self.big_endian {
+                out.extend_from_slice(&encoded[4 - bytes..]);
+            } else {
+                out.extend_from_slice(&encoded[..bytes]);
+            }
+        }
+    }
+}
+
+/// State of a connected client, owned by its thread
+struct Client {
+    name: String,
+    size: Size<i32, Physical>,
+    frame: Arc<Mutex<SharedFrame>>,
+    sender: channel::Sender<VncEvent>,
+    format: PixelFormat,
+    buttons: u8,
+    /// Generation of the last frame sent
+    sent: u64,
+    /// The client waits for a frame newer than the last one
+    pending: bool,
+}
+
+impl Client {
+    fn new(
+        name: String,
+        size: Size<i32, Physical>,
+        frame: Arc<Mutex<SharedFrame>>,
+        sender: channel::Sender<VncEvent>,
+    ) -> Client {
+        Client {
+            name,
+            size,
+            frame,
+            sender,
+            format: PixelFormat::native(),
+            buttons: 0,
+            sent: 0,
+            pending: false,
+        }
+    }
+
+    fn serve(mut self, mut stream: TcpStream) -> io::Result<()> {
+        self.handshake(&mut stream)?;
+
+        loop {
+            // poll for messages, so pending updates go out as soon as a frame is ready
+            stream.set_read_timeout(Some(FRAME_INTERVAL))?;
+            let mut message = [0u8; 1];
+            match stream.read(&mut message) {
+                Ok(0) => return Ok(()),
+                Ok(_) => {
+                    stream.set_read_timeout(None)?;
+                    self.process_message(message[0], &mut stream)?;
+                }
+                Err(err) if matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {}
+                Err(err) => return Err(err),
+            }
+
+            if self.pending && self.frame.lock().unwrap().generation != self.sent {
+                self.send_frame(&mut stream)?;
+            }
+        }
+    }
+
+    fn handshake(&mut self, stream: &mut TcpStream) -> io::Result<()> {
+        stream.write_all(b"RFB 003.008\n")?;
+        let mut version = [0u8; 12];
+        stream.read_exact(&mut version)?;
+        let minor = std::str::from_utf8(&version[8..11])
+            .ok()
+            .and_then(|minor| minor.parse::<u32>().ok())
+            .unwrap_or(3);
+
+        // no authentication
+        if minor < 7 {
+            stream.write_all(&1u32.to_be_bytes())?;
+        } else {
+            stream.write_all(&[1, 1])?;
+            let mut chosen = [0u8; 1];
+            stream.read_exact(&mut chosen)?;
+            if minor >= 8 {
+                stream.write_all(&0u32.to_be_bytes())?;
+            }
+        }
+
+        // the shared flag is irrelevant, all clients share the output
+        let mut shared = [0u8; 1];
+        stream.read_exact(&mut shared)?;
+
+        let mut init = Vec::new();
+        init.extend_from_slice(&(self.size.w as u16).to_be_bytes());
+        init.extend_from_slice(&(self.size.h as u16).to_be_bytes());
+        self.format.write(&mut init);
+        let name = format!("Fireplace - {}", self.name);
+        init.extend_from_slice(&(name.len() as u32).to_be_bytes());
+        init.extend_from_slice(name.as_bytes());
+        stream.write_all(&init)
+    }
+
+    fn process_message(&mut self, message: u8, stream: &mut TcpStream) -> io::Result<()> {
+        match message {
+            // SetPixelFormat
+            0 => {
+                let mut data = [0u8; 19];
+                stream.read_exact(&mut data)?;
+                let mut format = [0u8; 16];
+                format.copy_from_slice(&data[3..]);
+                let format = PixelFormat::parse(&format)?;
+                if format.true_color {
+                    self.format = format;
+                } else {
+                    slog_scope::warn!("Color maps are not supported by vnc, colors will be wrong");
+                }
+            }
+            // SetEncodings, only raw is supported, which every client has to accept
+            2 => {
+                let mut data = [0u8; 3];
+                stream.read_exact(&mut data)?;
+                let count = u16::from_be_bytes([data[1], data[2]]) as usize;
+                let mut encodings = vec![0u8; count * 4];
+                stream.read_exact(&mut encodings)?;
+            }
+            // FramebufferUpdateRequest, always answered with the whole frame
+            3 => {
+                let mut data = [0u8; 9];
+                stream.read_exact(&mut data)?;
+                let incremental = data[0] != 0;
+                if incremental {
+                    self.pending = true;
+                } else {
+                    self.send_frame(stream)?;
+                }
+            }
+            // KeyEvent
+            4 => {
+                let mut data = [0u8; 7];
+                stream.read_exact(&mut data)?;
+                let keysym = u32::from_be_bytes([data[3], data[4], data[5], data[6]]);
+                self.send_event(VncEvent::Key {
+                    keysym,
+                    pressed: data[0] != 0,
+                })?;
+            }
+            // PointerEvent
+            5 => {
+                let mut data = [0u8; 5];
+                stream.read_exact(&mut data)?;
+                let mask = data[0];
+                let x = u16::from_be_bytes([data[1], data[2]]);
+                let y = u16::from_be_bytes([data[3], data[4]]);
+                self.send_event(VncEvent::Motion { x, y })?;
+
+                // left, middle and right button
+                for (bit, button) in [(0, 0x110), (1, 0x112), (2, 0x111)].iter() {
+                    let pressed = mask & (1 << bit) != 0;
+                    if pressed != (self.buttons & (1 << bit) != 0) {
+                        self.send_event(VncEvent::Button {
+                            button: *button,
+                            pressed,
+                        })?;
+                    }
+                }
+                // wheel up and down are pressed and released for every step
+                if mask & (1 << 3) != 0 && self.buttons & (1 << 3) == 0 {
+                    self.send_event(VncEvent::Scroll { steps: -1 })?;
+                }
+                if mask & (1 << 4) != 0 && self.buttons & (1 << 4) == 0 {
+                    self.send_event(VncEvent::Scroll { steps: 1 })?;
+                }
+                self.buttons = mask;
+            }
+            // ClientCutText, the clipboard is not shared
+            6 => {
+                let mut data = [0u8; 7];
+                stream.read_exact(&mut data)?;
+                let length = u32::from_be_bytes([data[3], data[4], data[5], data[6]]) as u64;
+                io::copy(&mut (&mut *stream).take(length), &mut io::sink())?;
+            }
+            other => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Unknown vnc message type {}", other),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn send_event(&self, event: VncEvent) -> io::Result<()> {
+        self.sender
+            .send(event)
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "Compositor is gone"))
+    }
+
+    fn send_frame(&mut self, stream: &mut TcpStream) -> io::Result<()> {
+        let (w, h) = (self.size.w as u16, self.size.h as u16);
+        let mut update = Vec::new();
+        // FramebufferUpdate with a single raw rectangle
+        update.extend_from_slice(&[0, 0]);
+        update.extend_from_slice(&1u16.to_be_bytes());
+        for value in [0, 0, w, h].iter() {
+            update.extend_from_slice(&value.to_be_bytes());
+        }
+        update.extend_from_slice(&0i32.to_be_bytes());
+        {
+            let frame = self.frame.lock().unwrap();
+            if frame.pixels.len() == w as usize * h as usize * 4 {
+                self.format.encode(&frame.pixels, &mut update);
+            } else {
+                // nothing rendered yet
+                let black = vec![0u8; w as usize * h as usize * 4];
+                self.format.encode(&black, &mut update);
+            }
+            self.sent = frame.generation;
+        }
+        self.pending = false;
+        stream.write_all(&update)
+    }
+}
+
+impl Fireplace {
+    fn render_vnc(&mut self, dev_id: dev_t, output: &mut VncOutput) -> Result<()> {
+        let (mut device_backend, mut other_backends): (Vec<_>, Vec<_>) =
+            self.udev.iter_mut().partition(|(key, _)| **key == dev_id);
+        let backend = match device_backend.pop() {
+            Some((_, backend)) => backend,
+            // the gpu is gone
+            None => return Ok(()),
+        };
+
+        let mut workspaces = self.workspaces.borrow_mut();
+        let scale = match workspaces.output_by_name(&output.name) {
+            Some(o) => o.scale(),
+            None => return Ok(()),
+        };
+        let (space, switch) = workspaces.visible_spaces(&output.name, &self.config.animations).unwrap();
+        let popups = self.popups.borrow();
+        let seats = &self.seats;
+        let config = &self.config;
+        let lock = self.lock.as_ref();
+        let name = &output.name;
+        let cache = &mut backend.cache;
+        // rendered upside down into a dmabuf, like gbm surfaces
+        cache.blur.flipped = false;
+
+        backend.renderer.bind(output.buffer.clone())?;
+        backend
+            .renderer
+            .render(output.size, Transform::Flipped180, |renderer, frame| {
+                if let Some(lock) = lock {
+                    return render_lock(lock, name, scale, output.size, config, Some(DevId(dev_id)), renderer, frame, cache, &mut other_backends);
+                }
+                render_space(space, switch.as_ref(), scale, output.size, &**popups, config, Some(DevId(dev_id)), None, renderer, frame, cache, &mut other_backends)?;
+                draw_dnd_icons(seats, name, Some(DevId(dev_id)), renderer, frame, scale, &mut other_backends)
+            })
+            .and_then(|x| x)?;
+        let pixels = backend.renderer.export_bitmap(&output.buffer)?;
+        space.send_frames(self.start_time.elapsed().as_millis() as u32);
+        if let Some(lock) = lock {
+            lock.send_frames(name, self.start_time.elapsed().as_millis() as u32);
+        }
+
+        for screenshot in take_pending(&mut self.screenshots, name) {
+            let area = screenshot.area(output.size, scale);
+            let image = imageops::crop_imm(
+                &pixels,
+                area.loc.x as u32,
+                area.loc.y as u32,
+                area.size.w as u32,
+                area.size.h as u32,
+            );
+            save(&config.screenshot, screenshot, image.to_image());
+        }
+        if let Some(screencast) = self.screencast.as_ref() {
+            screencast.capture(space, name, output.size, scale, |area| {
+                let image = imageops::crop_imm(
+                    &pixels,
+                    area.loc.x as u32,
+                    area.loc.y as u32,
+                    area.size.w as u32,
+                    area.size.h as u32,
+                );
+                Some(image.to_image())
+            });
+        }
+
+        let mut frame = output.frame.lock().unwrap();
+        frame.generation += 1;
+        frame.pixels = pixels.into_raw();
+        Ok(())
+    }
+
+    /// Injects the input of remote clients into the last active seat
+    fn process_vnc_event(&mut self, name: &str, keycodes: &HashMap<u32, u32>, event: VncEvent) {
+        // the locked session is only unlocked at the machine itself
+        if self.is_locked() {
+            return;
+        }
+        let seat = self.last_active_seat.clone();
+        let serial = SCOUNTER.next_serial();
+        let time = self.start_time.elapsed().as_millis() as u32;
+
+        match event {
+            VncEvent::Motion { x, y } => {
+                if let Some(active) = seat.user_data().get::<ActiveOutput>() {
+                    *active.0.borrow_mut() = String::from(name);
+                }
+                let mut workspaces = self.workspaces.borrow_mut();
+                let scale = match workspaces.output_by_name(name) {
+                    Some(output) => output.scale(),
+                    None => return,
+                };
+                let location = (x as f64 / scale as f64, y as f64 / scale as f64).into();
+                let space = workspaces.space_by_output_name(name).unwrap();
+                let under = space.surface_under(location);
+                if let Some(pointer) = seat.get_pointer() {
+                    pointer.motion(location, under, serial, time);
+                }
+            }
+            VncEvent::Button { button, pressed } => {
+                let pointer = match seat.get_pointer() {
+                    Some(pointer) => pointer,
+                    None => return,
+                };
+                let state = if pressed {
+                    // change the keyboard focus unless the pointer is grabbed
+                    if !pointer.is_grabbed() {
+                        let mut workspaces = self.workspaces.borrow_mut();
+                        if let Some(space) = workspaces.space_by_output_name(name) {
+                            let under = space.surface_under(pointer.current_location());
+                            if let Some(&(ref under, _)) = under.as_ref() {
+                                space.on_focus(under);
+                            }
+                            if let Some(keyboard) = seat.get_keyboard() {
+                                keyboard.set_focus(under.as_ref().map(|&(ref s, _)| s), serial);
+                            }
+                        }
+                    }
+                    wl_pointer::ButtonState::Pressed
+                } else {
+                    wl_pointer::ButtonState::Released
+                };
+                pointer.button(button, state, serial, time);
+            }
+            VncEvent::Scroll { steps } => {
+                if let Some(pointer) = seat.get_pointer() {
+                    let frame = AxisFrame::new(time)
+                        .source(wl_pointer::AxisSource::Wheel)
+                        .value(wl_pointer::Axis::VerticalScroll, steps as f64 * 10.0)
+                        .discrete(wl_pointer::Axis::VerticalScroll, steps);
+                    pointer.axis(frame);
+                }
+            }
+            VncEvent::Key { keysym, pressed } => {
+                let keycode = match keycodes.get(&keysym) {
+                    Some(keycode) => *keycode,
+                    None => {
+                        slog_scope::debug!("No key produces {}", xkb::keysym_get_name(keysym));
+                        return;
+                    }
+                };
+                let state = if pressed { KeyState::Pressed } else { KeyState::Released };
+                if let Some(keyboard) = seat.get_keyboard() {
+                    // remote keys go straight to the clients, without compositor bindings
+                    keyboard.input::<(), _>(keycode, state, serial, time, |_, _| FilterResult::Forward);
+                }
+            }
+        }
+    }
+}
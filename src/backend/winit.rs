@@ -1,17 +1,25 @@
 use crate::{
-    backend::render::render_space,
+    backend::{
+        color_filter::ApplyColorFilter,
+        render::{draw_debug_damage, draw_debug_overlay, draw_dnd_icons, draw_message, draw_notifications, draw_osd, render_lock, render_space, RenderCache},
+        screenshot::{read_pixels, save, take_pending},
+        udev::DevId,
+    },
+    handler::ActiveOutput,
     state::Fireplace,
 };
 use anyhow::Result;
 use smithay::{
     backend::{
         input::{InputBackend, InputEvent},
-        renderer::{ImportDma, ImportEgl},
+        renderer::{gles2::Gles2Texture, ImportDma, ImportEgl},
         winit,
     },
     reexports::{
         calloop::{timer::Timer, EventLoop},
+        nix::sys::stat::dev_t,
         wayland_server::protocol::wl_output::Subpixel,
+        winit::window::WindowBuilder,
     },
     wayland::{
         dmabuf::init_dmabuf_global,
@@ -21,12 +29,26 @@ use smithay::{
 use std::{
     cell::RefCell,
     rc::Rc,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 
 pub fn init_winit(event_loop: &mut EventLoop<Fireplace>, state: &mut Fireplace) -> Result<()> {
-    let (renderer, input) = match winit::init(None) {
+    for idx in 0..state.config.winit.outputs.max(1) {
+        let name = if idx == 0 {
+            String::from("WINIT")
+        } else {
+            format!("WINIT-{}", idx + 1)
+        };
+        init_winit_output(event_loop, state, idx, name)?;
+    }
+    Ok(())
+}
+
+/// Opens a host window presenting a virtual output
+fn init_winit_output(event_loop: &mut EventLoop<Fireplace>, state: &mut Fireplace, idx: u32, name: String) -> Result<()> {
+    let builder = WindowBuilder::new().with_title(format!("Fireplace - {}", name));
+    let (renderer, input) = match winit::init_from_builder(builder, None) {
         Ok(ret) => ret,
         Err(err) => {
             slog_scope::crit!("Failed to initialize winit backend: {}", err);
@@ -34,8 +56,11 @@ pub fn init_winit(event_loop: &mut EventLoop<Fireplace>, state: &mut Fireplace)
         }
     };
     let renderer = Rc::new(RefCell::new(renderer));
+    // textures are cached per renderer, the windows do not share theirs
+    let device = if idx == 0 { None } else { Some(DevId(idx as dev_t)) };
 
-    if renderer
+    // the display can only be bound once, other windows only import shm and dmabuf buffers
+    if idx == 0 && renderer
         .borrow_mut()
         .renderer()
         .bind_wl_display(&state.display.borrow())
@@ -57,12 +82,11 @@ pub fn init_winit(event_loop: &mut EventLoop<Fireplace>, state: &mut Fireplace)
         );
     };
 
-    let name = "WINIT";
     let size = renderer.borrow().window_size();
     let props = PhysicalProperties {
         size: (0, 0).into(),
         subpixel: Subpixel::Unknown,
-        make: String::from(name),
+        make: name.clone(),
         model: String::from("Unknown"),
     };
     let mode = Mode {
@@ -72,7 +96,7 @@ pub fn init_winit(event_loop: &mut EventLoop<Fireplace>, state: &mut Fireplace)
     state
         .workspaces
         .borrow_mut()
-        .add_output(name.clone(), props, mode);
+        .add_output(&name, props, mode);
 
     let timer = Timer::new()?;
     let timer_handle = timer.handle();
@@ -80,27 +104,124 @@ pub fn init_winit(event_loop: &mut EventLoop<Fireplace>, state: &mut Fireplace)
         .handle()
         .insert_source(
             timer,
-            move |(mut input, renderer): (
+            move |(mut input, renderer, mut cache): (
                 winit::WinitInputBackend,
                 Rc<RefCell<winit::WinitGraphicsBackend>>,
+                RenderCache<Gles2Texture>,
             ),
             handle,
             state| {
-                match input.dispatch_new_events(|event| state.process_winit_event(&name, event)) {
+                match input.dispatch_new_events(|event| {
+                    state.last_input = Instant::now();
+                    state.guard("input", |state| state.process_winit_event(&name, event));
+                }) {
                     Ok(()) => {
-                        let mut workspaces = state.workspaces.borrow_mut();
-                        let scale = workspaces.output_by_name(&name).unwrap().scale();
-                        let space = workspaces.space_by_output_name(&name).unwrap();
-                        let popups = state.popups.borrow();
-                        if let Err(err) = renderer
-                            .borrow_mut()
-                            .render(|renderer, frame| render_space(&**space, scale, &**popups, None, renderer, frame, &mut []))
-                            .and_then(|x| x.map_err(Into::into))
-                        {
-                            slog_scope::error!("Failed to render frame: {}", err);
-                        };
-                        space.send_frames(state.start_time.elapsed().as_millis() as u32);
-                        handle.add_timeout(Duration::from_millis(16), (input, renderer));
+                        state.guard("overlays", |state| {
+                            state.expire_message();
+                            state.expire_osd();
+                        });
+                        state.guard("notifications", |state| state.expire_notifications());
+                        state.guard("render", |state| {
+                            profile_span!("render", output = %name);
+                            let mut workspaces = state.workspaces.borrow_mut();
+                            let scale = workspaces.output_by_name(&name).unwrap().scale();
+                            let (space, switch) = workspaces.visible_spaces(&name, &state.config.animations).unwrap();
+                            let popups = state.popups.borrow();
+                            let screenshots = take_pending(&mut state.screenshots, &name);
+                            let mut images = Vec::with_capacity(screenshots.len());
+                            let size = renderer.borrow().window_size().physical_size;
+                            // the window surface starts at the bottom row
+                            cache.blur.flipped = true;
+                            let debug_lines = if state.debug_overlay {
+                                let mut lines = vec![name.clone()];
+                                lines.extend(state.frame_stats.get(&name).map(|stats| stats.summary()).unwrap_or_default());
+                                Some(lines)
+                            } else {
+                                None
+                            };
+                            let filter = state.config.color_filter(state.color_filter, &name, &name);
+                            let started = Instant::now();
+                            let result = renderer
+                                .borrow_mut()
+                                .render(|renderer, frame| {
+                                    match state.lock.as_ref() {
+                                        Some(lock) => {
+                                            render_lock(lock, &name, scale, size, &state.config, device, renderer, frame, &mut cache, &mut [])?
+                                        }
+                                        None => {
+                                            render_space(
+                                                space,
+                                                switch.as_ref(),
+                                                scale,
+                                                size,
+                                                &**popups,
+                                                &state.config,
+                                                device,
+                                                None,
+                                                renderer,
+                                                frame,
+                                                &mut cache,
+                                                &mut [],
+                                            )?;
+                                            draw_dnd_icons(&state.seats, &name, device, renderer, frame, scale, &mut [])?;
+                                        }
+                                    }
+                                    for screenshot in screenshots.iter() {
+                                        // the window surface starts at the bottom row
+                                        let area = screenshot.area(size, scale);
+                                        images.push((screenshot.clone(), read_pixels(renderer, size, area, true)?));
+                                    }
+                                    if let Some(screencast) = state.screencast.as_ref() {
+                                        screencast.capture(space, &name, size, scale, |area| {
+                                            read_pixels(renderer, size, area, true)
+                                                .map_err(|err| slog_scope::error!("Failed to read back screencast frame: {}", err))
+                                                .ok()
+                                        });
+                                    }
+                                    if let Some(lines) = debug_lines.as_ref() {
+                                        draw_debug_overlay(renderer, frame, &mut cache, &state.config, lines, scale)?;
+                                    }
+                                    if state.debug_damage {
+                                        // every frame is redrawn completely
+                                        draw_debug_damage(renderer, frame, &mut cache, space, None, size, scale)?;
+                                    }
+                                    if let Some(message) = state.message.as_ref() {
+                                        draw_message(renderer, frame, &mut cache, &state.config, &message.lines, size, scale)?;
+                                    }
+                                    if let Some(osd) = state.osd.as_ref() {
+                                        draw_osd(renderer, frame, &mut cache, &state.config, &osd.label, osd.level, size, scale)?;
+                                    }
+                                    if let Some(daemon) = state.notifications.as_ref().filter(|_| state.lock.is_none()) {
+                                        draw_notifications(renderer, frame, &mut cache, &state.config, &daemon.notifications, size, scale)?;
+                                    }
+                                    if let Some(filter) = filter {
+                                        renderer.apply_color_filter(&mut cache.color_filter, filter)?;
+                                    }
+                                    Ok(())
+                                })
+                                .and_then(|x| x.map_err(Into::into));
+                            match result {
+                                // winit does not track damage, every frame redraws everything
+                                Ok(()) => {
+                                    let stats = state.frame_stats.entry(name.clone()).or_default();
+                                    let input = stats.new_input(state.last_input);
+                                    stats.record(started, None, None);
+                                    // the host compositor does not tell when the frame is shown, swapping is as close as it gets
+                                    stats.presented(input, 0);
+                                    #[cfg(feature = "profiling")]
+                                    crate::profiling::frame_mark();
+                                }
+                                Err(err) => slog_scope::error!("Failed to render frame: {}", err),
+                            }
+                            for (screenshot, image) in images {
+                                save(&state.config.screenshot, screenshot, image);
+                            }
+                            space.send_frames(state.start_time.elapsed().as_millis() as u32);
+                            if let Some(lock) = state.lock.as_ref() {
+                                lock.send_frames(&name, state.start_time.elapsed().as_millis() as u32);
+                            }
+                        });
+                        handle.add_timeout(Duration::from_millis(16), (input, renderer, cache));
                     }
                     Err(winit::WinitInputError::WindowClosed) => {
                         state.workspaces.borrow_mut().remove_output_by_name(&name);
@@ -110,7 +231,7 @@ pub fn init_winit(event_loop: &mut EventLoop<Fireplace>, state: &mut Fireplace)
             },
         )
         .map_err(|_| anyhow::anyhow!("Failed to init eventloop timer for winit"))?;
-    timer_handle.add_timeout(Duration::ZERO, (input, renderer));
+    timer_handle.add_timeout(Duration::ZERO, (input, renderer, RenderCache::new()));
     state.tokens.push(token);
 
     Ok(())
@@ -124,19 +245,22 @@ impl Fireplace {
         use smithay::backend::winit::WinitEvent;
 
         match event {
-            InputEvent::Special(WinitEvent::Resized { size, scale_factor }) => {
-                let mut workspaces = self.workspaces.borrow_mut();
-                if let Some(output) = workspaces.output_by_name(&name) {
-                    output.set_mode(smithay::wayland::output::Mode {
-                        size,
-                        refresh: 60_000,
-                    });
+            // the pointer entered the window of this output
+            x @ InputEvent::PointerMotionAbsolute { .. } => {
+                for seat in self.seats.iter() {
+                    if let Some(active) = seat.user_data().get::<ActiveOutput>() {
+                        *active.0.borrow_mut() = String::from(name);
+                    }
                 }
-
-                let _scale = scale_factor;
-                if let Some(space) = workspaces.space_by_output_name(&name) {
-                    space.rearrange(&size.to_logical(1));
-                };
+                self.process_input_event(x)
+            }
+            // the egl surface follows on the next frame, smithay resizes it while rendering
+            InputEvent::Special(WinitEvent::Resized { size, scale_factor }) => {
+                self.workspaces.borrow_mut().update_output_mode(
+                    name,
+                    Mode { size, refresh: 60_000 },
+                    Some(scale_factor as f32),
+                );
             }
             x => self.process_input_event(x),
         }
@@ -0,0 +1,102 @@
+//! Sends a request to a running fireplace and prints its json response
+//!
+//! ```text
+//! fireplace-msg [-s <seat>] <command>...   run commands, e.g. `fireplace-msg workspace 2`
+//! fireplace-msg -t workspaces|windows|outputs|metrics
+//! fireplace-msg --set <key> <value>        e.g. `fireplace-msg --set night_light.temperature 4000`
+//! ```
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    os::unix::net::UnixStream,
+    process::exit,
+};
+
+#[path = "../ipc/protocol.rs"]
+mod protocol;
+
+use protocol::{Request, Response};
+
+const USAGE: &str = "Usage:
+    fireplace-msg [-s <seat>] <command>...
+    fireplace-msg -t workspaces|windows|outputs|metrics
+    fireplace-msg --set <key> <value>";
+
+fn parse(mut args: Vec<String>) -> Result<Request, String> {
+    match args.first().map(String::as_str) {
+        Some("-t") | Some("--type") => match args.get(1).map(String::as_str) {
+            Some("workspaces") => Ok(Request::Workspaces),
+            Some("windows") => Ok(Request::Windows),
+            Some("outputs") => Ok(Request::Outputs),
+            Some("metrics") => Ok(Request::Metrics),
+            Some(other) => Err(format!("Unknown query {}", other)),
+            None => Err(String::from(USAGE)),
+        },
+        Some("--set") if args.len() == 3 => {
+            // values that are no valid json are taken as strings
+            let value = serde_json::from_str(&args[2]).unwrap_or_else(|_| serde_json::Value::String(args[2].clone()));
+            Ok(Request::SetConfig {
+                key: args[1].clone(),
+                value,
+            })
+        }
+        Some("-s") | Some("--seat") if args.len() > 2 => {
+            let seat = args[1].clone();
+            Ok(Request::Command {
+                commands: commands(args.split_off(2)),
+                seat: Some(seat),
+            })
+        }
+        Some(x) if !x.starts_with('-') => Ok(Request::Command {
+            commands: commands(args),
+            seat: None,
+        }),
+        _ => Err(String::from(USAGE)),
+    }
+}
+
+/// Joins the arguments to one command line, chained commands are split at `;`
+fn commands(args: Vec<String>) -> Vec<String> {
+    args.join(" ")
+        .split(';')
+        .map(|command| command.trim())
+        .filter(|command| !command.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+fn send(request: &Request) -> Result<Response, String> {
+    let path = protocol::socket_path().ok_or_else(|| String::from("XDG_RUNTIME_DIR is not set"))?;
+    let mut stream =
+        UnixStream::connect(&path).map_err(|err| format!("Failed to connect to {}: {}", path.display(), err))?;
+    let mut line = serde_json::to_string(request).map_err(|err| err.to_string())?;
+    line.push('\n');
+    stream.write_all(line.as_bytes()).map_err(|err| err.to_string())?;
+
+    let mut response = String::new();
+    BufReader::new(stream)
+        .read_line(&mut response)
+        .map_err(|err| err.to_string())?;
+    serde_json::from_str(&response).map_err(|err| format!("Malformed response: {}", err))
+}
+
+fn main() {
+    let request = match parse(std::env::args().skip(1).collect()) {
+        Ok(request) => request,
+        Err(message) => {
+            eprintln!("{}", message);
+            exit(2);
+        }
+    };
+    match send(&request) {
+        Ok(Response::Error { message }) => {
+            eprintln!("{}", message);
+            exit(1);
+        }
+        Ok(response) => println!("{}", serde_json::to_string_pretty(&response).unwrap()),
+        Err(message) => {
+            eprintln!("{}", message);
+            exit(1);
+        }
+    }
+}
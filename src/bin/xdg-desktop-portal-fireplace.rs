@@ -0,0 +1,363 @@
+//! Backend of xdg-desktop-portal, implementing the Screenshot, ScreenCast and Settings portals
+//!
+//! It is activated over d-bus on behalf of sandboxed applications, see `resources/fireplace.portal`,
+//! and asks the running compositor over its ipc socket.
+
+use anyhow::Result;
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    convert::TryFrom,
+    io::{BufRead, BufReader, Write},
+    os::unix::net::UnixStream,
+    process::exit,
+    rc::Rc,
+    time::Duration,
+};
+use zbus::{dbus_interface, fdo};
+use zvariant::{ObjectPath, OwnedObjectPath, OwnedValue, Value};
+
+#[path = "../ipc/protocol.rs"]
+mod protocol;
+
+use protocol::{Request, Response};
+
+/// Name xdg-desktop-portal looks for, see `resources/fireplace.portal`
+const BUS_NAME: &str = "org.freedesktop.impl.portal.desktop.fireplace";
+const OBJECT_PATH: &str = "/org/freedesktop/portal/desktop";
+
+const RESPONSE_SUCCESS: u32 = 0;
+const RESPONSE_CANCELLED: u32 = 1;
+const RESPONSE_OTHER: u32 = 2;
+
+/// Source types of the screencast portal
+const SOURCE_MONITOR: u32 = 1;
+const SOURCE_WINDOW: u32 = 2;
+/// Frames are captured before the cursors are drawn
+const CURSOR_MODE_HIDDEN: u32 = 1;
+
+const APPEARANCE: &str = "org.freedesktop.appearance";
+const COLOR_SCHEME: &str = "color-scheme";
+
+/// Time the compositor gets to answer, screenshots and streams wait for a frame
+const TIMEOUT: Duration = Duration::from_secs(5);
+/// The ipc socket has no events, changed settings are noticed by asking again
+const SETTINGS_INTERVAL: Duration = Duration::from_secs(2);
+
+type Results = HashMap<String, OwnedValue>;
+
+/// Sends a single request to the compositor, error responses included
+fn request(request: &Request) -> Result<Response, String> {
+    let path = protocol::socket_path().ok_or_else(|| String::from("XDG_RUNTIME_DIR is not set"))?;
+    let mut stream =
+        UnixStream::connect(&path).map_err(|err| format!("Failed to connect to {}: {}", path.display(), err))?;
+    stream.set_read_timeout(Some(TIMEOUT)).map_err(|err| err.to_string())?;
+    let mut line = serde_json::to_string(request).map_err(|err| err.to_string())?;
+    line.push('\n');
+    stream.write_all(line.as_bytes()).map_err(|err| err.to_string())?;
+
+    let mut response = String::new();
+    BufReader::new(stream)
+        .read_line(&mut response)
+        .map_err(|err| err.to_string())?;
+    match serde_json::from_str(&response) {
+        Ok(Response::Error { message }) => Err(message),
+        Ok(response) => Ok(response),
+        Err(err) => Err(format!("Malformed response: {}", err)),
+    }
+}
+
+/// `org.freedesktop.impl.portal.Screenshot`, always taken of the active output without asking
+struct Screenshot;
+
+#[dbus_interface(name = "org.freedesktop.impl.portal.Screenshot")]
+impl Screenshot {
+    fn screenshot(
+        &self,
+        _handle: ObjectPath<'_>,
+        _app_id: &str,
+        _parent_window: &str,
+        _options: HashMap<&str, Value<'_>>,
+    ) -> (u32, Results) {
+        match request(&Request::Screenshot { window: false }) {
+            Ok(Response::Screenshot { path }) => {
+                let mut results = Results::new();
+                results.insert(
+                    String::from("uri"),
+                    Value::from(format!("file://{}", path.display())).into(),
+                );
+                (RESPONSE_SUCCESS, results)
+            }
+            Ok(_) => (RESPONSE_OTHER, Results::new()),
+            Err(err) => {
+                eprintln!("Failed to take screenshot: {}", err);
+                (RESPONSE_OTHER, Results::new())
+            }
+        }
+    }
+
+    #[dbus_interface(property, name = "version")]
+    fn version(&self) -> u32 {
+        1
+    }
+}
+
+enum SessionEvent {
+    Created(OwnedObjectPath),
+    Closed(OwnedObjectPath),
+}
+
+/// `org.freedesktop.impl.portal.ScreenCast`, its streams are served by the compositor
+struct ScreenCast {
+    /// Source types selected per session
+    sessions: Rc<RefCell<HashMap<String, u32>>>,
+    events: Rc<RefCell<Vec<SessionEvent>>>,
+}
+
+#[dbus_interface(name = "org.freedesktop.impl.portal.ScreenCast")]
+impl ScreenCast {
+    fn create_session(
+        &mut self,
+        _handle: ObjectPath<'_>,
+        session_handle: ObjectPath<'_>,
+        _app_id: &str,
+        _options: HashMap<&str, Value<'_>>,
+    ) -> (u32, Results) {
+        self.sessions
+            .borrow_mut()
+            .insert(session_handle.to_string(), SOURCE_MONITOR);
+        self.events
+            .borrow_mut()
+            .push(SessionEvent::Created(session_handle.into()));
+        (RESPONSE_SUCCESS, Results::new())
+    }
+
+    fn select_sources(
+        &mut self,
+        _handle: ObjectPath<'_>,
+        session_handle: ObjectPath<'_>,
+        _app_id: &str,
+        options: HashMap<&str, Value<'_>>,
+    ) -> (u32, Results) {
+        match self.sessions.borrow_mut().get_mut(session_handle.as_str()) {
+            Some(types) => {
+                if let Some(Value::U32(requested)) = options.get("types") {
+                    *types = *requested;
+                }
+                (RESPONSE_SUCCESS, Results::new())
+            }
+            None => (RESPONSE_OTHER, Results::new()),
+        }
+    }
+
+    fn start(
+        &mut self,
+        _handle: ObjectPath<'_>,
+        session_handle: ObjectPath<'_>,
+        _app_id: &str,
+        _parent_window: &str,
+        _options: HashMap<&str, Value<'_>>,
+    ) -> (u32, Results) {
+        let types = match self.sessions.borrow().get(session_handle.as_str()) {
+            Some(types) => *types,
+            None => return (RESPONSE_OTHER, Results::new()),
+        };
+        let session = session_handle.to_string();
+        match request(&Request::StartScreencast {
+            session: session.clone(),
+            types,
+        }) {
+            Ok(Response::Screencast { stream }) => {
+                let mut properties = HashMap::<String, OwnedValue>::new();
+                properties.insert(String::from("position"), Value::from(stream.position).into());
+                properties.insert(String::from("size"), Value::from(stream.size).into());
+                properties.insert(String::from("source_type"), Value::from(stream.source_type).into());
+                let mut results = Results::new();
+                results.insert(
+                    String::from("streams"),
+                    Value::from(vec![(stream.node_id, properties)]).into(),
+                );
+                (RESPONSE_SUCCESS, results)
+            }
+            Ok(_) => (RESPONSE_OTHER, Results::new()),
+            Err(err) => {
+                eprintln!("Failed to start screencast: {}", err);
+                // the stream might still come up after we gave up on it
+                let _ = request(&Request::StopScreencast { session });
+                (RESPONSE_CANCELLED, Results::new())
+            }
+        }
+    }
+
+    #[dbus_interface(property)]
+    fn available_source_types(&self) -> u32 {
+        SOURCE_MONITOR | SOURCE_WINDOW
+    }
+
+    #[dbus_interface(property)]
+    fn available_cursor_modes(&self) -> u32 {
+        CURSOR_MODE_HIDDEN
+    }
+
+    #[dbus_interface(property, name = "version")]
+    fn version(&self) -> u32 {
+        2
+    }
+}
+
+/// `org.freedesktop.impl.portal.Session`, exported for every session created
+struct Session {
+    path: OwnedObjectPath,
+    events: Rc<RefCell<Vec<SessionEvent>>>,
+}
+
+#[dbus_interface(name = "org.freedesktop.impl.portal.Session")]
+impl Session {
+    fn close(&mut self) {
+        if let Err(err) = request(&Request::StopScreencast {
+            session: self.path.to_string(),
+        }) {
+            eprintln!("Failed to stop screencast: {}", err);
+        }
+        self.events
+            .borrow_mut()
+            .push(SessionEvent::Closed(self.path.clone()));
+    }
+
+    #[dbus_interface(property, name = "version")]
+    fn version(&self) -> u32 {
+        1
+    }
+}
+
+/// `org.freedesktop.impl.portal.Settings`, currently only the color scheme
+struct Settings;
+
+impl Settings {
+    /// Settings of the compositor by namespace and key
+    fn all() -> fdo::Result<HashMap<String, HashMap<String, OwnedValue>>> {
+        let color_scheme = color_scheme().map_err(fdo::Error::Failed)?;
+        let mut appearance = HashMap::new();
+        appearance.insert(String::from(COLOR_SCHEME), Value::from(color_scheme).into());
+        let mut settings = HashMap::new();
+        settings.insert(String::from(APPEARANCE), appearance);
+        Ok(settings)
+    }
+}
+
+#[dbus_interface(name = "org.freedesktop.impl.portal.Settings")]
+impl Settings {
+    /// Namespaces may end with a `*`, no namespaces at all match every one
+    fn read_all(&self, namespaces: Vec<&str>) -> fdo::Result<HashMap<String, HashMap<String, OwnedValue>>> {
+        let mut settings = Settings::all()?;
+        settings.retain(|namespace, _| {
+            namespaces.is_empty()
+                || namespaces.iter().any(|pattern| match pattern.strip_suffix('*') {
+                    Some(prefix) => namespace.starts_with(prefix),
+                    None => pattern.is_empty() || *pattern == namespace.as_str(),
+                })
+        });
+        Ok(settings)
+    }
+
+    fn read(&self, namespace: &str, key: &str) -> fdo::Result<OwnedValue> {
+        Settings::all()?
+            .remove(namespace)
+            .and_then(|mut values| values.remove(key))
+            .ok_or_else(|| fdo::Error::Failed(format!("Unknown setting {}.{}", namespace, key)))
+    }
+
+    #[dbus_interface(property, name = "version")]
+    fn version(&self) -> u32 {
+        1
+    }
+}
+
+fn color_scheme() -> Result<u32, String> {
+    match request(&Request::Settings)? {
+        Response::Settings { color_scheme } => Ok(color_scheme),
+        _ => Err(String::from("Unexpected response")),
+    }
+}
+
+/// Emits `SettingChanged` whenever the configured color scheme changes
+fn watch_settings(connection: zbus::Connection) {
+    let mut current = color_scheme().ok();
+    loop {
+        std::thread::sleep(SETTINGS_INTERVAL);
+        let color_scheme = match color_scheme() {
+            Ok(color_scheme) => color_scheme,
+            // the compositor is gone or restarting
+            Err(_) => continue,
+        };
+        if current == Some(color_scheme) {
+            continue;
+        }
+        current = Some(color_scheme);
+        if let Err(err) = connection.emit_signal(
+            None,
+            OBJECT_PATH,
+            "org.freedesktop.impl.portal.Settings",
+            "SettingChanged",
+            &(APPEARANCE, COLOR_SCHEME, Value::from(color_scheme)),
+        ) {
+            eprintln!("Failed to emit SettingChanged: {}", err);
+        }
+    }
+}
+
+fn serve() -> Result<()> {
+    let connection = zbus::Connection::new_session()?;
+    fdo::DBusProxy::new(&connection)?.request_name(BUS_NAME, fdo::RequestNameFlags::ReplaceExisting.into())?;
+
+    let settings_connection = connection.clone();
+    std::thread::Builder::new()
+        .name(String::from("settings"))
+        .spawn(move || watch_settings(settings_connection))?;
+
+    let sessions = Rc::new(RefCell::new(HashMap::new()));
+    let events = Rc::new(RefCell::new(Vec::new()));
+    let path = ObjectPath::try_from(OBJECT_PATH)?;
+    let mut server = zbus::ObjectServer::new(&connection);
+    server.at(&path, Screenshot)?;
+    server.at(
+        &path,
+        ScreenCast {
+            sessions: sessions.clone(),
+            events: events.clone(),
+        },
+    )?;
+    server.at(&path, Settings)?;
+
+    loop {
+        if let Err(err) = server.try_handle_next() {
+            eprintln!("Failed to handle portal message: {}", err);
+        }
+        // objects can not be added while a method of another one is running
+        let pending = events.borrow_mut().drain(..).collect::<Vec<_>>();
+        for event in pending {
+            match event {
+                SessionEvent::Created(path) => {
+                    server.at(
+                        &path,
+                        Session {
+                            path: path.clone(),
+                            events: events.clone(),
+                        },
+                    )?;
+                }
+                SessionEvent::Closed(path) => {
+                    sessions.borrow_mut().remove(path.as_str());
+                    server.remove::<Session>(&path)?;
+                }
+            }
+        }
+    }
+}
+
+fn main() {
+    if let Err(err) = serve() {
+        eprintln!("{:#}", err);
+        exit(1);
+    }
+}
@@ -0,0 +1,169 @@
+//! Checks of a parsed configuration beyond its syntax, reported by `fireplace --check`
+
+use std::collections::HashMap;
+
+use crate::{
+    config::{ChainedBinding, Config},
+    handler::{expand_aliases, is_known_command, keyboard::KeyPattern},
+};
+
+/// Prefixes of connector names, as named by the udev, winit, vnc and headless backends
+const CONNECTORS: &[&str] = &[
+    "DVI-I", "DVI-D", "DVI-A", "S-VIDEO", "DP", "HDMI-A", "HDMI-B", "eDP", "VGA", "LVDS", "Composite", "Component",
+    "NinePinDIN", "TV", "Virtual", "DSI", "DPI", "Writeback", "SPI", "USB", "Unknown", "WINIT", "VNC", "HEADLESS",
+];
+
+/// Describes everything that is likely a mistake, an empty list means the configuration is fine
+pub fn check(config: &Config) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    check_keys(
+        config,
+        "the default mode",
+        &config.bindings,
+        &[&config.keys, &config.workspace.keys, &config.view.keys],
+        &config.exec.keys,
+        &mut problems,
+    );
+    let mut modes = config.modes.iter().collect::<Vec<_>>();
+    modes.sort_by(|a, b| a.0.cmp(b.0));
+    for (name, mode) in modes {
+        check_keys(
+            config,
+            &format!("mode {}", name),
+            &mode.bindings,
+            &[&mode.keys, &mode.workspace, &mode.view],
+            &mode.exec,
+            &mut problems,
+        );
+    }
+
+    let gestures = &config.gestures;
+    for table in [&gestures.global, &gestures.workspace, &gestures.view].iter() {
+        for command in table.keys() {
+            check_commands(config, "gestures", split(command), &mut problems);
+        }
+    }
+    let switches = &config.switches;
+    for (name, commands) in [
+        ("switches.lid_closed", &switches.lid_closed),
+        ("switches.lid_opened", &switches.lid_opened),
+        ("switches.tablet_mode_on", &switches.tablet_mode_on),
+        ("switches.tablet_mode_off", &switches.tablet_mode_off),
+    ]
+    .iter()
+    {
+        check_commands(config, name, commands.iter().map(String::as_str), &mut problems);
+    }
+
+    let mut aliases = config.aliases.keys().collect::<Vec<_>>();
+    aliases.sort();
+    for alias in aliases {
+        match expand_aliases(&config.aliases, alias) {
+            Ok(commands) => check_commands(config, &format!("alias {}", alias), commands.iter().map(String::as_str), &mut problems),
+            Err(err) => problems.push(err),
+        }
+    }
+
+    check_outputs(config, &mut problems);
+    problems
+}
+
+/// Checks the commands of a binding mode and finds keys bound more than once
+fn check_keys(
+    config: &Config,
+    mode: &str,
+    bindings: &[ChainedBinding],
+    tables: &[&HashMap<String, KeyPattern>],
+    exec: &HashMap<String, KeyPattern>,
+    problems: &mut Vec<String>,
+) {
+    let mut bound: Vec<(&KeyPattern, String)> = Vec::new();
+    for binding in bindings {
+        check_commands(config, mode, binding.commands.iter().map(String::as_str), problems);
+        bound.push((&binding.key, binding.commands.join("; ")));
+    }
+    for table in tables {
+        for (command, pattern) in table.iter() {
+            check_commands(config, mode, split(command), problems);
+            bound.push((pattern, command.clone()));
+        }
+    }
+    for (command, pattern) in exec.iter() {
+        bound.push((pattern, format!("exec {}", command)));
+    }
+
+    bound.sort_by(|a, b| a.1.cmp(&b.1));
+    for (i, (pattern, command)) in bound.iter().enumerate() {
+        if let Some((_, other)) = bound[..i].iter().find(|(other, _)| other == pattern) {
+            problems.push(format!(
+                "{} is bound to both '{}' and '{}' in {}",
+                pattern, other, command, mode
+            ));
+        }
+    }
+}
+
+fn split(commands: &str) -> impl Iterator<Item = &str> {
+    commands.split(';').map(str::trim).filter(|command| !command.is_empty())
+}
+
+fn check_commands<'a>(config: &Config, context: &str, commands: impl Iterator<Item = &'a str>, problems: &mut Vec<String>) {
+    for command in commands {
+        // checked on their own
+        if config.aliases.contains_key(command) {
+            continue;
+        }
+        if !is_known_command(command) {
+            problems.push(format!("Unknown command '{}' in {}", command, context));
+            continue;
+        }
+        let mut words = command.split_whitespace();
+        if words.next() == Some("mode") {
+            match words.next() {
+                Some("default") => {}
+                Some(name) if config.modes.contains_key(name) => {}
+                Some(name) => problems.push(format!("'{}' in {} enters the undefined mode {}", command, context, name)),
+                None => problems.push(format!("'mode' in {} lacks the name of a mode", context)),
+            }
+        }
+    }
+}
+
+/// Output names are either connector names like `HDMI-A-1` or identifiers like `Dell Inc. DELL U2415 ABC123`
+fn check_outputs(config: &Config, problems: &mut Vec<String>) {
+    let valid = |name: &str| name.contains(' ') || is_connector_name(name);
+
+    let mut names = config.outputs.keys().collect::<Vec<_>>();
+    names.sort();
+    for name in names {
+        if !valid(name) {
+            problems.push(format!("outputs: {} is no connector name like HDMI-A-1 or monitor identifier", name));
+        }
+        if let Some(mirror) = config.outputs[name].mirror.as_deref() {
+            if mirror == name {
+                problems.push(format!("outputs: {} mirrors itself", name));
+            } else if !valid(mirror) {
+                problems.push(format!("outputs: {} mirrors {}, which is no output name", name, mirror));
+            }
+        }
+    }
+    let mut workspaces = config.workspace.outputs.iter().collect::<Vec<_>>();
+    workspaces.sort();
+    for (workspace, output) in workspaces {
+        if !valid(output) {
+            problems.push(format!("workspace.outputs: workspace {} is assigned to {}, which is no output name", workspace, output));
+        }
+    }
+}
+
+fn is_connector_name(name: &str) -> bool {
+    // the first winit window has no number
+    if name == "WINIT" {
+        return true;
+    }
+    match name.rsplit_once('-') {
+        Some((prefix, id)) => CONNECTORS.contains(&prefix) && id.parse::<u32>().is_ok(),
+        None => false,
+    }
+}
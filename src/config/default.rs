@@ -1,4 +1,11 @@
-use crate::handler::keyboard::{KeyModifier, KeyPattern, KeySyms};
+use crate::{
+    config::{Color, ColorFilter, ColorScheme, Easing, FillMode, PowerAction, WorkspaceTransition},
+    handler::{
+        gesture::{SwipeDirection, SwipePattern},
+        keyboard::{KeyModifier, KeyPattern, KeySyms},
+    },
+    shell::layout::ContainerLayout,
+};
 
 use std::collections::HashMap;
 
@@ -32,3 +39,228 @@ pub fn exec_keys() -> HashMap<String, KeyPattern> {
 pub fn workspace_keys() -> HashMap<String, KeyPattern> {
     HashMap::new()
 }
+
+pub fn swipe_threshold() -> f64 {
+    100.0
+}
+
+pub fn workspace_gestures() -> HashMap<String, SwipePattern> {
+    let mut map = HashMap::new();
+    map.insert(
+        String::from("workspace next"),
+        SwipePattern::new(3, SwipeDirection::Left),
+    );
+    map.insert(
+        String::from("workspace prev"),
+        SwipePattern::new(3, SwipeDirection::Right),
+    );
+    map
+}
+
+pub fn repeat_delay() -> i32 {
+    200
+}
+
+pub fn repeat_rate() -> i32 {
+    25
+}
+
+pub fn tiling_layout() -> ContainerLayout {
+    ContainerLayout::SplitH
+}
+
+pub fn split_ratio() -> f64 {
+    0.5
+}
+
+pub fn resize_step() -> f64 {
+    0.05
+}
+
+pub fn master_ratio() -> f64 {
+    0.5
+}
+
+pub fn master_count() -> usize {
+    1
+}
+
+pub fn drag_modifier() -> Option<KeyModifier> {
+    Some(KeyModifier::Logo)
+}
+
+pub fn inactive_opacity() -> f32 {
+    1.0
+}
+
+pub fn border_width() -> u32 {
+    2
+}
+
+pub fn border_focused() -> Color {
+    Color([0x4c, 0x78, 0x99, 0xff])
+}
+
+pub fn border_unfocused() -> Color {
+    Color([0x33, 0x33, 0x33, 0xff])
+}
+
+pub fn border_urgent() -> Color {
+    Color([0x90, 0x00, 0x00, 0xff])
+}
+
+pub fn title_bar_height() -> u32 {
+    20
+}
+
+pub fn title_bar_text() -> Color {
+    Color([0xff, 0xff, 0xff, 0xff])
+}
+
+pub fn title_bar_close() -> Color {
+    Color([0xcc, 0x33, 0x33, 0xff])
+}
+
+pub fn title_bar_maximize() -> Color {
+    Color([0x88, 0x88, 0x88, 0xff])
+}
+
+pub fn blur_passes() -> u32 {
+    3
+}
+
+pub fn blur_strength() -> f32 {
+    2.0
+}
+
+pub fn night_temperature() -> u32 {
+    4500
+}
+
+pub fn night_start() -> String {
+    String::from("20:00")
+}
+
+pub fn night_end() -> String {
+    String::from("06:00")
+}
+
+pub fn night_transition() -> u32 {
+    30
+}
+
+pub fn color_filter() -> ColorFilter {
+    ColorFilter::Grayscale
+}
+
+pub fn max_render_time() -> u64 {
+    8
+}
+
+pub fn bit_depth() -> u32 {
+    8
+}
+
+pub fn sdr_brightness() -> u32 {
+    203
+}
+
+pub fn clamshell() -> bool {
+    true
+}
+
+pub fn winit_outputs() -> u32 {
+    1
+}
+
+pub fn vnc_address() -> String {
+    String::from("127.0.0.1:5900")
+}
+
+pub fn vnc_width() -> u32 {
+    1920
+}
+
+pub fn vnc_height() -> u32 {
+    1080
+}
+
+pub fn background_mode() -> FillMode {
+    FillMode::Fill
+}
+
+pub fn background_color() -> Color {
+    Color([0xcc, 0xcc, 0xcc, 0xff])
+}
+
+pub fn screenshot_directory() -> String {
+    String::from("~/Pictures")
+}
+
+pub fn autostart_once() -> bool {
+    true
+}
+
+pub fn power_key() -> PowerAction {
+    PowerAction::Prompt
+}
+
+pub fn suspend_key() -> PowerAction {
+    PowerAction::LockThenSuspend
+}
+
+pub fn brightness_step() -> u32 {
+    5
+}
+
+pub fn brightness_minimum() -> u32 {
+    1
+}
+
+pub fn brightness_feedback() -> bool {
+    true
+}
+
+pub fn media_keys_enabled() -> bool {
+    true
+}
+
+pub fn volume_step() -> u32 {
+    5
+}
+
+pub fn notification_timeout() -> u64 {
+    10
+}
+
+pub fn notifications_visible() -> usize {
+    5
+}
+
+pub fn screencast() -> bool {
+    true
+}
+
+pub fn color_scheme() -> ColorScheme {
+    ColorScheme::Default
+}
+
+pub fn animations_enabled() -> bool {
+    true
+}
+
+pub fn animation_duration() -> u64 {
+    150
+}
+
+pub fn workspace_duration() -> u64 {
+    200
+}
+
+pub fn workspace_transition() -> WorkspaceTransition {
+    WorkspaceTransition::Slide
+}
+
+pub fn easing() -> Easing {
+    Easing::EaseOut
+}
@@ -0,0 +1,137 @@
+//! Files merged into the configuration by `include`
+//!
+//! Paths are relative to the including file and may use `*` and `?` in their file name,
+//! matches are merged in alphabetical order, each over the file including it.
+
+use anyhow::{Context, Result};
+use serde_yaml::Value;
+use std::path::{Path, PathBuf};
+
+use crate::backend::screenshot::expand_home;
+
+/// Included files may include others, but not endlessly
+const MAX_DEPTH: usize = 8;
+
+/// Reads a file and everything it includes
+///
+/// `stack` holds the canonical paths of the files including this one.
+pub fn read(path: &Path, stack: &mut Vec<PathBuf>) -> Result<Value> {
+    let canonical = path.canonicalize()?;
+    if stack.contains(&canonical) {
+        anyhow::bail!("Cyclic include of {}", path.display());
+    }
+    let mut source = parse(path)?;
+    let patterns = match source
+        .as_mapping_mut()
+        .and_then(|mapping| mapping.remove(&Value::String(String::from("include"))))
+    {
+        None | Some(Value::Null) => return Ok(source),
+        Some(Value::String(pattern)) => vec![pattern],
+        Some(Value::Sequence(patterns)) => patterns
+            .into_iter()
+            .map(|pattern| match pattern {
+                Value::String(pattern) => Ok(pattern),
+                _ => Err(anyhow::anyhow!("include expects paths")),
+            })
+            .collect::<Result<Vec<_>>>()?,
+        Some(_) => anyhow::bail!("include expects a path or a list of paths"),
+    };
+    if stack.len() >= MAX_DEPTH {
+        anyhow::bail!("Includes are nested more than {} levels deep", MAX_DEPTH);
+    }
+
+    let directory = path.parent().unwrap_or_else(|| Path::new("."));
+    stack.push(canonical);
+    for pattern in patterns {
+        // absolute paths replace the directory
+        let pattern = directory.join(expand_home(&pattern));
+        for file in expand_glob(&pattern)? {
+            let included = read(&file, stack).with_context(|| format!("Failed to include {}", file.display()))?;
+            merge(&mut source, included);
+        }
+    }
+    stack.pop();
+    Ok(source)
+}
+
+/// Parses a single file, as toml if its name ends in `.toml` and as yaml otherwise
+fn parse(path: &Path) -> Result<Value> {
+    let contents = std::fs::read_to_string(path)?;
+    let source = if path.extension().map(|ext| ext == "toml").unwrap_or(false) {
+        serde_yaml::to_value(toml::from_str::<toml::Value>(&contents)?)?
+    } else {
+        serde_yaml::from_str(&contents)?
+    };
+    Ok(source)
+}
+
+/// Merges mappings key by key, anything else is replaced
+fn merge(base: &mut Value, over: Value) {
+    match (base, over) {
+        (Value::Mapping(base), Value::Mapping(over)) => {
+            for (key, value) in over {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, over) => *base = over,
+    }
+}
+
+/// Files matching the wildcards of a file name, paths without wildcards are taken as they are
+fn expand_glob(pattern: &Path) -> Result<Vec<PathBuf>> {
+    let name = match pattern.file_name().and_then(|name| name.to_str()) {
+        Some(name) if name.contains(|c| c == '*' || c == '?') => name,
+        _ => return Ok(vec![pattern.to_path_buf()]),
+    };
+    let directory = pattern.parent().unwrap_or_else(|| Path::new("."));
+    // an empty or missing directory of optional snippets is fine
+    let entries = match std::fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(_) => return Ok(Vec::new()),
+    };
+    let mut files = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        let matched = entry.file_name().to_str().map(|file| matches(name, file)).unwrap_or(false);
+        if matched && entry.path().is_file() {
+            files.push(entry.path());
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Matches `*` against any number of characters and `?` against exactly one
+fn matches(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let name = name.chars().collect::<Vec<_>>();
+    let (mut p, mut n) = (0, 0);
+    // position after the last `*` and the character it was matched up to
+    let mut star = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p + 1, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((after, matched)) => {
+                    p = after;
+                    n = matched + 1;
+                    star = Some((after, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
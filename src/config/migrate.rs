@@ -0,0 +1,211 @@
+//! Conversion of the `fireplace.json` of the wlc based fireplace, see `fireplace --migrate-config`
+//!
+//! Its sections were named after the handlers of `fireplace_flavors`, each binding actions to keys in `keys`.
+//! Actions and modifiers are renamed to their current equivalents, everything else is dropped and reported.
+
+use anyhow::{Context, Result};
+use serde_json::Value as Json;
+use serde_yaml::{Mapping, Value};
+
+use crate::config::Config;
+
+/// Tables of bindings by the path of their keys
+const GLOBAL: &[&str] = &["keys"];
+const VIEW: &[&str] = &["view", "keys"];
+const WORKSPACE: &[&str] = &["workspace", "keys"];
+const EXEC: &[&str] = &["exec", "keys"];
+
+/// Converts the contents of a `fireplace.json`, returns the configuration and what could not be converted
+pub fn migrate(json: &str) -> Result<(Value, Vec<String>)> {
+    let old = serde_json::from_str::<Json>(json).context("Malformed json")?;
+    let sections = old.as_object().context("Expected an object of sections")?;
+
+    let mut config = Mapping::new();
+    let mut dropped = Vec::new();
+    for (section, value) in sections {
+        match section.as_str() {
+            // unchanged since
+            "logging" => {
+                config.insert(Value::from("logging"), serde_yaml::to_value(value)?);
+            }
+            "global" | "view" | "focus" | "layout" | "exec" | "workspace" => {
+                migrate_section(&mut config, section, value, &mut dropped)
+            }
+            _ => dropped.push(format!("{}: the section has no equivalent", section)),
+        }
+    }
+
+    let config = Value::Mapping(config);
+    // the result has to load like any other configuration
+    serde_yaml::from_value::<Config>(config.clone()).context("The converted configuration is malformed")?;
+    Ok((config, dropped))
+}
+
+fn migrate_section(config: &mut Mapping, section: &str, value: &Json, dropped: &mut Vec<String>) {
+    let fields = match value.as_object() {
+        Some(fields) => fields,
+        None => {
+            dropped.push(format!("{}: expected an object", section));
+            return;
+        }
+    };
+    for (field, value) in fields {
+        match (section, field.as_str()) {
+            (_, "keys") => migrate_keys(config, section, value, dropped),
+            ("workspace", "mode") | ("workspace", "default_mode") => {
+                migrate_mode(config, "default", value, &format!("{}.{}", section, field), dropped)
+            }
+            ("workspace", "modes") | ("workspace", "layouts") => match value.as_object() {
+                Some(modes) => {
+                    for (workspace, mode) in modes {
+                        migrate_mode(config, workspace, mode, &format!("{}.{}.{}", section, field, workspace), dropped);
+                    }
+                }
+                None => dropped.push(format!("{}.{}: expected an object", section, field)),
+            },
+            _ => dropped.push(format!("{}.{}: the option has no equivalent", section, field)),
+        }
+    }
+}
+
+fn migrate_keys(config: &mut Mapping, section: &str, keys: &Json, dropped: &mut Vec<String>) {
+    let keys = match keys.as_object() {
+        Some(keys) => keys,
+        None => {
+            dropped.push(format!("{}.keys: expected an object", section));
+            return;
+        }
+    };
+    for (action, pattern) in keys {
+        let (table, command) = match command(section, action) {
+            Some(command) => command,
+            None => {
+                dropped.push(format!("{}.keys.{}: the action has no equivalent", section, action));
+                continue;
+            }
+        };
+        match key_pattern(pattern) {
+            Ok(pattern) => {
+                table_mut(config, table).insert(Value::from(command), pattern);
+            }
+            Err(err) => dropped.push(format!("{}.keys.{}: {}", section, action, err)),
+        }
+    }
+}
+
+/// Current command of an action and the path of the table it is bound in
+fn command(section: &str, action: &str) -> Option<(&'static [&'static str], String)> {
+    if section == "exec" {
+        return Some((EXEC, String::from(action)));
+    }
+    let action = action.to_lowercase();
+    let workspace = |prefixes: &[&str]| {
+        prefixes
+            .iter()
+            .find_map(|prefix| action.strip_prefix(prefix))
+            .map(|number| number.trim_start_matches('_'))
+            .filter(|number| number.parse::<u32>().is_ok())
+            .map(String::from)
+    };
+    if let Some(number) = workspace(&["move_to_workspace", "moveto_workspace", "move_workspace"]) {
+        return Some((WORKSPACE, format!("moveto_workspace{}", number)));
+    }
+    if let Some(number) = workspace(&["switch_workspace", "switch_to_workspace", "workspace"]) {
+        return Some((WORKSPACE, format!("workspace{}", number)));
+    }
+    match action.as_str() {
+        "terminate" | "quit" | "exit" => Some((GLOBAL, String::from("terminate"))),
+        "reload" => Some((GLOBAL, String::from("reload"))),
+        "close" | "kill" => Some((VIEW, String::from("close"))),
+        "focus_left" | "focus_right" | "focus_up" | "focus_down" | "focus_parent" | "focus_child" => {
+            Some((VIEW, action.replacen('_', " ", 1)))
+        }
+        "split_horizontal" | "split_vertical" => Some((VIEW, action.replacen('_', " ", 1))),
+        "layout_tabbed" | "layout_stacking" | "layout_splith" | "layout_splitv" => {
+            Some((VIEW, action.replacen('_', " ", 1)))
+        }
+        _ => None,
+    }
+}
+
+/// Converts `{ "modifiers": [...], "key": ... }` or `"Mod+Key"` to the current key pattern
+fn key_pattern(pattern: &Json) -> std::result::Result<Value, String> {
+    let (modifiers, key) = match pattern {
+        Json::String(pattern) => {
+            let mut parts = pattern.split('+').map(str::trim).collect::<Vec<_>>();
+            let key = parts.pop().filter(|key| !key.is_empty()).ok_or("expected a key")?;
+            (parts.into_iter().map(String::from).collect::<Vec<_>>(), String::from(key))
+        }
+        Json::Object(fields) => {
+            let modifiers = match fields.get("modifiers") {
+                None | Some(Json::Null) => Vec::new(),
+                Some(Json::Array(modifiers)) => modifiers
+                    .iter()
+                    .map(|modifier| modifier.as_str().map(String::from).ok_or("expected modifier names"))
+                    .collect::<std::result::Result<Vec<_>, _>>()?,
+                Some(_) => return Err(String::from("expected a list of modifiers")),
+            };
+            let key = match fields.get("key") {
+                Some(Json::String(key)) => key.clone(),
+                // digits were written as numbers
+                Some(Json::Number(key)) => key.to_string(),
+                _ => return Err(String::from("expected a key")),
+            };
+            (modifiers, key)
+        }
+        _ => return Err(String::from("expected a key pattern")),
+    };
+
+    let modifiers = modifiers
+        .iter()
+        .map(|name| modifier(name).map(Value::from).ok_or_else(|| format!("unknown modifier {}", name)))
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    let mut pattern = Mapping::new();
+    pattern.insert(Value::from("modifiers"), Value::Sequence(modifiers));
+    pattern.insert(Value::from("key"), Value::from(key));
+    Ok(Value::Mapping(pattern))
+}
+
+/// Current name of a modifier, wlc named some of them by their X11 modifier
+fn modifier(name: &str) -> Option<&'static str> {
+    match name.to_lowercase().as_str() {
+        "ctrl" | "control" => Some("Ctrl"),
+        "alt" | "mod1" => Some("Alt"),
+        "shift" => Some("Shift"),
+        "logo" | "super" | "mod4" => Some("Logo"),
+        "caps" | "capslock" => Some("CapsLock"),
+        "numlock" | "mod2" => Some("NumLock"),
+        _ => None,
+    }
+}
+
+/// Sets the mode of a workspace, or of all by `default`
+fn migrate_mode(config: &mut Mapping, workspace: &str, mode: &Json, option: &str, dropped: &mut Vec<String>) {
+    let mode = match mode.as_str().map(str::to_lowercase).as_deref() {
+        Some("tiling") | Some("tiled") | Some("i3") => "tiling",
+        Some("floating") | Some("float") => "floating",
+        Some("monocle") => "monocle",
+        Some("master_stack") | Some("masterstack") | Some("master") => "master_stack",
+        _ => {
+            dropped.push(format!("{}: unknown mode {}", option, mode));
+            return;
+        }
+    };
+    table_mut(config, &["workspace", "layouts", workspace]).insert(Value::from("mode"), Value::from(mode));
+}
+
+/// Nested mapping at the given path, created if missing
+fn table_mut<'a>(config: &'a mut Mapping, path: &[&str]) -> &'a mut Mapping {
+    let mut table = config;
+    for name in path {
+        let key = Value::from(*name);
+        if !matches!(table.get(&key), Some(Value::Mapping(_))) {
+            table.insert(key.clone(), Value::Mapping(Mapping::new()));
+        }
+        table = match table.get_mut(&key) {
+            Some(Value::Mapping(mapping)) => mapping,
+            _ => unreachable!(),
+        };
+    }
+    table
+}
@@ -1,11 +1,23 @@
 //! # Fireplace configuration
 //!
-use crate::{handler::keyboard::KeyPattern, logger::Logging};
+use crate::{handler::{gesture::SwipePattern, keyboard::{KeyModifier, KeyPattern}}, logger::Logging, shell::layout::ContainerLayout};
 
+use regex::Regex;
 use serde::Deserialize;
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    path::{Path, PathBuf},
+};
 
+mod check;
 mod default;
+mod include;
+mod migrate;
+mod variables;
+
+pub use self::check::check;
+pub use self::migrate::migrate;
 
 /// Main configuration struct
 ///
@@ -22,15 +34,847 @@ pub struct Config {
     /// * terminate => End the compositor
     #[serde(default = "crate::config::default::keys")]
     pub keys: HashMap<String, KeyPattern>,
+    /// Keys bound to a list of commands of any kind
+    #[serde(default)]
+    pub bindings: Vec<ChainedBinding>,
+    /// Names for commands separated by `;`, usable wherever commands are
+    #[serde(default)]
+    pub aliases: HashMap<String, String>,
     /// Configuration of keys related to `View`s
     #[serde(default)]
     pub view: View,
     /// Configuration of program execution by keys.
     #[serde(default)]
     pub exec: Exec,
+    /// Commands started with the session
+    #[serde(default)]
+    pub autostart: Vec<Autostart>,
+    /// Start the desktop entries of the XDG autostart directories with the session
+    #[serde(default)]
+    pub xdg_autostart: bool,
     /// Configuration for Workspaces
     #[serde(default)]
     pub workspace: WorkspacesConfig,
+    /// Configuration of server-side decorations
+    #[serde(default)]
+    pub decoration: Decoration,
+    /// Configuration of input devices
+    #[serde(default)]
+    pub input: InputConfig,
+    /// Named binding modes, entered by the `mode <name>` command
+    #[serde(default)]
+    pub modes: HashMap<String, BindingMode>,
+    /// Touchpad gesture bindings
+    #[serde(default)]
+    pub gestures: Gestures,
+    /// Names of additional seats, `seat-1` always exists
+    #[serde(default)]
+    pub seats: Vec<String>,
+    /// Rules assigning input devices to seats
+    #[serde(default)]
+    pub seat_rules: Vec<SeatRule>,
+    /// Commands run by the lid and tablet mode switches
+    #[serde(default)]
+    pub switches: Switches,
+    /// Visibility of the cursor
+    #[serde(default)]
+    pub cursor: CursorConfig,
+    /// Per output configuration by monitor or output name
+    #[serde(default)]
+    pub outputs: HashMap<String, OutputConfig>,
+    /// Seconds without input until outputs are powered off
+    #[serde(default)]
+    pub dpms_timeout: Option<u64>,
+    /// Milliseconds before the vblank rendering a frame starts
+    #[serde(default = "default::max_render_time")]
+    pub max_render_time: u64,
+    /// Warmer colors at night
+    #[serde(default)]
+    pub night_light: NightLight,
+    /// Color filter applied to the outputs, for users with impaired vision
+    #[serde(default)]
+    pub color_filter: ColorFilterConfig,
+    /// Disable the internal panel while the lid is closed and an external output is connected
+    #[serde(default = "default::clamshell")]
+    pub clamshell: bool,
+    /// Actions of the power and suspend keys
+    #[serde(default)]
+    pub power_keys: PowerKeys,
+    /// Backlight controlled by the `brightness` command
+    #[serde(default)]
+    pub brightness: BrightnessConfig,
+    /// Built-in handling of the volume and media player keys
+    #[serde(default)]
+    pub media_keys: MediaKeys,
+    /// Built-in notification daemon
+    #[serde(default)]
+    pub notifications: NotificationsConfig,
+    /// Nested mode configuration
+    #[serde(default)]
+    pub winit: WinitConfig,
+    /// Serve an additional virtual output over vnc
+    #[serde(default)]
+    pub vnc: Option<VncConfig>,
+    /// Address to serve frame statistics on in the Prometheus text format
+    #[serde(default)]
+    pub prometheus: Option<String>,
+    /// Where the `screenshot` command saves its images
+    #[serde(default)]
+    pub screenshot: ScreenshotConfig,
+    /// Offer outputs and windows for screen sharing through xdg-desktop-portal
+    #[serde(default = "default::screencast")]
+    pub screencast: bool,
+    /// Preferred color scheme announced to applications by the settings portal
+    #[serde(default = "default::color_scheme")]
+    pub color_scheme: ColorScheme,
+    /// Pass the wayland display and other session variables to the systemd user instance
+    #[serde(default)]
+    pub import_environment: bool,
+    /// Window animations
+    #[serde(default)]
+    pub animations: Animations,
+    /// Wallpaper drawn behind the windows of every output
+    #[serde(default)]
+    pub background: Background,
+}
+
+/// Configuration of the nested winit backend
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct WinitConfig {
+    /// Number of virtual outputs, each gets a window of its own
+    #[serde(default = "default::winit_outputs")]
+    pub outputs: u32,
+}
+
+impl Default for WinitConfig {
+    fn default() -> WinitConfig {
+        WinitConfig {
+            outputs: default::winit_outputs(),
+        }
+    }
+}
+
+/// Durations of window animations in milliseconds, 0 disables an animation
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Animations {
+    /// Disables all animations
+    #[serde(default = "default::animations_enabled")]
+    pub enabled: bool,
+    /// New windows fade and scale in
+    #[serde(default = "default::animation_duration")]
+    pub open: u64,
+    /// Closed windows fade and scale out
+    #[serde(default = "default::animation_duration")]
+    pub close: u64,
+    /// Windows moved by their layout slide to their new location
+    #[serde(default = "default::animation_duration", rename = "move")]
+    pub moves: u64,
+    /// Outputs switching their workspace transition between both
+    #[serde(default = "default::workspace_duration")]
+    pub workspace: u64,
+    #[serde(default = "default::workspace_transition")]
+    pub workspace_transition: WorkspaceTransition,
+    #[serde(default = "default::easing")]
+    pub easing: Easing,
+}
+
+impl Default for Animations {
+    fn default() -> Animations {
+        Animations {
+            enabled: default::animations_enabled(),
+            open: default::animation_duration(),
+            close: default::animation_duration(),
+            moves: default::animation_duration(),
+            workspace: default::workspace_duration(),
+            workspace_transition: default::workspace_transition(),
+            easing: default::easing(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum WorkspaceTransition {
+    /// The new workspace pushes the old one off the output, from the right when switching to a higher number
+    Slide,
+    /// The old workspace fades into the new one
+    Crossfade,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Easing {
+    Linear,
+    EaseOut,
+    EaseInOut,
+}
+
+impl Easing {
+    /// Maps the linear progress of an animation between 0 and 1
+    pub fn apply(self, t: f32) -> f32 {
+        let t = t.max(0.0).min(1.0);
+        match self {
+            Easing::Linear => t,
+            Easing::EaseOut => 1.0 - (1.0 - t).powi(3),
+            Easing::EaseInOut => {
+                if t < 0.5 {
+                    4.0 * t.powi(3)
+                } else {
+                    1.0 - (-2.0 * t + 2.0).powi(3) / 2.0
+                }
+            }
+        }
+    }
+}
+
+/// Configuration of the wallpaper
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Background {
+    /// Image file, `~` expands to the home directory, only the color is drawn if unset
+    #[serde(default)]
+    pub image: Option<String>,
+    /// How the image is fitted to the output
+    #[serde(default = "default::background_mode")]
+    pub mode: FillMode,
+    /// Color of the parts of the output not covered by the image
+    #[serde(default = "default::background_color")]
+    pub color: Color,
+}
+
+impl Default for Background {
+    fn default() -> Background {
+        Background {
+            image: None,
+            mode: default::background_mode(),
+            color: default::background_color(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FillMode {
+    /// Scaled to cover the whole output, cutting off the edges
+    Fill,
+    /// Scaled to fit onto the output, leaving bars of the color
+    Fit,
+    /// Scaled to the size of the output, ignoring the aspect ratio
+    Stretch,
+    /// Drawn unscaled at the center
+    Center,
+    /// Repeated unscaled from the top left corner
+    Tile,
+}
+
+/// Configuration of the `screenshot` command
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ScreenshotConfig {
+    /// Directory the timestamped png files are written to, `~` expands to the home directory
+    #[serde(default = "default::screenshot_directory")]
+    pub directory: String,
+}
+
+impl Default for ScreenshotConfig {
+    fn default() -> ScreenshotConfig {
+        ScreenshotConfig {
+            directory: default::screenshot_directory(),
+        }
+    }
+}
+
+/// Values of `color-scheme` in the `org.freedesktop.appearance` namespace
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ColorScheme {
+    /// Applications choose themselves
+    Default,
+    Dark,
+    Light,
+}
+
+/// Configuration of the virtual vnc output
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct VncConfig {
+    /// Address to listen on for clients
+    #[serde(default = "default::vnc_address")]
+    pub address: String,
+    /// Width of the virtual output in pixels
+    #[serde(default = "default::vnc_width")]
+    pub width: u32,
+    /// Height of the virtual output in pixels
+    #[serde(default = "default::vnc_height")]
+    pub height: u32,
+}
+
+/// Color temperature schedule of the outputs
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct NightLight {
+    /// Enabled on startup, may be toggled with the `night_light` command
+    #[serde(default)]
+    pub enabled: bool,
+    /// Color temperature at night in kelvin
+    #[serde(default = "default::night_temperature")]
+    pub temperature: u32,
+    /// Start of the night as `HH:MM`, unless a location is given
+    #[serde(default = "default::night_start")]
+    pub start: String,
+    /// End of the night as `HH:MM`, unless a location is given
+    #[serde(default = "default::night_end")]
+    pub end: String,
+    /// Location to follow sunset and sunrise at
+    #[serde(default)]
+    pub latitude: Option<f64>,
+    #[serde(default)]
+    pub longitude: Option<f64>,
+    /// Minutes to ramp the temperature
+    #[serde(default = "default::night_transition")]
+    pub transition: u32,
+}
+
+impl Default for NightLight {
+    fn default() -> NightLight {
+        NightLight {
+            enabled: false,
+            temperature: default::night_temperature(),
+            start: default::night_start(),
+            end: default::night_end(),
+            latitude: None,
+            longitude: None,
+            transition: default::night_transition(),
+        }
+    }
+}
+
+/// Filter the colors of an output are drawn through, see `ColorFilterConfig`
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ColorFilter {
+    Grayscale,
+    Invert,
+    /// Shifts red, which is hard to tell apart without red cones, towards distinguishable colors
+    Protanopia,
+    /// Shifts green, which is hard to tell apart without green cones, towards distinguishable colors
+    Deuteranopia,
+}
+
+/// Color filter of all outputs, unless overridden per output
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ColorFilterConfig {
+    /// Enabled on startup, may be toggled with the `color_filter` command
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default::color_filter")]
+    pub filter: ColorFilter,
+}
+
+impl Default for ColorFilterConfig {
+    fn default() -> ColorFilterConfig {
+        ColorFilterConfig {
+            enabled: false,
+            filter: default::color_filter(),
+        }
+    }
+}
+
+impl Config {
+    /// Filter of an output, while color filters are `active`
+    pub fn color_filter(&self, active: Option<ColorFilter>, connector: &str, identifier: &str) -> Option<ColorFilter> {
+        active.map(|filter| {
+            self.output(connector, identifier)
+                .and_then(|output| output.color_filter)
+                .unwrap_or(filter)
+        })
+    }
+
+    /// Looks up the configuration of an output
+    ///
+    /// Entries naming the monitor by `<make> <model> <serial>` take precedence over
+    /// those naming the connector, as connector names change between docks.
+    pub fn output(&self, connector: &str, identifier: &str) -> Option<&OutputConfig> {
+        self.outputs.get(identifier).or_else(|| self.outputs.get(connector))
+    }
+
+    /// Combines the settings of all `view.rules` matching a window, later rules take precedence
+    pub fn window_settings(&self, app_id: Option<&str>, title: Option<&str>) -> WindowSettings {
+        self.view
+            .rules
+            .iter()
+            .filter(|rule| rule.matches(app_id, title))
+            .fold(WindowSettings::default(), |settings, rule| WindowSettings {
+                opacity: rule.opacity.or(settings.opacity),
+                corner_radius: rule.corner_radius.or(settings.corner_radius),
+                blur: rule.blur.or(settings.blur),
+                decorations: rule.decorations.or(settings.decorations),
+                workspace: rule.workspace.clone().or(settings.workspace),
+                floating: rule.floating.or(settings.floating),
+                inhibit_idle: rule.inhibit_idle.or(settings.inhibit_idle),
+            })
+    }
+}
+
+/// Configuration of a single output
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct OutputConfig {
+    /// Present the content of another output instead of a workspace
+    #[serde(default)]
+    pub mirror: Option<String>,
+    /// Overrides the global dpms timeout, 0 keeps the output on
+    #[serde(default)]
+    pub dpms_timeout: Option<u64>,
+    /// Custom mode in the xorg.conf modeline format
+    #[serde(default)]
+    pub modeline: Option<String>,
+    /// Location in the global space, instead of the automatic arrangement
+    #[serde(default)]
+    pub position: Option<OutputPosition>,
+    /// Bits per color channel of the scanout buffers, 8 or 10
+    #[serde(default = "default::bit_depth")]
+    pub bit_depth: u32,
+    /// Drive the monitor with the PQ curve and BT.2020 primaries, implies a bit depth of 10
+    #[serde(default)]
+    pub hdr: bool,
+    /// Luminance in nits white of sdr content is shown with in hdr mode
+    #[serde(default = "default::sdr_brightness")]
+    pub sdr_brightness: u32,
+    /// Overrides the filter of `color_filter` for this output
+    #[serde(default)]
+    pub color_filter: Option<ColorFilter>,
+}
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        OutputConfig {
+            mirror: None,
+            dpms_timeout: None,
+            modeline: None,
+            position: None,
+            bit_depth: default::bit_depth(),
+            hdr: false,
+            sdr_brightness: default::sdr_brightness(),
+            color_filter: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(deny_unknown_fields)]
+pub struct OutputPosition {
+    pub x: i32,
+    pub y: i32,
+}
+
+/// When to hide the cursor until it is moved again
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct CursorConfig {
+    /// Seconds without motion
+    #[serde(default)]
+    pub hide_after: Option<u64>,
+    /// Hide on key presses
+    #[serde(default)]
+    pub hide_when_typing: bool,
+}
+
+/// Commands run when a switch is toggled, e.g. `exec systemctl suspend`
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Switches {
+    #[serde(default)]
+    pub lid_closed: Vec<String>,
+    #[serde(default)]
+    pub lid_opened: Vec<String>,
+    #[serde(default)]
+    pub tablet_mode_on: Vec<String>,
+    #[serde(default)]
+    pub tablet_mode_off: Vec<String>,
+}
+
+/// Configuration of the `brightness` command
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct BrightnessConfig {
+    /// Name of the device in `/sys/class/backlight`, the first one if unset
+    #[serde(default)]
+    pub device: Option<String>,
+    /// Percent `brightness up` and `brightness down` change the brightness by
+    #[serde(default = "default::brightness_step")]
+    pub step: u32,
+    /// Lowest percentage, so the panel does not go dark
+    #[serde(default = "default::brightness_minimum")]
+    pub minimum: u32,
+    /// Show the new brightness on screen
+    #[serde(default = "default::brightness_feedback")]
+    pub feedback: bool,
+}
+
+impl Default for BrightnessConfig {
+    fn default() -> BrightnessConfig {
+        BrightnessConfig {
+            device: None,
+            step: default::brightness_step(),
+            minimum: default::brightness_minimum(),
+            feedback: default::brightness_feedback(),
+        }
+    }
+}
+
+/// Handling of the volume and media player keys not bound to commands
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct MediaKeys {
+    #[serde(default = "default::media_keys_enabled")]
+    pub enabled: bool,
+    /// Percent the volume keys change the volume by
+    #[serde(default = "default::volume_step")]
+    pub volume_step: u32,
+}
+
+impl Default for MediaKeys {
+    fn default() -> MediaKeys {
+        MediaKeys {
+            enabled: default::media_keys_enabled(),
+            volume_step: default::volume_step(),
+        }
+    }
+}
+
+/// Serving `org.freedesktop.Notifications` and showing the popups on the active output
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct NotificationsConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Seconds notifications are shown, unless they ask for another timeout
+    #[serde(default = "default::notification_timeout")]
+    pub timeout: u64,
+    /// Notifications shown at once, older ones are closed
+    #[serde(default = "default::notifications_visible")]
+    pub max_visible: usize,
+}
+
+impl Default for NotificationsConfig {
+    fn default() -> NotificationsConfig {
+        NotificationsConfig {
+            enabled: false,
+            timeout: default::notification_timeout(),
+            max_visible: default::notifications_visible(),
+        }
+    }
+}
+
+/// Actions of the power and suspend keys, keys not left to logind are taken away from it
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct PowerKeys {
+    #[serde(default = "default::power_key")]
+    pub power: PowerAction,
+    #[serde(default = "default::suspend_key")]
+    pub suspend: PowerAction,
+}
+
+impl Default for PowerKeys {
+    fn default() -> PowerKeys {
+        PowerKeys {
+            power: default::power_key(),
+            suspend: default::suspend_key(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PowerAction {
+    /// Handled by logind as configured in `logind.conf`
+    Logind,
+    Suspend,
+    /// Locks the session before suspending, so it is locked after waking up
+    LockThenSuspend,
+    PowerOff,
+    /// Asks to press the key again, to power off or suspend
+    Prompt,
+}
+
+/// Assigns all input devices matching the given criteria to a seat
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SeatRule {
+    /// Matches the name of the device, if set
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Matches the beginning of the sysfs path of the device, if set
+    #[serde(default)]
+    pub syspath: Option<String>,
+    /// Matches the usb vendor and product id as `vvvv:pppp` in hex, if set
+    #[serde(default)]
+    pub usb_id: Option<String>,
+    /// Name of the seat
+    pub seat: String,
+}
+
+impl SeatRule {
+    /// Checks if all given criteria match the device
+    pub fn matches(&self, name: &str, syspath: Option<&Path>, usb_id: Option<(u32, u32)>) -> bool {
+        let usb_id = usb_id.map(|(vendor, product)| format!("{:04x}:{:04x}", vendor, product));
+        self.name.as_deref().map(|x| x == name).unwrap_or(true)
+            && self
+                .syspath
+                .as_deref()
+                .map(|x| syspath.map(|path| path.starts_with(x)).unwrap_or(false))
+                .unwrap_or(true)
+            && self
+                .usb_id
+                .as_deref()
+                .map(|x| usb_id.as_deref().map(|id| id.eq_ignore_ascii_case(x)).unwrap_or(false))
+                .unwrap_or(true)
+    }
+}
+
+/// Touchpad swipes bound to commands, grouped like the keybindings
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Gestures {
+    /// Distance in touchpad units a swipe has to travel to trigger a binding
+    #[serde(default = "crate::config::default::swipe_threshold")]
+    pub threshold: f64,
+    #[serde(default)]
+    pub global: HashMap<String, SwipePattern>,
+    #[serde(default = "crate::config::default::workspace_gestures")]
+    pub workspace: HashMap<String, SwipePattern>,
+    #[serde(default)]
+    pub view: HashMap<String, SwipePattern>,
+    #[serde(default)]
+    pub exec: HashMap<String, SwipePattern>,
+}
+
+impl Default for Gestures {
+    fn default() -> Gestures {
+        Gestures {
+            threshold: default::swipe_threshold(),
+            global: HashMap::new(),
+            workspace: default::workspace_gestures(),
+            view: HashMap::new(),
+            exec: HashMap::new(),
+        }
+    }
+}
+
+/// Key bound to a list of commands, run in order
+///
+/// Commands are routed to their handler by name, programs are
+/// started with `exec <command>`.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ChainedBinding {
+    pub key: KeyPattern,
+    pub commands: Vec<String>,
+}
+
+/// Keybindings active while a binding mode is entered
+///
+/// They replace all other bindings. `Escape` returns to the default mode,
+/// unless it is bound to something else.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct BindingMode {
+    /// Keys bound to a list of commands
+    #[serde(default)]
+    pub bindings: Vec<ChainedBinding>,
+    /// Global actions, including `mode default`
+    #[serde(default)]
+    pub keys: HashMap<String, KeyPattern>,
+    /// Actions related to `View`s
+    #[serde(default)]
+    pub view: HashMap<String, KeyPattern>,
+    /// Workspace actions
+    #[serde(default)]
+    pub workspace: HashMap<String, KeyPattern>,
+    /// Commands to launch
+    #[serde(default)]
+    pub exec: HashMap<String, KeyPattern>,
+}
+
+/// Configuration of libinput devices
+///
+/// Unset options keep the defaults of libinput.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct InputConfig {
+    /// Tapping on a touchpad generates clicks
+    #[serde(default)]
+    pub tap_to_click: Option<bool>,
+    /// Content moves with the fingers instead of the scrollbars
+    #[serde(default)]
+    pub natural_scroll: Option<bool>,
+    #[serde(default)]
+    pub scroll_method: Option<ScrollMethod>,
+    #[serde(default)]
+    pub click_method: Option<ClickMethod>,
+    /// Pointer acceleration between -1.0 (slowest) and 1.0 (fastest)
+    #[serde(default)]
+    pub accel_speed: Option<f64>,
+    #[serde(default)]
+    pub accel_profile: Option<AccelProfile>,
+    /// Swaps the left and right buttons
+    #[serde(default)]
+    pub left_handed: Option<bool>,
+    /// Ignore touchpads for this many milliseconds after a key press
+    #[serde(default)]
+    pub disable_while_typing: Option<u32>,
+    /// Mapping of drawing tablets
+    #[serde(default)]
+    pub tablet: TabletConfig,
+    /// Keyboard configuration
+    #[serde(default)]
+    pub keyboard: KeyboardConfig,
+    /// Overrides of the options above for devices with the given name
+    #[serde(default)]
+    pub devices: HashMap<String, DeviceConfig>,
+}
+
+impl InputConfig {
+    /// Options of a device, its overrides take precedence over the global options
+    pub fn device(&self, name: &str) -> DeviceConfig {
+        let global = DeviceConfig {
+            tap_to_click: self.tap_to_click,
+            natural_scroll: self.natural_scroll,
+            scroll_method: self.scroll_method,
+            click_method: self.click_method,
+            accel_speed: self.accel_speed,
+            accel_profile: self.accel_profile,
+            left_handed: self.left_handed,
+        };
+        match self.devices.get(name) {
+            Some(device) => DeviceConfig {
+                tap_to_click: device.tap_to_click.or(global.tap_to_click),
+                natural_scroll: device.natural_scroll.or(global.natural_scroll),
+                scroll_method: device.scroll_method.or(global.scroll_method),
+                click_method: device.click_method.or(global.click_method),
+                accel_speed: device.accel_speed.or(global.accel_speed),
+                accel_profile: device.accel_profile.or(global.accel_profile),
+                left_handed: device.left_handed.or(global.left_handed),
+            },
+            None => global,
+        }
+    }
+}
+
+/// libinput options of a single device
+///
+/// Unset options fall back to the global input configuration.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceConfig {
+    #[serde(default)]
+    pub tap_to_click: Option<bool>,
+    #[serde(default)]
+    pub natural_scroll: Option<bool>,
+    #[serde(default)]
+    pub scroll_method: Option<ScrollMethod>,
+    #[serde(default)]
+    pub click_method: Option<ClickMethod>,
+    #[serde(default)]
+    pub accel_speed: Option<f64>,
+    #[serde(default)]
+    pub accel_profile: Option<AccelProfile>,
+    #[serde(default)]
+    pub left_handed: Option<bool>,
+}
+
+/// Area of the outputs drawing tablets are mapped to
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct TabletConfig {
+    /// Output the tablet is mapped to, defaults to the output with the pointer
+    #[serde(default)]
+    pub output: Option<String>,
+    /// Region of the output in logical pixels, defaults to the whole output
+    #[serde(default)]
+    pub region: Option<TabletRegion>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(deny_unknown_fields)]
+pub struct TabletRegion {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+/// Configuration of keyboards
+///
+/// Empty XKB values use the system defaults (or the `XKB_DEFAULT_*` variables).
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct KeyboardConfig {
+    /// Delay before a held key starts repeating in milliseconds
+    #[serde(default = "crate::config::default::repeat_delay")]
+    pub repeat_delay: i32,
+    /// Repeated key presses per second
+    #[serde(default = "crate::config::default::repeat_rate")]
+    pub repeat_rate: i32,
+    /// XKB rules, e.g. "evdev"
+    #[serde(default)]
+    pub rules: String,
+    /// XKB model, e.g. "pc105"
+    #[serde(default)]
+    pub model: String,
+    /// XKB layouts, comma separated, e.g. "us,de"
+    #[serde(default)]
+    pub layout: String,
+    /// XKB variants of the layouts, e.g. "nodeadkeys"
+    #[serde(default)]
+    pub variant: String,
+    /// XKB options, e.g. "caps:escape"
+    #[serde(default)]
+    pub options: Option<String>,
+}
+
+impl Default for KeyboardConfig {
+    fn default() -> KeyboardConfig {
+        KeyboardConfig {
+            repeat_delay: default::repeat_delay(),
+            repeat_rate: default::repeat_rate(),
+            rules: String::new(),
+            model: String::new(),
+            layout: String::new(),
+            variant: String::new(),
+            options: None,
+        }
+    }
+}
+
+/// When scroll events are generated
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ScrollMethod {
+    None,
+    TwoFinger,
+    Edge,
+    OnButtonDown,
+}
+
+/// How clicks on touchpads without buttons are generated
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ClickMethod {
+    ButtonAreas,
+    Clickfinger,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AccelProfile {
+    Flat,
+    Adaptive,
 }
 
 impl Default for Config {
@@ -38,13 +882,56 @@ impl Default for Config {
         Config {
             logging: Logging::default(),
             keys: default::keys(),
+            bindings: Vec::new(),
+            aliases: HashMap::new(),
             view: View::default(),
             exec: Exec::default(),
+            autostart: Vec::new(),
+            xdg_autostart: false,
             workspace: WorkspacesConfig::default(),
+            decoration: Decoration::default(),
+            input: InputConfig::default(),
+            modes: HashMap::new(),
+            gestures: Gestures::default(),
+            seats: Vec::new(),
+            seat_rules: Vec::new(),
+            switches: Switches::default(),
+            cursor: CursorConfig::default(),
+            outputs: HashMap::new(),
+            dpms_timeout: None,
+            max_render_time: default::max_render_time(),
+            night_light: NightLight::default(),
+            color_filter: ColorFilterConfig::default(),
+            clamshell: default::clamshell(),
+            power_keys: PowerKeys::default(),
+            brightness: BrightnessConfig::default(),
+            media_keys: MediaKeys::default(),
+            notifications: NotificationsConfig::default(),
+            winit: WinitConfig::default(),
+            vnc: None,
+            screenshot: ScreenshotConfig::default(),
+            screencast: default::screencast(),
+            color_scheme: default::color_scheme(),
+            import_environment: false,
+            animations: Animations::default(),
+            background: Background::default(),
         }
     }
 }
 
+/// Names of the configuration file, the first one found is used
+pub const FILE_NAMES: &[&str] = &["fireplace.yaml", "fireplace.toml"];
+
+/// Reads a configuration file, as toml if its name ends in `.toml` and as yaml otherwise
+///
+/// Toml is converted to yaml, which is what values set by ipc are merged into.
+/// The files listed by `include` are merged into it, then the `variables` of all of them are substituted.
+pub fn read_source(path: &Path) -> anyhow::Result<serde_yaml::Value> {
+    let mut source = include::read(path, &mut Vec::new())?;
+    variables::substitute(&mut source)?;
+    Ok(source)
+}
+
 #[derive(Deserialize, Debug)]
 #[serde(deny_unknown_fields)]
 /// View related configuration options
@@ -52,18 +939,117 @@ pub struct View {
     /// A `HashMap` of global actions that may be invoked through keys.
     ///
     /// * close => Close the currently focused `View`
+    /// * opacity <value|+step|-step> => Change the opacity of the focused `View`
     #[serde(default = "crate::config::default::view_keys")]
     pub keys: HashMap<String, KeyPattern>,
+    /// Opacity multiplied onto all unfocused `View`s, 1.0 disables dimming
+    #[serde(default = "crate::config::default::inactive_opacity")]
+    pub inactive_opacity: f32,
+    /// Settings of `View`s, matched by app_id and title
+    #[serde(default)]
+    pub rules: Vec<WindowRule>,
+    /// Modifier to move `View`s with the left and resize them with the right button
+    #[serde(default = "crate::config::default::drag_modifier")]
+    pub drag_modifier: Option<KeyModifier>,
+    /// Move the pointer to `View`s focused by keyboard commands
+    #[serde(default)]
+    pub warp_pointer: bool,
 }
 
 impl Default for View {
     fn default() -> View {
         View {
             keys: default::view_keys(),
+            inactive_opacity: default::inactive_opacity(),
+            rules: Vec::new(),
+            drag_modifier: default::drag_modifier(),
+            warp_pointer: false,
         }
     }
 }
 
+/// Settings of all windows matching the given patterns
+///
+/// Rules are checked again whenever a window changes its app_id or title,
+/// later rules take precedence over earlier ones.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct WindowRule {
+    /// Matches the app_id of the window, if set
+    #[serde(default)]
+    pub app_id: Option<WindowPattern>,
+    /// Matches the title of the window, if set
+    #[serde(default)]
+    pub title: Option<WindowPattern>,
+    /// Opacity of the window between 0.0 and 1.0
+    #[serde(default)]
+    pub opacity: Option<f32>,
+    /// Radius of the window corners, overriding `decoration.corner_radius`
+    #[serde(default)]
+    pub corner_radius: Option<u32>,
+    /// Blur whatever is behind the window, for translucent terminals or bars
+    #[serde(default)]
+    pub blur: Option<bool>,
+    /// Draw the title bar and border around the window
+    #[serde(default)]
+    pub decorations: Option<bool>,
+    /// Workspace the window is moved to, by number or name
+    #[serde(default)]
+    pub workspace: Option<String>,
+    /// Keep the window out of the tiling tree, centered above the tiled windows
+    #[serde(default)]
+    pub floating: Option<bool>,
+    /// Keep the outputs powered on, while the window is shown
+    #[serde(default)]
+    pub inhibit_idle: Option<bool>,
+}
+
+impl WindowRule {
+    /// Checks if all given patterns match the window, windows without app_id or title match no pattern for them
+    pub fn matches(&self, app_id: Option<&str>, title: Option<&str>) -> bool {
+        let matches = |pattern: &Option<WindowPattern>, text: Option<&str>| {
+            pattern
+                .as_ref()
+                .map(|pattern| text.map(|text| pattern.is_match(text)).unwrap_or(false))
+                .unwrap_or(true)
+        };
+        matches(&self.app_id, app_id) && matches(&self.title, title)
+    }
+}
+
+/// Regular expression, which has to match a whole app_id or title
+///
+/// Plain names without special characters match exactly.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(try_from = "String")]
+pub struct WindowPattern(Regex);
+
+impl TryFrom<String> for WindowPattern {
+    type Error = regex::Error;
+
+    fn try_from(pattern: String) -> Result<WindowPattern, regex::Error> {
+        Regex::new(&format!("^(?:{})$", pattern)).map(WindowPattern)
+    }
+}
+
+impl WindowPattern {
+    pub fn is_match(&self, text: &str) -> bool {
+        self.0.is_match(text)
+    }
+}
+
+/// Combined settings of all `WindowRule`s matching a window
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WindowSettings {
+    pub opacity: Option<f32>,
+    pub corner_radius: Option<u32>,
+    pub blur: Option<bool>,
+    pub decorations: Option<bool>,
+    pub workspace: Option<String>,
+    pub floating: Option<bool>,
+    pub inhibit_idle: Option<bool>,
+}
+
 #[derive(Deserialize, Debug)]
 #[serde(deny_unknown_fields)]
 /// Exec/Launcher related configuration options
@@ -75,12 +1061,56 @@ pub struct Exec {
     /// Executables available in `$PATH` do not need to be given in full path.
     #[serde(default = "crate::config::default::exec_keys")]
     pub keys: HashMap<String, KeyPattern>,
+    /// Run every spawned command in a transient systemd user scope named after it
+    #[serde(default)]
+    pub scopes: bool,
 }
 
 impl Default for Exec {
     fn default() -> Exec {
         Exec {
             keys: default::exec_keys(),
+            scopes: false,
+        }
+    }
+}
+
+/// Command run by the shell once the first output is up
+#[derive(Deserialize, Debug, Clone)]
+#[serde(from = "AutostartRepr")]
+pub struct Autostart {
+    pub command: String,
+    /// Only run at startup, otherwise again whenever the configuration file is reloaded
+    pub once: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AutostartDef {
+    command: String,
+    #[serde(default = "default::autostart_once")]
+    once: bool,
+}
+
+/// Plain strings are commands run once
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AutostartRepr {
+    Short(String),
+    Full(AutostartDef),
+}
+
+impl From<AutostartRepr> for Autostart {
+    fn from(repr: AutostartRepr) -> Autostart {
+        match repr {
+            AutostartRepr::Short(command) => Autostart {
+                command,
+                once: default::autostart_once(),
+            },
+            AutostartRepr::Full(def) => Autostart {
+                command: def.command,
+                once: def.once,
+            },
         }
     }
 }
@@ -92,12 +1122,236 @@ pub struct WorkspacesConfig {
     /// Key configuration
     #[serde(default = "crate::config::default::workspace_keys")]
     pub keys: HashMap<String, KeyPattern>,
+    /// Names of workspaces by their number
+    #[serde(default)]
+    pub names: HashMap<u32, String>,
+    /// Give every output its own set of workspaces instead of sharing them
+    #[serde(default)]
+    pub per_output: bool,
+    /// Create and destroy workspaces on demand, `workspace next` and
+    /// `workspace prev` then only visit populated ones plus an empty one
+    #[serde(default)]
+    pub dynamic: bool,
+    /// Layouts of new workspaces by number, name or `default`
+    #[serde(default)]
+    pub layouts: HashMap<String, LayoutConfig>,
+    /// Outputs workspaces are shown on, by workspace number or name
+    #[serde(default)]
+    pub outputs: HashMap<String, String>,
+    /// Switch to the workspace a window is moved to with `moveto_workspace`
+    #[serde(default)]
+    pub follow: bool,
 }
 
 impl Default for WorkspacesConfig {
     fn default() -> WorkspacesConfig {
         WorkspacesConfig {
             keys: default::workspace_keys(),
+            names: HashMap::new(),
+            per_output: false,
+            dynamic: false,
+            layouts: HashMap::new(),
+            outputs: HashMap::new(),
+            follow: false,
+        }
+    }
+}
+
+/// Layout a workspace starts with and its parameters
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "mode", rename_all = "snake_case", deny_unknown_fields)]
+pub enum LayoutConfig {
+    /// Freely movable and resizable windows
+    Floating,
+    /// i3-like tiling
+    Tiling {
+        /// How the outermost container arranges its children
+        #[serde(default = "crate::config::default::tiling_layout")]
+        layout: ContainerLayout,
+        /// Share of the space of the focused window it keeps, when a new window opens next to it
+        #[serde(default = "crate::config::default::split_ratio")]
+        split_ratio: f64,
+        /// Share of a container added or removed by `resize grow` and `resize shrink`
+        #[serde(default = "crate::config::default::resize_step")]
+        resize_step: f64,
+    },
+    /// Every window takes the whole workspace, `focus` commands cycle through them
+    Monocle,
+    /// dwm-like master area on the left and stack on the right
+    MasterStack {
+        /// Share of the workspace width taken by the master area
+        #[serde(default = "crate::config::default::master_ratio")]
+        ratio: f64,
+        /// Windows in the master area
+        #[serde(default = "crate::config::default::master_count")]
+        count: usize,
+    },
+}
+
+impl Default for LayoutConfig {
+    fn default() -> LayoutConfig {
+        LayoutConfig::Tiling {
+            layout: default::tiling_layout(),
+            split_ratio: default::split_ratio(),
+            resize_step: default::resize_step(),
+        }
+    }
+}
+
+/// Configuration of server-side decorations
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Decoration {
+    /// Borders drawn around windows
+    #[serde(default)]
+    pub border: Border,
+    /// Title bars drawn above windows
+    #[serde(default)]
+    pub title_bar: TitleBar,
+    /// Radius of the corners of windows in logical pixels, 0 keeps them square
+    #[serde(default)]
+    pub corner_radius: u32,
+    /// Blur behind windows enabling it by a window rule
+    #[serde(default)]
+    pub blur: BlurConfig,
+}
+
+impl Default for Decoration {
+    fn default() -> Decoration {
+        Decoration {
+            border: Border::default(),
+            title_bar: TitleBar::default(),
+            corner_radius: 0,
+            blur: BlurConfig::default(),
+        }
+    }
+}
+
+/// Configuration of the dual kawase blur
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct BlurConfig {
+    /// Number of times the background is halved in size and blurred, 0 disables blurring
+    #[serde(default = "crate::config::default::blur_passes")]
+    pub passes: u32,
+    /// Distance of the sampled pixels in each pass
+    #[serde(default = "crate::config::default::blur_strength")]
+    pub strength: f32,
+}
+
+impl Default for BlurConfig {
+    fn default() -> BlurConfig {
+        BlurConfig {
+            passes: default::blur_passes(),
+            strength: default::blur_strength(),
+        }
+    }
+}
+
+/// Configuration of window borders
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Border {
+    /// Width of the border in logical pixels, 0 disables borders
+    #[serde(default = "crate::config::default::border_width")]
+    pub width: u32,
+    /// Color of the focused window's border
+    #[serde(default = "crate::config::default::border_focused")]
+    pub focused: Color,
+    /// Color of all other windows' borders
+    #[serde(default = "crate::config::default::border_unfocused")]
+    pub unfocused: Color,
+    /// Color of windows requesting attention
+    #[serde(default = "crate::config::default::border_urgent")]
+    pub urgent: Color,
+}
+
+impl Default for Border {
+    fn default() -> Border {
+        Border {
+            width: default::border_width(),
+            focused: default::border_focused(),
+            unfocused: default::border_unfocused(),
+            urgent: default::border_urgent(),
+        }
+    }
+}
+
+/// Configuration of server-side title bars
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TitleBar {
+    /// Draw title bars and ask clients to not draw their own decorations
+    #[serde(default)]
+    pub enabled: bool,
+    /// Height of the title bar in logical pixels, also used by the title rows of stacked containers
+    #[serde(default = "crate::config::default::title_bar_height")]
+    pub height: u32,
+    /// Path of the TrueType/OpenType font used for titles,
+    /// a few common locations are tried if unset
+    #[serde(default)]
+    pub font: Option<PathBuf>,
+    /// Background of the focused window's title bar
+    #[serde(default = "crate::config::default::border_focused")]
+    pub focused: Color,
+    /// Background of all other windows' title bars
+    #[serde(default = "crate::config::default::border_unfocused")]
+    pub unfocused: Color,
+    /// Color of the title text
+    #[serde(default = "crate::config::default::title_bar_text")]
+    pub text: Color,
+    /// Color of the close button
+    #[serde(default = "crate::config::default::title_bar_close")]
+    pub close: Color,
+    /// Color of the maximize button
+    #[serde(default = "crate::config::default::title_bar_maximize")]
+    pub maximize: Color,
+}
+
+impl TitleBar {
+    /// Height actually taken by title bars, 0 if disabled
+    pub fn height(&self) -> i32 {
+        if self.enabled {
+            self.height as i32
+        } else {
+            0
+        }
+    }
+}
+
+impl Default for TitleBar {
+    fn default() -> TitleBar {
+        TitleBar {
+            enabled: false,
+            height: default::title_bar_height(),
+            font: None,
+            focused: default::border_focused(),
+            unfocused: default::border_unfocused(),
+            text: default::title_bar_text(),
+            close: default::title_bar_close(),
+            maximize: default::title_bar_maximize(),
+        }
+    }
+}
+
+/// RGBA color, written as `"#rrggbb"` or `"#rrggbbaa"`
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(try_from = "String")]
+pub struct Color(pub [u8; 4]);
+
+impl TryFrom<String> for Color {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Color, String> {
+        let hex = value
+            .strip_prefix('#')
+            .filter(|hex| (hex.len() == 6 || hex.len() == 8) && hex.is_ascii())
+            .ok_or_else(|| format!("Invalid color '{}', expected \"#rrggbb\" or \"#rrggbbaa\"", value))?;
+        let mut color = [255u8; 4];
+        for (i, channel) in color.iter_mut().enumerate().take(hex.len() / 2) {
+            *channel = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+                .map_err(|_| format!("Invalid color '{}', expected hexadecimal digits", value))?;
         }
+        Ok(Color(color))
     }
 }
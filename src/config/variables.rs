@@ -0,0 +1,82 @@
+//! User defined `variables`, like `$mod` or `$term`
+//!
+//! They are replaced in every string of the configuration, keys included, before it is deserialized.
+//! References to undefined variables are kept, exec commands pass them on to the shell.
+
+use anyhow::Result;
+use serde_yaml::{Mapping, Value};
+use std::collections::HashMap;
+
+/// Removes the `variables` section and replaces the references to them
+pub fn substitute(source: &mut Value) -> Result<()> {
+    let variables = match source
+        .as_mapping_mut()
+        .and_then(|mapping| mapping.remove(&Value::String(String::from("variables"))))
+    {
+        None | Some(Value::Null) => return Ok(()),
+        Some(Value::Mapping(variables)) => variables,
+        Some(_) => anyhow::bail!("variables expects a mapping of names to values"),
+    };
+    let mut names = HashMap::new();
+    for (name, value) in variables {
+        let name = match name {
+            Value::String(name) => name,
+            _ => anyhow::bail!("Variable names have to be strings"),
+        };
+        let name = name.strip_prefix('$').map(String::from).unwrap_or(name);
+        if name.is_empty() || !name.chars().all(is_name_char) {
+            anyhow::bail!("Invalid variable name {:?}, only letters, digits and _ are allowed", name);
+        }
+        let value = match value {
+            Value::String(value) => value,
+            Value::Number(value) => value.to_string(),
+            Value::Bool(value) => value.to_string(),
+            _ => anyhow::bail!("Variable {} has to be a string or a number", name),
+        };
+        names.insert(name, value);
+    }
+    replace(source, &names);
+    Ok(())
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+fn replace(value: &mut Value, variables: &HashMap<String, String>) {
+    match value {
+        Value::String(text) => *text = expand(text, variables),
+        Value::Sequence(values) => {
+            for value in values.iter_mut() {
+                replace(value, variables);
+            }
+        }
+        Value::Mapping(mapping) => {
+            let entries = std::mem::replace(mapping, Mapping::new());
+            for (mut key, mut value) in entries {
+                replace(&mut key, variables);
+                replace(&mut value, variables);
+                mapping.insert(key, value);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Replaces `$name` by the value of the variable, the name ends at the first character not allowed in names
+fn expand(text: &str, variables: &HashMap<String, String>) -> String {
+    let mut expanded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let end = after.find(|c| !is_name_char(c)).unwrap_or_else(|| after.len());
+        match variables.get(&after[..end]) {
+            Some(value) => expanded.push_str(value),
+            None => expanded.push_str(&rest[start..start + 1 + end]),
+        }
+        rest = &after[end..];
+    }
+    expanded.push_str(rest);
+    expanded
+}
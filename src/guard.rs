@@ -0,0 +1,51 @@
+//! # Isolation of panics
+//!
+//! A panic in an event source would unwind through the event loop and end the session with all its clients.
+//! Sources run their callbacks through `Fireplace::guard` instead, which logs the panic and carries on.
+//! Optional subsystems panicking again and again are disabled for the rest of the session.
+
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+use crate::state::Fireplace;
+
+/// Panics after which an optional subsystem is disabled
+const MAX_PANICS: usize = 3;
+
+/// Subsystems the session cannot do without, they are never disabled
+const ESSENTIAL: &[&str] = &["wayland", "input", "render", "lock"];
+
+impl Fireplace {
+    /// Runs a callback of the given subsystem, returns `None` if it panicked or the subsystem is disabled
+    pub fn guard<T, F>(&mut self, subsystem: &'static str, f: F) -> Option<T>
+    where
+        F: FnOnce(&mut Fireplace) -> T,
+    {
+        if self.is_disabled(subsystem) {
+            return None;
+        }
+        // borrows of the state are released while unwinding, whatever the callback left half done stays so
+        let payload = match catch_unwind(AssertUnwindSafe(|| f(self))) {
+            Ok(result) => return Some(result),
+            Err(payload) => payload,
+        };
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|message| String::from(*message))
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| String::from("unknown cause"));
+
+        let count = self.panics.entry(subsystem).or_insert(0);
+        *count += 1;
+        slog_scope::error!("Recovered from a panic in {} ({} so far): {}", subsystem, count, message);
+        if self.is_disabled(subsystem) {
+            slog_scope::error!("Disabled {} for the rest of the session", subsystem);
+            self.show_message(&format!("{} crashed repeatedly and was disabled, see the log", subsystem));
+        }
+        None
+    }
+
+    /// Checks if an optional subsystem panicked too often to be run again
+    pub fn is_disabled(&self, subsystem: &str) -> bool {
+        !ESSENTIAL.contains(&subsystem) && self.panics.get(subsystem).copied().unwrap_or(0) >= MAX_PANICS
+    }
+}
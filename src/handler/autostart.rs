@@ -0,0 +1,118 @@
+//! Desktop entries of the XDG autostart specification
+//!
+//! Entries are read from `autostart` in `$XDG_CONFIG_HOME` and `$XDG_CONFIG_DIRS`,
+//! those of the user hide system ones of the same file name.
+
+use std::{collections::HashMap, fs, path::Path};
+
+/// Name matched against `OnlyShowIn` and `NotShowIn`
+const DESKTOP: &str = "fireplace";
+
+/// Shell commands of all entries to start in this session
+pub fn xdg_autostart_commands() -> Vec<String> {
+    let base = match xdg::BaseDirectories::new() {
+        Ok(base) => base,
+        Err(err) => {
+            slog_scope::warn!("Failed to find the autostart directories: {}", err);
+            return Vec::new();
+        }
+    };
+    let mut paths = base
+        .list_config_files_once("autostart")
+        .into_iter()
+        .filter(|path| path.extension().map(|ext| ext == "desktop").unwrap_or(false))
+        .collect::<Vec<_>>();
+    paths.sort_by(|a, b| a.file_name().cmp(&b.file_name()));
+    paths
+        .iter()
+        .filter_map(|path| match fs::read_to_string(path) {
+            Ok(contents) => command(path, &contents),
+            Err(err) => {
+                slog_scope::warn!("Failed to read {}: {}", path.display(), err);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Command of an entry, unless it is hidden or meant for other desktops
+fn command(path: &Path, contents: &str) -> Option<String> {
+    let entry = desktop_entry(contents);
+    let get = |key: &str| entry.get(key).map(String::as_str);
+    let listed = |key: &str| get(key).map(|list| list.split(';').any(|name| name.eq_ignore_ascii_case(DESKTOP)));
+
+    if get("Type").unwrap_or("Application") != "Application"
+        || get("Hidden") == Some("true")
+        || get("X-GNOME-Autostart-enabled") == Some("false")
+        || listed("OnlyShowIn") == Some(false)
+        || listed("NotShowIn") == Some(true)
+    {
+        slog_scope::debug!("Skipping autostart entry {}", path.display());
+        return None;
+    }
+    if let Some(program) = get("TryExec") {
+        if !installed(program) {
+            slog_scope::debug!("Skipping autostart entry {}, {} is not installed", path.display(), program);
+            return None;
+        }
+    }
+    match get("Exec") {
+        Some(exec) => Some(strip_field_codes(exec)),
+        None => {
+            slog_scope::warn!("Autostart entry {} has no Exec key", path.display());
+            None
+        }
+    }
+}
+
+/// Keys of the `[Desktop Entry]` group, localized ones are left out
+fn desktop_entry(contents: &str) -> HashMap<String, String> {
+    let mut entry = HashMap::new();
+    let mut in_group = false;
+    for line in contents.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with('[') {
+            in_group = line == "[Desktop Entry]";
+            continue;
+        }
+        if !in_group {
+            continue;
+        }
+        if let Some((key, value)) = line.split_once('=') {
+            let key = key.trim();
+            if !key.contains('[') {
+                entry.insert(String::from(key), String::from(value.trim()));
+            }
+        }
+    }
+    entry
+}
+
+/// Removes the `%f`-like placeholders for files and urls, nothing is passed at startup
+fn strip_field_codes(exec: &str) -> String {
+    let mut command = String::with_capacity(exec.len());
+    let mut chars = exec.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            command.push(c);
+            continue;
+        }
+        if let Some('%') = chars.next() {
+            command.push('%');
+        }
+    }
+    String::from(command.trim())
+}
+
+/// Checks if a program exists, either as an absolute path or in `$PATH`
+fn installed(program: &str) -> bool {
+    let program = Path::new(program);
+    if program.is_absolute() {
+        return program.exists();
+    }
+    std::env::var_os("PATH")
+        .map(|path| std::env::split_paths(&path).any(|dir| dir.join(program).exists()))
+        .unwrap_or(false)
+}
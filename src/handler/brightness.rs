@@ -0,0 +1,75 @@
+use anyhow::{Context, Result};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{logind, state::Fireplace};
+
+const BACKLIGHTS: &str = "/sys/class/backlight";
+
+/// Backlight devices by how directly they control the panel, see `type` in sysfs
+const TYPES: &[&str] = &["firmware", "platform", "raw"];
+
+/// Finds the configured backlight, or the preferred one of all
+fn backlight(configured: Option<&str>) -> Result<PathBuf> {
+    if let Some(name) = configured {
+        return Ok(Path::new(BACKLIGHTS).join(name));
+    }
+    let mut devices = fs::read_dir(BACKLIGHTS)
+        .with_context(|| format!("Failed to list {}", BACKLIGHTS))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .collect::<Vec<_>>();
+    devices.sort();
+    devices
+        .into_iter()
+        .min_by_key(|path| {
+            let kind = fs::read_to_string(path.join("type")).unwrap_or_default();
+            TYPES.iter().position(|t| *t == kind.trim()).unwrap_or(TYPES.len())
+        })
+        .context("There is no backlight")
+}
+
+fn read_value(path: &Path) -> Result<u32> {
+    let value = fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    value
+        .trim()
+        .parse()
+        .with_context(|| format!("Malformed value in {}", path.display()))
+}
+
+impl Fireplace {
+    /// Handles `brightness up|down [percent]` and `brightness set <percent>`
+    pub fn brightness_command(&mut self, args: &str) -> Result<()> {
+        let config = &self.config.brightness;
+        let device = backlight(config.device.as_deref())?;
+        let max = read_value(&device.join("max_brightness"))?.max(1);
+        let current = read_value(&device.join("brightness"))?;
+        let current_percent = (current as f64 * 100.0 / max as f64).round() as i64;
+
+        let mut args = args.split_whitespace();
+        let step = |arg: Option<&str>| match arg {
+            Some(arg) => arg.trim_end_matches('%').parse::<i64>().context("Invalid brightness step"),
+            None => Ok(config.step as i64),
+        };
+        let percent = match args.next() {
+            Some("up") => current_percent + step(args.next())?,
+            Some("down") => current_percent - step(args.next())?,
+            Some("set") => {
+                let arg = args.next().context("Missing brightness")?;
+                arg.trim_end_matches('%').parse::<i64>().context("Invalid brightness")?
+            }
+            _ => anyhow::bail!("Expected up, down or set"),
+        };
+        let percent = percent.max(config.minimum as i64).min(100);
+        let value = ((percent as f64 / 100.0) * max as f64).round() as u32;
+
+        let name = device.file_name().and_then(|name| name.to_str()).context("Invalid backlight name")?;
+        logind::set_brightness(name, value)?;
+        slog_scope::debug!("Brightness of {} set to {}/{}", name, value, max);
+        if config.feedback {
+            self.show_osd(format!("Brightness {}%", percent), percent as f64 / 100.0);
+        }
+        Ok(())
+    }
+}
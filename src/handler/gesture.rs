@@ -0,0 +1,106 @@
+use serde::Deserialize;
+use smithay::utils::{Logical, Point};
+
+use super::{table_commands, Binding};
+use crate::state::Fireplace;
+
+/// Direction of a finished swipe
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SwipeDirection {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+/// Description of a touchpad swipe that might be
+/// handled by the compositor.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SwipePattern {
+    /// Number of fingers on the touchpad
+    pub fingers: u32,
+    /// Direction the fingers moved in
+    pub direction: SwipeDirection,
+}
+
+impl SwipePattern {
+    pub fn new(fingers: u32, direction: SwipeDirection) -> SwipePattern {
+        SwipePattern { fingers, direction }
+    }
+}
+
+/// Swipe currently in progress
+#[derive(Debug, Clone, Copy)]
+pub struct Swipe {
+    fingers: u32,
+    delta: Point<f64, Logical>,
+}
+
+impl Swipe {
+    /// Direction the swipe has moved the furthest in, once it passes the threshold
+    fn direction(&self, threshold: f64) -> Option<SwipeDirection> {
+        let (dx, dy) = (self.delta.x, self.delta.y);
+        if dx.abs() >= dy.abs() && dx.abs() >= threshold {
+            Some(if dx < 0.0 {
+                SwipeDirection::Left
+            } else {
+                SwipeDirection::Right
+            })
+        } else if dy.abs() > dx.abs() && dy.abs() >= threshold {
+            Some(if dy < 0.0 {
+                SwipeDirection::Up
+            } else {
+                SwipeDirection::Down
+            })
+        } else {
+            None
+        }
+    }
+}
+
+impl Fireplace {
+    pub fn swipe_begin(&mut self, fingers: u32) {
+        self.swipe = Some(Swipe {
+            fingers,
+            delta: (0.0, 0.0).into(),
+        });
+    }
+
+    pub fn swipe_update(&mut self, delta: Point<f64, Logical>) {
+        if let Some(swipe) = self.swipe.as_mut() {
+            swipe.delta += delta;
+        }
+    }
+
+    pub fn swipe_end(&mut self, cancelled: bool) {
+        let swipe = match self.swipe.take() {
+            Some(swipe) if !cancelled => swipe,
+            _ => return,
+        };
+        let gestures = &self.config.gestures;
+        let pattern = match swipe.direction(gestures.threshold) {
+            Some(direction) => SwipePattern::new(swipe.fingers, direction),
+            None => return,
+        };
+
+        let tables = [
+            (Binding::Global, &gestures.global),
+            (Binding::Workspace, &gestures.workspace),
+            (Binding::View, &gestures.view),
+            (Binding::Exec, &gestures.exec),
+        ];
+        let found = tables.iter().find_map(|&(binding, swipes)| {
+            swipes
+                .iter()
+                .find(|(_, p)| **p == pattern)
+                .map(|(command, _)| table_commands(binding, command))
+        });
+        if let Some(commands) = found {
+            slog_scope::debug!("Found commands for {:?}: {:?}", pattern, commands);
+            let seat = self.last_active_seat.clone();
+            self.process_commands(&commands, &seat);
+        }
+    }
+}
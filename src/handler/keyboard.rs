@@ -29,6 +29,20 @@ impl std::ops::AddAssign<KeyModifier> for KeyModifiers {
     }
 }
 
+impl KeyModifier {
+    /// Checks if this modifier is part of the given modifier state
+    pub fn is_set(&self, modifiers: &KeyModifiers) -> bool {
+        match self {
+            KeyModifier::Ctrl => modifiers.ctrl,
+            KeyModifier::Alt => modifiers.alt,
+            KeyModifier::Shift => modifiers.shift,
+            KeyModifier::Logo => modifiers.logo,
+            KeyModifier::CapsLock => modifiers.caps_lock,
+            KeyModifier::NumLock => modifiers.num_lock,
+        }
+    }
+}
+
 impl std::ops::BitOr for KeyModifier {
     type Output = KeyModifiers;
 
@@ -85,47 +99,146 @@ where
     KeyModifiersDef::deserialize(deserializer).map(Into::into)
 }
 
-#[allow(non_snake_case)]
-fn deserialize_Keysym<'de, D>(deserializer: D) -> Result<Keysym, D::Error>
-where
-    D: serde::Deserializer<'de>,
-{
-    use serde::de::{Error, Unexpected};
-
-    let name = String::deserialize(deserializer)?;
-    //let name = format!("KEY_{}", code);
-    match xkb::keysym_from_name(&name, xkb::KEYSYM_NO_FLAGS) {
-        KeySyms::KEY_NoSymbol => match xkb::keysym_from_name(&name, xkb::KEYSYM_CASE_INSENSITIVE) {
-            KeySyms::KEY_NoSymbol => Err(<D::Error as Error>::invalid_value(
-                Unexpected::Str(&name),
-                &"One of the keysym names of xkbcommon.h without the 'KEY_' prefix",
-            )),
+/// Looks up a keysym by its xkbcommon name, falling back to a case insensitive match
+fn keysym_from_name(name: &str) -> Option<Keysym> {
+    match xkb::keysym_from_name(name, xkb::KEYSYM_NO_FLAGS) {
+        KeySyms::KEY_NoSymbol => match xkb::keysym_from_name(name, xkb::KEYSYM_CASE_INSENSITIVE) {
+            KeySyms::KEY_NoSymbol => None,
             x => {
                 slog_scope::warn!(
                     "Key-Binding '{}' only matched case insensitive for {:?}",
                     name,
                     xkb::keysym_get_name(x)
                 );
-                Ok(x)
+                Some(x)
             }
         },
-        x => Ok(x),
+        x => Some(x),
     }
 }
 
+#[allow(non_snake_case)]
+fn deserialize_Keysym<'de, D>(deserializer: D) -> Result<Keysym, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    use serde::de::{Error, Unexpected};
+
+    let name = String::deserialize(deserializer)?;
+    keysym_from_name(&name).ok_or_else(|| {
+        <D::Error as Error>::invalid_value(
+            Unexpected::Str(&name),
+            &"One of the keysym names of xkbcommon.h without the 'KEY_' prefix",
+        )
+    })
+}
+
 /// Describtion of a key combination that might be
 /// handled by the compositor.
-#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
-#[serde(deny_unknown_fields)]
+///
+/// Can be deserialized from its fields or a string like `"Logo+Shift+q"`.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct KeyPattern {
     /// What modifiers are expected to be pressed alongside the key
-    #[serde(deserialize_with = "deserialize_KeyModifiers")]
     pub modifiers: KeyModifiers,
     /// The actual key, that was pressed
-    #[serde(deserialize_with = "deserialize_Keysym")]
     pub key: u32,
 }
 
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeyPatternDef {
+    #[serde(deserialize_with = "deserialize_KeyModifiers")]
+    modifiers: KeyModifiers,
+    #[serde(deserialize_with = "deserialize_Keysym")]
+    key: u32,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum KeyPatternRepr {
+    Short(String),
+    Full(KeyPatternDef),
+}
+
+impl<'de> Deserialize<'de> for KeyPattern {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        use serde::de::{Error, Unexpected};
+
+        match KeyPatternRepr::deserialize(deserializer)? {
+            KeyPatternRepr::Short(pattern) => pattern.parse().map_err(|err: String| {
+                <D::Error as Error>::invalid_value(Unexpected::Str(&pattern), &err.as_str())
+            }),
+            KeyPatternRepr::Full(def) => Ok(KeyPattern {
+                modifiers: def.modifiers,
+                key: def.key,
+            }),
+        }
+    }
+}
+
+impl std::str::FromStr for KeyPattern {
+    type Err = String;
+
+    /// Parses modifiers and a keysym name separated by `+`, e.g. `"Ctrl+Alt+F2"`
+    fn from_str(pattern: &str) -> Result<Self, Self::Err> {
+        // a trailing '+' is the plus key itself
+        let (rest, key) = match pattern.strip_suffix("++") {
+            Some(rest) => (rest, "plus"),
+            None => match pattern.rsplit_once('+') {
+                Some((rest, key)) => (rest, key),
+                None => ("", pattern),
+            },
+        };
+
+        let mut modifiers = KeyModifiers {
+            ctrl: false,
+            alt: false,
+            shift: false,
+            caps_lock: false,
+            logo: false,
+            num_lock: false,
+        };
+        for name in rest.split('+').map(str::trim).filter(|name| !name.is_empty()) {
+            modifiers += match name.to_lowercase().as_str() {
+                "ctrl" | "control" => KeyModifier::Ctrl,
+                "alt" | "mod1" => KeyModifier::Alt,
+                "shift" => KeyModifier::Shift,
+                "logo" | "super" | "mod4" | "win" => KeyModifier::Logo,
+                "capslock" | "lock" => KeyModifier::CapsLock,
+                "numlock" | "mod2" => KeyModifier::NumLock,
+                _ => return Err(format!("Unknown modifier '{}'", name)),
+            };
+        }
+
+        let key = key.trim();
+        keysym_from_name(key)
+            .map(|key| KeyPattern { modifiers, key })
+            .ok_or_else(|| format!("Unknown key '{}', expected a keysym name of xkbcommon.h", key))
+    }
+}
+
+impl std::fmt::Display for KeyPattern {
+    /// Written like it is parsed, e.g. `Ctrl+Alt+F2`
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let modifiers = [
+            (self.modifiers.ctrl, "Ctrl"),
+            (self.modifiers.alt, "Alt"),
+            (self.modifiers.shift, "Shift"),
+            (self.modifiers.logo, "Logo"),
+            (self.modifiers.caps_lock, "CapsLock"),
+            (self.modifiers.num_lock, "NumLock"),
+        ];
+        for (_, name) in modifiers.iter().filter(|(set, _)| *set) {
+            write!(f, "{}+", name)?;
+        }
+        write!(f, "{}", xkb::keysym_get_name(self.key))
+    }
+}
+
 impl KeyPattern {
     pub fn new(modifiers: impl Into<KeyModifiers>, key: u32) -> KeyPattern {
         KeyPattern {
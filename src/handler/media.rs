@@ -0,0 +1,207 @@
+//! Built-in handling of the volume and media player keys
+//!
+//! Volumes are changed with `pactl`, served by PulseAudio and pipewire-pulse alike,
+//! players are controlled over MPRIS. Both may block, so they run on threads of their own.
+
+use anyhow::{Context, Result};
+use smithay::{
+    reexports::calloop::{channel, EventLoop},
+    wayland::seat::Keysym,
+};
+use std::{
+    process::Command,
+    time::{Duration, Instant},
+};
+use zvariant::{OwnedValue, Value};
+
+use crate::{handler::keyboard::KeySyms, state::Fireplace};
+
+/// How long the on-screen display stays visible
+const OSD_DURATION: Duration = Duration::from_secs(2);
+
+const SINK: &str = "@DEFAULT_SINK@";
+const MPRIS_PREFIX: &str = "org.mpris.MediaPlayer2.";
+const MPRIS_PATH: &str = "/org/mpris/MediaPlayer2";
+const MPRIS_PLAYER: &str = "org.mpris.MediaPlayer2.Player";
+
+/// Level shown by the on-screen display, like the volume
+pub struct Osd {
+    pub label: String,
+    /// Between 0 and 1
+    pub level: f64,
+    shown: Instant,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum MediaKey {
+    RaiseVolume,
+    LowerVolume,
+    Mute,
+    Play,
+    Next,
+    Previous,
+}
+
+impl MediaKey {
+    fn from_keysym(keysym: Keysym) -> Option<MediaKey> {
+        match keysym {
+            KeySyms::KEY_XF86AudioRaiseVolume => Some(MediaKey::RaiseVolume),
+            KeySyms::KEY_XF86AudioLowerVolume => Some(MediaKey::LowerVolume),
+            KeySyms::KEY_XF86AudioMute => Some(MediaKey::Mute),
+            KeySyms::KEY_XF86AudioPlay | KeySyms::KEY_XF86AudioPause => Some(MediaKey::Play),
+            KeySyms::KEY_XF86AudioNext => Some(MediaKey::Next),
+            KeySyms::KEY_XF86AudioPrev => Some(MediaKey::Previous),
+            _ => None,
+        }
+    }
+}
+
+/// Volume of the default sink
+struct Volume {
+    percent: u32,
+    muted: bool,
+}
+
+/// Receives the volumes changed on other threads, to show them
+pub struct MediaControl {
+    volumes: channel::Sender<Volume>,
+}
+
+pub fn init_media(event_loop: &mut EventLoop<'static, Fireplace>, state: &mut Fireplace) -> Result<()> {
+    let (volumes, receiver) = channel::channel();
+    let token = event_loop
+        .handle()
+        .insert_source(receiver, |event, _, state| {
+            if let channel::Event::Msg(Volume { percent, muted }) = event {
+                let label = if muted {
+                    String::from("Volume muted")
+                } else {
+                    format!("Volume {}%", percent)
+                };
+                let level = if muted { 0.0 } else { percent as f64 / 100.0 };
+                state.guard("media keys", |state| state.show_osd(label, level));
+            }
+        })
+        .map_err(|_| anyhow::anyhow!("Failed to register the media keys on the event loop"))?;
+    state.tokens.push(token);
+    state.media = Some(MediaControl { volumes });
+    Ok(())
+}
+
+impl Fireplace {
+    /// Handles volume and player keys, unless bound to commands, returns if the key was one
+    pub fn process_media_key(&mut self, keysym: Keysym) -> bool {
+        if !self.config.media_keys.enabled {
+            return false;
+        }
+        let key = match MediaKey::from_keysym(keysym) {
+            Some(key) => key,
+            None => return false,
+        };
+        let volumes = match self.media.as_ref() {
+            Some(media) => media.volumes.clone(),
+            None => return false,
+        };
+        let step = self.config.media_keys.volume_step;
+        let _ = std::thread::Builder::new().name(String::from("media key")).spawn(move || {
+            let result = match key {
+                MediaKey::RaiseVolume | MediaKey::LowerVolume | MediaKey::Mute => {
+                    change_volume(key, step).map(|volume| {
+                        let _ = volumes.send(volume);
+                    })
+                }
+                MediaKey::Play => control_player("PlayPause"),
+                MediaKey::Next => control_player("Next"),
+                MediaKey::Previous => control_player("Previous"),
+            };
+            if let Err(err) = result {
+                slog_scope::warn!("Failed to handle {:?}: {:#}", key, err);
+            }
+        });
+        true
+    }
+
+    /// Shows a level on every output for a moment
+    pub fn show_osd(&mut self, label: String, level: f64) {
+        self.osd = Some(Osd {
+            label,
+            level: level.max(0.0).min(1.0),
+            shown: Instant::now(),
+        });
+        self.damage();
+    }
+
+    /// Hides the on-screen display once it was shown long enough
+    pub fn expire_osd(&mut self) {
+        if self.osd.as_ref().map(|osd| osd.shown.elapsed() >= OSD_DURATION).unwrap_or(false) {
+            self.osd = None;
+            self.damage();
+        }
+    }
+}
+
+fn pactl(args: &[&str]) -> Result<String> {
+    let output = Command::new("pactl").args(args).output().context("Failed to run pactl")?;
+    if !output.status.success() {
+        anyhow::bail!("pactl {} failed: {}", args.join(" "), String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Reads the volume of the first channel, as `Volume: front-left: 32768 /  50% / ...`
+fn volume() -> Result<Volume> {
+    let volume = pactl(&["get-sink-volume", SINK])?;
+    let percent = volume
+        .split('/')
+        .find_map(|part| part.trim().strip_suffix('%').and_then(|percent| percent.parse().ok()))
+        .context("Malformed volume")?;
+    let muted = pactl(&["get-sink-mute", SINK])?.contains("yes");
+    Ok(Volume { percent, muted })
+}
+
+/// Changes the volume of the default sink, raising it no further than 100%
+fn change_volume(key: MediaKey, step: u32) -> Result<Volume> {
+    match key {
+        MediaKey::Mute => {
+            pactl(&["set-sink-mute", SINK, "toggle"])?;
+        }
+        MediaKey::RaiseVolume => {
+            let current = volume()?.percent;
+            pactl(&["set-sink-mute", SINK, "0"])?;
+            // volumes raised beyond 100% elsewhere are kept
+            if current < 100 {
+                pactl(&["set-sink-volume", SINK, &format!("{}%", (current + step).min(100))])?;
+            }
+        }
+        _ => {
+            pactl(&["set-sink-volume", SINK, &format!("-{}%", step)])?;
+        }
+    }
+    volume()
+}
+
+/// Calls a method of the playing player, or any player if none plays
+fn control_player(method: &str) -> Result<()> {
+    let connection = zbus::Connection::new_session()?;
+    let players = zbus::fdo::DBusProxy::new(&connection)?
+        .list_names()?
+        .into_iter()
+        .filter(|name| name.starts_with(MPRIS_PREFIX))
+        .collect::<Vec<_>>();
+    let playing = players.iter().find(|player| {
+        connection
+            .call_method(
+                Some(player.as_str()),
+                MPRIS_PATH,
+                Some("org.freedesktop.DBus.Properties"),
+                "Get",
+                &(MPRIS_PLAYER, "PlaybackStatus"),
+            )
+            .and_then(|reply| reply.body::<OwnedValue>())
+            .map(|status| matches!(&*status, Value::Str(status) if status.as_str() == "Playing"))
+            .unwrap_or(false)
+    });
+    let player = playing.or_else(|| players.first()).context("No media player is running")?;
+    connection.call_method(Some(player.as_str()), MPRIS_PATH, Some(MPRIS_PLAYER), method, &())?;
+    Ok(())
+}
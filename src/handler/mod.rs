@@ -1,21 +1,119 @@
-use crate::state::Fireplace;
+use crate::{
+    backend::screenshot::Screenshot,
+    config::{ColorFilter, CursorConfig, KeyboardConfig},
+    handler::keyboard::{KeyModifiers, KeySyms},
+    ipc::protocol::Response,
+    shell::{
+        decoration::{self, Button, Hit},
+        layout::SavedNode,
+        workspace::Workspaces,
+    },
+    state::Fireplace,
+    systemd,
+};
 use smithay::{
     backend::input::{Device, DeviceCapability, InputBackend, InputEvent, KeyState},
-    reexports::wayland_server::Display,
+    reexports::{
+        wayland_protocols::xdg_shell::server::xdg_toplevel::ResizeEdge,
+        wayland_server::{protocol::wl_surface::WlSurface, Display},
+    },
+    utils::{Logical, Point, Rectangle},
     wayland::{
         data_device::set_data_device_focus,
-        seat::{CursorImageStatus, FilterResult, Seat, XkbConfig},
+        tablet_manager::{TabletDescriptor, TabletSeatTrait},
+        seat::{CursorImageStatus, FilterResult, GrabStartData, Keysym, Seat, XkbConfig},
         SERIAL_COUNTER as SCOUNTER,
     },
 };
-use std::{cell::RefCell, collections::HashMap};
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    sync::mpsc,
+    time::{Duration, Instant},
+};
 
+mod autostart;
+mod brightness;
+pub mod gesture;
 pub mod keyboard;
+mod media;
+mod power;
+mod reload;
+pub mod switch;
+
+pub use self::media::{init_media, MediaControl, Osd};
+pub use self::reload::{watch_config, wrap, Message};
 
 pub struct ActiveOutput(pub RefCell<String>);
 
+/// Name the seat was created with
+pub struct SeatName(pub String);
+
+/// Keybinding table a command was found in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Binding {
+    Global,
+    Workspace,
+    View,
+    Exec,
+}
+
 struct Devices(RefCell<HashMap<String, Vec<DeviceCapability>>>);
 
+/// Time of the last key press of a seat
+#[derive(Default)]
+struct LastTyped(Cell<Option<u32>>);
+
+/// Buttons of a seat pressed with the drag modifier, their releases are not sent to clients either
+#[derive(Default)]
+struct DragButtons(RefCell<Vec<u32>>);
+
+/// Binding state of the keyboard of a seat, kept apart from other seats typing at the same time
+#[derive(Default)]
+struct KeyBindings {
+    /// Keys which triggered a binding, their releases are not forwarded either
+    suppressed: RefCell<Vec<Keysym>>,
+    /// Active binding mode, `None` for the default one
+    mode: RefCell<Option<String>>,
+}
+
+/// Tracks if the cursor of a seat should be drawn
+pub struct CursorVisibility {
+    last_motion: Cell<Instant>,
+    typing: Cell<bool>,
+}
+
+impl CursorVisibility {
+    fn new() -> CursorVisibility {
+        CursorVisibility {
+            last_motion: Cell::new(Instant::now()),
+            typing: Cell::new(false),
+        }
+    }
+
+    /// Shows the cursor again after it was moved
+    fn moved(&self) {
+        self.last_motion.set(Instant::now());
+        self.typing.set(false);
+    }
+
+    pub fn is_visible(&self, config: &CursorConfig) -> bool {
+        let idle = config
+            .hide_after
+            .map(|secs| self.last_motion.get().elapsed() >= Duration::from_secs(secs))
+            .unwrap_or(false);
+        !idle && !self.typing.get()
+    }
+}
+
+/// Icon of the drag-and-drop operation of a seat
+#[derive(Default)]
+pub struct DndIcon(pub RefCell<Option<WlSurface>>);
+
+/// Position of a tablet tool in proximity, rendered as a cursor
+#[derive(Default)]
+pub struct TabletCursor(pub Cell<Option<Point<f64, Logical>>>);
+
 impl Devices {
     fn new() -> Devices {
         Devices(RefCell::new(HashMap::new()))
@@ -24,11 +122,15 @@ impl Devices {
     fn add_device<D: Device>(&self, device: &D) -> Vec<DeviceCapability> {
         let id = device.id();
         let mut map = self.0.borrow_mut();
-        let caps = [DeviceCapability::Keyboard, DeviceCapability::Pointer]
-            .iter()
-            .cloned()
-            .filter(|c| device.has_capability(*c))
-            .collect::<Vec<_>>();
+        let caps = [
+            DeviceCapability::Keyboard,
+            DeviceCapability::Pointer,
+            DeviceCapability::TabletTool,
+        ]
+        .iter()
+        .cloned()
+        .filter(|c| device.has_capability(*c))
+        .collect::<Vec<_>>();
         let new_caps = caps
             .iter()
             .cloned()
@@ -38,10 +140,6 @@ impl Devices {
         new_caps
     }
 
-    fn has_device<D: Device>(&self, device: &D) -> bool {
-        self.0.borrow().contains_key(&device.id())
-    }
-
     fn remove_device<D: Device>(&self, device: &D) -> Vec<DeviceCapability> {
         let id = device.id();
         let mut map = self.0.borrow_mut();
@@ -53,11 +151,36 @@ impl Devices {
     }
 }
 
+fn keyboard_focus_changed(seat: &Seat, focus: Option<&WlSurface>) {
+    set_data_device_focus(seat, focus.and_then(|s| s.as_ref().client()))
+}
+
 pub fn add_seat(display: &mut Display, name: String) -> Seat {
-    let (seat, _) = Seat::new(display, name, None);
+    let (seat, _) = Seat::new(display, name.clone(), None);
     let userdata = seat.user_data();
+    userdata.insert_if_missing(|| SeatName(name));
     userdata.insert_if_missing(|| Devices::new());
     userdata.insert_if_missing(|| RefCell::new(CursorImageStatus::Hidden));
+    userdata.insert_if_missing(TabletCursor::default);
+    userdata.insert_if_missing(DndIcon::default);
+    userdata.insert_if_missing(LastTyped::default);
+    userdata.insert_if_missing(DragButtons::default);
+    userdata.insert_if_missing(KeyBindings::default);
+    userdata.insert_if_missing(CursorVisibility::new);
+    let owned_seat = seat.clone();
+    seat.tablet_seat().on_cursor_surface(move |_tool, status| {
+        *owned_seat.user_data().get::<RefCell<CursorImageStatus>>().unwrap().borrow_mut() = status;
+    });
+    userdata.insert_if_missing(|| {
+        RefCell::new(KeyModifiers {
+            ctrl: false,
+            alt: false,
+            shift: false,
+            caps_lock: false,
+            logo: false,
+            num_lock: false,
+        })
+    });
     seat
 }
 
@@ -65,29 +188,38 @@ impl Fireplace {
     pub fn process_input_event<B: InputBackend>(&mut self, event: InputEvent<B>) {
         use smithay::backend::input::Event;
 
+        profile_span!("input");
+
+        // while locked, only the keyboard reaches the lock
+        if self.is_locked()
+            && !matches!(
+                event,
+                InputEvent::DeviceAdded { .. } | InputEvent::DeviceRemoved { .. } | InputEvent::Keyboard { .. }
+            )
+        {
+            return;
+        }
+
         match event {
             InputEvent::DeviceAdded { device } => {
-                let seat = &mut self.last_active_seat;
+                let idx = self.seat_for_device(&device);
+                self.device_seats.insert(device.id(), self.seats[idx].clone());
+                let seat = &mut self.seats[idx];
+                let output = String::from(
+                    self.workspaces
+                        .borrow_mut()
+                        .output(|_| true)
+                        .map(|x| x.name())
+                        .unwrap_or("headless"),
+                );
+                seat.user_data()
+                    .insert_if_missing(|| ActiveOutput(RefCell::new(output)));
                 let userdata = seat.user_data();
                 let devices = userdata.get::<Devices>().unwrap();
                 for cap in devices.add_device(&device) {
                     match cap {
-                        DeviceCapability::Keyboard => {
-                            let _ =
-                                seat.add_keyboard(XkbConfig::default(), 200, 25, |seat, focus| {
-                                    set_data_device_focus(seat, focus.and_then(|s| s.as_ref().client()))
-                                });
-                        }
+                        DeviceCapability::Keyboard => add_keyboard(seat, &self.config.input.keyboard),
                         DeviceCapability::Pointer => {
-                            let output = String::from(
-                                self.workspaces
-                                    .borrow_mut()
-                                    .output(|_| true)
-                                    .map(|x| x.name())
-                                    .unwrap_or("headless"),
-                            );
-                            seat.user_data()
-                                .insert_if_missing(|| ActiveOutput(RefCell::new(output)));
                             let owned_seat = seat.clone();
                             seat.add_pointer(move |status| {
                                 *owned_seat.user_data().get::<RefCell<CursorImageStatus>>().unwrap().borrow_mut() = status;
@@ -96,24 +228,33 @@ impl Fireplace {
                         _ => {}
                     }
                 }
+                if device.has_capability(DeviceCapability::TabletTool) {
+                    seat.tablet_seat().add_tablet(&TabletDescriptor::from(&device));
+                }
             }
             InputEvent::DeviceRemoved { device } => {
-                for seat in &mut self.seats {
-                    let userdata = seat.user_data();
-                    let devices = userdata.get::<Devices>().unwrap();
-                    if devices.has_device(&device) {
-                        for cap in devices.remove_device(&device) {
-                            match cap {
-                                DeviceCapability::Keyboard => {
-                                    seat.remove_keyboard();
-                                }
-                                DeviceCapability::Pointer => {
-                                    seat.remove_pointer();
-                                }
-                                _ => {}
-                            }
+                let mut seat = match self.device_seats.remove(&device.id()) {
+                    Some(seat) => seat,
+                    None => return,
+                };
+                let userdata = seat.user_data();
+                let devices = userdata.get::<Devices>().unwrap();
+                if device.has_capability(DeviceCapability::TabletTool) {
+                    let tablet_seat = seat.tablet_seat();
+                    tablet_seat.remove_tablet(&TabletDescriptor::from(&device));
+                    if tablet_seat.count_tablets() == 0 {
+                        tablet_seat.clear_tools();
+                    }
+                }
+                for cap in devices.remove_device(&device) {
+                    match cap {
+                        DeviceCapability::Keyboard => {
+                            seat.remove_keyboard();
+                        }
+                        DeviceCapability::Pointer => {
+                            seat.remove_pointer();
                         }
-                        break;
+                        _ => {}
                     }
                 }
             }
@@ -121,185 +262,172 @@ impl Fireplace {
                 use smithay::backend::input::KeyboardKeyEvent;
 
                 let device = event.device();
-                for seat in self.seats.clone().iter() {
-                    let userdata = seat.user_data();
-                    let devices = userdata.get::<Devices>().unwrap();
-                    if devices.has_device(&device) {
-                        let keycode = event.key_code();
-                        let state = event.state();
-                        slog_scope::debug!("key"; "keycode" => keycode, "state" => format!("{:?}", state));
-                        let serial = SCOUNTER.next_serial();
-                        let time = Event::time(&event);
-                        seat.get_keyboard().unwrap().input(
-                            keycode,
-                            state,
-                            serial,
-                            time,
-                            |modifiers, handle| {
-                                let mut result = FilterResult::Forward;
-                                for keysym in handle.raw_syms().iter().copied() {
-                                    slog_scope::debug!("keysym";
-                                        "state" => format!("{:?}", state),
-                                        "mods" => format!("{:?}", modifiers),
-                                        "keysym" => ::xkbcommon::xkb::keysym_get_name(keysym)
-                                    );
-
-                                    // If the key is pressed and triggered a action
-                                    // we will not forward the key to the client.
-                                    // Additionally add the key to the suppressed keys
-                                    // so that we can decide on a release if the key
-                                    // should be forwarded to the client or not.
-                                    if let KeyState::Pressed = state {
-                                        if let Some(command) = self
-                                            .config
-                                            .keys
-                                            .iter()
-                                            .find(|(_, p)| p.modifiers == *modifiers && p.key == keysym)
-                                            .map(|(c, _)| c)
-                                            .cloned()
-                                        {
-                                            slog_scope::debug!("Found global cmd");
-                                            self.process_global_command(&command);
-                                            self.suppressed_keys.push(keysym);
-                                            result = FilterResult::Intercept(());
-                                            break;
-                                        }
-                                        if let Some(command) = self
-                                            .config
-                                            .workspace
-                                            .keys
-                                            .iter()
-                                            .find(|(_, p)| p.modifiers == *modifiers && p.key == keysym)
-                                            .map(|(c, _)| c)
-                                            .cloned()
-                                        {
-                                            slog_scope::debug!("Found workspace cmd");
-                                            self.process_workspace_command(&command, seat);
-                                            self.suppressed_keys.push(keysym);
-                                            result = FilterResult::Intercept(());
-                                            break;
-                                        }
-                                        if let Some(command) = self
-                                            .config
-                                            .view
-                                            .keys
-                                            .iter()
-                                            .find(|(_, p)| p.modifiers == *modifiers && p.key == keysym)
-                                            .map(|(c, _)| c)
-                                            .cloned()
-                                        {
-                                            slog_scope::debug!("Found view cmd");
-                                            self.process_view_command(&command, seat);
-                                            self.suppressed_keys.push(keysym);
-                                            result = FilterResult::Intercept(());
-                                            break;
-                                        }
-                                        if let Some(command) = self
-                                            .config
-                                            .exec
-                                            .keys
-                                            .iter()
-                                            .find(|(_, p)| p.modifiers == *modifiers && p.key == keysym)
-                                            .map(|(c, _)| c)
-                                            .cloned()
-                                        {
-                                            slog_scope::debug!("Found command: {}", command);
-                                            if let Err(err) = self.process_exec_command(&command) {
-                                                slog_scope::warn!("Failed to spawn process: {}", err);
-                                            }
-                                            self.suppressed_keys.push(keysym);
-                                            result = FilterResult::Intercept(());
-                                            break;
-                                        }
-                                    } else {
-                                        let suppressed = self.suppressed_keys.contains(&keysym);
-                                        if suppressed {
-                                            self.suppressed_keys.retain(|k| *k != keysym);
-                                            result = FilterResult::Intercept(());
-                                        }
-                                    }
+                let seat = match self.seat_of(&device) {
+                    Some(seat) => seat,
+                    None => return,
+                };
+                self.last_active_seat = seat.clone();
+                let keycode = event.key_code();
+                let state = event.state();
+                slog_scope::debug!("key"; "keycode" => keycode, "state" => format!("{:?}", state));
+                let serial = SCOUNTER.next_serial();
+                let time = Event::time(&event);
+                let keyboard = seat.get_keyboard().unwrap();
+                if let Some(lock) = self.lock.as_ref() {
+                    let output = seat.user_data().get::<ActiveOutput>().unwrap().0.borrow().clone();
+                    keyboard.set_focus(lock.focus(&output).as_ref(), serial);
+                }
+                keyboard.input(
+                    keycode,
+                    state,
+                    serial,
+                    time,
+                    |modifiers, handle| {
+                        *seat.user_data().get::<RefCell<KeyModifiers>>().unwrap().borrow_mut() = *modifiers;
+                        let suppressed = &seat.user_data().get::<KeyBindings>().unwrap().suppressed;
+                        // power keys work even while locked
+                        if let KeyState::Pressed = state {
+                            for keysym in handle.raw_syms().iter().copied() {
+                                if self.process_power_key(keysym) {
+                                    suppressed.borrow_mut().push(keysym);
+                                    return FilterResult::Intercept(());
                                 }
-                                result
-                            },
-                        );
+                            }
+                        }
+                        if let Some(lock) = self.lock.as_ref() {
+                            if lock.has_client() {
+                                return FilterResult::Forward;
+                            }
+                            // releases are swallowed too, including the one of the binding that locked
+                            if let KeyState::Pressed = state {
+                                self.lock_key(handle.modified_sym());
+                            } else {
+                                suppressed.borrow_mut().retain(|k| !handle.raw_syms().contains(k));
+                            }
+                            return FilterResult::Intercept(());
+                        }
+                        let mut result = FilterResult::Forward;
+                        for keysym in handle.raw_syms().iter().copied() {
+                            slog_scope::debug!("keysym";
+                                "state" => format!("{:?}", state),
+                                "mods" => format!("{:?}", modifiers),
+                                "keysym" => ::xkbcommon::xkb::keysym_get_name(keysym)
+                            );
 
-                        break;
-                    }
-                }
+                            // modifiers are ignored, so they can be combined with the touchpad
+                            if matches!(state, KeyState::Pressed) && !is_modifier(keysym) {
+                                seat.user_data().get::<LastTyped>().unwrap().0.set(Some(time));
+                                if self.config.cursor.hide_when_typing {
+                                    seat.user_data().get::<CursorVisibility>().unwrap().typing.set(true);
+                                }
+                            }
+
+                            // If the key is pressed and triggered a action
+                            // we will not forward the key to the client.
+                            // Additionally add the key to the suppressed keys
+                            // so that we can decide on a release if the key
+                            // should be forwarded to the client or not.
+                            if let KeyState::Pressed = state {
+                                if let Some(commands) = self.find_binding(&seat, modifiers, keysym) {
+                                    slog_scope::debug!("Found commands: {:?}", commands);
+                                    self.process_commands(&commands, &seat);
+                                    suppressed.borrow_mut().push(keysym);
+                                    result = FilterResult::Intercept(());
+                                    break;
+                                }
+                                if self.process_media_key(keysym) {
+                                    suppressed.borrow_mut().push(keysym);
+                                    result = FilterResult::Intercept(());
+                                    break;
+                                }
+                            } else {
+                                let mut suppressed = suppressed.borrow_mut();
+                                if suppressed.contains(&keysym) {
+                                    suppressed.retain(|k| *k != keysym);
+                                    result = FilterResult::Intercept(());
+                                }
+                            }
+                        }
+                        result
+                    },
+                );
             }
             InputEvent::PointerMotion { event, .. } => {
                 use smithay::backend::input::PointerMotionEvent;
 
                 let device = event.device();
-                for seat in self.seats.clone().iter() {
-                    let userdata = seat.user_data();
-                    let devices = userdata.get::<Devices>().unwrap();
-                    if devices.has_device(&device) {
-                        let mut current_output_name =
-                            userdata.get::<ActiveOutput>().unwrap().0.borrow_mut();
-                        let mut workspaces = self.workspaces.borrow_mut();
-
-                        let serial = SCOUNTER.next_serial();
-
-                        // clamp coordinates
-                        let mut location = seat.get_pointer().unwrap().current_location();
-                        let output_name = {
-                            location += event.delta();
-                            let current_output_geo = workspaces.output_by_name(&*current_output_name).unwrap().geometry();
-                            if (current_output_geo.size.w as f64) < location.x
-                                || location.x < 0.0
-                            {
-                                let mut x = location.x + current_output_geo.loc.x as f64;
-                                x = f64::min(f64::max(0.0, x), workspaces.width() as f64);
-                                let new_output = workspaces
-                                    .output(|o| {
-                                        let geo = o.geometry();
-                                        (geo.loc.x as f64) <= x
-                                            && (geo.loc.x + geo.size.w) as f64 >= x
-                                    }).unwrap();
-                                location.x = x - new_output.location().x as f64;
-                                String::from(new_output.name())
-                            } else {
-                                current_output_name.clone()
-                            }
-                        };
-                        location.y =
-                            f64::min(f64::max(0.0, location.y), workspaces.output_by_name(&output_name).unwrap().size().h as f64);
+                let seat = match self.seat_of(&device) {
+                    Some(seat) => seat,
+                    None => return,
+                };
+                let userdata = seat.user_data();
+                if self.suppressed_while_typing(&seat, &device, event.time()) {
+                    return;
+                }
+                userdata.get::<CursorVisibility>().unwrap().moved();
+                let mut current_output_name =
+                    userdata.get::<ActiveOutput>().unwrap().0.borrow_mut();
+                let mut workspaces = self.workspaces.borrow_mut();
 
-                        let space = workspaces.space_by_output_name(&output_name).unwrap();
-                        let under = space.surface_under(location);
-                        seat.get_pointer()
-                            .unwrap()
-                            .motion(location, under, serial, event.time());
+                let serial = SCOUNTER.next_serial();
 
-                        *current_output_name = output_name;
-                        break;
+                // clamp coordinates
+                let mut location = seat.get_pointer().unwrap().current_location();
+                let output_name = {
+                    location += event.delta();
+                    let current_output_geo = workspaces.output_by_name(&*current_output_name).unwrap().geometry();
+                    let global = location + current_output_geo.loc.to_f64();
+                    let new_output = workspaces.output(|o| {
+                        let geo = o.geometry();
+                        (geo.loc.x as f64) <= global.x
+                            && global.x < (geo.loc.x + geo.size.w) as f64
+                            && (geo.loc.y as f64) <= global.y
+                            && global.y < (geo.loc.y + geo.size.h) as f64
+                    });
+                    match new_output {
+                        Some(new_output) => {
+                            location = global - new_output.location().to_f64();
+                            String::from(new_output.name())
+                        }
+                        // there is no output, stay on the edge of the current one
+                        None => {
+                            location.x = f64::min(f64::max(0.0, location.x), current_output_geo.size.w as f64);
+                            current_output_name.clone()
+                        }
                     }
-                }
+                };
+                location.y =
+                    f64::min(f64::max(0.0, location.y), workspaces.output_by_name(&output_name).unwrap().size().h as f64);
+
+                let space = workspaces.space_by_output_name(&output_name).unwrap();
+                let under = space.surface_under(location);
+                seat.get_pointer()
+                    .unwrap()
+                    .motion(location, under, serial, event.time());
+
+                *current_output_name = output_name;
             }
             InputEvent::PointerMotionAbsolute { event, .. } => {
                 use smithay::backend::input::PointerMotionAbsoluteEvent;
 
                 let device = event.device();
-                for seat in self.seats.clone().iter() {
-                    let userdata = seat.user_data();
-                    let devices = userdata.get::<Devices>().unwrap();
-                    if devices.has_device(&device) {
-                        let output_name = userdata.get::<ActiveOutput>().unwrap().0.borrow();
-                        let mut workspaces = self.workspaces.borrow_mut();
-                        let output = workspaces.output_by_name(&*output_name).unwrap();
-                        let output_size = output.size();
-                        let pos =
-                            output.location().to_f64() + event.position_transformed(output_size);
-                        let serial = SCOUNTER.next_serial();
-                        let space = workspaces.space_by_output_name(&*output_name).unwrap();
-                        let under = space.surface_under(pos);
-                        seat.get_pointer()
-                            .unwrap()
-                            .motion(pos, under, serial, event.time());
-                        break;
-                    }
-                }
+                let seat = match self.seat_of(&device) {
+                    Some(seat) => seat,
+                    None => return,
+                };
+                let userdata = seat.user_data();
+                userdata.get::<CursorVisibility>().unwrap().moved();
+                let output_name = userdata.get::<ActiveOutput>().unwrap().0.borrow();
+                let mut workspaces = self.workspaces.borrow_mut();
+                let output = workspaces.output_by_name(&*output_name).unwrap();
+                // pointer locations are relative to the active output
+                let pos = event.position_transformed(output.size());
+                let serial = SCOUNTER.next_serial();
+                let space = workspaces.space_by_output_name(&*output_name).unwrap();
+                let under = space.surface_under(pos);
+                seat.get_pointer()
+                    .unwrap()
+                    .motion(pos, under, serial, event.time());
             }
             InputEvent::PointerButton { event, .. } => {
                 use smithay::{
@@ -308,42 +436,110 @@ impl Fireplace {
                 };
 
                 let device = event.device();
-                for seat in self.seats.clone().iter() {
-                    let userdata = seat.user_data();
-                    let devices = userdata.get::<Devices>().unwrap();
-                    if devices.has_device(&device) {
-                        let serial = SCOUNTER.next_serial();
-                        let button = match event.button() {
-                            MouseButton::Left => 0x110,
-                            MouseButton::Right => 0x111,
-                            MouseButton::Middle => 0x112,
-                            MouseButton::Other(b) => b as u32,
-                        };
-                        let state = match event.state() {
-                            ButtonState::Pressed => {
-                                // change the keyboard focus unless the pointer is grabbed
-                                if !seat.get_pointer().unwrap().is_grabbed() {
-                                    let mut workspaces = self.workspaces.borrow_mut();
-                                    let space = workspaces.space_by_seat(&seat).unwrap();
-                                    let pos = seat.get_pointer().unwrap().current_location();
-                                    let under = space.surface_under(pos);
-                                    if let Some(&(ref under, _)) = under.as_ref() {
-                                        space.on_focus(under);
+                let seat = match self.seat_of(&device) {
+                    Some(seat) => seat,
+                    None => return,
+                };
+                // releases are always forwarded to not leave buttons pressed
+                if matches!(event.state(), ButtonState::Pressed)
+                    && self.suppressed_while_typing(&seat, &device, event.time())
+                {
+                    return;
+                }
+                self.last_active_seat = seat.clone();
+                let serial = SCOUNTER.next_serial();
+                let button = match event.button() {
+                    MouseButton::Left => 0x110,
+                    MouseButton::Right => 0x111,
+                    MouseButton::Middle => 0x112,
+                    MouseButton::Other(b) => b as u32,
+                };
+                let mut intercept = false;
+                let state = match event.state() {
+                    ButtonState::Pressed => {
+                        // change the keyboard focus unless the pointer is grabbed
+                        if !seat.get_pointer().unwrap().is_grabbed() {
+                            let mut workspaces = self.workspaces.borrow_mut();
+                            let space = workspaces.space_by_seat(&seat).unwrap();
+                            let pointer = seat.get_pointer().unwrap();
+                            let pos = pointer.current_location();
+                            let mut under = space.surface_under(pos);
+                            let drag_modifier = self.config.view.drag_modifier.as_ref().map_or(false, |m| {
+                                m.is_set(&*seat.user_data().get::<RefCell<KeyModifiers>>().unwrap().borrow())
+                            });
+                            if drag_modifier && (button == 0x110 || button == 0x111) {
+                                // move or resize the window under the pointer
+                                // without forwarding the button to the client
+                                if let Some((toplevel, geometry)) = space
+                                    .toplevel_under(pos)
+                                    .filter(|(toplevel, _)| space.is_draggable(toplevel))
+                                {
+                                    // grabs without focus do not pass the buttons on
+                                    let start_data = GrabStartData {
+                                        focus: None,
+                                        button,
+                                        location: pos,
+                                    };
+                                    if button == 0x110 {
+                                        space.move_request(toplevel, &seat, serial, start_data);
+                                    } else {
+                                        let edges = resize_edges(geometry, pos);
+                                        space.resize_request(toplevel, &seat, serial, start_data, edges);
                                     }
-                                    if let Some(keyboard) = seat.get_keyboard() {
-                                        keyboard
-                                            .set_focus(under.as_ref().map(|&(ref s, _)| s), serial);
+                                    seat.user_data().get::<DragButtons>().unwrap().0.borrow_mut().push(button);
+                                    intercept = true;
+                                }
+                            }
+                            if under.is_none() && !intercept {
+                                if let Some((toplevel, hit)) =
+                                    decoration::under(&**space, &self.config.decoration, pos)
+                                {
+                                    under = toplevel
+                                        .get_surface()
+                                        .map(|surface| (surface.clone(), (0, 0).into()));
+                                    match hit {
+                                        Hit::Button(Button::Close) => toplevel.send_close(),
+                                        Hit::Button(Button::Maximize) => {
+                                            let maximized = toplevel.is_maximized();
+                                            space.maximize_request(toplevel, !maximized);
+                                        }
+                                        Hit::TitleBar => {
+                                            // dragging the title bar moves the window,
+                                            // if the layout allows it
+                                            let start_data = GrabStartData {
+                                                focus: None,
+                                                button,
+                                                location: pos,
+                                            };
+                                            space.move_request(toplevel, &seat, serial, start_data);
+                                        }
                                     }
                                 }
-                                wl_pointer::ButtonState::Pressed
                             }
-                            ButtonState::Released => wl_pointer::ButtonState::Released,
-                        };
-                        seat.get_pointer()
-                            .unwrap()
-                            .button(button, state, serial, event.time());
-                        break;
+                            if let Some(&(ref under, _)) = under.as_ref() {
+                                space.on_focus(under);
+                            }
+                            if let Some(keyboard) = seat.get_keyboard() {
+                                keyboard
+                                    .set_focus(under.as_ref().map(|&(ref s, _)| s), serial);
+                            }
+                        }
+                        wl_pointer::ButtonState::Pressed
+                    }
+                    ButtonState::Released => {
+                        let mut dragged = seat.user_data().get::<DragButtons>().unwrap().0.borrow_mut();
+                        if let Some(idx) = dragged.iter().position(|b| *b == button) {
+                            dragged.remove(idx);
+                            // the grab of the drag still has to see it to end
+                            intercept = !seat.get_pointer().unwrap().is_grabbed();
+                        }
+                        wl_pointer::ButtonState::Released
                     }
+                };
+                if !intercept {
+                    seat.get_pointer()
+                        .unwrap()
+                        .button(button, state, serial, event.time());
                 }
             }
             InputEvent::PointerAxis { event, .. } => {
@@ -354,68 +550,380 @@ impl Fireplace {
                 };
 
                 let device = event.device();
-                for seat in self.seats.clone().iter() {
-                    let userdata = seat.user_data();
-                    let devices = userdata.get::<Devices>().unwrap();
-                    if devices.has_device(&device) {
-                        let source = match event.source() {
-                            AxisSource::Continuous => wl_pointer::AxisSource::Continuous,
-                            AxisSource::Finger => wl_pointer::AxisSource::Finger,
-                            AxisSource::Wheel | AxisSource::WheelTilt => {
-                                wl_pointer::AxisSource::Wheel
-                            }
+                let seat = match self.seat_of(&device) {
+                    Some(seat) => seat,
+                    None => return,
+                };
+                if self.suppressed_while_typing(&seat, &device, event.time()) {
+                    return;
+                }
+                let source = match event.source() {
+                    AxisSource::Continuous => wl_pointer::AxisSource::Continuous,
+                    AxisSource::Finger => wl_pointer::AxisSource::Finger,
+                    AxisSource::Wheel | AxisSource::WheelTilt => {
+                        wl_pointer::AxisSource::Wheel
+                    }
+                };
+                let horizontal_amount =
+                    event.amount(Axis::Horizontal).unwrap_or_else(|| {
+                        event.amount_discrete(Axis::Horizontal).unwrap() * 3.0
+                    });
+                let vertical_amount = event.amount(Axis::Vertical).unwrap_or_else(|| {
+                    event.amount_discrete(Axis::Vertical).unwrap() * 3.0
+                });
+                let horizontal_amount_discrete = event.amount_discrete(Axis::Horizontal);
+                let vertical_amount_discrete = event.amount_discrete(Axis::Vertical);
+
+                {
+                    let mut frame = AxisFrame::new(event.time()).source(source);
+                    if horizontal_amount != 0.0 {
+                        frame = frame
+                            .value(wl_pointer::Axis::HorizontalScroll, horizontal_amount);
+                        if let Some(discrete) = horizontal_amount_discrete {
+                            frame = frame.discrete(
+                                wl_pointer::Axis::HorizontalScroll,
+                                discrete as i32,
+                            );
+                        }
+                    } else if source == wl_pointer::AxisSource::Finger {
+                        frame = frame.stop(wl_pointer::Axis::HorizontalScroll);
+                    }
+                    if vertical_amount != 0.0 {
+                        frame =
+                            frame.value(wl_pointer::Axis::VerticalScroll, vertical_amount);
+                        if let Some(discrete) = vertical_amount_discrete {
+                            frame = frame.discrete(
+                                wl_pointer::Axis::VerticalScroll,
+                                discrete as i32,
+                            );
+                        }
+                    } else if source == wl_pointer::AxisSource::Finger {
+                        frame = frame.stop(wl_pointer::Axis::VerticalScroll);
+                    }
+                    seat.get_pointer().unwrap().axis(frame);
+                }
+            }
+            InputEvent::TabletToolAxis { event, .. } => {
+                use smithay::backend::input::TabletToolEvent;
+
+                let device = event.device();
+                let seat = match self.seat_of(&device) {
+                    Some(seat) => seat,
+                    None => return,
+                };
+                let userdata = seat.user_data();
+                userdata.get::<CursorVisibility>().unwrap().moved();
+                let (output_name, pos) = match self.tablet_position::<B, _>(&seat, &event) {
+                    Some(mapped) => mapped,
+                    None => return,
+                };
+                let mut workspaces = self.workspaces.borrow_mut();
+                let space = workspaces.space_by_output_name(&output_name).unwrap();
+                let under = space.surface_under(pos);
+                *userdata.get::<ActiveOutput>().unwrap().0.borrow_mut() = output_name;
+                userdata.get::<TabletCursor>().unwrap().0.set(Some(pos));
+
+                let tablet_seat = seat.tablet_seat();
+                let tablet = tablet_seat.get_tablet(&TabletDescriptor::from(&device));
+                let tool = tablet_seat.get_tool(&event.tool());
+                if let (Some(tablet), Some(tool)) = (tablet, tool) {
+                    if event.pressure_has_changed() {
+                        tool.pressure(event.pressure());
+                    }
+                    if event.distance_has_changed() {
+                        tool.distance(event.distance());
+                    }
+                    if event.tilt_has_changed() {
+                        tool.tilt(event.tilt());
+                    }
+                    if event.slider_has_changed() {
+                        tool.slider_position(event.slider_position());
+                    }
+                    if event.rotation_has_changed() {
+                        tool.rotation(event.rotation());
+                    }
+                    if event.wheel_has_changed() {
+                        tool.wheel(event.wheel_delta(), event.wheel_delta_discrete());
+                    }
+                    tool.motion(pos, under, &tablet, SCOUNTER.next_serial(), event.time());
+                }
+            }
+            InputEvent::TabletToolProximity { event, .. } => {
+                use smithay::backend::input::{ProximityState, TabletToolProximityEvent};
+
+                let device = event.device();
+                let seat = match self.seat_of(&device) {
+                    Some(seat) => seat,
+                    None => return,
+                };
+                let userdata = seat.user_data();
+                let tablet_seat = seat.tablet_seat();
+                let tool = tablet_seat.add_tool(&event.tool());
+                match event.state() {
+                    ProximityState::In => {
+                        let (output_name, pos) = match self.tablet_position::<B, _>(&seat, &event) {
+                            Some(mapped) => mapped,
+                            None => return,
                         };
-                        let horizontal_amount =
-                            event.amount(Axis::Horizontal).unwrap_or_else(|| {
-                                event.amount_discrete(Axis::Horizontal).unwrap() * 3.0
-                            });
-                        let vertical_amount = event.amount(Axis::Vertical).unwrap_or_else(|| {
-                            event.amount_discrete(Axis::Vertical).unwrap() * 3.0
-                        });
-                        let horizontal_amount_discrete = event.amount_discrete(Axis::Horizontal);
-                        let vertical_amount_discrete = event.amount_discrete(Axis::Vertical);
-
-                        {
-                            let mut frame = AxisFrame::new(event.time()).source(source);
-                            if horizontal_amount != 0.0 {
-                                frame = frame
-                                    .value(wl_pointer::Axis::HorizontalScroll, horizontal_amount);
-                                if let Some(discrete) = horizontal_amount_discrete {
-                                    frame = frame.discrete(
-                                        wl_pointer::Axis::HorizontalScroll,
-                                        discrete as i32,
-                                    );
-                                }
-                            } else if source == wl_pointer::AxisSource::Finger {
-                                frame = frame.stop(wl_pointer::Axis::HorizontalScroll);
+                        let mut workspaces = self.workspaces.borrow_mut();
+                        let space = workspaces.space_by_output_name(&output_name).unwrap();
+                        let under = space.surface_under(pos);
+                        *userdata.get::<ActiveOutput>().unwrap().0.borrow_mut() = output_name;
+                        userdata.get::<TabletCursor>().unwrap().0.set(Some(pos));
+
+                        let tablet = tablet_seat.get_tablet(&TabletDescriptor::from(&device));
+                        if let (Some(under), Some(tablet)) = (under, tablet) {
+                            tool.proximity_in(pos, under, &tablet, SCOUNTER.next_serial(), event.time());
+                        }
+                    }
+                    ProximityState::Out => {
+                        userdata.get::<TabletCursor>().unwrap().0.set(None);
+                        tool.proximity_out(event.time());
+                    }
+                }
+            }
+            InputEvent::TabletToolTip { event, .. } => {
+                use smithay::backend::input::{TabletToolTipEvent, TabletToolTipState};
+
+                let device = event.device();
+                let seat = match self.seat_of(&device) {
+                    Some(seat) => seat,
+                    None => return,
+                };
+                let userdata = seat.user_data();
+                if let Some(tool) = seat.tablet_seat().get_tool(&event.tool()) {
+                    match event.tip_state() {
+                        TabletToolTipState::Down => {
+                            let serial = SCOUNTER.next_serial();
+                            tool.tip_down(serial, event.time());
+
+                            // touching a window with the pen focuses it
+                            let mut workspaces = self.workspaces.borrow_mut();
+                            let space = workspaces.space_by_seat(&seat).unwrap();
+                            let under = userdata
+                                .get::<TabletCursor>()
+                                .unwrap()
+                                .0
+                                .get()
+                                .and_then(|pos| space.surface_under(pos));
+                            if let Some(&(ref surface, _)) = under.as_ref() {
+                                space.on_focus(surface);
                             }
-                            if vertical_amount != 0.0 {
-                                frame =
-                                    frame.value(wl_pointer::Axis::VerticalScroll, vertical_amount);
-                                if let Some(discrete) = vertical_amount_discrete {
-                                    frame = frame.discrete(
-                                        wl_pointer::Axis::VerticalScroll,
-                                        discrete as i32,
-                                    );
-                                }
-                            } else if source == wl_pointer::AxisSource::Finger {
-                                frame = frame.stop(wl_pointer::Axis::VerticalScroll);
+                            if let Some(keyboard) = seat.get_keyboard() {
+                                keyboard.set_focus(under.as_ref().map(|&(ref s, _)| s), serial);
                             }
-                            seat.get_pointer().unwrap().axis(frame);
                         }
-                        break;
+                        TabletToolTipState::Up => {
+                            tool.tip_up(event.time());
+                        }
                     }
                 }
             }
+            InputEvent::TabletToolButton { event, .. } => {
+                use smithay::backend::input::TabletToolButtonEvent;
+
+                let device = event.device();
+                let seat = match self.seat_of(&device) {
+                    Some(seat) => seat,
+                    None => return,
+                };
+                if let Some(tool) = seat.tablet_seat().get_tool(&event.tool()) {
+                    tool.button(
+                        event.button(),
+                        event.button_state(),
+                        SCOUNTER.next_serial(),
+                        event.time(),
+                    );
+                }
+            }
             _ => {}
         }
     }
 
-    pub fn process_global_command(&mut self, command: &str) {
+    /// Checks if events of a touchpad should be dropped, because the seat was used for typing
+    ///
+    /// Touchpads are told apart from mice by their gesture capability.
+    fn suppressed_while_typing<D: Device>(&self, seat: &Seat, device: &D, time: u32) -> bool {
+        let timeout = match self.config.input.disable_while_typing {
+            Some(timeout) => timeout,
+            None => return false,
+        };
+        if !device.has_capability(DeviceCapability::Gesture) {
+            return false;
+        }
+        match seat.user_data().get::<LastTyped>().unwrap().0.get() {
+            Some(last) => time.wrapping_sub(last) < timeout,
+            None => false,
+        }
+    }
+
+    /// Maps the position of a tablet tool onto the configured output and region
+    fn tablet_position<B, E>(&self, seat: &Seat, event: &E) -> Option<(String, Point<f64, Logical>)>
+    where
+        B: InputBackend,
+        E: smithay::backend::input::TabletToolEvent<B>,
+    {
+        let tablet = &self.config.input.tablet;
+        let active = seat.user_data().get::<ActiveOutput>().unwrap().0.borrow().clone();
+        let mut workspaces = self.workspaces.borrow_mut();
+        let output_name = match tablet.output.as_ref() {
+            Some(name) if workspaces.output_by_name(name).is_some() => name.clone(),
+            _ => active,
+        };
+        let output_size = workspaces.output_by_name(&output_name)?.size();
+        let region = tablet
+            .region
+            .map(|region| Rectangle::from_loc_and_size((region.x, region.y), (region.width, region.height)))
+            .unwrap_or_else(|| Rectangle::from_loc_and_size((0, 0), output_size));
+        let pos = region.loc.to_f64() + event.position_transformed(region.size);
+        Some((output_name, pos))
+    }
+
+    /// Moves the seats of an unplugged output onto a remaining one
+    pub fn output_removed(&mut self, name: &str) {
+        let fallback = String::from(
+            self.workspaces
+                .borrow_mut()
+                .output(|_| true)
+                .map(|x| x.name())
+                .unwrap_or("headless"),
+        );
+        for seat in self.seats.iter() {
+            if let Some(active) = seat.user_data().get::<ActiveOutput>() {
+                if &*active.0.borrow() == name {
+                    slog_scope::debug!("Moving seat from {} to {}", name, fallback);
+                    *active.0.borrow_mut() = fallback.clone();
+                }
+            }
+        }
+    }
+
+    /// Queues a screenshot of the active output of the seat or its focused window
+    ///
+    /// It is served by the backend on the next frame of the output.
+    pub fn queue_screenshot(&mut self, seat: &Seat, window: bool, reply: Option<mpsc::Sender<Response>>) -> Result<(), String> {
+        let output = seat.user_data().get::<ActiveOutput>().unwrap().0.borrow().clone();
+        let window = if window {
+            let mut workspaces = self.workspaces.borrow_mut();
+            let space = workspaces.space_by_seat(seat).unwrap();
+            let focused = space.focused_window();
+            let geometry = space
+                .windows_from_bottom_to_top()
+                .find(|(toplevel, _, _)| Some(toplevel) == focused.as_ref())
+                .map(|(toplevel, location, bounding_box)| {
+                    decoration::window_geometry(&toplevel, location, bounding_box)
+                });
+            match geometry {
+                Some(geometry) => Some(geometry),
+                None => return Err(String::from("No focused window to take a screenshot of")),
+            }
+        } else {
+            None
+        };
+        self.screenshots.push(Screenshot { output, window, reply });
+        Ok(())
+    }
+
+    pub fn process_global_command(&mut self, command: &str, seat: &Seat) {
         match command {
             "terminate" => {
                 self.should_stop = true;
             }
+            "reload" => {
+                self.reload_config_or_report();
+            }
+            "lock" => {
+                self.lock_session();
+            }
+            "dismiss_notification" => {
+                self.dismiss_notification();
+            }
+            x if x.starts_with("brightness") => {
+                if let Err(err) = self.brightness_command(x.strip_prefix("brightness").unwrap()) {
+                    slog_scope::warn!("Failed to change the brightness: {:#}", err);
+                }
+            }
+            x if x.starts_with("mode ") => {
+                let name = x.strip_prefix("mode ").unwrap().trim();
+                let mode = &seat.user_data().get::<KeyBindings>().unwrap().mode;
+                if name == "default" {
+                    *mode.borrow_mut() = None;
+                } else if self.config.modes.contains_key(name) {
+                    *mode.borrow_mut() = Some(String::from(name));
+                } else {
+                    slog_scope::debug!("Unknown binding mode: {}", name);
+                    return;
+                }
+                slog_scope::info!("{} entered binding mode {}", seat.user_data().get::<SeatName>().unwrap().0, name);
+            }
+            x if x.starts_with("night_light") => {
+                self.night_light = match x.strip_prefix("night_light").unwrap().trim() {
+                    "on" => true,
+                    "off" => false,
+                    "" | "toggle" => !self.night_light,
+                    arg => {
+                        slog_scope::debug!("Unknown night_light argument: {}", arg);
+                        return;
+                    }
+                };
+                slog_scope::info!("Night light {}", if self.night_light { "enabled" } else { "disabled" });
+            }
+            x if x.starts_with("color_filter") => {
+                let configured = self.config.color_filter.filter;
+                self.color_filter = match x.strip_prefix("color_filter").unwrap().trim() {
+                    "on" => Some(self.color_filter.unwrap_or(configured)),
+                    "off" => None,
+                    "" | "toggle" if self.color_filter.is_some() => None,
+                    "" | "toggle" => Some(configured),
+                    "grayscale" => Some(ColorFilter::Grayscale),
+                    "invert" => Some(ColorFilter::Invert),
+                    "protanopia" => Some(ColorFilter::Protanopia),
+                    "deuteranopia" => Some(ColorFilter::Deuteranopia),
+                    arg => {
+                        slog_scope::debug!("Unknown color_filter argument: {}", arg);
+                        return;
+                    }
+                };
+                slog_scope::info!("Color filter set to {:?}", self.color_filter);
+            }
+            x if x.starts_with("debug_overlay") => {
+                self.debug_overlay = match x.strip_prefix("debug_overlay").unwrap().trim() {
+                    "on" => true,
+                    "off" => false,
+                    "" | "toggle" => !self.debug_overlay,
+                    arg => {
+                        slog_scope::debug!("Unknown debug_overlay argument: {}", arg);
+                        return;
+                    }
+                };
+                // statistics start over, frames rendered without the overlay are not comparable
+                self.frame_stats.clear();
+            }
+            x if x.starts_with("debug_damage") => {
+                self.debug_damage = match x.strip_prefix("debug_damage").unwrap().trim() {
+                    "on" => true,
+                    "off" => false,
+                    "" | "toggle" => !self.debug_damage,
+                    arg => {
+                        slog_scope::debug!("Unknown debug_damage argument: {}", arg);
+                        return;
+                    }
+                };
+            }
+            x if x.starts_with("output ") => {
+                let mut args = x.strip_prefix("output ").unwrap().split_whitespace();
+                match (args.next(), args.next()) {
+                    (Some(name), Some("enable")) => self.set_output_enabled(name, true),
+                    (Some(name), Some("disable")) => self.set_output_enabled(name, false),
+                    (Some(name), Some("position")) => {
+                        match (args.next().and_then(|x| x.parse().ok()), args.next().and_then(|y| y.parse().ok())) {
+                            (Some(x), Some(y)) => self.workspaces.borrow_mut().move_output(name, (x, y).into()),
+                            _ => slog_scope::debug!("Invalid output position: {}", command),
+                        }
+                    }
+                    _ => slog_scope::debug!("Invalid output command: {}", command),
+                }
+            }
             _ => {
                 slog_scope::debug!("Unknown global command: {}", command);
             }
@@ -426,12 +934,26 @@ impl Fireplace {
         let mut workspaces = self.workspaces.borrow_mut();
         match command {
             x if x.starts_with("workspace") => {
-                if let Ok(idx) = x.strip_prefix("workspace").unwrap().parse::<u8>() {
-                    workspaces.switch_workspace(seat, idx);
+                let name = x.strip_prefix("workspace").unwrap().trim();
+                match resolve_workspace(&workspaces, seat, name) {
+                    Some(idx) => {
+                        workspaces.switch_workspace(seat, idx);
+                        if self.config.view.warp_pointer {
+                            warp_to_focus(&mut workspaces, seat, self.start_time.elapsed().as_millis() as u32);
+                        }
+                    }
+                    None => slog_scope::debug!("Unknown workspace: {}", name),
                 }
             }
             x if x.starts_with("moveto_workspace") => {
-                if let Ok(idx) = x.strip_prefix("moveto_workspace").unwrap().parse::<u8>() {
+                let (name, follow) = match x.strip_prefix("moveto_workspace_follow") {
+                    Some(name) => (name.trim(), true),
+                    None => (
+                        x.strip_prefix("moveto_workspace").unwrap().trim(),
+                        self.config.workspace.follow,
+                    ),
+                };
+                if let Some(idx) = resolve_workspace(&workspaces, seat, name) {
                     slog_scope::debug!("Moveto: {}", idx);
                     let output_name = &seat.user_data().get::<ActiveOutput>().unwrap().0;
                     let current_space_idx = workspaces
@@ -439,7 +961,8 @@ impl Fireplace {
                         .unwrap();
                     if current_space_idx != idx {
                         let window = {
-                            let current_space = workspaces.space_by_idx(current_space_idx);
+                            let current_space =
+                                workspaces.space_by_idx(&*output_name.borrow(), current_space_idx);
                             if let Some(window) = current_space.focused_window() {
                                 current_space.remove_toplevel(window.clone());
                                 window
@@ -447,11 +970,68 @@ impl Fireplace {
                                 return;
                             }
                         };
-                        let new_space = workspaces.space_by_idx(idx);
-                        new_space.new_toplevel(window);
+                        let new_space = workspaces.space_by_idx(&*output_name.borrow(), idx);
+                        new_space.new_toplevel(window.clone());
+
+                        if follow {
+                            workspaces.switch_workspace(seat, idx);
+                            if let Some(surface) = window.get_surface() {
+                                if let Some(space) = workspaces.space_by_seat(seat) {
+                                    space.on_focus(surface);
+                                }
+                                if let Some(keyboard) = seat.get_keyboard() {
+                                    keyboard.set_focus(Some(surface), SCOUNTER.next_serial());
+                                }
+                            }
+                            if self.config.view.warp_pointer {
+                                warp_to_focus(&mut workspaces, seat, self.start_time.elapsed().as_millis() as u32);
+                            }
+                        }
                     }
                 }
             }
+            x if x.starts_with("rename_workspace") => {
+                let name = x.strip_prefix("rename_workspace").unwrap().trim();
+                let output_name = &seat.user_data().get::<ActiveOutput>().unwrap().0;
+                if let Some(idx) = workspaces.idx_by_output_name(&*output_name.borrow()) {
+                    workspaces.rename(idx, name);
+                }
+            }
+            x if x.starts_with("save_layout ") => {
+                let path = x.strip_prefix("save_layout ").unwrap().trim();
+                let space = workspaces.space_by_seat(seat).unwrap();
+                match space.save() {
+                    Some(saved) => {
+                        let result = std::fs::File::create(path)
+                            .map_err(|err| err.to_string())
+                            .and_then(|file| {
+                                serde_yaml::to_writer(file, &saved).map_err(|err| err.to_string())
+                            });
+                        match result {
+                            Ok(()) => slog_scope::info!("Saved layout to {}", path),
+                            Err(err) => slog_scope::warn!("Failed to save layout to {}: {}", path, err),
+                        }
+                    }
+                    None => slog_scope::warn!("The current layout cannot be saved"),
+                }
+            }
+            x if x.starts_with("restore_layout ") => {
+                let path = x.strip_prefix("restore_layout ").unwrap().trim();
+                let saved = std::fs::File::open(path)
+                    .map_err(|err| err.to_string())
+                    .and_then(|file| {
+                        serde_yaml::from_reader::<_, SavedNode>(file).map_err(|err| err.to_string())
+                    });
+                match saved {
+                    Ok(saved) => {
+                        let space = workspaces.space_by_seat(seat).unwrap();
+                        if !space.restore(saved) {
+                            slog_scope::warn!("The current layout cannot restore saved layouts");
+                        }
+                    }
+                    Err(err) => slog_scope::warn!("Failed to load layout from {}: {}", path, err),
+                }
+            }
             _ => {
                 slog_scope::debug!("Unknown workspace command: {}", command);
             }
@@ -467,24 +1047,436 @@ impl Fireplace {
                     window.send_close();
                 }
             }
-            _ => {
-                slog_scope::debug!("Unknown view command: {}", command);
+            x if x.starts_with("opacity ") => {
+                let mut workspaces = self.workspaces.borrow_mut();
+                let space = workspaces.space_by_seat(&seat).unwrap();
+                if let Some(window) = space.focused_window() {
+                    let arg = x.strip_prefix("opacity ").unwrap().trim();
+                    let opacity = if arg.starts_with('+') || arg.starts_with('-') {
+                        arg.parse::<f32>().map(|step| space.opacity(&window) + step)
+                    } else {
+                        arg.parse::<f32>()
+                    };
+                    match opacity {
+                        Ok(opacity) => space.set_opacity(&window, opacity),
+                        Err(_) => slog_scope::debug!("Invalid opacity: {}", arg),
+                    }
+                }
+            }
+            x if x.starts_with("screenshot") => {
+                let window = match x.strip_prefix("screenshot").unwrap().trim() {
+                    "" | "output" => false,
+                    "window" => true,
+                    arg => {
+                        slog_scope::debug!("Unknown screenshot argument: {}", arg);
+                        return;
+                    }
+                };
+                if let Err(err) = self.queue_screenshot(&seat, window, None) {
+                    slog_scope::debug!("{}", err);
+                }
+            }
+            x => {
+                let mut workspaces = self.workspaces.borrow_mut();
+                let space = workspaces.space_by_seat(&seat).unwrap();
+                if space.command(x) {
+                    // the command might have moved the focus
+                    if let Some(keyboard) = seat.get_keyboard() {
+                        let focused = space.focused_window();
+                        keyboard.set_focus(
+                            focused.as_ref().and_then(|w| w.get_surface()),
+                            SCOUNTER.next_serial(),
+                        );
+                    }
+                    if self.config.view.warp_pointer {
+                        warp_to_focus(&mut workspaces, seat, self.start_time.elapsed().as_millis() as u32);
+                    }
+                } else {
+                    slog_scope::debug!("Unknown view command: {}", command);
+                }
             }
         }
     }
 
+    /// Variables telling programs which display and desktop they run on
+    ///
+    /// The i3 ipc socket is only announced once it is up.
+    pub fn session_environment(&self) -> Vec<(&'static str, String)> {
+        let mut variables = vec![
+            ("WAYLAND_DISPLAY", self.socket_name.to_string_lossy().into_owned()),
+            ("XDG_CURRENT_DESKTOP", String::from("fireplace")),
+            ("XDG_SESSION_DESKTOP", String::from("fireplace")),
+            ("XDG_SESSION_TYPE", String::from("wayland")),
+        ];
+        for name in &["I3SOCK", "SWAYSOCK"] {
+            if let Ok(value) = std::env::var(name) {
+                variables.push((*name, value));
+            }
+        }
+        variables
+    }
+
     pub fn process_exec_command(&mut self, command: &str) -> std::io::Result<()> {
-        std::process::Command::new("/bin/sh")
+        let child = std::process::Command::new("/bin/sh")
             .arg("-c")
             .arg(command)
             .env_remove("DISPLAY")
-            .env("WAYLAND_DISPLAY", &self.socket_name)
+            .envs(self.session_environment())
             .env("WAYLAND_DEBUG", if cfg!(debug_assertions) { "1" } else { "0" })
-            .spawn()
-            .map(|_| ())
+            .spawn()?;
+        if self.config.exec.scopes && systemd::is_running() {
+            let program = program_name(command);
+            let pid = child.id();
+            // the user instance may take a moment to answer
+            let _ = std::thread::Builder::new().name(String::from("scope")).spawn(move || {
+                if let Err(err) = systemd::start_scope(&program, pid) {
+                    slog_scope::warn!("Failed to start a scope for {}: {}", program, err);
+                }
+            });
+        }
+        Ok(())
+    }
+
+    /// Starts the autostart commands, at startup all of them, on reloads those not marked `once`
+    ///
+    /// Desktop entries of the XDG autostart directories are only started with the session.
+    pub fn run_autostart(&mut self, startup: bool) {
+        let mut commands = self
+            .config
+            .autostart
+            .iter()
+            .filter(|autostart| startup || !autostart.once)
+            .map(|autostart| autostart.command.clone())
+            .collect::<Vec<_>>();
+        if startup && self.config.xdg_autostart {
+            commands.extend(autostart::xdg_autostart_commands());
+        }
+        for command in commands {
+            slog_scope::debug!("Autostarting {}", command);
+            if let Err(err) = self.process_exec_command(&command) {
+                slog_scope::warn!("Failed to autostart {}: {}", command, err);
+            }
+        }
+    }
+
+    /// Applies the repeat configuration to the keyboards of all seats
+    pub fn apply_keyboard_config(&mut self) {
+        let keyboard = &self.config.input.keyboard;
+        for seat in self.seats.iter() {
+            if let Some(handle) = seat.get_keyboard() {
+                handle.change_repeat_info(keyboard.repeat_rate, keyboard.repeat_delay);
+            }
+        }
+    }
+
+    /// Recreates the keyboards of all seats with the configured keymap, keeping their focus
+    pub fn apply_keymap(&mut self) {
+        for seat in self.seats.iter_mut() {
+            if seat.get_keyboard().is_none() {
+                continue;
+            }
+            add_keyboard(seat, &self.config.input.keyboard);
+            let focused = self
+                .workspaces
+                .borrow_mut()
+                .space_by_seat(seat)
+                .and_then(|space| space.focused_window());
+            if let Some(keyboard) = seat.get_keyboard() {
+                keyboard.set_focus(focused.as_ref().and_then(|w| w.get_surface()), SCOUNTER.next_serial());
+            }
+        }
+    }
+
+    /// Seat of an input device, assigned once it was added
+    ///
+    /// Seats are handles, the clone is cheap and leaves `self` free to be borrowed mutably.
+    fn seat_of<D: Device>(&self, device: &D) -> Option<Seat> {
+        self.device_seats.get(&device.id()).cloned()
+    }
+
+    /// Index of the seat a new device is assigned to, the first one without a matching rule
+    fn seat_for_device<D: Device>(&self, device: &D) -> usize {
+        let name = device.name();
+        let syspath = device.syspath();
+        let usb_id = device.usb_id();
+        self.config
+            .seat_rules
+            .iter()
+            .find(|rule| rule.matches(&name, syspath.as_deref(), usb_id))
+            .and_then(|rule| {
+                let idx = self.seats.iter().position(|seat| seat.user_data().get::<SeatName>().unwrap().0 == rule.seat);
+                if idx.is_none() {
+                    slog_scope::warn!("Device {} is assigned to unknown seat {}", name, rule.seat);
+                }
+                idx
+            })
+            .unwrap_or(0)
+    }
+
+    /// Runs a single command with the handler it belongs to
+    ///
+    /// Commands are routed by their name, programs are started by `exec <command>`.
+    pub fn process_command(&mut self, command: &str, seat: &Seat) {
+        let command = command.trim();
+        if self.config.aliases.contains_key(command) {
+            match expand_aliases(&self.config.aliases, command) {
+                Ok(commands) => self.process_commands(&commands, seat),
+                Err(err) => slog_scope::warn!("{}", err),
+            }
+            return;
+        }
+        match command_table(command) {
+            Binding::Global => self.process_global_command(command, seat),
+            Binding::Workspace => self.process_workspace_command(command, seat),
+            Binding::View => self.process_view_command(command, seat),
+            Binding::Exec => {
+                let program = command.strip_prefix("exec").unwrap().trim();
+                if let Err(err) = self.process_exec_command(program) {
+                    slog_scope::warn!("Failed to spawn process: {}", err);
+                }
+            }
+        }
+    }
+
+    /// Runs a chain of commands in order
+    pub fn process_commands(&mut self, commands: &[String], seat: &Seat) {
+        for command in commands {
+            self.process_command(command, seat);
+        }
+    }
+
+    /// Looks up the commands bound to a key in the binding mode of the seat
+    fn find_binding(&self, seat: &Seat, modifiers: &KeyModifiers, keysym: Keysym) -> Option<Vec<String>> {
+        let mode = seat.user_data().get::<KeyBindings>().unwrap().mode.borrow().clone();
+        let (bindings, tables) = match mode.as_ref().and_then(|name| self.config.modes.get(name)) {
+            Some(mode) => (
+                &mode.bindings,
+                [
+                    (Binding::Global, &mode.keys),
+                    (Binding::Workspace, &mode.workspace),
+                    (Binding::View, &mode.view),
+                    (Binding::Exec, &mode.exec),
+                ],
+            ),
+            None => (
+                &self.config.bindings,
+                [
+                    (Binding::Global, &self.config.keys),
+                    (Binding::Workspace, &self.config.workspace.keys),
+                    (Binding::View, &self.config.view.keys),
+                    (Binding::Exec, &self.config.exec.keys),
+                ],
+            ),
+        };
+
+        let found = bindings
+            .iter()
+            .find(|b| b.key.modifiers == *modifiers && b.key.key == keysym)
+            .map(|b| b.commands.clone())
+            .or_else(|| {
+                tables.iter().find_map(|&(binding, keys)| {
+                    keys.iter()
+                        .find(|(_, p)| p.modifiers == *modifiers && p.key == keysym)
+                        .map(|(command, _)| table_commands(binding, command))
+                })
+            });
+        if found.is_none() && mode.is_some() && keysym == KeySyms::KEY_Escape {
+            return Some(vec![String::from("mode default")]);
+        }
+        found
+    }
+
+    /// Name of the active binding mode of a seat
+    pub fn binding_mode(&self, seat: &Seat) -> String {
+        seat.user_data()
+            .get::<KeyBindings>()
+            .unwrap()
+            .mode
+            .borrow()
+            .clone()
+            .unwrap_or_else(|| String::from("default"))
     }
 
     pub fn last_active_seat(&self) -> &Seat {
         &self.last_active_seat
     }
 }
+
+/// Adds a keyboard with the configured keymap to a seat, replacing an existing one
+fn add_keyboard(seat: &mut Seat, keyboard: &KeyboardConfig) {
+    let xkb = XkbConfig {
+        rules: &keyboard.rules,
+        model: &keyboard.model,
+        layout: &keyboard.layout,
+        variant: &keyboard.variant,
+        options: keyboard.options.clone(),
+    };
+    let (delay, rate) = (keyboard.repeat_delay, keyboard.repeat_rate);
+    if let Err(err) = seat.add_keyboard(xkb, delay, rate, keyboard_focus_changed) {
+        slog_scope::warn!("Invalid keymap configuration, using the default keymap: {}", err);
+        let _ = seat.add_keyboard(XkbConfig::default(), delay, rate, keyboard_focus_changed);
+    }
+}
+
+/// Moves the pointer to the center of the focused window, unless it is already above it
+fn warp_to_focus(workspaces: &mut Workspaces, seat: &Seat, time: u32) {
+    let pointer = match seat.get_pointer() {
+        Some(pointer) => pointer,
+        None => return,
+    };
+    let space = match workspaces.space_by_seat(seat) {
+        Some(space) => space,
+        None => return,
+    };
+    let focused = match space.focused_window() {
+        Some(focused) => focused,
+        None => return,
+    };
+    let geometry = match space
+        .windows_from_bottom_to_top()
+        .find(|(toplevel, _, _)| *toplevel == focused)
+        .map(|(toplevel, location, bounding_box)| decoration::window_geometry(&toplevel, location, bounding_box))
+    {
+        Some(geometry) => geometry,
+        None => return,
+    };
+    if geometry.to_f64().contains(pointer.current_location()) {
+        return;
+    }
+
+    let center: Point<f64, Logical> = (
+        geometry.loc.x as f64 + geometry.size.w as f64 / 2.0,
+        geometry.loc.y as f64 + geometry.size.h as f64 / 2.0,
+    )
+        .into();
+    let under = space.surface_under(center);
+    pointer.motion(center, under, SCOUNTER.next_serial(), time);
+}
+
+/// Checks if a keysym belongs to a modifier key
+fn is_modifier(keysym: Keysym) -> bool {
+    (KeySyms::KEY_Shift_L..=KeySyms::KEY_Hyper_R).contains(&keysym)
+        || keysym == KeySyms::KEY_ISO_Level3_Shift
+        || keysym == KeySyms::KEY_Mode_switch
+}
+
+/// Name of the program a shell command runs, skipping variable assignments and paths
+fn program_name(command: &str) -> String {
+    command
+        .split_whitespace()
+        .find(|word| !word.contains('='))
+        .map(|word| word.rsplit('/').next().unwrap_or(word))
+        .filter(|name| !name.is_empty())
+        .map(String::from)
+        .unwrap_or_else(|| String::from("command"))
+}
+
+/// Checks if a command would be understood by its handler, without running it
+///
+/// Arguments are left to the handlers, layout commands may still be unknown to the layout of a workspace.
+pub fn is_known_command(command: &str) -> bool {
+    let mut words = command.split_whitespace();
+    let name = words.next().unwrap_or("");
+    match command_table(command) {
+        Binding::Exec => words.next().is_some(),
+        Binding::Global => true,
+        Binding::Workspace => matches!(
+            name,
+            "workspace" | "moveto_workspace" | "rename_workspace" | "save_layout" | "restore_layout"
+        ),
+        Binding::View => matches!(
+            name,
+            "close"
+                | "opacity"
+                | "screenshot"
+                | "split"
+                | "layout"
+                | "focus"
+                | "swap"
+                | "resize"
+                | "equalize"
+                | "promote"
+                | "master"
+        ),
+    }
+}
+
+/// Replaces an alias by the commands it stands for, recursively
+///
+/// Fails for aliases using themselves, directly or through other aliases.
+pub fn expand_aliases(aliases: &HashMap<String, String>, command: &str) -> Result<Vec<String>, String> {
+    fn expand<'a>(
+        aliases: &'a HashMap<String, String>,
+        command: &'a str,
+        stack: &mut Vec<&'a str>,
+        expanded: &mut Vec<String>,
+    ) -> Result<(), String> {
+        let commands = match aliases.get(command) {
+            Some(commands) => commands,
+            None => {
+                expanded.push(String::from(command));
+                return Ok(());
+            }
+        };
+        if stack.contains(&command) {
+            return Err(format!("Alias {} expands to itself: {} -> {}", stack[0], stack.join(" -> "), command));
+        }
+        stack.push(command);
+        for command in commands.split(';').map(str::trim).filter(|command| !command.is_empty()) {
+            expand(aliases, command, stack, expanded)?;
+        }
+        stack.pop();
+        Ok(())
+    }
+
+    let mut expanded = Vec::new();
+    expand(aliases, command.trim(), &mut Vec::new(), &mut expanded)?;
+    Ok(expanded)
+}
+
+/// Finds the handler of a command by its name
+fn command_table(command: &str) -> Binding {
+    match command.split_whitespace().next().unwrap_or("") {
+        "exec" => Binding::Exec,
+        "terminate" | "reload" | "lock" | "dismiss_notification" | "brightness" | "mode" | "night_light" | "color_filter" | "debug_overlay" | "debug_damage" | "output" => Binding::Global,
+        "rename_workspace" | "save_layout" | "restore_layout" => Binding::Workspace,
+        x if x.starts_with("workspace") || x.starts_with("moveto_workspace") => Binding::Workspace,
+        _ => Binding::View,
+    }
+}
+
+/// Splits a command of a binding table into the commands it chains with `;`
+///
+/// Commands of the exec table are passed to the shell unmodified.
+fn table_commands(binding: Binding, command: &str) -> Vec<String> {
+    match binding {
+        Binding::Exec => vec![format!("exec {}", command)],
+        _ => command
+            .split(';')
+            .map(str::trim)
+            .filter(|command| !command.is_empty())
+            .map(String::from)
+            .collect(),
+    }
+}
+
+/// Resolves a workspace argument, either `next`, `prev` or a number/name
+fn resolve_workspace(workspaces: &Workspaces, seat: &Seat, name: &str) -> Option<u32> {
+    match name {
+        "next" => workspaces.neighbour(seat, true),
+        "prev" => workspaces.neighbour(seat, false),
+        name => workspaces.idx_by_name(name),
+    }
+}
+
+/// Picks the edges closest to the pointer for resizing a window
+fn resize_edges(geometry: Rectangle<i32, Logical>, pos: Point<f64, Logical>) -> ResizeEdge {
+    let center_x = geometry.loc.x as f64 + geometry.size.w as f64 / 2.0;
+    let center_y = geometry.loc.y as f64 + geometry.size.h as f64 / 2.0;
+    match (pos.x < center_x, pos.y < center_y) {
+        (true, true) => ResizeEdge::TopLeft,
+        (false, true) => ResizeEdge::TopRight,
+        (true, false) => ResizeEdge::BottomLeft,
+        (false, false) => ResizeEdge::BottomRight,
+    }
+}
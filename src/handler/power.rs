@@ -0,0 +1,85 @@
+use smithay::wayland::seat::Keysym;
+use std::time::{Duration, Instant};
+
+use crate::{config::PowerAction, handler::keyboard::KeySyms, logind, state::Fireplace};
+
+/// How long a prompt waits for the key to be pressed again
+const PROMPT_DURATION: Duration = Duration::from_secs(10);
+/// Time the outputs get to show the lock before the machine goes to sleep
+const LOCK_DELAY: Duration = Duration::from_millis(500);
+
+impl Fireplace {
+    /// Takes the keys handled by fireplace away from logind
+    pub fn apply_power_keys(&mut self) {
+        self.power_inhibitor = None;
+        let keys = &self.config.power_keys;
+        let mut what = Vec::new();
+        if keys.power != PowerAction::Logind {
+            what.push("handle-power-key");
+        }
+        if keys.suspend != PowerAction::Logind {
+            what.push("handle-suspend-key");
+        }
+        if what.is_empty() {
+            return;
+        }
+        match logind::inhibit(&what.join(":"), "Fireplace handles the power keys") {
+            Ok(inhibitor) => self.power_inhibitor = Some(inhibitor),
+            Err(err) => slog_scope::warn!("Failed to take the power keys from logind: {}", err),
+        }
+    }
+
+    /// Runs the action of the power and suspend keys, returns if the key was handled
+    pub fn process_power_key(&mut self, keysym: Keysym) -> bool {
+        let keys = &self.config.power_keys;
+        let (action, suspend) = match keysym {
+            KeySyms::KEY_XF86PowerOff => (keys.power, false),
+            KeySyms::KEY_XF86Sleep | KeySyms::KEY_XF86Suspend => (keys.suspend, true),
+            _ => return false,
+        };
+        match action {
+            PowerAction::Logind => return false,
+            PowerAction::Suspend => suspend_later(Duration::ZERO),
+            PowerAction::LockThenSuspend => {
+                self.lock_session();
+                suspend_later(LOCK_DELAY);
+            }
+            PowerAction::PowerOff => power_off(),
+            PowerAction::Prompt => {
+                let confirmed = self
+                    .power_prompt
+                    .take()
+                    .map(|(prompted, shown)| prompted == keysym && shown.elapsed() < PROMPT_DURATION)
+                    .unwrap_or(false);
+                if !confirmed {
+                    self.power_prompt = Some((keysym, Instant::now()));
+                    let action = if suspend { "suspend" } else { "power off" };
+                    self.show_message(&format!("Press the key again to {}", action));
+                } else if suspend {
+                    suspend_later(Duration::ZERO);
+                } else {
+                    power_off();
+                }
+            }
+        }
+        true
+    }
+}
+
+/// Asks logind to suspend on a thread of its own, it may take a while to authorize
+fn suspend_later(delay: Duration) {
+    let _ = std::thread::Builder::new().name(String::from("suspend")).spawn(move || {
+        std::thread::sleep(delay);
+        if let Err(err) = logind::suspend() {
+            slog_scope::error!("Failed to suspend: {}", err);
+        }
+    });
+}
+
+fn power_off() {
+    let _ = std::thread::Builder::new().name(String::from("power off")).spawn(|| {
+        if let Err(err) = logind::power_off() {
+            slog_scope::error!("Failed to power off: {}", err);
+        }
+    });
+}
@@ -0,0 +1,204 @@
+use anyhow::{Context, Result};
+use serde_yaml::Value;
+use smithay::reexports::{
+    calloop::{generic::Generic, EventLoop, Interest, Mode, PostAction},
+    nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify},
+};
+use std::{
+    collections::HashMap,
+    os::unix::io::AsRawFd,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    config::{self, Config},
+    state::Fireplace,
+};
+
+/// How long messages stay on screen
+const MESSAGE_DURATION: Duration = Duration::from_secs(10);
+/// Characters per line of a message
+const MESSAGE_COLUMNS: usize = 80;
+
+/// Message shown on top of every output, like errors in the configuration
+pub struct Message {
+    pub lines: Vec<String>,
+    shown: Instant,
+}
+
+/// Sections only read at startup
+const RESTART_SECTIONS: &[&str] = &["logging", "seats", "winit", "vnc", "prometheus", "screencast", "import_environment", "notifications"];
+
+/// Reloads the configuration whenever its file is written
+///
+/// The directory is watched, as editors tend to replace files instead of writing to them.
+pub fn watch_config(event_loop: &mut EventLoop<'static, Fireplace>, state: &mut Fireplace) -> Result<()> {
+    let path = match state.config_path.clone() {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+    let (directory, file) = match (path.parent(), path.file_name()) {
+        (Some(directory), Some(file)) => (directory.to_path_buf(), file.to_os_string()),
+        _ => return Ok(()),
+    };
+
+    let inotify = Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC)?;
+    inotify.add_watch(&directory, AddWatchFlags::IN_CLOSE_WRITE | AddWatchFlags::IN_MOVED_TO)?;
+    let token = event_loop
+        .handle()
+        .insert_source(
+            Generic::from_fd(inotify.as_raw_fd(), Interest::READ, Mode::Level),
+            move |_, _, state: &mut Fireplace| {
+                let events = match inotify.read_events() {
+                    Ok(events) => events,
+                    Err(err) => {
+                        slog_scope::debug!("Failed to read changes of the config directory: {}", err);
+                        return Ok(PostAction::Continue);
+                    }
+                };
+                if events.iter().any(|event| event.name.as_ref() == Some(&file)) {
+                    state.guard("reload", |state| state.reload_config_or_report());
+                }
+                Ok(PostAction::Continue)
+            },
+        )
+        .map_err(|_| anyhow::anyhow!("Failed to watch the configuration on the event loop"))?;
+    state.tokens.push(token);
+    slog_scope::debug!("Watching {} for changes", path.display());
+
+    Ok(())
+}
+
+/// Top-level keys of two configurations with different values
+fn changed_sections(old: &Value, new: &Value) -> Vec<String> {
+    let empty = serde_yaml::Mapping::new();
+    let old = old.as_mapping().unwrap_or(&empty);
+    let new = new.as_mapping().unwrap_or(&empty);
+    let mut changed = old
+        .iter()
+        .chain(new.iter())
+        .filter(|(key, _)| old.get(key) != new.get(key))
+        .filter_map(|(key, _)| key.as_str().map(String::from))
+        .collect::<Vec<_>>();
+    changed.sort();
+    changed.dedup();
+    changed
+}
+
+impl Fireplace {
+    /// Reads the configuration file again and applies it, leaving the current one in place on errors
+    pub fn reload_config(&mut self) -> Result<()> {
+        let path = self.config_path.clone().context("There is no config file, the defaults are used")?;
+        let source = config::read_source(&path).with_context(|| format!("Malformed config file {}", path.display()))?;
+        let config: Config = serde_yaml::from_value(source.clone())
+            .with_context(|| format!("Malformed config file {}", path.display()))?;
+        slog_scope::info!("Reloading the configuration from {}", path.display());
+        self.apply_config(source, config);
+        self.run_autostart(false);
+        Ok(())
+    }
+
+    /// Reloads the configuration, showing what went wrong on every output
+    pub fn reload_config_or_report(&mut self) {
+        match self.reload_config() {
+            Ok(()) => {
+                // the error was fixed
+                if self.message.take().is_some() {
+                    self.damage();
+                }
+            }
+            Err(err) => {
+                slog_scope::error!("Failed to reload the configuration: {:#}", err);
+                self.show_message(&format!("Failed to reload the configuration: {:#}", err));
+            }
+        }
+    }
+
+    /// Shows a message on every output for a few seconds
+    pub fn show_message(&mut self, text: &str) {
+        self.message = Some(Message {
+            lines: wrap(text, MESSAGE_COLUMNS),
+            shown: Instant::now(),
+        });
+        self.damage();
+    }
+
+    /// Hides the message once it was shown long enough
+    pub fn expire_message(&mut self) {
+        if self.message.as_ref().map(|message| message.shown.elapsed() >= MESSAGE_DURATION).unwrap_or(false) {
+            self.message = None;
+            self.damage();
+        }
+    }
+
+    /// Replaces the configuration and applies the changed sections that are not read on demand
+    pub fn apply_config(&mut self, source: Value, config: Config) {
+        let changed = changed_sections(&self.config_source, &source);
+        slog_scope::debug!("Changed configuration sections: {:?}", changed);
+        for section in changed.iter().filter(|section| RESTART_SECTIONS.contains(&section.as_str())) {
+            slog_scope::warn!("Changes of {} take effect after a restart", section);
+        }
+        self.config = config;
+        self.config_source = source;
+
+        let changed = |name: &str| changed.iter().any(|section| section == name);
+        if changed("input") {
+            self.apply_keymap();
+            self.apply_keyboard_config();
+            self.configure_input_devices();
+        }
+        if changed("workspace") || changed("decoration") {
+            self.workspaces.borrow_mut().apply_config(&self.config);
+        }
+        if changed("outputs") {
+            self.apply_output_positions();
+        }
+        if changed("power_keys") {
+            self.apply_power_keys();
+        }
+        self.damage();
+    }
+
+    /// Moves outputs to their configured positions
+    ///
+    /// Modes, mirrors, bit depths and hdr are only set up when an output is connected.
+    fn apply_output_positions(&mut self) {
+        let mut identifiers = self
+            .udev
+            .values()
+            .flat_map(|backend| backend.surfaces.values())
+            .map(|surface| (surface.output.clone(), surface.identifier.clone()))
+            .collect::<HashMap<_, _>>();
+        let mut workspaces = self.workspaces.borrow_mut();
+        let names = workspaces.outputs().map(|o| String::from(o.name())).collect::<Vec<_>>();
+        for name in names {
+            // nested outputs have no monitor to identify them by
+            let identifier = identifiers.remove(&name).unwrap_or_else(|| name.clone());
+            let position = self
+                .config
+                .output(&name, &identifier)
+                .and_then(|config| config.position)
+                .map(|position| (position.x, position.y).into());
+            workspaces.set_preferred_location(&name, position);
+        }
+    }
+}
+
+/// Breaks text into lines of at most `columns` characters at whitespace, where possible
+pub fn wrap(text: &str, columns: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            if !line.is_empty() && line.chars().count() + 1 + word.chars().count() > columns {
+                lines.push(std::mem::take(&mut line));
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(word);
+        }
+        lines.push(line);
+    }
+    lines
+}
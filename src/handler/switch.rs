@@ -0,0 +1,43 @@
+use crate::state::Fireplace;
+
+impl Fireplace {
+    /// Handles the lid of a laptop being opened or closed
+    pub fn lid_switch(&mut self, closed: bool) {
+        if self.lid_closed == closed {
+            return;
+        }
+        slog_scope::info!("Lid {}", if closed { "closed" } else { "opened" });
+        self.lid_closed = closed;
+        self.update_clamshell();
+
+        let commands = if closed {
+            self.config.switches.lid_closed.clone()
+        } else {
+            self.config.switches.lid_opened.clone()
+        };
+        let seat = self.last_active_seat.clone();
+        self.process_commands(&commands, &seat);
+    }
+
+    /// Handles a convertible being folded into or out of tablet mode
+    pub fn tablet_mode_switch(&mut self, enabled: bool) {
+        if self.tablet_mode == enabled {
+            return;
+        }
+        slog_scope::info!("Tablet mode {}", if enabled { "enabled" } else { "disabled" });
+        self.tablet_mode = enabled;
+
+        let commands = if enabled {
+            self.config.switches.tablet_mode_on.clone()
+        } else {
+            self.config.switches.tablet_mode_off.clone()
+        };
+        let seat = self.last_active_seat.clone();
+        self.process_commands(&commands, &seat);
+    }
+
+    /// Whether a convertible is currently in tablet mode
+    pub fn is_tablet_mode(&self) -> bool {
+        self.tablet_mode
+    }
+}
@@ -0,0 +1,178 @@
+use anyhow::Result;
+use smithay::reexports::calloop::{channel, EventLoop};
+use std::{collections::HashMap, convert::TryFrom, sync::mpsc};
+use zbus::{dbus_interface, fdo};
+use zvariant::ObjectPath;
+
+use super::{
+    protocol::{Request, Response},
+    Message,
+};
+use crate::{handler::ActiveOutput, state::Fireplace};
+
+const BUS_NAME: &str = "org.fireplace.Compositor";
+const OBJECT_PATH: &str = "/org/fireplace/Compositor";
+const INTERFACE: &str = "org.fireplace.Compositor";
+
+/// `org.fireplace.Compositor`, answered by the same dispatcher as the ipc socket
+///
+/// Additionally emits `WorkspaceChanged(name, output)` and `FocusChanged(app_id, title)`.
+struct Compositor {
+    compositor: channel::Sender<Message>,
+}
+
+impl Compositor {
+    fn request(&self, request: Request) -> fdo::Result<Response> {
+        let (reply, response) = mpsc::channel();
+        self.compositor
+            .send(Message { request, reply })
+            .map_err(|_| fdo::Error::Failed(String::from("The compositor is shutting down")))?;
+        match response.recv() {
+            Ok(Response::Error { message }) => Err(fdo::Error::Failed(message)),
+            Ok(response) => Ok(response),
+            Err(_) => Err(fdo::Error::Failed(String::from("The compositor is shutting down"))),
+        }
+    }
+
+    fn command(&self, command: String) -> fdo::Result<()> {
+        self.request(Request::Command {
+            commands: vec![command],
+            seat: None,
+        })
+        .map(|_| ())
+    }
+}
+
+#[dbus_interface(name = "org.fireplace.Compositor")]
+impl Compositor {
+    fn run_command(&self, command: &str) -> fdo::Result<()> {
+        self.command(String::from(command))
+    }
+
+    fn switch_workspace(&self, name: &str) -> fdo::Result<()> {
+        self.command(format!("workspace {}", name))
+    }
+
+    /// Saves a screenshot of the active output or its focused window
+    fn screenshot(&self, window: bool) -> fdo::Result<()> {
+        self.command(String::from(if window { "screenshot window" } else { "screenshot output" }))
+    }
+
+    /// Number, name, output showing it or an empty string and if it is focused of every workspace
+    fn list_workspaces(&self) -> fdo::Result<Vec<(u32, String, String, bool)>> {
+        match self.request(Request::Workspaces)? {
+            Response::Workspaces { workspaces } => Ok(workspaces
+                .into_iter()
+                .map(|ws| (ws.idx, ws.name, ws.output.unwrap_or_default(), ws.focused))
+                .collect()),
+            _ => Err(fdo::Error::Failed(String::from("Unexpected response"))),
+        }
+    }
+
+    /// App id, title, workspace and if it is focused of every window
+    fn list_windows(&self) -> fdo::Result<Vec<(String, String, u32, bool)>> {
+        match self.request(Request::Windows)? {
+            Response::Windows { windows } => Ok(windows
+                .into_iter()
+                .map(|w| (w.app_id.unwrap_or_default(), w.title.unwrap_or_default(), w.workspace, w.focused))
+                .collect()),
+            _ => Err(fdo::Error::Failed(String::from("Unexpected response"))),
+        }
+    }
+}
+
+/// Connection signals are emitted on, with what was last announced
+pub struct DbusService {
+    connection: zbus::Connection,
+    workspace: Option<(String, String)>,
+    window: Option<usize>,
+}
+
+/// Claims `org.fireplace.Compositor` on the session bus and serves it on a thread of its own
+///
+/// D-Bus activated portals are told which desktop they serve and where to find it.
+pub fn init_dbus(event_loop: &mut EventLoop<'static, Fireplace>, state: &mut Fireplace) -> Result<()> {
+    let connection = zbus::Connection::new_session()?;
+    let dbus = fdo::DBusProxy::new(&connection)?;
+    dbus.request_name(BUS_NAME, fdo::RequestNameFlags::ReplaceExisting.into())?;
+    // portals and apps activated from now on have to find us, `UseIn` of the portal file matches the desktop name
+    let variables = state.session_environment();
+    let environment = variables.iter().map(|(name, value)| (*name, value.as_str())).collect::<HashMap<_, _>>();
+    dbus.update_activation_environment(environment)?;
+
+    let (sender, messages) = channel::channel();
+    let server_connection = connection.clone();
+    std::thread::Builder::new()
+        .name(String::from("dbus"))
+        .spawn(move || {
+            if let Err(err) = serve(server_connection, sender) {
+                slog_scope::error!("D-Bus service failed: {}", err);
+            }
+        })?;
+
+    let token = event_loop
+        .handle()
+        .insert_source(messages, |event, _, state| {
+            if let channel::Event::Msg(message) = event {
+                state.guard("d-bus", |state| state.process_ipc_request(message));
+            }
+        })
+        .map_err(|_| anyhow::anyhow!("Failed to register the d-bus service on the event loop"))?;
+    state.tokens.push(token);
+    state.dbus = Some(DbusService {
+        connection,
+        workspace: None,
+        window: None,
+    });
+
+    Ok(())
+}
+
+fn serve(connection: zbus::Connection, compositor: channel::Sender<Message>) -> Result<()> {
+    let mut server = zbus::ObjectServer::new(&connection);
+    server.at(&ObjectPath::try_from(OBJECT_PATH)?, Compositor { compositor })?;
+    loop {
+        if let Err(err) = server.try_handle_next() {
+            slog_scope::debug!("Failed to handle d-bus message: {}", err);
+        }
+    }
+}
+
+impl Fireplace {
+    /// Signals changes of the focused workspace and window of the last active seat
+    pub fn emit_dbus_signals(&mut self) {
+        if self.dbus.is_none() {
+            return;
+        }
+        let output = self.last_active_seat.user_data().get::<ActiveOutput>().unwrap().0.borrow().clone();
+        let mut workspaces = self.workspaces.borrow_mut();
+        let workspace = workspaces
+            .idx_by_output_name(&output)
+            .map(|idx| (workspaces.name(idx), output.clone()));
+        let window = workspaces
+            .space_by_output_name(&output)
+            .and_then(|space| space.focused_window());
+        drop(workspaces);
+
+        let dbus = self.dbus.as_mut().unwrap();
+        if workspace != dbus.workspace {
+            if let Some((name, output)) = workspace.as_ref() {
+                emit(&dbus.connection, "WorkspaceChanged", &(name.as_str(), output.as_str()));
+            }
+            dbus.workspace = workspace;
+        }
+        let id = window.as_ref().map(|window| window.id());
+        if id != dbus.window {
+            let app_id = window.as_ref().and_then(|window| window.app_id()).unwrap_or_default();
+            let title = window.as_ref().and_then(|window| window.title()).unwrap_or_default();
+            emit(&dbus.connection, "FocusChanged", &(app_id.as_str(), title.as_str()));
+            dbus.window = id;
+        }
+    }
+}
+
+fn emit(connection: &zbus::Connection, signal: &str, body: &(&str, &str)) {
+    if let Err(err) = connection.emit_signal(None, OBJECT_PATH, INTERFACE, signal, body) {
+        slog_scope::debug!("Failed to emit {}: {}", signal, err);
+    }
+}
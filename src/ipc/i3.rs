@@ -0,0 +1,445 @@
+//! Subset of the i3/sway ipc protocol for existing bars and scripts
+//!
+//! Served on `$XDG_RUNTIME_DIR/fireplace-i3.sock`, which is announced to clients by `I3SOCK` and `SWAYSOCK`.
+//! The tree is flattened, every workspace directly holds its windows.
+
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use smithay::{
+    reexports::calloop::{channel, EventLoop},
+    utils::{Logical, Rectangle},
+};
+use std::{
+    collections::HashMap,
+    convert::TryInto,
+    io::{self, Read, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::PathBuf,
+    sync::{mpsc, Arc, Mutex},
+    time::Duration,
+};
+
+use crate::{
+    handler::ActiveOutput,
+    shell::{decoration, layout::{next_id, Layout}, output::Output, window::Kind},
+    state::Fireplace,
+};
+
+const MAGIC: &[u8] = b"i3-ipc";
+
+const RUN_COMMAND: u32 = 0;
+const GET_WORKSPACES: u32 = 1;
+const SUBSCRIBE: u32 = 2;
+const GET_OUTPUTS: u32 = 3;
+const GET_TREE: u32 = 4;
+const GET_VERSION: u32 = 7;
+
+const EVENT_WORKSPACE: u32 = 0x8000_0000;
+const EVENT_WINDOW: u32 = 0x8000_0003;
+
+/// Stalled subscribers are dropped instead of blocking the compositor
+const EVENT_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Connection of a client, replies and events are written through the same lock
+type Writer = Arc<Mutex<UnixStream>>;
+
+struct Subscriber {
+    writer: Writer,
+    events: Vec<String>,
+}
+
+/// Request of a client together with the channel its reply is sent back on
+struct Message {
+    kind: u32,
+    payload: String,
+    reply: mpsc::Sender<Value>,
+}
+
+/// Subscribed clients and what they were last told about
+pub struct I3Ipc {
+    root: usize,
+    subscribers: Arc<Mutex<Vec<Subscriber>>>,
+    last: Option<Snapshot>,
+}
+
+/// State events are derived from, by comparing it with the previous one
+struct Snapshot {
+    workspace: Option<Value>,
+    focused: Option<usize>,
+    windows: HashMap<usize, Value>,
+}
+
+fn socket_path() -> Option<PathBuf> {
+    let mut path: PathBuf = std::env::var_os("XDG_RUNTIME_DIR")?.into();
+    path.push("fireplace-i3.sock");
+    Some(path)
+}
+
+pub fn init_i3_ipc(event_loop: &mut EventLoop<'static, Fireplace>, state: &mut Fireplace) -> Result<()> {
+    let path = socket_path().context("XDG_RUNTIME_DIR is not set")?;
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path)
+        .with_context(|| format!("Failed to listen for i3 ipc clients on {}", path.display()))?;
+    slog_scope::info!("Listening for i3 ipc clients on {}", path.display());
+    // inherited by every program started afterwards
+    std::env::set_var("I3SOCK", &path);
+    std::env::set_var("SWAYSOCK", &path);
+
+    let subscribers = Arc::new(Mutex::new(Vec::new()));
+    let (sender, messages) = channel::channel();
+    let shared = subscribers.clone();
+    std::thread::Builder::new()
+        .name(String::from("i3 ipc"))
+        .spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(err) => {
+                        slog_scope::warn!("Failed to accept i3 ipc client: {}", err);
+                        continue;
+                    }
+                };
+                let sender = sender.clone();
+                let subscribers = shared.clone();
+                let _ = std::thread::Builder::new()
+                    .name(String::from("i3 ipc client"))
+                    .spawn(move || {
+                        if let Err(err) = serve(stream, sender, subscribers) {
+                            slog_scope::debug!("I3 ipc client disconnected: {}", err);
+                        }
+                    });
+            }
+        })?;
+
+    let token = event_loop
+        .handle()
+        .insert_source(messages, |event, _, state| {
+            if let channel::Event::Msg(Message { kind, payload, reply }) = event {
+                // the client gets no reply, if the request panicked
+                if let Some(response) = state.guard("i3 ipc", |state| state.process_i3_request(kind, &payload)) {
+                    let _ = reply.send(response);
+                }
+            }
+        })
+        .map_err(|_| anyhow::anyhow!("Failed to register the i3 ipc socket on the event loop"))?;
+    state.tokens.push(token);
+    state.i3_ipc = Some(I3Ipc {
+        root: next_id(),
+        subscribers,
+        last: None,
+    });
+
+    Ok(())
+}
+
+fn serve(mut stream: UnixStream, sender: channel::Sender<Message>, subscribers: Arc<Mutex<Vec<Subscriber>>>) -> Result<()> {
+    let writer = stream.try_clone()?;
+    writer.set_write_timeout(Some(EVENT_TIMEOUT))?;
+    let writer = Arc::new(Mutex::new(writer));
+    loop {
+        let (kind, payload) = read_message(&mut stream)?;
+        let payload = String::from_utf8_lossy(&payload).into_owned();
+        let reply = if kind == SUBSCRIBE {
+            match serde_json::from_str::<Vec<String>>(&payload) {
+                Ok(events) => {
+                    let mut subscribers = subscribers.lock().unwrap();
+                    match subscribers.iter_mut().find(|s| Arc::ptr_eq(&s.writer, &writer)) {
+                        Some(subscriber) => subscriber.events.extend(events),
+                        None => subscribers.push(Subscriber {
+                            writer: writer.clone(),
+                            events,
+                        }),
+                    }
+                    json!({ "success": true })
+                }
+                Err(err) => json!({ "success": false, "error": err.to_string() }),
+            }
+        } else {
+            let (reply, response) = mpsc::channel();
+            sender
+                .send(Message { kind, payload, reply })
+                .map_err(|_| anyhow::anyhow!("The compositor is shutting down"))?;
+            response.recv().context("The compositor is shutting down")?
+        };
+        write_message(&mut *writer.lock().unwrap(), kind, &reply)?;
+    }
+}
+
+fn read_message(stream: &mut impl Read) -> io::Result<(u32, Vec<u8>)> {
+    let mut header = [0; 14];
+    stream.read_exact(&mut header)?;
+    if &header[..6] != MAGIC {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid magic string"));
+    }
+    let len = u32::from_ne_bytes(header[6..10].try_into().unwrap());
+    let kind = u32::from_ne_bytes(header[10..14].try_into().unwrap());
+    let mut payload = vec![0; len as usize];
+    stream.read_exact(&mut payload)?;
+    Ok((kind, payload))
+}
+
+fn write_message(stream: &mut impl Write, kind: u32, payload: &Value) -> io::Result<()> {
+    let payload = payload.to_string();
+    let mut message = Vec::with_capacity(14 + payload.len());
+    message.extend_from_slice(MAGIC);
+    message.extend_from_slice(&(payload.len() as u32).to_ne_bytes());
+    message.extend_from_slice(&kind.to_ne_bytes());
+    message.extend_from_slice(payload.as_bytes());
+    stream.write_all(&message)
+}
+
+/// Rewrites common i3 commands to their fireplace equivalents
+fn translate(command: &str) -> String {
+    let words = command.split_whitespace().collect::<Vec<_>>();
+    match words.as_slice() {
+        ["workspace", "number", rest @ ..] => format!("workspace {}", rest.join(" ")),
+        ["move", "container", "to", "workspace", rest @ ..] | ["move", "to", "workspace", rest @ ..] => {
+            format!("moveto_workspace {}", rest.iter().filter(|w| **w != "number").cloned().collect::<Vec<_>>().join(" "))
+        }
+        ["splith"] => String::from("split h"),
+        ["splitv"] => String::from("split v"),
+        ["kill"] => String::from("close"),
+        _ => String::from(command),
+    }
+}
+
+fn rect(rect: Rectangle<i32, Logical>) -> Value {
+    json!({ "x": rect.loc.x, "y": rect.loc.y, "width": rect.size.w, "height": rect.size.h })
+}
+
+fn output_id(output: &Output) -> usize {
+    struct OutputId(usize);
+    output.userdata().insert_if_missing(|| OutputId(next_id()));
+    output.userdata().get::<OutputId>().unwrap().0
+}
+
+impl Fireplace {
+    fn process_i3_request(&mut self, kind: u32, payload: &str) -> Value {
+        match kind {
+            RUN_COMMAND => {
+                let seat = self.last_active_seat.clone();
+                let results = payload
+                    .split(|c| c == ';' || c == ',')
+                    .map(str::trim)
+                    .filter(|command| !command.is_empty())
+                    .map(|command| {
+                        self.process_command(&translate(command), &seat);
+                        json!({ "success": true })
+                    })
+                    .collect::<Vec<_>>();
+                self.damage();
+                Value::Array(results)
+            }
+            GET_WORKSPACES => Value::Array(self.i3_workspaces().into_iter().map(|(_, ws, _)| ws).collect()),
+            GET_OUTPUTS => {
+                let workspaces = self.workspaces.borrow();
+                Value::Array(
+                    workspaces
+                        .outputs()
+                        .map(|o| {
+                            json!({
+                                "id": output_id(o),
+                                "name": o.name(),
+                                "active": true,
+                                "primary": false,
+                                "current_workspace": workspaces.idx_by_output_name(o.name()).map(|idx| workspaces.name(idx)),
+                                "scale": o.scale(),
+                                "rect": rect(o.geometry()),
+                            })
+                        })
+                        .collect(),
+                )
+            }
+            GET_TREE => self.i3_tree(),
+            GET_VERSION => json!({
+                "major": 4,
+                "minor": 0,
+                "patch": 0,
+                "human_readable": format!("fireplace {}", std::env!("CARGO_PKG_VERSION")),
+                "loaded_config_file_name": "",
+            }),
+            kind => json!({ "success": false, "error": format!("Unsupported message type {}", kind) }),
+        }
+    }
+
+    /// Existing workspaces sorted by number with the output showing them and their windows
+    fn i3_workspaces(&self) -> Vec<(Option<String>, Value, Vec<Value>)> {
+        let active = self.last_active_seat.user_data().get::<ActiveOutput>().unwrap().0.borrow().clone();
+        let workspaces = self.workspaces.borrow();
+        let mut result = workspaces
+            .workspaces()
+            .filter(|(_, output, space)| output.is_some() || !space.is_empty())
+            .map(|(idx, output, space)| {
+                let geometry = output
+                    .and_then(|name| workspaces.outputs().find(|o| o.name() == name))
+                    .map(|o| o.geometry())
+                    .unwrap_or_else(|| Rectangle::from_loc_and_size((0, 0), (0, 0)));
+                let focused = output == Some(active.as_str());
+                let windows = window_nodes(space, geometry, focused);
+                let workspace = json!({
+                    "id": space.id(),
+                    "type": "workspace",
+                    "num": idx,
+                    "name": workspaces.name(idx),
+                    "visible": output.is_some(),
+                    "focused": focused && space.focused_window().is_none(),
+                    "urgent": space.windows().any(|w| space.is_urgent(&w)),
+                    "output": output.unwrap_or(""),
+                    "rect": rect(geometry),
+                });
+                (idx, output.map(String::from), workspace, windows)
+            })
+            .collect::<Vec<_>>();
+        result.sort_by_key(|(idx, ..)| *idx);
+        result.into_iter().map(|(_, output, workspace, windows)| (output, workspace, windows)).collect()
+    }
+
+    fn i3_tree(&self) -> Value {
+        let root = self.i3_ipc.as_ref().map(|i3| i3.root).unwrap_or(0);
+        let mut workspaces = self.i3_workspaces();
+        let outputs = self
+            .workspaces
+            .borrow()
+            .outputs()
+            .enumerate()
+            .map(|(i, o)| {
+                // hidden workspaces are not bound to an output, they are listed below the first one
+                let nodes = workspaces
+                    .iter_mut()
+                    .filter(|(output, _, _)| output.as_deref() == Some(o.name()) || (output.is_none() && i == 0))
+                    .map(|(_, workspace, windows)| {
+                        let mut node = workspace.clone();
+                        node["layout"] = json!("splith");
+                        node["nodes"] = Value::Array(std::mem::take(windows));
+                        node["floating_nodes"] = json!([]);
+                        node
+                    })
+                    .collect::<Vec<_>>();
+                json!({
+                    "id": output_id(o),
+                    "type": "output",
+                    "name": o.name(),
+                    "rect": rect(o.geometry()),
+                    "focused": false,
+                    "nodes": nodes,
+                    "floating_nodes": [],
+                })
+            })
+            .collect::<Vec<_>>();
+        json!({
+            "id": root,
+            "type": "root",
+            "name": "root",
+            "rect": rect(Rectangle::from_loc_and_size((0, 0), (self.workspaces.borrow().width(), 0))),
+            "focused": false,
+            "nodes": outputs,
+            "floating_nodes": [],
+        })
+    }
+
+    /// Sends workspace and window events to subscribed clients for everything changed since the last call
+    pub fn emit_i3_events(&mut self) {
+        let subscribed = self
+            .i3_ipc
+            .as_ref()
+            .map(|i3| !i3.subscribers.lock().unwrap().is_empty())
+            .unwrap_or(false);
+        if !subscribed {
+            if let Some(i3) = self.i3_ipc.as_mut() {
+                i3.last = None;
+            }
+            return;
+        }
+
+        let mut current = Snapshot {
+            workspace: None,
+            focused: None,
+            windows: HashMap::new(),
+        };
+        for (_, workspace, windows) in self.i3_workspaces() {
+            if workspace["focused"] == json!(true) || windows.iter().any(|w| w["focused"] == json!(true)) {
+                current.workspace = Some(workspace);
+            }
+            for window in windows {
+                let id = window["id"].as_u64().unwrap_or(0) as usize;
+                if window["focused"] == json!(true) {
+                    current.focused = Some(id);
+                }
+                current.windows.insert(id, window);
+            }
+        }
+
+        let i3 = self.i3_ipc.as_mut().unwrap();
+        let mut events = Vec::new();
+        if let Some(last) = i3.last.as_ref() {
+            if current.workspace.as_ref().map(|ws| &ws["id"]) != last.workspace.as_ref().map(|ws| &ws["id"]) {
+                events.push((
+                    EVENT_WORKSPACE,
+                    json!({ "change": "focus", "current": current.workspace, "old": last.workspace }),
+                ));
+            }
+            for (id, window) in current.windows.iter() {
+                match last.windows.get(id) {
+                    None => events.push((EVENT_WINDOW, json!({ "change": "new", "container": window }))),
+                    Some(old) if old["name"] != window["name"] => {
+                        events.push((EVENT_WINDOW, json!({ "change": "title", "container": window })))
+                    }
+                    _ => {}
+                }
+            }
+            for (id, window) in last.windows.iter() {
+                if !current.windows.contains_key(id) {
+                    events.push((EVENT_WINDOW, json!({ "change": "close", "container": window })));
+                }
+            }
+            if current.focused != last.focused {
+                if let Some(window) = current.focused.and_then(|id| current.windows.get(&id)) {
+                    events.push((EVENT_WINDOW, json!({ "change": "focus", "container": window })));
+                }
+            }
+        }
+        i3.last = Some(current);
+
+        let mut subscribers = i3.subscribers.lock().unwrap();
+        subscribers.retain(|subscriber| {
+            events
+                .iter()
+                .filter(|(kind, _)| {
+                    let name = if *kind == EVENT_WORKSPACE { "workspace" } else { "window" };
+                    subscriber.events.iter().any(|event| event == name)
+                })
+                .all(|(kind, payload)| write_message(&mut *subscriber.writer.lock().unwrap(), *kind, payload).is_ok())
+        });
+    }
+}
+
+/// Window containers of a workspace, `geometry` being the area of the output showing it
+fn window_nodes(space: &dyn Layout, geometry: Rectangle<i32, Logical>, focused_workspace: bool) -> Vec<Value> {
+    let focused = space.focused_window().filter(|_| focused_workspace);
+    space
+        .windows_from_bottom_to_top()
+        .map(|(toplevel, location, bounding_box)| {
+            let mut window = decoration::window_geometry(&toplevel, location, bounding_box);
+            window.loc += geometry.loc;
+            window_node(&toplevel, window, focused.as_ref() == Some(&toplevel), space.is_urgent(&toplevel))
+        })
+        .collect()
+}
+
+fn window_node(toplevel: &Kind, geometry: Rectangle<i32, Logical>, focused: bool, urgent: bool) -> Value {
+    json!({
+        "id": toplevel.id(),
+        "type": "con",
+        "name": toplevel.title(),
+        "app_id": toplevel.app_id(),
+        "shell": "xdg_shell",
+        "focused": focused,
+        "urgent": urgent,
+        "layout": "none",
+        "fullscreen_mode": if toplevel.is_fullscreen() { 1 } else { 0 },
+        "rect": rect(geometry),
+        "window_rect": rect(Rectangle::from_loc_and_size((0, 0), geometry.size)),
+        "nodes": [],
+        "floating_nodes": [],
+    })
+}
@@ -0,0 +1,165 @@
+//! # Prometheus endpoint
+//!
+//! Serves the frame statistics of the `metrics` request in the Prometheus text format,
+//! on the address given by `prometheus` in the configuration.
+
+use anyhow::{Context, Result};
+use smithay::reexports::calloop::{channel, EventLoop};
+use std::{
+    fmt::Write as _,
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+    time::Duration,
+};
+
+use super::protocol::OutputMetrics;
+use crate::state::Fireplace;
+
+/// Scrapers taking longer than this to send their request are dropped
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Listens for scrapes, if an address is configured
+pub fn init_metrics(event_loop: &mut EventLoop<'static, Fireplace>, state: &mut Fireplace) -> Result<()> {
+    let address = match state.config.prometheus.clone() {
+        Some(address) => address,
+        None => return Ok(()),
+    };
+    let listener = TcpListener::bind(&address).with_context(|| format!("Failed to listen on {}", address))?;
+    slog_scope::info!("Serving metrics on {}", address);
+
+    let (sender, scrapes) = channel::channel::<mpsc::Sender<String>>();
+    std::thread::Builder::new()
+        .name(String::from("metrics"))
+        .spawn(move || {
+            // scrapes are rare enough to be answered one after another
+            for stream in listener.incoming() {
+                let result = stream
+                    .map_err(Into::into)
+                    .and_then(|stream| serve(stream, &sender));
+                if let Err(err) = result {
+                    slog_scope::debug!("Failed to serve metrics: {}", err);
+                }
+            }
+        })?;
+
+    let token = event_loop
+        .handle()
+        .insert_source(scrapes, |event, _, state| {
+            if let channel::Event::Msg(reply) = event {
+                if let Some(text) = state.guard("metrics", |state| format_metrics(&state.output_metrics())) {
+                    let _ = reply.send(text);
+                }
+            }
+        })
+        .map_err(|_| anyhow::anyhow!("Failed to register the metrics endpoint on the event loop"))?;
+    state.tokens.push(token);
+
+    Ok(())
+}
+
+/// Answers a single http request with the current metrics, whatever was requested
+fn serve(stream: TcpStream, sender: &channel::Sender<mpsc::Sender<String>>) -> Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        // the headers end with an empty line
+        if line?.trim().is_empty() {
+            break;
+        }
+    }
+
+    let (reply, text) = mpsc::channel();
+    sender
+        .send(reply)
+        .map_err(|_| anyhow::anyhow!("The compositor is shutting down"))?;
+    let response = match text.recv() {
+        Ok(text) => format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            text.len(),
+            text
+        ),
+        // the request panicked
+        Err(_) => String::from("HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"),
+    };
+    writer.write_all(response.as_bytes())?;
+    Ok(())
+}
+
+/// Name, type, description and value of each metric
+type Family = (&'static str, &'static str, &'static str, fn(&OutputMetrics) -> Option<f64>);
+
+const FAMILIES: &[Family] = &[
+    ("fireplace_frames_total", "counter", "Frames rendered", |m| Some(m.frames as f64)),
+    (
+        "fireplace_missed_vblanks_total",
+        "counter",
+        "Frames presented after the vblank they were rendered for",
+        |m| Some(m.missed_vblanks as f64),
+    ),
+    ("fireplace_fps", "gauge", "Frames rendered during the last second", |m| Some(m.fps as f64)),
+    (
+        "fireplace_render_time_avg_seconds",
+        "gauge",
+        "Average time to render a frame",
+        |m| seconds(m.render_time_avg),
+    ),
+    (
+        "fireplace_render_time_max_seconds",
+        "gauge",
+        "Longest time to render a frame",
+        |m| seconds(m.render_time_max),
+    ),
+    (
+        "fireplace_input_latency_avg_seconds",
+        "gauge",
+        "Average time from an input event to the presentation of the first frame after it",
+        |m| seconds(m.latency_avg),
+    ),
+    (
+        "fireplace_input_latency_max_seconds",
+        "gauge",
+        "Longest time from an input event to the presentation of the first frame after it",
+        |m| seconds(m.latency_max),
+    ),
+    (
+        "fireplace_damage_events_avg",
+        "gauge",
+        "Average damage events drawn at once by a frame",
+        |m| m.damage_avg,
+    ),
+    (
+        "fireplace_redrawn_ratio_avg",
+        "gauge",
+        "Average share of the output redrawn by a frame",
+        |m| m.redrawn_avg,
+    ),
+];
+
+/// Renders the metrics of all outputs in the Prometheus text format, durations in seconds
+fn format_metrics(outputs: &[OutputMetrics]) -> String {
+    let mut text = String::new();
+    for (name, kind, help, value) in FAMILIES {
+        let _ = writeln!(text, "# HELP {} {}", name, help);
+        let _ = writeln!(text, "# TYPE {} {}", name, kind);
+        for output in outputs {
+            // nothing measured during the last second
+            if let Some(value) = value(output) {
+                let _ = writeln!(text, "{}{{output=\"{}\"}} {}", name, escape(&output.output), value);
+            }
+        }
+    }
+    text
+}
+
+fn seconds(millis: Option<f64>) -> Option<f64> {
+    millis.map(|millis| millis / 1000.0)
+}
+
+/// Escapes a label value
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -0,0 +1,320 @@
+use anyhow::{Context, Result};
+use serde_yaml::{Mapping, Value};
+use smithay::{
+    reexports::calloop::{channel, EventLoop},
+    utils::{Logical, Rectangle},
+};
+use std::{
+    io::{BufRead, BufReader, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    sync::mpsc,
+};
+
+use crate::{
+    backend::stats::FrameStats,
+    handler::{ActiveOutput, SeatName},
+    shell::decoration,
+    config::ColorScheme,
+    state::Fireplace,
+    Config,
+};
+
+mod dbus;
+mod i3;
+mod metrics;
+mod notifications;
+pub mod protocol;
+
+pub use self::dbus::{init_dbus, DbusService};
+pub use self::i3::{init_i3_ipc, I3Ipc};
+pub use self::metrics::init_metrics;
+pub use self::notifications::{init_notifications, Notification, NotificationDaemon, Urgency};
+
+use self::protocol::{Geometry, OutputInfo, OutputMetrics, Request, Response, WindowInfo, WorkspaceInfo};
+
+/// Request of a client together with the channel its response is sent back on
+struct Message {
+    request: Request,
+    reply: mpsc::Sender<Response>,
+}
+
+/// Accepts json requests on `$XDG_RUNTIME_DIR/fireplace.sock`
+///
+/// Clients are served by their own threads, their requests are processed on the event loop.
+pub fn init_ipc(event_loop: &mut EventLoop<'static, Fireplace>, state: &mut Fireplace) -> Result<()> {
+    let path = protocol::socket_path().context("XDG_RUNTIME_DIR is not set")?;
+    // a previous session might have left its socket behind, a running one keeps it
+    if UnixStream::connect(&path).is_ok() {
+        anyhow::bail!("Another compositor is listening for ipc clients on {}", path.display());
+    }
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path)
+        .with_context(|| format!("Failed to listen for ipc clients on {}", path.display()))?;
+    slog_scope::info!("Listening for ipc clients on {}", path.display());
+
+    let (sender, messages) = channel::channel();
+    std::thread::Builder::new()
+        .name(String::from("ipc"))
+        .spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(err) => {
+                        slog_scope::warn!("Failed to accept ipc client: {}", err);
+                        continue;
+                    }
+                };
+                let sender = sender.clone();
+                let _ = std::thread::Builder::new()
+                    .name(String::from("ipc client"))
+                    .spawn(move || {
+                        if let Err(err) = serve(stream, sender) {
+                            slog_scope::debug!("Ipc client disconnected: {}", err);
+                        }
+                    });
+            }
+        })?;
+
+    let token = event_loop
+        .handle()
+        .insert_source(messages, |event, _, state| {
+            if let channel::Event::Msg(message) = event {
+                state.guard("ipc", |state| state.process_ipc_request(message));
+            }
+        })
+        .map_err(|_| anyhow::anyhow!("Failed to register the ipc socket on the event loop"))?;
+    state.tokens.push(token);
+
+    Ok(())
+}
+
+/// Answers the requests of a client line by line, until it disconnects
+fn serve(stream: UnixStream, sender: channel::Sender<Message>) -> Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str::<Request>(&line) {
+            Ok(request) => {
+                let (reply, response) = mpsc::channel();
+                sender
+                    .send(Message { request, reply })
+                    .map_err(|_| anyhow::anyhow!("The compositor is shutting down"))?;
+                response.recv().context("The compositor is shutting down")?
+            }
+            Err(err) => error(format!("Malformed request: {}", err)),
+        };
+        serde_json::to_writer(&mut writer, &response)?;
+        writer.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+fn error(message: String) -> Response {
+    Response::Error { message }
+}
+
+impl Fireplace {
+    /// Answers a request, screenshots and screencasts are answered once they are ready
+    fn process_ipc_request(&mut self, Message { request, reply }: Message) {
+        let response = match request {
+            // the lock screen may not be bypassed by running commands
+            Request::Command { .. } if self.is_locked() => {
+                error(String::from("Commands are refused while the session is locked"))
+            }
+            Request::Command { commands, seat } => {
+                let seat = match seat {
+                    Some(name) => match self
+                        .seats
+                        .iter()
+                        .find(|seat| seat.user_data().get::<SeatName>().unwrap().0 == name)
+                    {
+                        Some(seat) => seat.clone(),
+                        None => {
+                            let _ = reply.send(error(format!("Seat {} does not exist", name)));
+                            return;
+                        }
+                    },
+                    None => self.last_active_seat.clone(),
+                };
+                slog_scope::debug!("Running {:?} by ipc", commands);
+                self.process_commands(&commands, &seat);
+                self.damage();
+                Response::Success
+            }
+            Request::Workspaces => Response::Workspaces {
+                workspaces: self.workspace_infos(),
+            },
+            Request::Windows => Response::Windows {
+                windows: self.window_infos(),
+            },
+            Request::Outputs => Response::Outputs {
+                outputs: self.output_infos(),
+            },
+            Request::SetConfig { key, value } => match self.set_config(&key, value) {
+                Ok(()) => Response::Success,
+                Err(err) => error(format!("{:#}", err)),
+            },
+            Request::Screenshot { window } => {
+                let seat = self.last_active_seat.clone();
+                if let Err(err) = self.queue_screenshot(&seat, window, Some(reply.clone())) {
+                    let _ = reply.send(error(err));
+                }
+                // idle outputs need a frame to serve it
+                self.damage();
+                return;
+            }
+            Request::StartScreencast { session, types } => return self.start_screencast(session, types, reply),
+            Request::StopScreencast { session } => {
+                self.stop_screencast(&session);
+                Response::Success
+            }
+            Request::Settings => Response::Settings {
+                color_scheme: match self.config.color_scheme {
+                    ColorScheme::Default => 0,
+                    ColorScheme::Dark => 1,
+                    ColorScheme::Light => 2,
+                },
+            },
+            Request::Metrics => Response::Metrics {
+                outputs: self.output_metrics(),
+            },
+        };
+        let _ = reply.send(response);
+    }
+
+    pub fn active_output(&self) -> String {
+        match self.last_active_seat.user_data().get::<ActiveOutput>() {
+            Some(output) => output.0.borrow().clone(),
+            // the seat has no input devices yet
+            None => self
+                .workspaces
+                .borrow()
+                .outputs()
+                .next()
+                .map(|o| String::from(o.name()))
+                .unwrap_or_default(),
+        }
+    }
+
+    fn workspace_infos(&self) -> Vec<WorkspaceInfo> {
+        let active = self.active_output();
+        let workspaces = self.workspaces.borrow();
+        let mut infos = workspaces
+            .workspaces()
+            .map(|(idx, output, space)| WorkspaceInfo {
+                idx,
+                name: workspaces.name(idx),
+                output: output.map(String::from),
+                focused: output == Some(active.as_str()),
+                urgent: space.windows().any(|w| space.is_urgent(&w)),
+                windows: space.windows().count(),
+            })
+            .collect::<Vec<_>>();
+        infos.sort_by(|a, b| (a.idx, &a.output).cmp(&(b.idx, &b.output)));
+        infos
+    }
+
+    fn window_infos(&self) -> Vec<WindowInfo> {
+        let workspaces = self.workspaces.borrow();
+        let mut infos = Vec::new();
+        for (idx, output, space) in workspaces.workspaces() {
+            let offset = output
+                .and_then(|name| workspaces.outputs().find(|o| o.name() == name))
+                .map(|o| o.location())
+                .unwrap_or_else(|| (0, 0).into());
+            let focused = space.focused_window().filter(|_| output.is_some());
+            for (toplevel, location, bounding_box) in space.windows_from_bottom_to_top() {
+                let mut geometry = decoration::window_geometry(&toplevel, location, bounding_box);
+                geometry.loc += offset;
+                infos.push(WindowInfo {
+                    app_id: toplevel.app_id(),
+                    title: toplevel.title(),
+                    workspace: idx,
+                    geometry: geometry.into(),
+                    focused: focused.as_ref() == Some(&toplevel),
+                    fullscreen: toplevel.is_fullscreen(),
+                    maximized: toplevel.is_maximized(),
+                });
+            }
+        }
+        infos
+    }
+
+    fn output_infos(&self) -> Vec<OutputInfo> {
+        let active = self.active_output();
+        let workspaces = self.workspaces.borrow();
+        workspaces
+            .outputs()
+            .map(|o| OutputInfo {
+                name: String::from(o.name()),
+                geometry: o.geometry().into(),
+                scale: o.scale(),
+                refresh: o.current_mode().refresh,
+                workspace: workspaces.idx_by_output_name(o.name()),
+                focused: o.name() == active,
+            })
+            .collect()
+    }
+
+    /// Frame statistics of the outputs, including those without frames so far
+    pub fn output_metrics(&self) -> Vec<OutputMetrics> {
+        let workspaces = self.workspaces.borrow();
+        let mut names = workspaces.outputs().map(|o| String::from(o.name())).collect::<Vec<_>>();
+        // mirrors render frames of their own but have no output
+        let mirrors = self
+            .frame_stats
+            .keys()
+            .filter(|name| !names.contains(name))
+            .cloned()
+            .collect::<Vec<_>>();
+        names.extend(mirrors);
+        names.sort();
+        names
+            .iter()
+            .map(|name| match self.frame_stats.get(name) {
+                Some(stats) => stats.metrics(name),
+                None => FrameStats::default().metrics(name),
+            })
+            .collect()
+    }
+
+    /// Replaces a single value of the configuration by its dotted path
+    ///
+    /// The whole configuration is validated again and left untouched on errors.
+    fn set_config(&mut self, key: &str, value: serde_json::Value) -> Result<()> {
+        let value = serde_yaml::to_value(value)?;
+        let mut source = self.config_source.clone();
+        let mut node = &mut source;
+        for part in key.split('.') {
+            if !node.is_mapping() {
+                *node = Value::Mapping(Mapping::new());
+            }
+            let mapping = node.as_mapping_mut().unwrap();
+            let part = Value::String(String::from(part));
+            if !mapping.contains_key(&part) {
+                mapping.insert(part.clone(), Value::Null);
+            }
+            node = mapping.get_mut(&part).unwrap();
+        }
+        *node = value;
+
+        let config: Config = serde_yaml::from_value(source.clone()).with_context(|| format!("Invalid value for {}", key))?;
+        slog_scope::info!("Setting {} by ipc", key);
+        self.apply_config(source, config);
+        Ok(())
+    }
+}
+
+impl From<Rectangle<i32, Logical>> for Geometry {
+    fn from(rect: Rectangle<i32, Logical>) -> Geometry {
+        Geometry {
+            x: rect.loc.x,
+            y: rect.loc.y,
+            width: rect.size.w,
+            height: rect.size.h,
+        }
+    }
+}
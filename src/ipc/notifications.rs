@@ -0,0 +1,270 @@
+//! # Notification daemon
+//!
+//! Implements `org.freedesktop.Notifications` for setups without mako or dunst,
+//! the notifications are drawn in the top right corner of the active output.
+
+use anyhow::Result;
+use smithay::reexports::calloop::{channel, EventLoop};
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    time::{Duration, Instant},
+};
+use zbus::{dbus_interface, fdo};
+use zvariant::{ObjectPath, Value};
+
+use crate::{handler::wrap, state::Fireplace};
+
+const BUS_NAME: &str = "org.freedesktop.Notifications";
+const OBJECT_PATH: &str = "/org/freedesktop/Notifications";
+const INTERFACE: &str = "org.freedesktop.Notifications";
+
+/// Characters per line of the body
+const BODY_COLUMNS: usize = 48;
+/// Lines of the body shown, the rest is cut off
+const BODY_LINES: usize = 4;
+
+/// Reasons of `NotificationClosed`
+const CLOSED_EXPIRED: u32 = 1;
+const CLOSED_DISMISSED: u32 = 2;
+const CLOSED_BY_CALL: u32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Urgency {
+    Low,
+    Normal,
+    Critical,
+}
+
+pub struct Notification {
+    pub id: u32,
+    pub app_name: String,
+    pub summary: String,
+    /// Wrapped to fit the popup
+    pub body: Vec<String>,
+    pub urgency: Urgency,
+    /// Requested by the client in milliseconds, -1 leaves it to the configuration and 0 never expires
+    timeout: i32,
+    shown: Instant,
+}
+
+enum NotificationEvent {
+    Notify(Notification),
+    Close(u32),
+}
+
+/// `org.freedesktop.Notifications`, ids are handed out by the d-bus thread
+struct Notifications {
+    compositor: channel::Sender<NotificationEvent>,
+    last_id: u32,
+}
+
+#[dbus_interface(name = "org.freedesktop.Notifications")]
+impl Notifications {
+    fn get_capabilities(&self) -> Vec<String> {
+        vec![String::from("body")]
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn notify(
+        &mut self,
+        app_name: &str,
+        replaces_id: u32,
+        _app_icon: &str,
+        summary: &str,
+        body: &str,
+        _actions: Vec<&str>,
+        hints: HashMap<&str, Value<'_>>,
+        expire_timeout: i32,
+    ) -> fdo::Result<u32> {
+        let id = if replaces_id != 0 {
+            replaces_id
+        } else {
+            self.last_id = self.last_id.wrapping_add(1).max(1);
+            self.last_id
+        };
+        let urgency = match hints.get("urgency") {
+            Some(Value::U8(0)) => Urgency::Low,
+            Some(Value::U8(2)) => Urgency::Critical,
+            _ => Urgency::Normal,
+        };
+        let mut body = wrap(body, BODY_COLUMNS);
+        body.retain(|line| !line.is_empty());
+        body.truncate(BODY_LINES);
+        self.compositor
+            .send(NotificationEvent::Notify(Notification {
+                id,
+                app_name: String::from(app_name),
+                summary: String::from(summary),
+                body,
+                urgency,
+                timeout: expire_timeout,
+                shown: Instant::now(),
+            }))
+            .map_err(|_| fdo::Error::Failed(String::from("The compositor is shutting down")))?;
+        Ok(id)
+    }
+
+    fn close_notification(&self, id: u32) -> fdo::Result<()> {
+        self.compositor
+            .send(NotificationEvent::Close(id))
+            .map_err(|_| fdo::Error::Failed(String::from("The compositor is shutting down")))
+    }
+
+    /// Name, vendor, version and version of the specification
+    fn get_server_information(&self) -> (String, String, String, String) {
+        (
+            String::from("fireplace"),
+            String::from("fireplace"),
+            String::from(std::env!("CARGO_PKG_VERSION")),
+            String::from("1.2"),
+        )
+    }
+}
+
+/// Shown notifications and the connection their closing is signaled on
+pub struct NotificationDaemon {
+    connection: zbus::Connection,
+    /// Oldest first
+    pub notifications: Vec<Notification>,
+}
+
+/// Claims `org.freedesktop.Notifications` on the session bus, if enabled
+pub fn init_notifications(event_loop: &mut EventLoop<'static, Fireplace>, state: &mut Fireplace) -> Result<()> {
+    if !state.config.notifications.enabled {
+        return Ok(());
+    }
+    let connection = zbus::Connection::new_session()?;
+    // another daemon keeps its name
+    let reply = fdo::DBusProxy::new(&connection)?.request_name(BUS_NAME, fdo::RequestNameFlags::DoNotQueue.into())?;
+    if reply != fdo::RequestNameReply::PrimaryOwner {
+        anyhow::bail!("Another notification daemon is running");
+    }
+
+    let (sender, events) = channel::channel();
+    let server_connection = connection.clone();
+    std::thread::Builder::new()
+        .name(String::from("notifications"))
+        .spawn(move || {
+            if let Err(err) = serve(server_connection, sender) {
+                slog_scope::error!("Notification daemon failed: {}", err);
+            }
+        })?;
+
+    let token = event_loop
+        .handle()
+        .insert_source(events, |event, _, state| {
+            if let channel::Event::Msg(event) = event {
+                state.guard("notifications", |state| state.process_notification_event(event));
+            }
+        })
+        .map_err(|_| anyhow::anyhow!("Failed to register the notification daemon on the event loop"))?;
+    state.tokens.push(token);
+    state.notifications = Some(NotificationDaemon {
+        connection,
+        notifications: Vec::new(),
+    });
+    slog_scope::info!("Serving notifications");
+
+    Ok(())
+}
+
+fn serve(connection: zbus::Connection, compositor: channel::Sender<NotificationEvent>) -> Result<()> {
+    let mut server = zbus::ObjectServer::new(&connection);
+    server.at(
+        &ObjectPath::try_from(OBJECT_PATH)?,
+        Notifications {
+            compositor,
+            last_id: 0,
+        },
+    )?;
+    loop {
+        if let Err(err) = server.try_handle_next() {
+            slog_scope::debug!("Failed to handle d-bus message: {}", err);
+        }
+    }
+}
+
+impl Fireplace {
+    fn process_notification_event(&mut self, event: NotificationEvent) {
+        let daemon = match self.notifications.as_mut() {
+            Some(daemon) => daemon,
+            None => return,
+        };
+        match event {
+            NotificationEvent::Notify(notification) => {
+                match daemon.notifications.iter_mut().find(|shown| shown.id == notification.id) {
+                    Some(shown) => *shown = notification,
+                    None => daemon.notifications.push(notification),
+                }
+                let max = self.config.notifications.max_visible.max(1);
+                while daemon.notifications.len() > max {
+                    let oldest = daemon.notifications.remove(0);
+                    daemon.closed(oldest.id, CLOSED_EXPIRED);
+                }
+            }
+            NotificationEvent::Close(id) => daemon.close(id, CLOSED_BY_CALL),
+        }
+        self.damage();
+    }
+
+    /// Closes the newest notification, for the `dismiss_notification` command
+    pub fn dismiss_notification(&mut self) {
+        if let Some(daemon) = self.notifications.as_mut() {
+            if let Some(id) = daemon.notifications.last().map(|notification| notification.id) {
+                daemon.close(id, CLOSED_DISMISSED);
+                self.damage();
+            }
+        }
+    }
+
+    /// Removes notifications shown long enough
+    pub fn expire_notifications(&mut self) {
+        let default_timeout = Duration::from_secs(self.config.notifications.timeout);
+        let daemon = match self.notifications.as_mut() {
+            Some(daemon) => daemon,
+            None => return,
+        };
+        let expired = daemon
+            .notifications
+            .iter()
+            .filter(|notification| {
+                let timeout = match notification.timeout {
+                    0 => return false,
+                    // critical notifications stay until dismissed, unless they ask otherwise
+                    -1 if notification.urgency == Urgency::Critical => return false,
+                    -1 => default_timeout,
+                    millis => Duration::from_millis(millis.max(0) as u64),
+                };
+                notification.shown.elapsed() >= timeout
+            })
+            .map(|notification| notification.id)
+            .collect::<Vec<_>>();
+        if expired.is_empty() {
+            return;
+        }
+        for id in expired {
+            daemon.close(id, CLOSED_EXPIRED);
+        }
+        self.damage();
+    }
+}
+
+impl NotificationDaemon {
+    fn close(&mut self, id: u32, reason: u32) {
+        let count = self.notifications.len();
+        self.notifications.retain(|notification| notification.id != id);
+        if self.notifications.len() != count {
+            self.closed(id, reason);
+        }
+    }
+
+    fn closed(&self, id: u32, reason: u32) {
+        if let Err(err) = self
+            .connection
+            .emit_signal(None, OBJECT_PATH, INTERFACE, "NotificationClosed", &(id, reason))
+        {
+            slog_scope::debug!("Failed to emit NotificationClosed: {}", err);
+        }
+    }
+}
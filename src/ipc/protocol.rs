@@ -0,0 +1,134 @@
+//! Messages exchanged over the ipc socket, shared with `fireplace-msg`
+//!
+//! Every request is a single line of json and answered by a single line of json.
+
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// Location of the socket, `$XDG_RUNTIME_DIR/fireplace.sock`
+pub fn socket_path() -> Option<PathBuf> {
+    let mut path: PathBuf = std::env::var_os("XDG_RUNTIME_DIR")?.into();
+    path.push("fireplace.sock");
+    Some(path)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Request {
+    /// Runs commands as if they were bound to a key, on the named seat or the last active one
+    Command {
+        commands: Vec<String>,
+        #[serde(default)]
+        seat: Option<String>,
+    },
+    Workspaces,
+    Windows,
+    Outputs,
+    /// Replaces a value of the configuration by its dotted path, e.g. `night_light.temperature`
+    SetConfig { key: String, value: serde_json::Value },
+    /// Saves a screenshot of the active output or its focused window, answered once it is written
+    Screenshot {
+        #[serde(default)]
+        window: bool,
+    },
+    /// Streams the active output or its focused window over pipewire, `types` are portal source types
+    StartScreencast { session: String, types: u32 },
+    StopScreencast { session: String },
+    /// Values of the settings portal
+    Settings,
+    /// Frame statistics of every output
+    Metrics,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Response {
+    Success,
+    Error { message: String },
+    Workspaces { workspaces: Vec<WorkspaceInfo> },
+    Windows { windows: Vec<WindowInfo> },
+    Outputs { outputs: Vec<OutputInfo> },
+    Screenshot { path: PathBuf },
+    Screencast { stream: StreamInfo },
+    Settings { color_scheme: u32 },
+    Metrics { outputs: Vec<OutputMetrics> },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WorkspaceInfo {
+    pub idx: u32,
+    pub name: String,
+    /// Output showing the workspace, if it is visible
+    pub output: Option<String>,
+    /// Shown on the output of the last active seat
+    pub focused: bool,
+    /// Any of its windows requests attention
+    pub urgent: bool,
+    pub windows: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WindowInfo {
+    pub app_id: Option<String>,
+    pub title: Option<String>,
+    pub workspace: u32,
+    /// Window geometry in global coordinates for visible workspaces, relative to the workspace otherwise
+    pub geometry: Geometry,
+    /// Focused window of a visible workspace
+    pub focused: bool,
+    pub fullscreen: bool,
+    pub maximized: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OutputInfo {
+    pub name: String,
+    /// Global logical coordinates
+    pub geometry: Geometry,
+    pub scale: f32,
+    /// Refresh rate in mHz
+    pub refresh: i32,
+    pub workspace: Option<u32>,
+    /// Active output of the last active seat
+    pub focused: bool,
+}
+
+/// Frame statistics of an output, durations are in milliseconds
+///
+/// Counters are totals since the output appeared, everything else covers the last second.
+/// Values are `None` if there was nothing to measure, damage is only tracked by the udev backend.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OutputMetrics {
+    pub output: String,
+    pub frames: u64,
+    /// Frames presented after the vblank they were rendered for
+    pub missed_vblanks: u64,
+    pub fps: u32,
+    pub render_time_avg: Option<f64>,
+    pub render_time_max: Option<f64>,
+    /// From an input event to the presentation of the first frame rendered after it
+    pub latency_avg: Option<f64>,
+    pub latency_max: Option<f64>,
+    /// Damage events drawn at once per frame
+    pub damage_avg: Option<f64>,
+    /// Share of the output redrawn per frame, from 0 to 1
+    pub redrawn_avg: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct Geometry {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+/// Pipewire stream of a screencast, as described to portal clients
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StreamInfo {
+    pub node_id: u32,
+    /// Global logical coordinates of the streamed area
+    pub position: (i32, i32),
+    pub size: (i32, i32),
+    pub source_type: u32,
+}
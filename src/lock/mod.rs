@@ -0,0 +1,331 @@
+//! # Locking the session
+//!
+//! The `lock` command hides all windows behind a prompt for the password of the user, checked by PAM.
+//! Lockers speaking ext-session-lock-v1 take over drawing and authentication while they are running,
+//! if they die the built-in prompt is shown again instead of unlocking the session.
+
+use anyhow::Result;
+use smithay::{
+    reexports::{
+        calloop::{channel, EventLoop},
+        wayland_server::{protocol::wl_surface::WlSurface, Global},
+    },
+    wayland::{
+        compositor::{with_surface_tree_downward, TraversalAction},
+        seat::Keysym,
+        SERIAL_COUNTER as SCOUNTER,
+    },
+};
+use std::ptr;
+
+use crate::{
+    handler::keyboard::KeySyms,
+    shell::SurfaceData,
+    state::Fireplace,
+    wayland::{
+        ext_session_lock_manager_v1::ExtSessionLockManagerV1, init_session_lock_global, LockSurface, SessionLock,
+        SessionLockEvent,
+    },
+};
+
+mod pam;
+
+/// Typed characters are stored without reallocating, which would leave copies behind
+const PASSWORD_CAPACITY: usize = 256;
+
+/// State of the built-in prompt
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Prompt {
+    Typing,
+    Authenticating,
+    Failed(String),
+}
+
+/// The locked session
+pub struct Lock {
+    password: String,
+    prompt: Prompt,
+    /// Locker drawing the outputs instead of the built-in prompt
+    client: Option<SessionLock>,
+    /// Surfaces of the locker by output
+    surfaces: Vec<(String, LockSurface)>,
+}
+
+impl Lock {
+    fn new() -> Lock {
+        Lock {
+            password: String::with_capacity(PASSWORD_CAPACITY),
+            prompt: Prompt::Typing,
+            client: None,
+            surfaces: Vec::new(),
+        }
+    }
+
+    /// Checks if a running locker is responsible for the session
+    pub fn has_client(&self) -> bool {
+        self.client.as_ref().map(|client| client.alive()).unwrap_or(false)
+    }
+
+    /// Surface the locker shows on an output
+    pub fn surface(&self, output: &str) -> Option<&WlSurface> {
+        if !self.has_client() {
+            return None;
+        }
+        self.surfaces
+            .iter()
+            .find(|(name, surface)| name == output && surface.alive())
+            .map(|(_, surface)| surface.surface())
+    }
+
+    /// Surface receiving keyboard input, preferably the one on the given output
+    pub fn focus(&self, output: &str) -> Option<WlSurface> {
+        if !self.has_client() {
+            return None;
+        }
+        self.surface(output)
+            .or_else(|| self.surfaces.iter().find(|(_, surface)| surface.alive()).map(|(_, surface)| surface.surface()))
+            .cloned()
+    }
+
+    /// Text of the built-in prompt, the password is only hinted at
+    pub fn prompt_lines(&self) -> Vec<String> {
+        let mut lines = vec![String::from("Locked")];
+        lines.push(match &self.prompt {
+            Prompt::Typing => format!("Password: {}", "*".repeat(self.password.chars().count())),
+            Prompt::Authenticating => String::from("Authenticating..."),
+            Prompt::Failed(message) => format!("Authentication failed: {}", message),
+        });
+        lines
+    }
+
+    /// Lets the locker draw its next frame on an output
+    pub fn send_frames(&self, output: &str, time: u32) {
+        if let Some(surface) = self.surface(output) {
+            with_surface_tree_downward(
+                surface,
+                (),
+                |_, _, &()| TraversalAction::DoChildren(()),
+                |_, states, &()| SurfaceData::send_frame(&mut *states.cached_state.current(), time),
+                |_, _, &()| true,
+            );
+        }
+    }
+
+    fn clear_password(&mut self) {
+        wipe(&mut self.password);
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        self.clear_password();
+    }
+}
+
+/// Overwrites the password in place, before it is freed
+fn wipe(password: &mut String) {
+    unsafe {
+        for byte in password.as_bytes_mut() {
+            ptr::write_volatile(byte, 0);
+        }
+    }
+    password.clear();
+}
+
+/// Handles of the session lock global and of authentication results
+pub struct Locker {
+    results: channel::Sender<Result<(), String>>,
+    _global: Global<ExtSessionLockManagerV1>,
+}
+
+/// Sets up the `lock` command and the ext-session-lock-v1 global
+pub fn init_lock(event_loop: &mut EventLoop<'static, Fireplace>, state: &mut Fireplace) -> Result<()> {
+    let (results, receiver) = channel::channel();
+    let token = event_loop
+        .handle()
+        .insert_source(receiver, |event, _, state| {
+            if let channel::Event::Msg(result) = event {
+                state.guard("lock", |state| state.authenticated(result));
+            }
+        })
+        .map_err(|_| anyhow::anyhow!("Failed to register authentication on the event loop"))?;
+    state.tokens.push(token);
+
+    let global = init_session_lock_global(&mut state.display.borrow_mut(), |event, mut ddata| {
+        let state = ddata.get::<Fireplace>().unwrap();
+        state.process_session_lock(event);
+    });
+    state.locker = Some(Locker {
+        results,
+        _global: global,
+    });
+    Ok(())
+}
+
+impl Fireplace {
+    pub fn is_locked(&self) -> bool {
+        self.lock.is_some()
+    }
+
+    /// Locks the session with the built-in prompt
+    pub fn lock_session(&mut self) {
+        if self.locker.is_none() {
+            slog_scope::error!("Locking is unavailable");
+            return;
+        }
+        if self.lock.is_none() {
+            slog_scope::info!("Locking the session");
+            self.lock = Some(Lock::new());
+            self.unfocus_clients();
+        }
+    }
+
+    /// Takes input away from all clients, only the locker gets it afterwards
+    fn unfocus_clients(&mut self) {
+        let time = self.start_time.elapsed().as_millis() as u32;
+        for seat in self.seats.iter() {
+            if let Some(keyboard) = seat.get_keyboard() {
+                keyboard.set_focus(None, SCOUNTER.next_serial());
+            }
+            if let Some(pointer) = seat.get_pointer() {
+                let location = pointer.current_location();
+                pointer.motion(location, None, SCOUNTER.next_serial(), time);
+            }
+        }
+        self.damage();
+    }
+
+    /// Unlocks the session and gives the focus back to the windows
+    pub fn unlock_session(&mut self) {
+        if self.lock.take().is_none() {
+            return;
+        }
+        slog_scope::info!("Unlocking the session");
+        for seat in self.seats.iter() {
+            let focused = self
+                .workspaces
+                .borrow_mut()
+                .space_by_seat(seat)
+                .and_then(|space| space.focused_window());
+            if let Some(keyboard) = seat.get_keyboard() {
+                keyboard.set_focus(focused.as_ref().and_then(|w| w.get_surface()), SCOUNTER.next_serial());
+            }
+        }
+        self.damage();
+    }
+
+    /// Types into the built-in prompt
+    pub fn lock_key(&mut self, keysym: Keysym) {
+        let results = match self.locker.as_ref() {
+            Some(locker) => locker.results.clone(),
+            None => return,
+        };
+        let lock = match self.lock.as_mut() {
+            Some(lock) => lock,
+            None => return,
+        };
+        if lock.prompt == Prompt::Authenticating {
+            return;
+        }
+        lock.prompt = Prompt::Typing;
+        match keysym {
+            KeySyms::KEY_Return | KeySyms::KEY_KP_Enter => {
+                let mut password = lock.password.clone();
+                lock.clear_password();
+                lock.prompt = Prompt::Authenticating;
+                let spawned = std::thread::Builder::new()
+                    .name(String::from("authentication"))
+                    .spawn(move || {
+                        let result = pam::authenticate(&password);
+                        wipe(&mut password);
+                        let _ = results.send(result);
+                    });
+                if let Err(err) = spawned {
+                    lock.prompt = Prompt::Failed(err.to_string());
+                }
+            }
+            KeySyms::KEY_BackSpace => {
+                lock.password.pop();
+            }
+            KeySyms::KEY_Escape => lock.clear_password(),
+            keysym => {
+                let text = ::xkbcommon::xkb::keysym_to_utf8(keysym);
+                for c in text.chars().filter(|c| !c.is_control()) {
+                    if lock.password.len() + c.len_utf8() <= PASSWORD_CAPACITY {
+                        lock.password.push(c);
+                    }
+                }
+            }
+        }
+        self.damage();
+    }
+
+    /// Unlocks the session once PAM accepted the password of the built-in prompt
+    fn authenticated(&mut self, result: Result<(), String>) {
+        let lock = match self.lock.as_mut() {
+            Some(lock) => lock,
+            None => return,
+        };
+        match result {
+            Ok(()) => self.unlock_session(),
+            Err(message) => {
+                slog_scope::warn!("Failed to authenticate: {}", message);
+                lock.prompt = Prompt::Failed(message);
+                self.damage();
+            }
+        }
+    }
+
+    /// Hands the lock to lockers and unlocks when they are done
+    ///
+    /// Only one locker is used at a time, the others are told to finish right away.
+    pub fn process_session_lock(&mut self, event: SessionLockEvent) {
+        match event {
+            SessionLockEvent::Lock(client) => {
+                if self.lock.as_ref().map(|lock| lock.has_client()).unwrap_or(false) {
+                    slog_scope::debug!("Session is already locked by another client");
+                    client.finished();
+                    return;
+                }
+                match self.lock.as_mut() {
+                    // the locker takes over from the built-in prompt
+                    Some(lock) => {
+                        lock.clear_password();
+                        lock.client = Some(client.clone());
+                        lock.surfaces.clear();
+                    }
+                    None => {
+                        slog_scope::info!("Session locked by a client");
+                        let mut lock = Lock::new();
+                        lock.client = Some(client.clone());
+                        self.lock = Some(lock);
+                        self.unfocus_clients();
+                    }
+                }
+                // nothing but the lock is drawn from now on
+                client.locked();
+                self.damage();
+            }
+            SessionLockEvent::Unlock(client) => {
+                if self.lock.as_ref().and_then(|lock| lock.client.as_ref()) == Some(&client) {
+                    self.unlock_session();
+                }
+            }
+            SessionLockEvent::NewSurface { lock, surface, output } => {
+                let current = match self.lock.as_mut() {
+                    Some(current) if current.client.as_ref() == Some(&lock) => current,
+                    // surfaces of finished locks are never shown
+                    _ => return,
+                };
+                let (name, size) = match self.workspaces.borrow_mut().output_by_wl(&output) {
+                    Some(output) => (String::from(output.name()), output.size()),
+                    None => return,
+                };
+                surface.configure(size);
+                current.surfaces.retain(|(output, _)| *output != name);
+                current.surfaces.push((name, surface));
+                self.damage();
+            }
+        }
+    }
+}
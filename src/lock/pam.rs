@@ -0,0 +1,113 @@
+//! Minimal bindings of libpam, just enough to check the password of the user running fireplace
+
+use smithay::reexports::nix::{
+    libc::{self, c_char, c_int, c_void},
+    unistd::{getuid, User},
+};
+use std::{
+    ffi::{CStr, CString},
+    ptr,
+};
+
+/// Service the rules are read from, see `resources/fireplace.pam`
+const SERVICE: &str = "fireplace";
+
+const PAM_SUCCESS: c_int = 0;
+const PAM_BUF_ERR: c_int = 5;
+const PAM_CONV_ERR: c_int = 19;
+const PAM_PROMPT_ECHO_OFF: c_int = 1;
+const PAM_PROMPT_ECHO_ON: c_int = 2;
+
+#[repr(C)]
+struct PamMessage {
+    msg_style: c_int,
+    msg: *const c_char,
+}
+
+#[repr(C)]
+struct PamResponse {
+    resp: *mut c_char,
+    resp_retcode: c_int,
+}
+
+#[repr(C)]
+struct PamConv {
+    conv: extern "C" fn(c_int, *mut *const PamMessage, *mut *mut PamResponse, *mut c_void) -> c_int,
+    appdata_ptr: *mut c_void,
+}
+
+enum PamHandle {}
+
+#[link(name = "pam")]
+extern "C" {
+    fn pam_start(service: *const c_char, user: *const c_char, conv: *const PamConv, handle: *mut *mut PamHandle) -> c_int;
+    fn pam_authenticate(handle: *mut PamHandle, flags: c_int) -> c_int;
+    fn pam_end(handle: *mut PamHandle, status: c_int) -> c_int;
+    fn pam_strerror(handle: *mut PamHandle, error: c_int) -> *const c_char;
+}
+
+/// Answers every prompt with the password, messages to the user are ignored
+///
+/// Responses are allocated with malloc, as libpam frees them.
+extern "C" fn conversation(
+    count: c_int,
+    messages: *mut *const PamMessage,
+    responses: *mut *mut PamResponse,
+    password: *mut c_void,
+) -> c_int {
+    if count <= 0 {
+        return PAM_CONV_ERR;
+    }
+    unsafe {
+        let replies = libc::calloc(count as usize, std::mem::size_of::<PamResponse>()) as *mut PamResponse;
+        if replies.is_null() {
+            return PAM_BUF_ERR;
+        }
+        for i in 0..count as usize {
+            let message = &**messages.add(i);
+            if matches!(message.msg_style, PAM_PROMPT_ECHO_OFF | PAM_PROMPT_ECHO_ON) {
+                (*replies.add(i)).resp = libc::strdup(password as *const c_char);
+            }
+        }
+        *responses = replies;
+    }
+    PAM_SUCCESS
+}
+
+/// Checks the password of the current user, blocking until PAM decided
+pub fn authenticate(password: &str) -> Result<(), String> {
+    let user = User::from_uid(getuid())
+        .map_err(|err| err.to_string())?
+        .ok_or_else(|| String::from("The current user is unknown"))?;
+    let service = CString::new(SERVICE).unwrap();
+    let name = CString::new(user.name).map_err(|err| err.to_string())?;
+    let password = CString::new(password).map_err(|_| String::from("The password contains a nul byte"))?;
+
+    let result = unsafe {
+        let conv = PamConv {
+            conv: conversation,
+            appdata_ptr: password.as_ptr() as *mut c_void,
+        };
+        let mut handle = ptr::null_mut();
+        let status = pam_start(service.as_ptr(), name.as_ptr(), &conv, &mut handle);
+        if status != PAM_SUCCESS {
+            Err(format!("Failed to start PAM: error {}", status))
+        } else {
+            let status = pam_authenticate(handle, 0);
+            let result = if status == PAM_SUCCESS {
+                Ok(())
+            } else {
+                Err(CStr::from_ptr(pam_strerror(handle, status)).to_string_lossy().into_owned())
+            };
+            pam_end(handle, status);
+            result
+        }
+    };
+
+    // no copies of the password are left behind
+    let mut bytes = password.into_bytes();
+    for byte in bytes.iter_mut() {
+        unsafe { ptr::write_volatile(byte, 0) };
+    }
+    result
+}
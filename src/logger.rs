@@ -42,8 +42,21 @@ impl Default for Mode {
     }
 }
 
-/// Initialize fireplace's logging system
-pub fn init(config: &Logging) -> slog_scope::GlobalLoggerGuard {
+/// Parses a level given on the command line, like `debug`
+pub fn parse_level(name: &str) -> Option<slog::Level> {
+    match name.to_lowercase().as_str() {
+        "critical" => Some(slog::Level::Critical),
+        "error" => Some(slog::Level::Error),
+        "warn" | "warning" => Some(slog::Level::Warning),
+        "info" => Some(slog::Level::Info),
+        "debug" => Some(slog::Level::Debug),
+        "trace" => Some(slog::Level::Trace),
+        _ => None,
+    }
+}
+
+/// Initialize fireplace's logging system, dropping messages less severe than `level`
+pub fn init(config: &Logging, level: slog::Level) -> slog_scope::GlobalLoggerGuard {
     let builder = slog_term::TermDecorator::new().stderr();
     let decorator = match config.color {
         Color::Always => builder.force_color(),
@@ -63,7 +76,9 @@ pub fn init(config: &Logging) -> slog_scope::GlobalLoggerGuard {
             )
             //)
             //.build()
-            .fuse(),
+            .fuse()
+            .filter_level(level)
+            .ignore_res(),
             params,
         ),
         Mode::Full => slog::Logger::root(
@@ -72,7 +87,9 @@ pub fn init(config: &Logging) -> slog_scope::GlobalLoggerGuard {
                 slog_term::FullFormat::new(decorator).build().ignore_res()
             )
             //    .build()
-                .fuse(),
+                .fuse()
+                .filter_level(level)
+                .ignore_res(),
             params,
         ),
     };
@@ -0,0 +1,63 @@
+//! # Power management by systemd-logind
+//!
+//! Fireplace takes over the power and suspend keys from logind and asks it to suspend or power off,
+//! as well as to change the brightness of backlights.
+
+use anyhow::Result;
+use smithay::reexports::nix::unistd::dup;
+use std::{
+    fs::File,
+    os::unix::io::{AsRawFd, FromRawFd},
+};
+use zvariant::Fd;
+
+const DESTINATION: &str = "org.freedesktop.login1";
+const PATH: &str = "/org/freedesktop/login1";
+const MANAGER: &str = "org.freedesktop.login1.Manager";
+
+/// Keeps logind from handling the given keys and events itself, for as long as the file is open
+///
+/// `what` is a colon separated list like `handle-power-key:handle-suspend-key`.
+pub fn inhibit(what: &str, why: &str) -> Result<File> {
+    let connection = zbus::Connection::new_system()?;
+    let reply = connection.call_method(
+        Some(DESTINATION),
+        PATH,
+        Some(MANAGER),
+        "Inhibit",
+        &(what, "fireplace", why, "block"),
+    )?;
+    let fd: Fd = reply.body()?;
+    // the descriptor is closed together with the message
+    let fd = dup(fd.as_raw_fd())?;
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+/// Suspends the machine, without asking for authorization interactively
+pub fn suspend() -> Result<()> {
+    call("Suspend")
+}
+
+/// Powers the machine off, without asking for authorization interactively
+pub fn power_off() -> Result<()> {
+    call("PowerOff")
+}
+
+fn call(method: &str) -> Result<()> {
+    let connection = zbus::Connection::new_system()?;
+    connection.call_method(Some(DESTINATION), PATH, Some(MANAGER), method, &(false,))?;
+    Ok(())
+}
+
+/// Sets the brightness of a backlight in `/sys/class/backlight`, which needs no privileges through logind
+pub fn set_brightness(device: &str, brightness: u32) -> Result<()> {
+    let connection = zbus::Connection::new_system()?;
+    connection.call_method(
+        Some(DESTINATION),
+        "/org/freedesktop/login1/session/auto",
+        Some("org.freedesktop.login1.Session"),
+        "SetBrightness",
+        &("backlight", device, brightness),
+    )?;
+    Ok(())
+}
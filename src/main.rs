@@ -1,4 +1,8 @@
-use std::{cell::RefCell, fs::OpenOptions, path::PathBuf, rc::Rc};
+use std::{
+    cell::RefCell,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
 
 use anyhow::{Context, Result};
 use smithay::reexports::{
@@ -6,54 +10,170 @@ use smithay::reexports::{
     wayland_server::Display,
 };
 
+#[macro_use]
+mod profiling;
+
 mod backend;
 mod config;
+mod guard;
 mod handler;
+mod ipc;
+mod lock;
 mod logger;
+mod logind;
 mod shell;
 mod state;
+mod systemd;
 mod wayland;
 pub use self::config::Config;
 pub use self::state::Fireplace;
 
-fn try_config_locations(paths: &[PathBuf]) -> (Option<PathBuf>, Config) {
+fn try_config_locations(paths: &[PathBuf]) -> Result<(Option<PathBuf>, serde_yaml::Value, Config)> {
     for path in paths {
         if path.exists() {
-            return (
-                Some(path.clone()),
-                serde_yaml::from_reader(OpenOptions::new().read(true).open(path).unwrap())
-                    .expect("Malformed config file"),
-            );
+            let source = config::read_source(path).with_context(|| format!("Malformed config file {}", path.display()))?;
+            let config = serde_yaml::from_value(source.clone())
+                .with_context(|| format!("Malformed config file {}", path.display()))?;
+            return Ok((Some(path.clone()), source, config));
+        }
+    }
+    Ok((None, serde_yaml::Value::Mapping(Default::default()), Config::default()))
+}
+
+/// Parses and checks a configuration file without starting the compositor, returns the exit code
+fn check_config(path: Option<PathBuf>) -> i32 {
+    let path = match path {
+        Some(path) => path,
+        None => {
+            println!("No configuration file found, the defaults are used");
+            return 0;
+        }
+    };
+    let config = config::read_source(&path)
+        .and_then(|source| serde_yaml::from_value::<Config>(source).map_err(anyhow::Error::from));
+    let config = match config {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}: {:#}", path.display(), err);
+            return 1;
+        }
+    };
+    let problems = config::check(&config);
+    if problems.is_empty() {
+        println!("{} is valid", path.display());
+        return 0;
+    }
+    for problem in problems.iter() {
+        eprintln!("{}: {}", path.display(), problem);
+    }
+    eprintln!("{} problem(s) found", problems.len());
+    1
+}
+
+/// Prints a `fireplace.json` of the wlc based fireplace as `fireplace.yaml`, returns the exit code
+fn migrate_config(path: &Path) -> i32 {
+    let migrated = std::fs::read_to_string(path)
+        .map_err(anyhow::Error::from)
+        .and_then(|json| config::migrate(&json))
+        .and_then(|(config, dropped)| Ok((serde_yaml::to_string(&config)?, dropped)));
+    let (yaml, dropped) = match migrated {
+        Ok(migrated) => migrated,
+        Err(err) => {
+            eprintln!("{}: {:#}", path.display(), err);
+            return 1;
+        }
+    };
+    println!("# Converted from {}", path.display());
+    print!("{}", yaml);
+    for option in dropped.iter() {
+        eprintln!("{}: dropped {}", path.display(), option);
+    }
+    0
+}
+
+const USAGE: &str = "Usage: fireplace [--config <file>] [--backend winit|udev|headless] \
+                     [--log-level error|warn|info|debug|trace] [--check] [--migrate-config <fireplace.json>]";
+
+/// Options of the command line, overriding the configuration and detection
+#[derive(Default)]
+struct Args {
+    config: Option<PathBuf>,
+    backend: Option<backend::Backend>,
+    log_level: Option<slog::Level>,
+    /// Validate the configuration instead of starting
+    check: bool,
+    /// Convert an old configuration instead of starting
+    migrate_config: Option<PathBuf>,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args> {
+    let mut parsed = Args::default();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().with_context(|| format!("{} expects a value\n{}", arg, USAGE));
+        match arg.as_str() {
+            "-c" | "--config" => parsed.config = Some(PathBuf::from(value()?)),
+            "-b" | "--backend" => parsed.backend = Some(value()?.parse().map_err(anyhow::Error::msg)?),
+            "-l" | "--log-level" => {
+                let level = value()?;
+                parsed.log_level =
+                    Some(logger::parse_level(&level).with_context(|| format!("Unknown log level {}\n{}", level, USAGE))?);
+            }
+            "--check" => parsed.check = true,
+            "--migrate-config" => parsed.migrate_config = Some(PathBuf::from(value()?)),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                std::process::exit(0);
+            }
+            _ => anyhow::bail!("Unknown argument {}\n{}", arg, USAGE),
         }
     }
-    (None, Config::default())
+    Ok(parsed)
 }
 
 fn main() -> Result<()> {
+    let args = parse_args(std::env::args().skip(1))?;
+    if let Some(path) = args.migrate_config.as_ref() {
+        std::process::exit(migrate_config(path));
+    }
+
     // Parse configuration
-    let mut locations = if let Ok(base) = xdg::BaseDirectories::new() {
-        base.list_config_files_once("fireplace.yaml")
-    } else {
-        Vec::with_capacity(3)
-    };
-    if cfg!(debug_assertions) {
-        if let Ok(mut cwd) = std::env::current_dir() {
-            cwd.push("fireplace.yaml");
-            locations.push(cwd);
+    // yaml is preferred over toml in every location
+    let mut locations = Vec::with_capacity(8);
+    if let Some(path) = args.config.as_ref() {
+        if !path.exists() {
+            anyhow::bail!("Config file {} does not exist", path.display());
+        }
+        locations.push(path.clone());
+    }
+    for name in config::FILE_NAMES.iter().filter(|_| args.config.is_none()) {
+        if let Ok(base) = xdg::BaseDirectories::new() {
+            locations.extend(base.list_config_files_once(name));
+        }
+        if cfg!(debug_assertions) {
+            if let Ok(mut cwd) = std::env::current_dir() {
+                cwd.push(name);
+                locations.push(cwd);
+            }
         }
+        locations.push(PathBuf::from("/etc/fireplace").join(name));
+        locations.push(PathBuf::from("/etc").join(name));
+    }
+    if args.check {
+        std::process::exit(check_config(locations.into_iter().find(|path| path.exists())));
     }
-    locations.push(PathBuf::from("/etc/fireplace/fireplace.yaml"));
-    locations.push(PathBuf::from("/etc/fireplace.yaml"));
-    let (config_path, config) = try_config_locations(&locations);
+    let (config_path, config_source, config) = try_config_locations(&locations)?;
 
     // Initialize logger
-    let _guard = logger::init(&config.logging);
+    let _guard = logger::init(&config.logging, args.log_level.unwrap_or(slog::Level::Trace));
+    #[cfg(feature = "profiling")]
+    profiling::init();
 
     slog_scope::info!("Version: {}", std::env!("CARGO_PKG_VERSION"));
     slog_scope::debug!("Debug build ({})", std::env!("GIT_HASH"));
     slog_scope::info!(
         "Fireplace starting up with {}.",
         config_path
+            .as_ref()
             .map(|x| format!("config at {}", x.display()))
             .unwrap_or(String::from("default config"))
     );
@@ -61,8 +181,11 @@ fn main() -> Result<()> {
 
     let mut event_loop = EventLoop::try_new().with_context(|| "Failed to initialize event loop")?;
     let mut display = Display::new();
-    let socket_name = display.add_socket_auto()?;
-    
+    let socket_name = match systemd::add_activated_socket(&mut display) {
+        Some(name) => name,
+        None => display.add_socket_auto()?,
+    };
+
     event_loop
         .handle()
         .insert_source(
@@ -70,8 +193,14 @@ fn main() -> Result<()> {
             move |_, _, state: &mut Fireplace| {
                 let display = state.display.clone();
                 let mut display = display.borrow_mut();
-                match display.dispatch(std::time::Duration::from_millis(0), state) {
-                    Ok(_) => Ok(PostAction::Continue),
+                // a panicking request handler must not take the other clients down
+                let result = state.guard("wayland", |state| display.dispatch(std::time::Duration::from_millis(0), state));
+                match result.unwrap_or(Ok(0)) {
+                    Ok(_) => {
+                        // any request might change what is shown
+                        state.damage();
+                        Ok(PostAction::Continue)
+                    }
                     Err(e) => {
                         slog_scope::error!("I/O error on the Wayland display: {}", e);
                         state.should_stop = true;
@@ -83,8 +212,47 @@ fn main() -> Result<()> {
         .expect("Failed to init the wayland event source.");
 
     slog_scope::info!("Listening on {:?}", socket_name);
-    let mut state = Fireplace::new(config, display, socket_name);
-    backend::initial_backend_auto(&mut event_loop, &mut state)?;
+    let mut state = Fireplace::new(config, config_source, config_path, display, socket_name);
+    match args.backend {
+        Some(kind) => backend::initial_backend(&mut event_loop, &mut state, kind)?,
+        None => backend::initial_backend_auto(&mut event_loop, &mut state)?,
+    }
+    if let Err(err) = backend::screencast::init_screencast(&mut state) {
+        slog_scope::warn!("Screen casting is unavailable: {}", err);
+    }
+    if let Err(err) = ipc::init_ipc(&mut event_loop, &mut state) {
+        slog_scope::warn!("The ipc socket is unavailable: {}", err);
+    }
+    if let Err(err) = ipc::init_i3_ipc(&mut event_loop, &mut state) {
+        slog_scope::warn!("The i3 ipc socket is unavailable: {}", err);
+    }
+    if let Err(err) = ipc::init_dbus(&mut event_loop, &mut state) {
+        slog_scope::warn!("The d-bus service is unavailable: {}", err);
+    }
+    if let Err(err) = ipc::init_metrics(&mut event_loop, &mut state) {
+        slog_scope::warn!("The metrics endpoint is unavailable: {}", err);
+    }
+    if let Err(err) = ipc::init_notifications(&mut event_loop, &mut state) {
+        slog_scope::warn!("The notification daemon is unavailable: {}", err);
+    }
+    if let Err(err) = lock::init_lock(&mut event_loop, &mut state) {
+        slog_scope::warn!("Locking the session is unavailable: {}", err);
+    }
+    state.apply_power_keys();
+    if let Err(err) = handler::init_media(&mut event_loop, &mut state) {
+        slog_scope::warn!("Media keys are unavailable: {}", err);
+    }
+    if let Err(err) = handler::watch_config(&mut event_loop, &mut state) {
+        slog_scope::warn!("Changes of the configuration are not applied: {}", err);
+    }
+    if state.config.import_environment {
+        if let Err(err) = systemd::import_environment(&state.session_environment()) {
+            slog_scope::warn!("Failed to import the environment into systemd: {}", err);
+        }
+    }
+    // the backend is up with its first outputs, programs find the display and sockets
+    state.run_autostart(true);
+    systemd::notify("READY=1");
 
     let signal = event_loop.get_signal();
     let handle = event_loop.handle();
@@ -109,9 +277,12 @@ fn main() -> Result<()> {
         }
 
         // send out events
+        state.guard("i3 ipc", |state| state.emit_i3_events());
+        state.guard("d-bus", |state| state.emit_dbus_signals());
         let display = state.display.clone();
         display.borrow_mut().flush_clients(state);
     })?;
+    systemd::notify("STOPPING=1");
 
     Ok(())
 }
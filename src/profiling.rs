@@ -0,0 +1,32 @@
+//! # Profiling
+//!
+//! Built with the `profiling` feature, the render loop, input dispatch and layout rearrangement
+//! are instrumented with `tracing` spans, which are sent to a running Tracy profiler.
+//! Without the feature the spans compile to nothing.
+
+/// Enters a span until the end of the enclosing block, taking the arguments of `tracing::info_span!`
+macro_rules! profile_span {
+    ($($args:tt)*) => {
+        #[cfg(feature = "profiling")]
+        let _span = tracing::info_span!($($args)*).entered();
+    };
+}
+
+/// Connects to Tracy, frames are collected from now on
+#[cfg(feature = "profiling")]
+pub fn init() {
+    use tracing_subscriber::layer::SubscriberExt;
+
+    let subscriber = tracing_subscriber::registry().with(tracing_tracy::TracyLayer::new());
+    if let Err(err) = tracing::subscriber::set_global_default(subscriber) {
+        slog_scope::warn!("Failed to set up profiling: {}", err);
+        return;
+    }
+    slog_scope::info!("Profiling enabled, connect Tracy to capture frames");
+}
+
+/// Marks the end of a frame of an output, Tracy groups spans by them
+#[cfg(feature = "profiling")]
+pub fn frame_mark() {
+    tracy_client::finish_continuous_frame!();
+}
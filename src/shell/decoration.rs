@@ -0,0 +1,112 @@
+use smithay::utils::{Logical, Point, Rectangle};
+
+use super::{layout::Layout, window::Kind};
+use crate::config::Decoration;
+
+/// Space taken by server-side decorations around a window
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Insets {
+    /// Width of the border on each side
+    pub border: i32,
+    /// Height of the title bar above the window
+    pub title: i32,
+    /// Height of the title rows stacked containers show for their hidden children
+    pub stack_row: i32,
+}
+
+impl Insets {
+    pub fn new(config: &Decoration) -> Insets {
+        Insets {
+            border: config.border.width as i32,
+            title: config.title_bar.height(),
+            // stacked containers list their children even without title bars
+            stack_row: config.title_bar.height as i32,
+        }
+    }
+
+    /// Shrinks an area to the part left for the client
+    pub fn shrink(&self, area: Rectangle<i32, Logical>) -> Rectangle<i32, Logical> {
+        Rectangle::from_loc_and_size(
+            (area.loc.x + self.border, area.loc.y + self.border + self.title),
+            (
+                (area.size.w - 2 * self.border).max(1),
+                (area.size.h - 2 * self.border - self.title).max(1),
+            ),
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Button {
+    Close,
+    Maximize,
+}
+
+/// Part of the decorations hit by the pointer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hit {
+    TitleBar,
+    Button(Button),
+}
+
+/// Returns the absolute window geometry of a toplevel placed at `location`
+pub fn window_geometry(
+    toplevel: &Kind,
+    location: Point<i32, Logical>,
+    bounding_box: Rectangle<i32, Logical>,
+) -> Rectangle<i32, Logical> {
+    toplevel
+        .geometry()
+        .map(|geo| Rectangle {
+            loc: location + geo.loc,
+            size: geo.size,
+        })
+        .unwrap_or(bounding_box)
+}
+
+/// Area of the title bar above the given window geometry
+pub fn title_bar(geometry: Rectangle<i32, Logical>, height: i32) -> Rectangle<i32, Logical> {
+    Rectangle::from_loc_and_size((geometry.loc.x, geometry.loc.y - height), (geometry.size.w, height))
+}
+
+/// Areas of the buttons inside a title bar, right to left
+pub fn buttons(title_bar: Rectangle<i32, Logical>) -> [(Button, Rectangle<i32, Logical>); 2] {
+    let size = title_bar.size.h;
+    let right = title_bar.loc.x + title_bar.size.w;
+    [
+        (
+            Button::Close,
+            Rectangle::from_loc_and_size((right - size, title_bar.loc.y), (size, size)),
+        ),
+        (
+            Button::Maximize,
+            Rectangle::from_loc_and_size((right - 2 * size, title_bar.loc.y), (size, size)),
+        ),
+    ]
+}
+
+/// Finds the topmost title bar under this point if any
+pub fn under(space: &dyn Layout, config: &Decoration, point: Point<f64, Logical>) -> Option<(Kind, Hit)> {
+    let height = config.title_bar.height();
+    if height == 0 {
+        return None;
+    }
+
+    let windows = space.windows_from_bottom_to_top().collect::<Vec<_>>();
+    for (toplevel, location, bounding_box) in windows.into_iter().rev() {
+        if toplevel.is_fullscreen() || !space.is_decorated(&toplevel) {
+            continue;
+        }
+        let bar = title_bar(window_geometry(&toplevel, location, bounding_box), height);
+        if !bar.to_f64().contains(point) {
+            continue;
+        }
+        let hit = buttons(bar)
+            .iter()
+            .find(|(_, area)| area.to_f64().contains(point))
+            .map(|&(button, _)| Hit::Button(button))
+            .unwrap_or(Hit::TitleBar);
+        return Some((toplevel, hit));
+    }
+    None
+}
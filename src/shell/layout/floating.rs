@@ -20,6 +20,7 @@ use smithay::{
 
 use super::{Layout, ID_COUNTER};
 use crate::shell::{
+    decoration::Insets,
     window::{Kind, Window},
     SurfaceData,
 };
@@ -187,7 +188,9 @@ impl PointerGrab for ResizeSurfaceGrab {
         serial: Serial,
         time: u32,
     ) {
-        handle.button(button, state, serial, time);
+        if self.start_data.focus.is_some() {
+            handle.button(button, state, serial, time);
+        }
         if handle.current_pressed().is_empty() {
             // No more buttons are pressed, release the grab.
             handle.unset_grab(serial, time);
@@ -272,7 +275,7 @@ impl PointerGrab for MoveSurfaceGrab {
         if handle.current_pressed().is_empty() {
             // No more buttons are pressed, release the grab.
             handle.unset_grab(serial, time);
-        } else {
+        } else if self.start_data.focus.is_some() {
             handle.button(button, state, serial, time);
         }
     }
@@ -290,6 +293,8 @@ pub struct Floating {
     id: usize,
     size: Size<i32, Logical>,
     windows: Vec<Rc<RefCell<Window>>>,
+    /// Space taken by window decorations
+    insets: Insets,
 }
 
 impl PartialEq for Floating {
@@ -299,11 +304,12 @@ impl PartialEq for Floating {
 }
 
 impl Floating {
-    pub fn new<S: Into<Size<i32, Logical>>>(size: S) -> Floating {
+    pub fn new<S: Into<Size<i32, Logical>>>(size: S, insets: Insets) -> Floating {
         Floating {
             id: ID_COUNTER.fetch_add(1, Ordering::SeqCst),
             size: size.into(),
             windows: Vec::new(),
+            insets,
         }
     }
 
@@ -321,6 +327,30 @@ impl Floating {
             .find(|w| &w.borrow().toplevel == surface)
             .cloned()
     }
+
+    /// Keeps fullscreen windows above all others,
+    /// while otherwise preserving the stacking order.
+    fn restack(&mut self) {
+        self.windows.sort_by_key(|w| !w.borrow().is_fullscreen());
+    }
+
+    /// Covers the whole output with the given window
+    fn configure_fullscreen(&self, window: &mut Window) {
+        // offset the client-side decorations, so only the content is visible
+        let location = Into::<Point<i32, Logical>>::into((0, 0)) - window.geometry().loc;
+        window.set_location(location);
+
+        #[allow(irrefutable_let_patterns)]
+        if let Kind::Xdg(xdg_surface) = &window.toplevel {
+            let ret = xdg_surface.with_pending_state(|state| {
+                state.states.set(xdg_toplevel::State::Fullscreen);
+                state.size = Some(self.size);
+            });
+            if ret.is_ok() {
+                xdg_surface.send_configure();
+            }
+        }
+    }
 }
 
 impl Layout for Floating {
@@ -342,6 +372,7 @@ impl Layout for Floating {
             window.set_location(location);
         }
         self.windows.insert(0, Rc::new(RefCell::new(window)));
+        self.restack();
     }
 
     fn remove_toplevel(&mut self, surface: Kind) {
@@ -359,6 +390,9 @@ impl Layout for Floating {
             Some(w) => w,
             None => return,
         };
+        if window.borrow().is_fullscreen() {
+            return;
+        }
         let pointer = seat.get_pointer().unwrap();
         let mut initial_window_location = match window.borrow().location() {
             Some(p) => p,
@@ -420,6 +454,9 @@ impl Layout for Floating {
             Some(w) => w,
             None => return,
         };
+        if window.borrow().is_fullscreen() {
+            return;
+        }
         let mut initial_window_location = match window.borrow().location() {
             Some(p) => p,
             None => return,
@@ -532,7 +569,11 @@ impl Layout for Floating {
         // set initial position
         {
             let mut window = window.borrow_mut();
-            if window.location().is_none() && window.bbox().size != (0, 0).into() {
+            if window.is_fullscreen() {
+                // the geometry might have changed with this commit
+                let location = Into::<Point<i32, Logical>>::into((0, 0)) - window.geometry().loc;
+                window.set_location(location);
+            } else if window.location().is_none() && window.bbox().size != (0, 0).into() {
                 let geometry = window.geometry();
                 // center the window for now
                 let location = (
@@ -604,18 +645,42 @@ impl Layout for Floating {
     }
 
     fn fullscreen_request(&mut self, surface: Kind, state: bool) {
-        // do not allow fullscreening
-        #[allow(irrefutable_let_patterns)]
-        if let Kind::Xdg(xdg_surface) = surface {
-            if !state {
+        let window = match self.window_for_toplevel(&surface) {
+            Some(w) => w,
+            None => return,
+        };
+
+        if state {
+            {
+                let mut window = window.borrow_mut();
+                if !window.is_fullscreen() {
+                    let restore = Rectangle {
+                        loc: window.location().unwrap_or_else(|| (0, 0).into()),
+                        size: window.geometry().size,
+                    };
+                    window.set_fullscreen(restore);
+                }
+                self.configure_fullscreen(&mut *window);
+            }
+            // raise the window above all others
+            self.windows.retain(|w| !Rc::ptr_eq(w, &window));
+            self.windows.insert(0, window);
+        } else {
+            let restore = window.borrow_mut().unset_fullscreen();
+            #[allow(irrefutable_let_patterns)]
+            if let Kind::Xdg(xdg_surface) = surface {
                 let _ = xdg_surface.with_pending_state(|state| {
                     state.states.unset(xdg_toplevel::State::Fullscreen);
-                    state.size = None;
+                    state.size = restore.map(|geo| geo.size);
                     state.fullscreen_output = None;
                 });
+                xdg_surface.send_configure();
+            }
+            if let Some(restore) = restore {
+                window.borrow_mut().set_location(restore.loc);
             }
-            xdg_surface.send_configure();
         }
+        self.restack();
     }
 
     fn maximize_request(&mut self, surface: Kind, state: bool) {
@@ -624,14 +689,21 @@ impl Layout for Floating {
                 Some(w) => w,
                 None => return,
             };
-            let pos = Into::<Point<i32, Logical>>::into((0, 0)) - window.borrow().geometry().loc;
+            // keep the decorations of maximized windows on screen
+            let insets = if window.borrow().is_decorated() {
+                self.insets
+            } else {
+                Insets::default()
+            };
+            let area = insets.shrink(Rectangle::from_loc_and_size((0, 0), self.size));
+            let pos = area.loc - window.borrow().geometry().loc;
             window.borrow_mut().set_location(pos);
 
             #[allow(irrefutable_let_patterns)]
             if let Kind::Xdg(xdg_surface) = surface {
                 let _ = xdg_surface.with_pending_state(|state| {
                     state.states.set(xdg_toplevel::State::Maximized);
-                    state.size = Some(self.size);
+                    state.size = Some(area.size);
                 });
                 xdg_surface.send_configure();
             }
@@ -662,8 +734,20 @@ impl Layout for Floating {
     }
 
     fn rearrange(&mut self, size: &Size<i32, Logical>) {
+        profile_span!("arrange", layout = "floating");
         // todo update windows out of new size
         self.size = *size;
+        for window in self.windows.iter() {
+            let mut window = window.borrow_mut();
+            if window.is_fullscreen() {
+                self.configure_fullscreen(&mut *window);
+            }
+        }
+    }
+
+    fn set_insets(&mut self, insets: Insets) {
+        // windows keep their geometry until they are maximized or resized again
+        self.insets = insets;
     }
 
     fn windows<'a>(&'a self) -> Box<dyn Iterator<Item = Kind> + 'a> {
@@ -695,7 +779,74 @@ impl Layout for Floating {
             }
 
             window.borrow_mut().toplevel.set_activated(true);
+            window.borrow_mut().set_urgent(false);
             self.windows.insert(0, window);
+            self.restack();
+        }
+    }
+
+    fn opacity(&self, surface: &Kind) -> f32 {
+        self.window_for_toplevel(surface)
+            .map(|w| w.borrow().opacity())
+            .unwrap_or(1.0)
+    }
+
+    fn set_opacity(&mut self, surface: &Kind, opacity: f32) {
+        if let Some(window) = self.window_for_toplevel(surface) {
+            window.borrow_mut().set_opacity(opacity);
+        }
+    }
+
+    fn corner_radius(&self, surface: &Kind) -> Option<u32> {
+        self.window_for_toplevel(surface)
+            .and_then(|w| w.borrow().corner_radius())
+    }
+
+    fn set_corner_radius(&mut self, surface: &Kind, radius: Option<u32>) {
+        if let Some(window) = self.window_for_toplevel(surface) {
+            window.borrow_mut().set_corner_radius(radius);
+        }
+    }
+
+    fn blur(&self, surface: &Kind) -> bool {
+        self.window_for_toplevel(surface)
+            .map(|w| w.borrow().blur())
+            .unwrap_or(false)
+    }
+
+    fn set_blur(&mut self, surface: &Kind, blur: bool) {
+        if let Some(window) = self.window_for_toplevel(surface) {
+            window.borrow_mut().set_blur(blur);
+        }
+    }
+
+    fn is_urgent(&self, surface: &Kind) -> bool {
+        self.window_for_toplevel(surface)
+            .map(|w| w.borrow().is_urgent())
+            .unwrap_or(false)
+    }
+
+    fn set_urgent(&mut self, surface: &Kind, urgent: bool) {
+        if let Some(window) = self.window_for_toplevel(surface) {
+            window.borrow_mut().set_urgent(urgent);
+        }
+    }
+
+    fn is_draggable(&self, surface: &Kind) -> bool {
+        self.window_for_toplevel(surface)
+            .map(|w| !w.borrow().is_fullscreen())
+            .unwrap_or(false)
+    }
+
+    fn is_decorated(&self, surface: &Kind) -> bool {
+        self.window_for_toplevel(surface)
+            .map(|w| w.borrow().is_decorated())
+            .unwrap_or(true)
+    }
+
+    fn set_decorated(&mut self, surface: &Kind, decorated: bool) {
+        if let Some(window) = self.window_for_toplevel(surface) {
+            window.borrow_mut().set_decorated(decorated);
         }
     }
 
@@ -10,15 +10,26 @@ use smithay::{
         Serial,
     },
 };
-use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
-use super::window::Kind;
+use super::{
+    decoration::{window_geometry, Insets},
+    window::Kind,
+};
+use crate::config::LayoutConfig;
 
 mod floating;
+mod tiling;
 pub use self::floating::Floating;
+pub use self::tiling::{ContainerLayout, SavedNode, Tiling};
 
 static ID_COUNTER: AtomicUsize = AtomicUsize::new(0);
 
+/// Returns a new id, unique among layouts, windows and outputs
+pub fn next_id() -> usize {
+    ID_COUNTER.fetch_add(1, Ordering::SeqCst)
+}
+
 pub trait Layout {
     fn id(&self) -> usize;
     fn new_toplevel(&mut self, surface: Kind);
@@ -44,21 +55,84 @@ pub trait Layout {
     fn minimize_request(&mut self, surface: Kind);
     fn remove_toplevel(&mut self, surface: Kind);
     fn on_focus(&mut self, surface: &WlSurface);
+    /// Returns the opacity of the given window, 1.0 if unknown
+    fn opacity(&self, surface: &Kind) -> f32;
+    fn set_opacity(&mut self, surface: &Kind, opacity: f32);
+    /// Returns the corner radius set for the given window, if it overrides the configured one
+    fn corner_radius(&self, surface: &Kind) -> Option<u32>;
+    fn set_corner_radius(&mut self, surface: &Kind, radius: Option<u32>);
+    /// Returns if whatever is behind the given window is blurred
+    fn blur(&self, surface: &Kind) -> bool;
+    fn set_blur(&mut self, surface: &Kind, blur: bool);
+    /// Returns if the window requested attention, cleared once it gets focused
+    fn is_urgent(&self, surface: &Kind) -> bool;
+    fn set_urgent(&mut self, surface: &Kind, urgent: bool);
+    /// Returns if server-side decorations are drawn around the given window
+    fn is_decorated(&self, surface: &Kind) -> bool;
+    fn set_decorated(&mut self, surface: &Kind, decorated: bool);
     //TODO: fn window_options(&mut self, surface: Kind) -> Vec<String>;
 
+    /// Takes a window out of the arrangement of the layout or puts it back,
+    /// layouts without arrangement float all windows anyway
+    fn set_floating(&mut self, _surface: &Kind, _floating: bool) {}
+
+    /// Handles a layout specific command, returns if the command was known
+    fn command(&mut self, _command: &str) -> bool {
+        false
+    }
+
+    /// Returns the current arrangement, if the layout supports saving it
+    fn save(&self) -> Option<SavedNode> {
+        None
+    }
+
+    /// Applies a saved arrangement, returns if the layout supports it
+    fn restore(&mut self, _saved: SavedNode) -> bool {
+        false
+    }
+
+    /// Windows are only moved by rearranging, never interactively, so their moves may be animated
+    fn animates_moves(&self) -> bool {
+        false
+    }
+
+    /// Returns if `move_request` and `resize_request` start a grab for the given window
+    fn is_draggable(&self, _surface: &Kind) -> bool {
+        false
+    }
+
     fn is_empty(&self) -> bool;
     fn rearrange(&mut self, size: &Size<i32, Logical>);
+    /// Changes the space reserved for decorations around every window
+    fn set_insets(&mut self, insets: Insets);
 
     fn surface_under(
         &mut self,
         point: Point<f64, Logical>,
     ) -> Option<(WlSurface, Point<i32, Logical>)>;
     fn focused_window(&self) -> Option<Kind>;
+    /// Title rows of windows in stacked and tabbed containers, drawn above the visible one
+    fn stacked_titles(&self) -> Vec<(Kind, Rectangle<i32, Logical>)> {
+        Vec::new()
+    }
     fn windows<'a>(&'a self) -> Box<dyn Iterator<Item = Kind> + 'a>;
     fn windows_from_bottom_to_top<'a>(
         &'a self,
     ) -> Box<dyn Iterator<Item = (Kind, Point<i32, Logical>, Rectangle<i32, Logical>)> + 'a>;
 
+    /// Finds the topmost window at this point and its window geometry
+    fn toplevel_under(&self, point: Point<f64, Logical>) -> Option<(Kind, Rectangle<i32, Logical>)> {
+        let windows = self.windows_from_bottom_to_top().collect::<Vec<_>>();
+        windows
+            .into_iter()
+            .rev()
+            .find(|(_, _, bounding_box)| bounding_box.to_f64().contains(point))
+            .map(|(toplevel, location, bounding_box)| {
+                let geometry = window_geometry(&toplevel, location, bounding_box);
+                (toplevel, geometry)
+            })
+    }
+
     /// Sends the frame callback to all the subsurfaces in this
     /// window that requested it
     fn send_frames(&self, time: u32) {
@@ -83,6 +157,24 @@ pub trait Layout {
     }
 }
 
+/// Creates the layout used for new workspaces
+pub fn new_layout<S: Into<Size<i32, Logical>>>(
+    size: S,
+    insets: Insets,
+    config: &LayoutConfig,
+) -> Box<dyn Layout> {
+    match *config {
+        LayoutConfig::Floating => Box::new(Floating::new(size, insets)),
+        LayoutConfig::Tiling {
+            layout,
+            split_ratio,
+            resize_step,
+        } => Box::new(Tiling::new(size, insets, layout).with_ratios(split_ratio, resize_step)),
+        LayoutConfig::Monocle => Box::new(Tiling::monocle(size, insets)),
+        LayoutConfig::MasterStack { ratio, count } => Box::new(Tiling::master_stack(size, insets, ratio, count)),
+    }
+}
+
 impl PartialEq for Box<dyn Layout> {
     fn eq(&self, other: &Self) -> bool {
         self.id() == other.id()
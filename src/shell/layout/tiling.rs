@@ -0,0 +1,1364 @@
+use std::sync::atomic::Ordering;
+
+use serde::{Deserialize, Serialize};
+use smithay::{
+    reexports::{
+        wayland_protocols::xdg_shell::server::xdg_toplevel,
+        wayland_server::protocol::wl_surface,
+    },
+    utils::{Logical, Point, Rectangle, Size},
+    wayland::{
+        seat::{GrabStartData, Seat},
+        shell::xdg::ToplevelConfigure,
+        Serial,
+    },
+};
+
+use super::{Layout, ID_COUNTER};
+use crate::shell::{
+    decoration::Insets,
+    window::{Kind, Window},
+};
+
+/// How a container arranges its children
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContainerLayout {
+    /// Children are placed next to each other
+    SplitH,
+    /// Children are placed below each other
+    SplitV,
+    /// Only the focused child is visible, the others are reachable horizontally
+    Tabbed,
+    /// Only the focused child is visible below the title rows of the others,
+    /// which are reachable vertically
+    ///
+    /// Takes the place of the `Stacked` workspace mode of the wlc based fireplace_lib.
+    #[serde(alias = "stacking")]
+    Stacked,
+}
+
+impl ContainerLayout {
+    fn is_horizontal(&self) -> bool {
+        matches!(self, ContainerLayout::SplitH | ContainerLayout::Tabbed)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+impl Direction {
+    pub fn from_name(name: &str) -> Option<Direction> {
+        match name {
+            "left" => Some(Direction::Left),
+            "right" => Some(Direction::Right),
+            "up" => Some(Direction::Up),
+            "down" => Some(Direction::Down),
+            _ => None,
+        }
+    }
+
+    fn is_horizontal(&self) -> bool {
+        matches!(self, Direction::Left | Direction::Right)
+    }
+
+    fn offset(&self) -> isize {
+        match self {
+            Direction::Left | Direction::Up => -1,
+            Direction::Right | Direction::Down => 1,
+        }
+    }
+}
+
+/// Serializable arrangement of a tiling workspace
+///
+/// Windows are stored as placeholders, which are taken by
+/// the next window with a matching app_id.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SavedNode {
+    Window {
+        #[serde(default)]
+        app_id: Option<String>,
+    },
+    Container {
+        layout: ContainerLayout,
+        #[serde(default)]
+        ratios: Vec<f64>,
+        children: Vec<SavedNode>,
+    },
+}
+
+struct Leaf {
+    window: Window,
+    geometry: Rectangle<i32, Logical>,
+    visible: bool,
+    /// If the window was already checked against placeholders
+    placed: bool,
+}
+
+impl Leaf {
+    fn new(window: Window) -> Leaf {
+        Leaf {
+            window,
+            geometry: Rectangle::default(),
+            visible: false,
+            placed: false,
+        }
+    }
+
+    /// Assigns an area to the window, keeping the space of its decorations free
+    fn configure(&mut self, area: Rectangle<i32, Logical>, visible: bool, insets: Insets) {
+        let insets = if self.window.is_decorated() {
+            insets
+        } else {
+            Insets::default()
+        };
+        self.geometry = insets.shrink(area);
+        self.visible = visible;
+        if !self.window.toplevel.alive() {
+            return;
+        }
+
+        let size = self.geometry.size;
+        #[allow(irrefutable_let_patterns)]
+        if let Kind::Xdg(xdg) = &self.window.toplevel {
+            let ret = xdg.with_pending_state(|state| {
+                let changed = state.size != Some(size);
+                state.size = Some(size);
+                changed
+            });
+            if let Ok(true) = ret {
+                xdg.send_configure();
+            }
+        }
+        self.update_location();
+    }
+
+    /// Places the content of the window into its area,
+    /// hiding client-side shadows outside of it.
+    fn update_location(&mut self) {
+        let location = self.geometry.loc - self.window.geometry().loc;
+        self.window.set_location(location);
+    }
+}
+
+enum Node {
+    Leaf(Leaf),
+    Container(Container),
+    /// Space reserved for a window with the given app_id, or any window
+    Placeholder(Option<String>),
+}
+
+impl Node {
+    fn from_saved(saved: SavedNode) -> Node {
+        match saved {
+            SavedNode::Window { app_id } => Node::Placeholder(app_id),
+            SavedNode::Container {
+                layout,
+                ratios,
+                children,
+            } => {
+                let mut container = Container::new(layout);
+                for child in children {
+                    let idx = container.children.len();
+                    container.insert(idx, Node::from_saved(child));
+                }
+                // saved files may be edited by hand, only matching ratios are used and then validated
+                if ratios.len() == container.children.len() {
+                    container.ratios = ratios;
+                    normalize_ratios(&mut container.ratios);
+                }
+                Node::Container(container)
+            }
+        }
+    }
+
+    fn save(&self) -> SavedNode {
+        match self {
+            Node::Leaf(leaf) => SavedNode::Window {
+                app_id: leaf.window.toplevel.app_id(),
+            },
+            Node::Placeholder(app_id) => SavedNode::Window {
+                app_id: app_id.clone(),
+            },
+            Node::Container(container) => container.save(),
+        }
+    }
+
+    fn arrange(&mut self, area: Rectangle<i32, Logical>, visible: bool, insets: Insets) {
+        match self {
+            Node::Leaf(leaf) => leaf.configure(area, visible, insets),
+            Node::Container(container) => container.arrange(area, visible, insets),
+            Node::Placeholder(_) => {}
+        }
+    }
+}
+
+struct Container {
+    layout: ContainerLayout,
+    children: Vec<Node>,
+    /// Share of the available space for each child, adds up to 1.0
+    ratios: Vec<f64>,
+    /// Index of the child, that was last focused
+    focus: usize,
+    /// Title rows of the hidden children of a visible stacked container,
+    /// or the tabs of all children of a visible tabbed one, by child index
+    rows: Vec<(usize, Rectangle<i32, Logical>)>,
+}
+
+impl Container {
+    fn new(layout: ContainerLayout) -> Container {
+        Container {
+            layout,
+            children: Vec::new(),
+            ratios: Vec::new(),
+            focus: 0,
+            rows: Vec::new(),
+        }
+    }
+
+    fn insert(&mut self, idx: usize, node: Node) {
+        let count = self.children.len() as f64;
+        for ratio in self.ratios.iter_mut() {
+            *ratio *= count / (count + 1.0);
+        }
+        self.children.insert(idx, node);
+        self.ratios.insert(idx, 1.0 / (count + 1.0));
+    }
+
+    fn remove(&mut self, idx: usize) -> Node {
+        let node = self.children.remove(idx);
+        self.ratios.remove(idx);
+        let sum: f64 = self.ratios.iter().sum();
+        for ratio in self.ratios.iter_mut() {
+            *ratio /= sum;
+        }
+        if self.focus >= idx && self.focus > 0 {
+            self.focus -= 1;
+        }
+        node
+    }
+
+    fn arrange(&mut self, area: Rectangle<i32, Logical>, visible: bool, insets: Insets) {
+        self.rows.clear();
+        match self.layout {
+            ContainerLayout::SplitH | ContainerLayout::SplitV => {
+                let horizontal = self.layout == ContainerLayout::SplitH;
+                let total = if horizontal { area.size.w } else { area.size.h };
+                let count = self.children.len();
+                let mut offset = 0;
+                for (i, (child, ratio)) in self.children.iter_mut().zip(self.ratios.iter()).enumerate() {
+                    let len = if i == count - 1 {
+                        total - offset
+                    } else {
+                        (total as f64 * ratio).round() as i32
+                    };
+                    let rect = if horizontal {
+                        Rectangle::from_loc_and_size((area.loc.x + offset, area.loc.y), (len, area.size.h))
+                    } else {
+                        Rectangle::from_loc_and_size((area.loc.x, area.loc.y + offset), (area.size.w, len))
+                    };
+                    child.arrange(rect, visible, insets);
+                    offset += len;
+                }
+            }
+            ContainerLayout::Tabbed => {
+                let focus = self.focus;
+                let count = self.children.len() as i32;
+                // a single row of tabs, the focused child has one as well
+                let row = if count > 1 {
+                    insets.stack_row.min(area.size.h / 2)
+                } else {
+                    0
+                };
+                if visible && row > 0 {
+                    let mut x = area.loc.x;
+                    for i in 0..count {
+                        let width = if i == count - 1 {
+                            area.loc.x + area.size.w - x
+                        } else {
+                            area.size.w / count
+                        };
+                        self.rows
+                            .push((i as usize, Rectangle::from_loc_and_size((x, area.loc.y), (width, row))));
+                        x += width;
+                    }
+                }
+                let content = Rectangle::from_loc_and_size(
+                    (area.loc.x, area.loc.y + row),
+                    (area.size.w, area.size.h - row),
+                );
+                for (i, child) in self.children.iter_mut().enumerate() {
+                    child.arrange(content, visible && i == focus, insets);
+                }
+            }
+            ContainerLayout::Stacked => {
+                let focus = self.focus;
+                let hidden = self.children.len().saturating_sub(1) as i32;
+                // the rows take at most half of the container
+                let row = if hidden > 0 {
+                    insets.stack_row.min(area.size.h / 2 / hidden)
+                } else {
+                    0
+                };
+                let reserved = row * hidden;
+                if visible && row > 0 {
+                    let mut y = area.loc.y;
+                    for i in (0..self.children.len()).filter(|i| *i != focus) {
+                        self.rows
+                            .push((i, Rectangle::from_loc_and_size((area.loc.x, y), (area.size.w, row))));
+                        y += row;
+                    }
+                }
+                let content = Rectangle::from_loc_and_size(
+                    (area.loc.x, area.loc.y + reserved),
+                    (area.size.w, area.size.h - reserved),
+                );
+                for (i, child) in self.children.iter_mut().enumerate() {
+                    child.arrange(content, visible && i == focus, insets);
+                }
+            }
+        }
+    }
+
+    fn save(&self) -> SavedNode {
+        SavedNode::Container {
+            layout: self.layout,
+            ratios: self.ratios.clone(),
+            children: self.children.iter().map(Node::save).collect(),
+        }
+    }
+
+    /// Returns the indices leading from this container to its focused leaf
+    fn focused_path(&self) -> Vec<usize> {
+        let mut path = Vec::new();
+        let mut container = self;
+        while !container.children.is_empty() {
+            path.push(container.focus);
+            match &container.children[container.focus] {
+                Node::Container(child) => container = child,
+                _ => break,
+            }
+        }
+        path
+    }
+
+    fn container(&self, path: &[usize]) -> Option<&Container> {
+        let mut container = self;
+        for &idx in path {
+            match container.children.get(idx) {
+                Some(Node::Container(child)) => container = child,
+                _ => return None,
+            }
+        }
+        Some(container)
+    }
+
+    fn container_mut(&mut self, path: &[usize]) -> Option<&mut Container> {
+        let mut container = self;
+        for &idx in path {
+            match container.children.get_mut(idx) {
+                Some(Node::Container(child)) => container = child,
+                _ => return None,
+            }
+        }
+        Some(container)
+    }
+
+    fn leaf(&self, path: &[usize]) -> Option<&Leaf> {
+        let (idx, parent) = path.split_last()?;
+        match self.container(parent)?.children.get(*idx) {
+            Some(Node::Leaf(leaf)) => Some(leaf),
+            _ => None,
+        }
+    }
+
+    fn leaf_mut(&mut self, path: &[usize]) -> Option<&mut Leaf> {
+        let (idx, parent) = path.split_last()?;
+        match self.container_mut(parent)?.children.get_mut(*idx) {
+            Some(Node::Leaf(leaf)) => Some(leaf),
+            _ => None,
+        }
+    }
+
+    /// Marks every container along the path as focused
+    fn focus_path(&mut self, path: &[usize]) {
+        let mut container = self;
+        for &idx in path {
+            container.focus = idx;
+            match &mut container.children[idx] {
+                Node::Container(child) => container = child,
+                _ => break,
+            }
+        }
+    }
+
+    /// Finds the path of the first leaf matching the predicate
+    fn find<F>(&self, f: &F) -> Option<Vec<usize>>
+    where
+        F: Fn(&Window) -> bool,
+    {
+        for (i, child) in self.children.iter().enumerate() {
+            match child {
+                Node::Leaf(leaf) if f(&leaf.window) => return Some(vec![i]),
+                Node::Container(container) => {
+                    if let Some(mut path) = container.find(f) {
+                        path.insert(0, i);
+                        return Some(path);
+                    }
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+
+    /// Finds the path of the first placeholder accepting the given app_id
+    fn find_placeholder(&self, app_id: Option<&str>) -> Option<Vec<usize>> {
+        for (i, child) in self.children.iter().enumerate() {
+            match child {
+                Node::Placeholder(wanted) if wanted.is_none() || wanted.as_deref() == app_id => {
+                    return Some(vec![i])
+                }
+                Node::Container(container) => {
+                    if let Some(mut path) = container.find_placeholder(app_id) {
+                        path.insert(0, i);
+                        return Some(path);
+                    }
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+
+    fn node_mut(&mut self, path: &[usize]) -> Option<&mut Node> {
+        let (idx, parent) = path.split_last()?;
+        self.container_mut(parent)?.children.get_mut(*idx)
+    }
+
+    /// Removes the node at the given path and any container, that became empty
+    fn remove_path(&mut self, mut path: Vec<usize>) {
+        while let Some(idx) = path.pop() {
+            let parent = self.container_mut(&path).unwrap();
+            parent.remove(idx);
+            if !parent.children.is_empty() || path.is_empty() {
+                break;
+            }
+        }
+    }
+
+    fn leaves<'a>(&'a self, leaves: &mut Vec<&'a Leaf>) {
+        for child in self.children.iter() {
+            match child {
+                Node::Leaf(leaf) => leaves.push(leaf),
+                Node::Container(container) => container.leaves(leaves),
+                Node::Placeholder(_) => {}
+            }
+        }
+    }
+
+    /// Changes the share of a child of a split container by `step`, the others make up for it.
+    /// Returns if the container is split.
+    fn resize(&mut self, idx: usize, step: f64) -> bool {
+        if !matches!(self.layout, ContainerLayout::SplitH | ContainerLayout::SplitV) || self.children.len() < 2 {
+            return false;
+        }
+        let others = (self.ratios.len() - 1) as f64;
+        let old = self.ratios[idx];
+        let new = (old + step).max(MIN_SHARE).min(1.0 - MIN_SHARE * others);
+        for (i, ratio) in self.ratios.iter_mut().enumerate() {
+            *ratio = if i == idx {
+                new
+            } else if old < 1.0 {
+                *ratio * (1.0 - new) / (1.0 - old)
+            } else {
+                (1.0 - new) / others
+            };
+        }
+        normalize_ratios(&mut self.ratios);
+        true
+    }
+
+    /// Collects the title rows of stacked and tabbed containers, with the window last focused in each child
+    fn stacked_titles(&self, titles: &mut Vec<(Kind, Rectangle<i32, Logical>)>) {
+        for (idx, row) in self.rows.iter() {
+            let window = match self.children.get(*idx) {
+                Some(Node::Leaf(leaf)) => Some(&leaf.window),
+                Some(Node::Container(container)) => {
+                    container.leaf(&container.focused_path()).map(|leaf| &leaf.window)
+                }
+                _ => None,
+            };
+            if let Some(window) = window {
+                titles.push((window.toplevel.clone(), *row));
+            }
+        }
+        for child in self.children.iter() {
+            if let Node::Container(container) = child {
+                container.stacked_titles(titles);
+            }
+        }
+    }
+
+    /// Gives every child of this container and the ones below an equal share of the space
+    fn equalize(&mut self) {
+        let count = self.children.len() as f64;
+        for ratio in self.ratios.iter_mut() {
+            *ratio = 1.0 / count;
+        }
+        for child in self.children.iter_mut() {
+            if let Node::Container(container) = child {
+                container.equalize();
+            }
+        }
+    }
+
+    fn into_leaves(self, leaves: &mut Vec<Leaf>) {
+        for child in self.children {
+            match child {
+                Node::Leaf(leaf) => leaves.push(leaf),
+                Node::Container(container) => container.into_leaves(leaves),
+                Node::Placeholder(_) => {}
+            }
+        }
+    }
+}
+
+/// i3-like tiling layout
+///
+/// Windows are organized in a tree of containers, each of them
+/// either splitting its space between its children or only showing
+/// the focused one (tabbed and stacked).
+pub struct Tiling {
+    id: usize,
+    size: Size<i32, Logical>,
+    root: Container,
+    /// How many levels above the focused window are selected
+    selected: usize,
+    fullscreen: Option<Kind>,
+    /// Windows kept out of the tree, the topmost last
+    floating: Vec<Leaf>,
+    /// If the topmost floating window is focused instead of the focused window of the tree
+    floating_focus: bool,
+    /// Space taken by window decorations
+    insets: Insets,
+    mode: Mode,
+    /// Share of the space of the selected node it keeps, when a new window is opened next to it
+    split_ratio: f64,
+    /// Change of the share of the selected node by `resize grow` and `resize shrink`
+    resize_step: f64,
+}
+
+/// Smallest share of a split container a child is resized to
+const MIN_SHARE: f64 = 0.05;
+
+/// Bounds of the share of the workspace taken by the master area
+const MIN_MASTER_RATIO: f64 = 0.1;
+const MAX_MASTER_RATIO: f64 = 0.9;
+
+/// Change of the master area by `master grow` and `master shrink`
+const MASTER_RATIO_STEP: f64 = 0.05;
+
+/// Raises all shares to at least `MIN_SHARE`, treating invalid ones as the smallest share,
+/// and scales the others so they add up to 1.0 again
+fn normalize_ratios(ratios: &mut [f64]) {
+    let count = ratios.len() as f64;
+    if count * MIN_SHARE >= 1.0 {
+        for ratio in ratios.iter_mut() {
+            *ratio = 1.0 / count;
+        }
+        return;
+    }
+
+    // shares raised to the minimum are fixed, the rest is scaled into the remaining space,
+    // which may push further shares below the minimum
+    let mut fixed = vec![false; ratios.len()];
+    loop {
+        for (ratio, fixed) in ratios.iter_mut().zip(fixed.iter_mut()) {
+            if !ratio.is_finite() || *ratio < MIN_SHARE {
+                *ratio = MIN_SHARE;
+                *fixed = true;
+            }
+        }
+        let free: f64 = ratios.iter().zip(fixed.iter()).filter(|(_, fixed)| !**fixed).map(|(ratio, _)| ratio).sum();
+        if free <= 0.0 {
+            for ratio in ratios.iter_mut() {
+                *ratio = 1.0 / count;
+            }
+            return;
+        }
+        let space = 1.0 - MIN_SHARE * fixed.iter().filter(|fixed| **fixed).count() as f64;
+        let mut done = true;
+        for (ratio, _) in ratios.iter_mut().zip(fixed.iter()).filter(|(_, fixed)| !**fixed) {
+            *ratio *= space / free;
+            done &= *ratio >= MIN_SHARE;
+        }
+        if done {
+            return;
+        }
+    }
+}
+
+/// How the windows of the tree are arranged
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    /// Nested containers split by the user
+    Tree,
+    /// Every window takes the whole workspace, the tree is never split
+    Monocle,
+    /// dwm-like, the first `count` windows share the left `ratio` of the workspace
+    /// and the remaining ones are stacked on the right
+    MasterStack { ratio: f64, count: usize },
+}
+
+impl Mode {
+    /// If the windows stay in a single row below the root
+    fn is_flat(&self) -> bool {
+        *self != Mode::Tree
+    }
+}
+
+impl PartialEq for Tiling {
+    fn eq(&self, other: &Tiling) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Tiling {
+    pub fn new<S: Into<Size<i32, Logical>>>(size: S, insets: Insets, layout: ContainerLayout) -> Tiling {
+        Tiling {
+            id: ID_COUNTER.fetch_add(1, Ordering::SeqCst),
+            size: size.into(),
+            root: Container::new(layout),
+            selected: 0,
+            fullscreen: None,
+            floating: Vec::new(),
+            floating_focus: false,
+            insets,
+            mode: Mode::Tree,
+            split_ratio: 0.5,
+            resize_step: 0.05,
+        }
+    }
+
+    /// Overrides how new windows divide the space and how much `resize` changes it
+    pub fn with_ratios(self, split_ratio: f64, resize_step: f64) -> Tiling {
+        Tiling {
+            split_ratio: split_ratio.max(MIN_SHARE).min(1.0 - MIN_SHARE),
+            resize_step: resize_step.abs(),
+            ..self
+        }
+    }
+
+    /// A single row of windows each taking the whole workspace, cycled through by the focus commands
+    pub fn monocle<S: Into<Size<i32, Logical>>>(size: S, insets: Insets) -> Tiling {
+        Tiling {
+            mode: Mode::Monocle,
+            ..Tiling::new(size, insets, ContainerLayout::Tabbed)
+        }
+    }
+
+    /// A single row of windows split into a master area on the left and a stack on the right
+    pub fn master_stack<S: Into<Size<i32, Logical>>>(size: S, insets: Insets, ratio: f64, count: usize) -> Tiling {
+        Tiling {
+            mode: Mode::MasterStack {
+                ratio: ratio.max(MIN_MASTER_RATIO).min(MAX_MASTER_RATIO),
+                count: count.max(1),
+            },
+            ..Tiling::new(size, insets, ContainerLayout::SplitH)
+        }
+    }
+
+    fn arrange(&mut self) {
+        profile_span!("arrange", layout = "tiling");
+        let area = Rectangle::from_loc_and_size((0, 0), self.size);
+        let fullscreen = self.fullscreen.clone();
+
+        match self.mode {
+            Mode::MasterStack { ratio, count } => self.arrange_master_stack(area, fullscreen.is_none(), ratio, count),
+            _ => self.root.arrange(area, fullscreen.is_none(), self.insets),
+        }
+        // floating windows are centered, taking half of the workspace
+        let floating_area = Rectangle::from_loc_and_size(
+            (self.size.w / 4, self.size.h / 4),
+            (self.size.w / 2, self.size.h / 2),
+        );
+        for leaf in self.floating.iter_mut() {
+            leaf.configure(floating_area, fullscreen.is_none(), self.insets);
+        }
+        if let Some(leaf) = fullscreen.and_then(|toplevel| self.leaf_mut(&toplevel)) {
+            // fullscreen windows are shown without any decorations
+            leaf.configure(area, true, Insets::default());
+        }
+    }
+
+    fn arrange_master_stack(&mut self, area: Rectangle<i32, Logical>, visible: bool, ratio: f64, count: usize) {
+        let masters = count.min(self.root.children.len());
+        let stacked = self.root.children.len() - masters;
+        // without a stack the masters take the whole workspace
+        let master_width = if stacked == 0 {
+            area.size.w
+        } else {
+            (area.size.w as f64 * ratio).round() as i32
+        };
+        let master_area = Rectangle::from_loc_and_size(area.loc, (master_width, area.size.h));
+        let stack_area = Rectangle::from_loc_and_size(
+            (area.loc.x + master_width, area.loc.y),
+            (area.size.w - master_width, area.size.h),
+        );
+        let insets = self.insets;
+        for (i, child) in self.root.children.iter_mut().enumerate() {
+            let rect = if i < masters {
+                row(master_area, i, masters)
+            } else {
+                row(stack_area, i - masters, stacked)
+            };
+            child.arrange(rect, visible, insets);
+        }
+    }
+
+    /// Finds the leaf of a window, tiled or floating
+    fn leaf(&self, surface: &Kind) -> Option<&Leaf> {
+        match self.root.find(&|w| &w.toplevel == surface) {
+            Some(path) => self.root.leaf(&path),
+            None => self.floating.iter().find(|leaf| &leaf.window.toplevel == surface),
+        }
+    }
+
+    fn leaf_mut(&mut self, surface: &Kind) -> Option<&mut Leaf> {
+        match self.root.find(&|w| &w.toplevel == surface) {
+            Some(path) => self.root.leaf_mut(&path),
+            None => self.floating.iter_mut().find(|leaf| &leaf.window.toplevel == surface),
+        }
+    }
+
+    /// Tiled leaves followed by the floating ones, from bottom to top
+    fn all_leaves(&self) -> Vec<&Leaf> {
+        let mut leaves = Vec::new();
+        self.root.leaves(&mut leaves);
+        leaves.extend(self.floating.iter());
+        leaves
+    }
+
+    /// Inserts a node next to the selected one and focuses it
+    fn insert_selected(&mut self, node: Node) {
+        let path = self.selected_path();
+        match path.split_last() {
+            Some((idx, parent_path)) => {
+                let parent = self.root.container_mut(parent_path).unwrap();
+                parent.insert(idx + 1, node);
+                parent.focus = idx + 1;
+                // the new window takes its part of the space from the selected node
+                let share = parent.ratios[*idx] + parent.ratios[idx + 1];
+                parent.ratios[*idx] = share * self.split_ratio;
+                parent.ratios[idx + 1] = share * (1.0 - self.split_ratio);
+            }
+            None => {
+                let idx = self.root.children.len();
+                self.root.insert(idx, node);
+                self.root.focus = idx;
+            }
+        }
+        self.selected = 0;
+        self.floating_focus = false;
+    }
+
+    fn update_activation(&mut self) {
+        let focused = self.focused_window();
+        for leaf in self.all_leaves() {
+            leaf.window
+                .toplevel
+                .set_activated(Some(&leaf.window.toplevel) == focused.as_ref());
+        }
+        if let Some(focused) = focused {
+            self.set_urgent(&focused, false);
+        }
+    }
+
+    /// Path to the selected node, which is the focused window
+    /// or one of its parents after `focus parent`.
+    fn selected_path(&self) -> Vec<usize> {
+        let mut path = self.root.focused_path();
+        let len = path.len().saturating_sub(self.selected);
+        path.truncate(len);
+        path
+    }
+
+    fn split(&mut self, layout: ContainerLayout) {
+        let path = self.selected_path();
+        let (idx, parent_path) = match path.split_last() {
+            Some(x) => x,
+            None => {
+                self.root.layout = layout;
+                return;
+            }
+        };
+        let parent = self.root.container_mut(parent_path).unwrap();
+        if parent.children.len() == 1 {
+            parent.layout = layout;
+        } else {
+            let node = std::mem::replace(&mut parent.children[*idx], Node::Container(Container::new(layout)));
+            if let Node::Container(container) = &mut parent.children[*idx] {
+                container.insert(0, node);
+            }
+        }
+    }
+
+    fn set_layout(&mut self, layout: Option<ContainerLayout>) {
+        let mut path = self.selected_path();
+        path.pop();
+        let container = self.root.container_mut(&path).unwrap();
+        container.layout = match layout {
+            Some(layout) => layout,
+            // toggle split
+            None => match container.layout {
+                ContainerLayout::SplitH => ContainerLayout::SplitV,
+                _ => ContainerLayout::SplitH,
+            },
+        };
+    }
+
+    /// Moves a window into a matching placeholder, returns if one was found
+    fn place(&mut self, path: &[usize]) -> bool {
+        let leaf = self.root.leaf_mut(path).unwrap();
+        leaf.placed = true;
+        let toplevel = leaf.window.toplevel.clone();
+        let target = match self.root.find_placeholder(toplevel.app_id().as_deref()) {
+            Some(target) => target,
+            None => return false,
+        };
+
+        // swap the window with the placeholder and drop the latter,
+        // this keeps both paths valid until the removal
+        let node = std::mem::replace(self.root.node_mut(path).unwrap(), Node::Placeholder(None));
+        *self.root.node_mut(&target).unwrap() = node;
+        self.root.remove_path(path.to_vec());
+
+        let path = self.root.find(&|w| w.toplevel == toplevel).unwrap();
+        self.root.focus_path(&path);
+        self.selected = 0;
+        true
+    }
+
+    /// Replaces the arrangement with a saved one, existing windows fill
+    /// matching placeholders or are appended to the new tree.
+    fn restore_tree(&mut self, saved: SavedNode) {
+        let root = match Node::from_saved(saved) {
+            Node::Container(container) => container,
+            node => {
+                let mut container = Container::new(ContainerLayout::SplitH);
+                container.insert(0, node);
+                container
+            }
+        };
+        let old = std::mem::replace(&mut self.root, root);
+        let mut leaves = Vec::new();
+        old.into_leaves(&mut leaves);
+
+        for leaf in leaves {
+            let idx = self.root.children.len();
+            self.root.insert(idx, Node::Leaf(leaf));
+            self.place(&[idx]);
+        }
+        self.selected = 0;
+    }
+
+    /// Swaps the focused window with the first master, or the first master with the next window,
+    /// and focuses the new master
+    fn promote(&mut self) {
+        let focus = self.root.focus;
+        if self.root.children.len() < 2 {
+            return;
+        }
+        let target = if focus == 0 { 1 } else { 0 };
+        self.root.children.swap(focus, target);
+        self.root.focus = 0;
+        self.selected = 0;
+    }
+
+    /// Grows or shrinks the master area of a master/stack workspace and changes how many windows it holds
+    fn adjust_master(&mut self, ratio_step: f64, count_step: isize) {
+        if let Mode::MasterStack { ratio, count } = &mut self.mode {
+            *ratio = (*ratio + ratio_step).max(MIN_MASTER_RATIO).min(MAX_MASTER_RATIO);
+            *count = (*count as isize + count_step).max(1) as usize;
+        }
+    }
+
+    /// Focuses the next or previous window of a flat workspace, wrapping around at the ends
+    fn cycle(&mut self, offset: isize) {
+        let count = self.root.children.len() as isize;
+        if count == 0 {
+            return;
+        }
+        self.root.focus = (self.root.focus as isize + offset).rem_euclid(count) as usize;
+        self.selected = 0;
+    }
+
+    /// Resizes the selected node within the closest split container around it
+    fn resize_selected(&mut self, step: f64) {
+        let path = self.selected_path();
+        for depth in (0..path.len()).rev() {
+            let container = self.root.container_mut(&path[..depth]).unwrap();
+            if container.resize(path[depth], step) {
+                return;
+            }
+        }
+    }
+
+    /// Exchanges the focused window with the next or previous one of a flat workspace, wrapping around at the ends
+    fn swap_cycle(&mut self, offset: isize) {
+        let count = self.root.children.len() as isize;
+        if count < 2 {
+            return;
+        }
+        let focus = self.root.focus;
+        let target = (focus as isize + offset).rem_euclid(count) as usize;
+        self.root.children.swap(focus, target);
+        self.root.focus = target;
+        self.selected = 0;
+    }
+
+    /// Exchanges the focused window with the window `focus` would move to,
+    /// which may be in another container. The window keeps the focus.
+    fn swap_direction(&mut self, direction: Direction) {
+        let path = self.root.focused_path();
+        if self.root.leaf(&path).is_none() {
+            return;
+        }
+        for depth in (0..path.len()).rev() {
+            let container = self.root.container(&path[..depth]).unwrap();
+            if container.layout.is_horizontal() != direction.is_horizontal() {
+                continue;
+            }
+            let idx = path[depth] as isize + direction.offset();
+            if idx < 0 || idx as usize >= container.children.len() {
+                continue;
+            }
+            // the window last focused within the neighbouring node
+            let mut target = path[..depth].to_vec();
+            target.push(idx as usize);
+            while let Some(container) = self.root.container(&target) {
+                if container.children.is_empty() {
+                    return;
+                }
+                target.push(container.focus);
+            }
+
+            // both paths part at `depth`, so neither contains the other
+            let node = std::mem::replace(self.root.node_mut(&path).unwrap(), Node::Placeholder(None));
+            let other = std::mem::replace(self.root.node_mut(&target).unwrap(), node);
+            *self.root.node_mut(&path).unwrap() = other;
+            self.root.focus_path(&target);
+            self.selected = 0;
+            return;
+        }
+    }
+
+    fn focus_direction(&mut self, direction: Direction) {
+        let path = self.root.focused_path();
+        for depth in (0..path.len()).rev() {
+            let container = self.root.container_mut(&path[..depth]).unwrap();
+            if container.layout.is_horizontal() != direction.is_horizontal() {
+                continue;
+            }
+            let idx = path[depth] as isize + direction.offset();
+            if idx >= 0 && (idx as usize) < container.children.len() {
+                container.focus = idx as usize;
+                self.selected = 0;
+                return;
+            }
+        }
+    }
+}
+
+impl Layout for Tiling {
+    fn id(&self) -> usize {
+        self.id
+    }
+
+    fn new_toplevel(&mut self, surface: Kind) {
+        // open new windows next to the selected node
+        self.insert_selected(Node::Leaf(Leaf::new(Window::new(None, None, surface))));
+
+        self.arrange();
+        self.update_activation();
+    }
+
+    fn remove_toplevel(&mut self, surface: Kind) {
+        let path = self.root.find(&|w| w.toplevel == surface);
+        let floating = self.floating.iter().position(|leaf| leaf.window.toplevel == surface);
+        if path.is_none() && floating.is_none() {
+            return;
+        }
+        if self.fullscreen.as_ref() == Some(&surface) {
+            self.fullscreen = None;
+        }
+
+        match (path, floating) {
+            // remove the window and any container, that became empty
+            (Some(path), _) => {
+                self.root.remove_path(path);
+                self.selected = 0;
+            }
+            (None, Some(idx)) => {
+                self.floating.remove(idx);
+                self.floating_focus &= !self.floating.is_empty();
+            }
+            (None, None) => {}
+        }
+
+        self.arrange();
+        self.update_activation();
+    }
+
+    fn move_request(
+        &mut self,
+        _surface: Kind,
+        _seat: &Seat,
+        _serial: Serial,
+        _start_data: GrabStartData,
+    ) {
+        // tiled windows are placed by the layout
+    }
+
+    fn resize_request(
+        &mut self,
+        _surface: Kind,
+        _seat: &Seat,
+        _serial: Serial,
+        _start_data: GrabStartData,
+        _edges: xdg_toplevel::ResizeEdge,
+    ) {
+        // tiled windows are sized by the layout
+    }
+
+    fn ack_configure(&mut self, _surface: wl_surface::WlSurface, _configure: ToplevelConfigure) {}
+
+    fn commit(&mut self, surface: Kind) {
+        if let Some(path) = self.root.find(&|w| w.toplevel == surface) {
+            // the app_id is known by now, check for a saved place
+            if !self.root.leaf(&path).unwrap().placed && self.place(&path) {
+                self.arrange();
+                self.update_activation();
+                return;
+            }
+            // the geometry might have changed with this commit
+            self.root.leaf_mut(&path).unwrap().update_location();
+        } else if let Some(leaf) = self.floating.iter_mut().find(|leaf| leaf.window.toplevel == surface) {
+            leaf.update_location();
+        }
+    }
+
+    fn fullscreen_request(&mut self, surface: Kind, state: bool) {
+        if self.leaf(&surface).is_none() {
+            // only tiled windows are made fullscreen, but the client expects a configure
+            #[allow(irrefutable_let_patterns)]
+            if let Kind::Xdg(xdg_surface) = surface {
+                xdg_surface.send_configure();
+            }
+            return;
+        }
+
+        if state {
+            if let Some(previous) = self.fullscreen.replace(surface.clone()) {
+                #[allow(irrefutable_let_patterns)]
+                if let Kind::Xdg(xdg_surface) = previous {
+                    let _ = xdg_surface.with_pending_state(|state| {
+                        state.states.unset(xdg_toplevel::State::Fullscreen);
+                        state.fullscreen_output = None;
+                    });
+                }
+            }
+            #[allow(irrefutable_let_patterns)]
+            if let Kind::Xdg(ref xdg_surface) = surface {
+                let _ = xdg_surface.with_pending_state(|state| {
+                    state.states.set(xdg_toplevel::State::Fullscreen);
+                });
+            }
+        } else {
+            if self.fullscreen.as_ref() == Some(&surface) {
+                self.fullscreen = None;
+            }
+            #[allow(irrefutable_let_patterns)]
+            if let Kind::Xdg(ref xdg_surface) = surface {
+                let _ = xdg_surface.with_pending_state(|state| {
+                    state.states.unset(xdg_toplevel::State::Fullscreen);
+                    state.fullscreen_output = None;
+                });
+            }
+        }
+
+        self.arrange();
+        #[allow(irrefutable_let_patterns)]
+        if let Kind::Xdg(xdg_surface) = surface {
+            xdg_surface.send_configure();
+        }
+    }
+
+    fn maximize_request(&mut self, surface: Kind, _state: bool) {
+        // tiled windows cannot be maximized, but the client expects a configure
+        #[allow(irrefutable_let_patterns)]
+        if let Kind::Xdg(xdg_surface) = surface {
+            xdg_surface.send_configure();
+        }
+    }
+
+    fn minimize_request(&mut self, surface: Kind) {
+        #[allow(irrefutable_let_patterns)]
+        if let Kind::Xdg(xdg_surface) = surface {
+            xdg_surface.send_configure();
+        }
+    }
+
+    fn set_floating(&mut self, surface: &Kind, floating: bool) {
+        if floating {
+            let path = match self.root.find(&|w| &w.toplevel == surface) {
+                Some(path) => path,
+                None => return,
+            };
+            let node = std::mem::replace(self.root.node_mut(&path).unwrap(), Node::Placeholder(None));
+            self.root.remove_path(path);
+            self.selected = 0;
+            if let Node::Leaf(leaf) = node {
+                self.floating.push(leaf);
+                self.floating_focus = true;
+            }
+        } else {
+            let idx = match self.floating.iter().position(|leaf| &leaf.window.toplevel == surface) {
+                Some(idx) => idx,
+                None => return,
+            };
+            let leaf = self.floating.remove(idx);
+            self.insert_selected(Node::Leaf(leaf));
+        }
+
+        self.arrange();
+        self.update_activation();
+    }
+
+    fn command(&mut self, command: &str) -> bool {
+        let args = command.split_whitespace().collect::<Vec<_>>();
+        match args.as_slice() {
+            // the windows of monocle and master/stack workspaces stay in a single row
+            ["split", ..] | ["layout", ..] | ["resize", ..] | ["focus", "parent"] | ["focus", "child"]
+                if self.mode.is_flat() =>
+            {
+                return true
+            }
+            ["focus", "left"] | ["focus", "up"] | ["focus", "prev"] if self.mode.is_flat() => self.cycle(-1),
+            ["focus", "right"] | ["focus", "down"] | ["focus", "next"] if self.mode.is_flat() => self.cycle(1),
+            ["swap", "left"] | ["swap", "up"] if self.mode.is_flat() => self.swap_cycle(-1),
+            ["swap", "right"] | ["swap", "down"] if self.mode.is_flat() => self.swap_cycle(1),
+            ["promote"] if self.mode.is_flat() => self.promote(),
+            ["master", "grow"] => self.adjust_master(MASTER_RATIO_STEP, 0),
+            ["master", "shrink"] => self.adjust_master(-MASTER_RATIO_STEP, 0),
+            ["master", "add"] => self.adjust_master(0.0, 1),
+            ["master", "remove"] => self.adjust_master(0.0, -1),
+            ["split", "h"] | ["split", "horizontal"] => self.split(ContainerLayout::SplitH),
+            ["split", "v"] | ["split", "vertical"] => self.split(ContainerLayout::SplitV),
+            ["layout", "splith"] => self.set_layout(Some(ContainerLayout::SplitH)),
+            ["layout", "splitv"] => self.set_layout(Some(ContainerLayout::SplitV)),
+            ["layout", "tabbed"] => self.set_layout(Some(ContainerLayout::Tabbed)),
+            ["layout", "stacking"] => self.set_layout(Some(ContainerLayout::Stacked)),
+            ["layout", "toggle", "split"] => self.set_layout(None),
+            ["equalize"] => self.root.equalize(),
+            ["resize", "grow"] => self.resize_selected(self.resize_step),
+            ["resize", "shrink"] => self.resize_selected(-self.resize_step),
+            ["focus", "parent"] => {
+                let depth = self.root.focused_path().len();
+                self.selected = (self.selected + 1).min(depth.saturating_sub(1));
+            }
+            ["focus", "child"] => {
+                self.selected = self.selected.saturating_sub(1);
+            }
+            ["focus", direction] => match Direction::from_name(direction) {
+                Some(direction) => self.focus_direction(direction),
+                None => return false,
+            },
+            ["swap", direction] if !self.floating_focus => match Direction::from_name(direction) {
+                Some(direction) => self.swap_direction(direction),
+                None => return false,
+            },
+            // floating windows have no place in the tree to swap with
+            ["swap", direction] if Direction::from_name(direction).is_some() => return true,
+            _ => return false,
+        }
+        // focus commands move within the tree
+        if args.first() == Some(&"focus") {
+            self.floating_focus = false;
+        }
+
+        self.arrange();
+        self.update_activation();
+        true
+    }
+
+    fn save(&self) -> Option<SavedNode> {
+        Some(self.root.save())
+    }
+
+    fn restore(&mut self, saved: SavedNode) -> bool {
+        // saved trees might be split
+        if self.mode.is_flat() {
+            return false;
+        }
+        self.restore_tree(saved);
+        self.arrange();
+        self.update_activation();
+        true
+    }
+
+    fn animates_moves(&self) -> bool {
+        true
+    }
+
+    fn is_empty(&self) -> bool {
+        self.root.children.is_empty() && self.floating.is_empty()
+    }
+
+    fn rearrange(&mut self, size: &Size<i32, Logical>) {
+        self.size = *size;
+        self.arrange();
+    }
+
+    fn set_insets(&mut self, insets: Insets) {
+        self.insets = insets;
+        self.arrange();
+    }
+
+    fn windows<'a>(&'a self) -> Box<dyn Iterator<Item = Kind> + 'a> {
+        Box::new(self.all_leaves().into_iter().map(|leaf| leaf.window.toplevel.clone()))
+    }
+
+    fn windows_from_bottom_to_top<'a>(
+        &'a self,
+    ) -> Box<dyn Iterator<Item = (Kind, Point<i32, Logical>, Rectangle<i32, Logical>)> + 'a> {
+        Box::new(self.all_leaves().into_iter().filter(|leaf| leaf.visible).flat_map(|leaf| {
+            leaf.window
+                .location()
+                .map(|location| (leaf.window.toplevel.clone(), location, leaf.window.bbox()))
+        }))
+    }
+
+    fn on_focus(&mut self, surface: &wl_surface::WlSurface) {
+        if let Some(path) = self.root.find(&|w| w.contains_surface(surface)) {
+            self.root.focus_path(&path);
+            self.selected = 0;
+            self.floating_focus = false;
+        } else if let Some(idx) = self.floating.iter().position(|leaf| leaf.window.contains_surface(surface)) {
+            // raise the window
+            let leaf = self.floating.remove(idx);
+            self.floating.push(leaf);
+            self.floating_focus = true;
+        } else {
+            return;
+        }
+        self.arrange();
+        self.update_activation();
+    }
+
+    fn opacity(&self, surface: &Kind) -> f32 {
+        self.leaf(surface)
+            .map(|leaf| leaf.window.opacity())
+            .unwrap_or(1.0)
+    }
+
+    fn set_opacity(&mut self, surface: &Kind, opacity: f32) {
+        if let Some(leaf) = self.leaf_mut(surface) {
+            leaf.window.set_opacity(opacity);
+        }
+    }
+
+    fn corner_radius(&self, surface: &Kind) -> Option<u32> {
+        self.leaf(surface)
+            .and_then(|leaf| leaf.window.corner_radius())
+    }
+
+    fn set_corner_radius(&mut self, surface: &Kind, radius: Option<u32>) {
+        if let Some(leaf) = self.leaf_mut(surface) {
+            leaf.window.set_corner_radius(radius);
+        }
+    }
+
+    fn blur(&self, surface: &Kind) -> bool {
+        self.leaf(surface)
+            .map(|leaf| leaf.window.blur())
+            .unwrap_or(false)
+    }
+
+    fn set_blur(&mut self, surface: &Kind, blur: bool) {
+        if let Some(leaf) = self.leaf_mut(surface) {
+            leaf.window.set_blur(blur);
+        }
+    }
+
+    fn is_urgent(&self, surface: &Kind) -> bool {
+        self.leaf(surface)
+            .map(|leaf| leaf.window.is_urgent())
+            .unwrap_or(false)
+    }
+
+    fn set_urgent(&mut self, surface: &Kind, urgent: bool) {
+        if let Some(leaf) = self.leaf_mut(surface) {
+            leaf.window.set_urgent(urgent);
+        }
+    }
+
+    fn is_decorated(&self, surface: &Kind) -> bool {
+        self.leaf(surface)
+            .map(|leaf| leaf.window.is_decorated())
+            .unwrap_or(true)
+    }
+
+    fn set_decorated(&mut self, surface: &Kind, decorated: bool) {
+        if let Some(leaf) = self.leaf_mut(surface) {
+            leaf.window.set_decorated(decorated);
+        }
+        self.arrange();
+    }
+
+    fn stacked_titles(&self) -> Vec<(Kind, Rectangle<i32, Logical>)> {
+        let mut titles = Vec::new();
+        self.root.stacked_titles(&mut titles);
+        titles
+    }
+
+    fn focused_window(&self) -> Option<Kind> {
+        if let Some(fullscreen) = self.fullscreen.as_ref() {
+            return Some(fullscreen.clone());
+        }
+        if self.floating_focus {
+            if let Some(leaf) = self.floating.last() {
+                return Some(leaf.window.toplevel.clone());
+            }
+        }
+        self.root
+            .leaf(&self.root.focused_path())
+            .map(|leaf| leaf.window.toplevel.clone())
+    }
+
+    fn surface_under(
+        &mut self,
+        point: Point<f64, Logical>,
+    ) -> Option<(wl_surface::WlSurface, Point<i32, Logical>)> {
+        self.all_leaves()
+            .into_iter()
+            .rev()
+            .filter(|leaf| leaf.visible)
+            .find_map(|leaf| leaf.window.matching(point))
+    }
+}
+
+/// The `idx`th of `count` rows of equal height, dividing `column`
+fn row(column: Rectangle<i32, Logical>, idx: usize, count: usize) -> Rectangle<i32, Logical> {
+    let top = column.size.h * idx as i32 / count as i32;
+    let bottom = column.size.h * (idx as i32 + 1) / count as i32;
+    Rectangle::from_loc_and_size((column.loc.x, column.loc.y + top), (column.size.w, bottom - top))
+}
@@ -17,9 +17,11 @@ use smithay::{
             BufferAssignment, SurfaceAttributes, TraversalAction,
         },
         seat::Seat,
+        xdg_activation::{init_xdg_activation_global, XdgActivationEvent},
         shell::{
             wlr_layer::{LayerShellRequest, LayerSurfaceAttributes},
             xdg::{
+                decoration::{init_xdg_decoration_manager, XdgDecorationRequest},
                 xdg_shell_init, Configure, ShellState as XdgShellState,
                 XdgPopupSurfaceRoleAttributes, XdgRequest, XdgToplevelSurfaceRoleAttributes,
             },
@@ -29,6 +31,7 @@ use smithay::{
 };
 
 //pub mod layer;
+pub mod decoration;
 pub mod layout;
 pub mod output;
 pub mod window;
@@ -41,8 +44,9 @@ use self::{
 };
 use crate::{
     backend::render::BufferTextures,
+    config::{Config, WindowSettings},
     state::Fireplace,
-    wayland::EGLStream,
+    wayland::{take_sync_points, EGLStream, SyncPoint},
 };
 
 #[derive(Clone)]
@@ -52,7 +56,7 @@ pub struct ShellHandles {
     pub popups: Rc<RefCell<Vec<PopupKind>>>,
 }
 
-pub fn init_shell(display: Rc<RefCell<Display>>) -> ShellHandles {
+pub fn init_shell(display: Rc<RefCell<Display>>, config: &Config) -> ShellHandles {
     // Create the compositor
     compositor_init(
         &mut *display.borrow_mut(),
@@ -60,13 +64,13 @@ pub fn init_shell(display: Rc<RefCell<Display>>) -> ShellHandles {
             let state = ddata.get::<Fireplace>().unwrap();
             let mut workspaces = state.workspaces.borrow_mut();
             let mut popups = state.popups.borrow_mut();
-            surface_commit(&surface, &mut *workspaces, &mut *popups)
+            surface_commit(&surface, &mut *workspaces, &mut *popups, &state.config)
         },
         None,
     );
 
     let popups = Rc::new(RefCell::new(Vec::new()));
-    let workspaces = Rc::new(RefCell::new(Workspaces::new(display.clone())));
+    let workspaces = Rc::new(RefCell::new(Workspaces::new(display.clone(), config)));
 
     // init the xdg_shell
     let (xdg_shell_state, _, _) = xdg_shell_init(
@@ -165,9 +169,8 @@ pub fn init_shell(display: Rc<RefCell<Display>>) -> ShellHandles {
                 XdgRequest::Fullscreen {
                     surface, output, ..
                 } => {
-                    // NOTE: This is only one part of the solution. We can set the
-                    // location and configure size here, but the surface should be rendered fullscreen
-                    // independently from its buffer size
+                    // Move the window to the requested output first, the layout
+                    // then takes care of sizing and stacking it.
                     if let Some(wl_surface) = surface.get_surface() {
                         let toplevel = SurfaceKind::Xdg(surface.clone());
                         if let Some(space) = if let Some(output) = output {
@@ -233,6 +236,51 @@ pub fn init_shell(display: Rc<RefCell<Display>>) -> ShellHandles {
         None,
     );
 
+    // focus is never passed on by activation requests,
+    // the window is flagged as urgent instead
+    init_xdg_activation_global(
+        &mut *display.borrow_mut(),
+        move |_activation_state, event, mut ddata| {
+            if let XdgActivationEvent::RequestActivation { surface, .. } = event {
+                let state = ddata.get::<Fireplace>().unwrap();
+                let client = surface.as_ref().client();
+                let focused = state.seats.iter().any(|seat| {
+                    match (seat.get_keyboard(), client.as_ref()) {
+                        (Some(keyboard), Some(client)) => keyboard.has_focus(client),
+                        _ => false,
+                    }
+                });
+                if !focused {
+                    state.workspaces.borrow_mut().set_urgent(&surface);
+                }
+            }
+        },
+        None,
+    );
+
+    // ask clients to leave the decorations to us, if we draw title bars
+    if config.decoration.title_bar.enabled {
+        use smithay::reexports::wayland_protocols::unstable::xdg_decoration::v1::server::zxdg_toplevel_decoration_v1::Mode;
+
+        init_xdg_decoration_manager(
+            &mut *display.borrow_mut(),
+            move |request, _ddata| {
+                let toplevel = match request {
+                    XdgDecorationRequest::NewToplevelDecoration { toplevel } => toplevel,
+                    XdgDecorationRequest::SetMode { toplevel, .. } => toplevel,
+                    XdgDecorationRequest::UnsetMode { toplevel } => toplevel,
+                };
+                let ret = toplevel.with_pending_state(|state| {
+                    state.decoration_mode = Some(Mode::ServerSide);
+                });
+                if ret.is_ok() {
+                    toplevel.send_configure();
+                }
+            },
+            None,
+        );
+    }
+
     /*
     smithay::wayland::shell::wlr_layer::wlr_layer_shell_init(
         &mut *display.borrow_mut(),
@@ -272,6 +320,75 @@ pub fn init_shell(display: Rc<RefCell<Display>>) -> ShellHandles {
     }
 }
 
+/// Applies the settings of matching `view.rules`, whenever they change with the app_id or title
+///
+/// Settings no longer set by any matching rule go back to their defaults.
+fn apply_rules(workspaces: &mut Workspaces, toplevel: &SurfaceKind, config: &Config) {
+    let surface = match toplevel.get_surface() {
+        Some(surface) => surface,
+        None => return,
+    };
+    let settings = config.window_settings(toplevel.app_id().as_deref(), toplevel.title().as_deref());
+    let previous = with_states(surface, |states| {
+        states
+            .data_map
+            .insert_if_missing(|| RefCell::new(WindowSettings::default()));
+        states
+            .data_map
+            .get::<RefCell<WindowSettings>>()
+            .unwrap()
+            .replace(settings.clone())
+    })
+    .unwrap_or_default();
+    if settings == previous {
+        return;
+    }
+    slog_scope::debug!("Applying {:?} to {:?}", settings, toplevel.app_id());
+
+    if let Some(space) = workspaces.space_by_surface(surface) {
+        if settings.decorations != previous.decorations {
+            space.set_decorated(toplevel, settings.decorations.unwrap_or(true));
+        }
+        if settings.opacity != previous.opacity {
+            space.set_opacity(toplevel, settings.opacity.unwrap_or(1.0));
+        }
+        if settings.corner_radius != previous.corner_radius {
+            space.set_corner_radius(toplevel, settings.corner_radius);
+        }
+        if settings.blur != previous.blur {
+            space.set_blur(toplevel, settings.blur.unwrap_or(false));
+        }
+        if settings.floating != previous.floating {
+            space.set_floating(toplevel, settings.floating.unwrap_or(false));
+        }
+    }
+    if settings.workspace != previous.workspace {
+        if let Some(name) = settings.workspace.as_deref() {
+            match workspaces.idx_by_name(name) {
+                Some(idx) => workspaces.move_toplevel(toplevel, idx),
+                None => slog_scope::warn!("Unknown workspace {} for {:?}", name, toplevel.app_id()),
+            }
+        }
+    }
+}
+
+/// Checks if a matching rule of `view.rules` keeps the outputs showing the window powered on
+pub fn inhibits_idle(toplevel: &SurfaceKind) -> bool {
+    toplevel
+        .get_surface()
+        .and_then(|surface| {
+            with_states(surface, |states| {
+                states
+                    .data_map
+                    .get::<RefCell<WindowSettings>>()
+                    .and_then(|settings| settings.borrow().inhibit_idle)
+            })
+            .ok()
+            .flatten()
+        })
+        .unwrap_or(false)
+}
+
 #[derive(Default)]
 pub struct SurfaceData {
     pub buffer: Option<wl_buffer::WlBuffer>,
@@ -279,11 +396,16 @@ pub struct SurfaceData {
     pub geometry: Option<Rectangle<i32, Logical>>,
     pub buffer_dimensions: Option<Size<i32, Physical>>,
     pub buffer_scale: i32,
+    /// Bumped whenever the contents change, to find out which parts of an output need to be redrawn
+    pub contents: u64,
+    /// Sync points of `buffer`, if the client uses explicit synchronization
+    pub sync_points: Option<(SyncPoint, SyncPoint)>,
     pub userdata: UserDataMap,
 }
 
 impl SurfaceData {
-    pub fn update_buffer(&mut self, attrs: &mut SurfaceAttributes) {
+    /// Takes over a newly attached buffer and its sync points, see `take_sync_points`
+    pub fn update_buffer(&mut self, attrs: &mut SurfaceAttributes, sync_points: Option<(SyncPoint, SyncPoint)>) {
         match attrs.buffer.take() {
             Some(BufferAssignment::NewBuffer { buffer, .. }) => {
                 // new contents
@@ -295,20 +417,29 @@ impl SurfaceData {
                 }
                 self.buffer_scale = attrs.buffer_scale;
                                 
+                let old_points = std::mem::replace(&mut self.sync_points, sync_points);
                 if let Some(old_buffer) = std::mem::replace(&mut self.buffer, Some(buffer)) {
-                    if &old_buffer != self.buffer.as_ref().unwrap() {
-                        old_buffer.release();
+                    match old_points {
+                        // never drawn, but the client still waits for the release point
+                        Some((_, release)) => release.release(old_buffer),
+                        None if &old_buffer != self.buffer.as_ref().unwrap() => old_buffer.release(),
+                        None => {}
                     }
                 }
                 self.texture = None;
+                self.contents = self.contents.wrapping_add(1);
             }
             Some(BufferAssignment::Removed) => {
                 // remove the contents
                 if let Some(buffer) = self.buffer.take() {
-                    buffer.release();
+                    match self.sync_points.take() {
+                        Some((_, release)) => release.release(buffer),
+                        None => buffer.release(),
+                    }
                 };
                 self.buffer_dimensions = None;
                 self.texture = None;
+                self.contents = self.contents.wrapping_add(1);
             }
             None => {}
         }
@@ -367,6 +498,7 @@ fn surface_commit(
     surface: &wl_surface::WlSurface,
     workspaces: &mut Workspaces,
     popups: &mut Vec<PopupKind>,
+    config: &Config,
 ) {
     #[cfg(feature = "xwayland")]
     super::xwayland::commit_hook(surface);
@@ -386,7 +518,8 @@ fn surface_commit(
                     .get::<RefCell<SurfaceData>>()
                     .unwrap()
                     .borrow_mut();
-                data.update_buffer(&mut *states.cached_state.current::<SurfaceAttributes>());
+                let sync_points = take_sync_points(states);
+                data.update_buffer(&mut *states.cached_state.current::<SurfaceAttributes>(), sync_points);
             },
             |_, _, _| true,
         );
@@ -415,6 +548,7 @@ fn surface_commit(
         if let Some(space) = workspaces.space_by_surface(surface) {
             space.commit(toplevel.clone());
         }
+        apply_rules(workspaces, &toplevel, config);
     }
 
     if let Some(popup) = popups.iter().find(|x| x.get_surface() == Some(surface)) {
@@ -36,6 +36,8 @@ pub struct Output {
     scale: f32,
     output_scale: i32,
     location: Point<i32, Logical>,
+    /// Location the output was explicitly placed at
+    preferred_location: Option<Point<i32, Logical>>,
     userdata: UserDataMap,
 }
 
@@ -87,6 +89,7 @@ impl Output {
             global: Some(global),
             output,
             location,
+            preferred_location: None,
             surfaces: Vec::new(),
             layer_surfaces: Default::default(),
             current_mode: mode,
@@ -124,10 +127,26 @@ impl Output {
         self.output.change_current_state(None, None, None, Some(to));
     }
 
+    pub fn preferred_location(&self) -> Option<Point<i32, Logical>> {
+        self.preferred_location
+    }
+
+    pub fn set_preferred_location(&mut self, to: Option<Point<i32, Logical>>) {
+        self.preferred_location = to;
+    }
+
     pub fn scale(&self) -> f32 {
         self.scale
     }
 
+    /// Overrides the scale guessed from the physical size
+    pub fn set_scale(&mut self, scale: f32) {
+        self.scale = scale;
+        self.output_scale = scale.ceil() as i32;
+        self.output
+            .change_current_state(None, None, Some(self.output_scale), None);
+    }
+
     pub fn name(&self) -> &str {
         self.name.as_str()
     }
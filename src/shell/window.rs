@@ -14,14 +14,18 @@ use smithay::{
         },
         shell::xdg::{
             PopupSurface, SurfaceCachedState, ToplevelSurface, XdgPopupSurfaceRoleAttributes,
+            XdgToplevelSurfaceRoleAttributes,
         },
     },
 };
 
-use super::SurfaceData;
+use super::{layout::next_id, SurfaceData};
 #[cfg(feature = "xwayland")]
 use crate::xwayland::X11Surface;
 
+/// Id of a toplevel surface, assigned on first use
+struct WindowId(usize);
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Kind {
     Xdg(ToplevelSurface),
@@ -68,6 +72,84 @@ impl Kind {
             Kind::Xdg(ref t) => t.send_close(),
         }
     }
+
+    /// Returns the window geometry set by the client relative to its surface, if any
+    pub fn geometry(&self) -> Option<Rectangle<i32, Logical>> {
+        self.get_surface().and_then(|wl_surface| {
+            with_states(wl_surface, |states| {
+                states.cached_state.current::<SurfaceCachedState>().geometry
+            })
+            .ok()
+            .flatten()
+        })
+    }
+
+    /// Returns the app_id set by the client, if any
+    pub fn app_id(&self) -> Option<String> {
+        self.get_surface().and_then(|wl_surface| {
+            with_states(wl_surface, |states| {
+                states
+                    .data_map
+                    .get::<Mutex<XdgToplevelSurfaceRoleAttributes>>()
+                    .unwrap()
+                    .lock()
+                    .unwrap()
+                    .app_id
+                    .clone()
+            })
+            .ok()
+            .flatten()
+        })
+    }
+
+    /// Returns an id unique among windows, layouts and outputs, stable for the lifetime of the window
+    pub fn id(&self) -> usize {
+        self.get_surface()
+            .and_then(|wl_surface| {
+                with_states(wl_surface, |states| {
+                    states.data_map.insert_if_missing(|| WindowId(next_id()));
+                    states.data_map.get::<WindowId>().unwrap().0
+                })
+                .ok()
+            })
+            .unwrap_or(0)
+    }
+
+    /// Returns the title set by the client, if any
+    pub fn title(&self) -> Option<String> {
+        self.get_surface().and_then(|wl_surface| {
+            with_states(wl_surface, |states| {
+                states
+                    .data_map
+                    .get::<Mutex<XdgToplevelSurfaceRoleAttributes>>()
+                    .unwrap()
+                    .lock()
+                    .unwrap()
+                    .title
+                    .clone()
+            })
+            .ok()
+            .flatten()
+        })
+    }
+
+    pub fn is_maximized(&self) -> bool {
+        match *self {
+            Kind::Xdg(ref t) => t
+                .current_state()
+                .map(|state| state.states.contains(xdg_toplevel::State::Maximized))
+                .unwrap_or(false),
+        }
+    }
+
+    pub fn is_fullscreen(&self) -> bool {
+        match *self {
+            Kind::Xdg(ref t) => t
+                .current_state()
+                .map(|state| state.states.contains(xdg_toplevel::State::Fullscreen))
+                .unwrap_or(false),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -131,6 +213,17 @@ impl PopupKind {
 pub struct Window {
     location: Option<Point<i32, Logical>>,
     size: Size<i32, Logical>,
+    /// Geometry to restore, while the window is fullscreen
+    fullscreen: Option<Rectangle<i32, Logical>>,
+    opacity: f32,
+    /// Overrides the configured corner radius
+    corner_radius: Option<u32>,
+    /// Whatever is behind the window gets blurred
+    blur: bool,
+    /// The client requested attention
+    urgent: bool,
+    /// Server-side decorations are drawn around the window
+    decorated: bool,
     pub toplevel: Kind,
 }
 
@@ -156,6 +249,12 @@ impl Window {
         let mut window = Window {
             location,
             size: size.unwrap_or((0, 0).into()),
+            fullscreen: None,
+            opacity: 1.0,
+            corner_radius: None,
+            blur: false,
+            urgent: false,
+            decorated: true,
             toplevel,
         };
         window
@@ -284,4 +383,61 @@ impl Window {
     pub fn set_location(&mut self, location: Point<i32, Logical>) {
         self.location = Some(location);
     }
+
+    pub fn opacity(&self) -> f32 {
+        self.opacity
+    }
+
+    /// Sets the opacity, clamped between 0.0 (invisible) and 1.0 (opaque)
+    pub fn set_opacity(&mut self, opacity: f32) {
+        self.opacity = opacity.max(0.0).min(1.0);
+    }
+
+    pub fn corner_radius(&self) -> Option<u32> {
+        self.corner_radius
+    }
+
+    pub fn set_corner_radius(&mut self, radius: Option<u32>) {
+        self.corner_radius = radius;
+    }
+
+    pub fn blur(&self) -> bool {
+        self.blur
+    }
+
+    pub fn set_blur(&mut self, blur: bool) {
+        self.blur = blur;
+    }
+
+    pub fn is_urgent(&self) -> bool {
+        self.urgent
+    }
+
+    pub fn set_urgent(&mut self, urgent: bool) {
+        self.urgent = urgent;
+    }
+
+    pub fn is_decorated(&self) -> bool {
+        self.decorated
+    }
+
+    pub fn set_decorated(&mut self, decorated: bool) {
+        self.decorated = decorated;
+    }
+
+    pub fn is_fullscreen(&self) -> bool {
+        self.fullscreen.is_some()
+    }
+
+    /// Marks the window as fullscreen, remembering the geometry to restore later on
+    pub fn set_fullscreen(&mut self, restore: Rectangle<i32, Logical>) {
+        if self.fullscreen.is_none() {
+            self.fullscreen = Some(restore);
+        }
+    }
+
+    /// Leaves fullscreen and returns the geometry the window had before
+    pub fn unset_fullscreen(&mut self) -> Option<Rectangle<i32, Logical>> {
+        self.fullscreen.take()
+    }
 }
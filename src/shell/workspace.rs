@@ -1,7 +1,10 @@
 use crate::handler::ActiveOutput;
 use std::{
     cell::{Cell, RefCell},
+    collections::HashMap,
+    path::PathBuf,
     rc::Rc,
+    time::Instant,
 };
 
 use linked_hash_map::LinkedHashMap;
@@ -10,76 +13,334 @@ use smithay::{
         protocol::{wl_output, wl_surface::WlSurface},
         Display,
     },
-    utils::{Logical, Size},
+    utils::{Logical, Point, Size},
     wayland::{
         output::{Mode, PhysicalProperties},
         seat::Seat,
     },
 };
 
-use crate::shell::{layout::Layout, output::Output, window::Kind};
+use crate::{
+    config::{Animations, Config, LayoutConfig},
+    shell::{decoration::Insets, layout::Layout, output::Output, window::Kind},
+};
+
+/// Identifies a workspace
+///
+/// Workspaces are numbered globally, unless every output
+/// has its own set, in which case `output` is set.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct WorkspaceId {
+    output: Option<String>,
+    idx: u32,
+}
 
 pub struct Workspaces {
     display: Rc<RefCell<Display>>,
-    spaces: LinkedHashMap<u8, Box<dyn Layout>>,
+    spaces: LinkedHashMap<WorkspaceId, Box<dyn Layout>>,
     outputs: Vec<Output>,
+    insets: Insets,
+    names: HashMap<u32, String>,
+    /// Every output has its own workspaces numbered from 1
+    per_output: bool,
+    /// Only populated workspaces and one trailing empty one are offered
+    dynamic: bool,
+    layouts: HashMap<String, LayoutConfig>,
+    /// Preferred outputs of workspaces by number or name
+    assignments: HashMap<String, String>,
+    /// Remembered output locations per set of connected outputs
+    arrangements: HashMap<String, HashMap<String, (i32, i32)>>,
+    /// Workspace last replaced on an output and when
+    switches: HashMap<String, (WorkspaceId, Instant)>,
+}
+
+/// Workspace an output showed before its current one, while transitioning between both
+pub struct Switch<'a> {
+    pub previous: &'a dyn Layout,
+    /// Eased progress of the transition between 0 and 1
+    pub progress: f32,
+    /// The new workspace has a higher number
+    pub forward: bool,
 }
 
-struct ActiveWorkspace(Cell<u8>);
+struct ActiveWorkspace(Cell<u32>);
 
 impl ActiveWorkspace {
-    fn new(val: u8) -> ActiveWorkspace {
+    fn new(val: u32) -> ActiveWorkspace {
         ActiveWorkspace(Cell::new(val))
     }
 }
 
 impl Workspaces {
-    pub fn new(display: Rc<RefCell<Display>>) -> Workspaces {
+    pub fn new(display: Rc<RefCell<Display>>, config: &Config) -> Workspaces {
         Workspaces {
             display,
             spaces: LinkedHashMap::new(),
             outputs: Vec::new(),
+            insets: Insets::new(&config.decoration),
+            names: config.workspace.names.clone(),
+            per_output: config.workspace.per_output,
+            dynamic: config.workspace.dynamic,
+            layouts: config.workspace.layouts.clone(),
+            assignments: config.workspace.outputs.clone(),
+            arrangements: load_arrangements(),
+            switches: HashMap::new(),
         }
     }
 
-    fn next_available(&mut self, size: Size<i32, Logical>) -> u8 {
-        for i in 1..::std::u8::MAX {
-            if let Some(space) = self.spaces.get_mut(&i) {
-                let mut available = true;
-                for output in &self.outputs {
-                    if output
-                        .userdata()
-                        .get::<ActiveWorkspace>()
-                        .map(|x| x.0.get() as i32)
-                        .unwrap()
-                        == i as i32
-                    {
-                        available = false;
-                    }
-                }
-                if available {
-                    space.rearrange(&size);
-                    return i;
-                }
+    /// Applies a changed workspace and decoration configuration
+    ///
+    /// Existing workspaces keep their layout, switching between per-output and global workspaces requires a restart.
+    pub fn apply_config(&mut self, config: &Config) {
+        if config.workspace.per_output != self.per_output {
+            slog_scope::warn!("Changing workspace.per_output requires a restart");
+        }
+        self.names = config.workspace.names.clone();
+        self.dynamic = config.workspace.dynamic;
+        self.layouts = config.workspace.layouts.clone();
+        self.assignments = config.workspace.outputs.clone();
+
+        let insets = Insets::new(&config.decoration);
+        if insets != self.insets {
+            self.insets = insets;
+            for (_, space) in self.spaces.iter_mut() {
+                space.set_insets(insets);
+            }
+        }
+    }
+
+    /// Returns the connected output a workspace is assigned to, if any
+    fn preferred_output(&self, idx: u32) -> Option<String> {
+        if self.per_output {
+            return None;
+        }
+        self.assignments
+            .iter()
+            .find(|(workspace, _)| self.idx_by_name(workspace) == Some(idx))
+            .map(|(_, output)| output.clone())
+            .filter(|output| self.outputs.iter().any(|o| o.name() == output))
+    }
+
+    /// Returns the first workspace assigned to the given output
+    fn assigned_workspace(&self, output: &str) -> Option<u32> {
+        if self.per_output {
+            return None;
+        }
+        self.assignments
+            .iter()
+            .filter(|(_, name)| *name == output)
+            .filter_map(|(workspace, _)| self.idx_by_name(workspace))
+            .min()
+    }
+
+    /// Makes the output the active one of the seat and moves the pointer onto it
+    fn focus_output(&mut self, seat: &Seat, name: &str) {
+        let output_name = &seat.user_data().get::<ActiveOutput>().unwrap().0;
+        if let Some(output) = self.output_by_name(name) {
+            *output_name.borrow_mut() = String::from(output.name());
+            if let Some(ptr) = seat.get_pointer() {
+                let (w, h) = output.size().into();
+                ptr.unset_grab();
+                ptr.motion((w as f64 / 2.0, h as f64 / 2.0).into(), None, 0.into(), 0);
+            }
+        }
+    }
+
+    /// Flags a window and thereby its workspace as requesting attention
+    pub fn set_urgent(&mut self, surface: &WlSurface) {
+        let toplevel = match self.toplevel_by_surface(surface) {
+            Some(toplevel) => toplevel,
+            None => return,
+        };
+        if let Some((id, space)) = self
+            .spaces
+            .iter_mut()
+            .find(|(_, space)| space.windows().any(|w| w == toplevel))
+        {
+            slog_scope::debug!("Window on workspace {} requests attention", id.idx);
+            space.set_urgent(&toplevel, true);
+        }
+    }
+
+    /// Checks if any window on the workspace requests attention
+    pub fn is_urgent(&self, idx: u32) -> bool {
+        self.spaces
+            .iter()
+            .filter(|(id, _)| id.idx == idx)
+            .any(|(_, space)| space.windows().any(|w| space.is_urgent(&w)))
+    }
+
+    /// Returns the layout configured for a workspace by number, name
+    /// or the `default` entry
+    fn layout_config(&self, idx: u32) -> LayoutConfig {
+        self.layouts
+            .get(&idx.to_string())
+            .or_else(|| self.names.get(&idx).and_then(|name| self.layouts.get(name)))
+            .or_else(|| self.layouts.get("default"))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Returns the workspace next to the active one of the seat
+    ///
+    /// Only populated workspaces are considered and in dynamic mode
+    /// an additional empty one after the last of them.
+    pub fn neighbour(&self, seat: &Seat, forward: bool) -> Option<u32> {
+        let output_name = seat.user_data().get::<ActiveOutput>()?.0.borrow().clone();
+        let current = self.idx_by_output_name(&output_name)?;
+        let output = self.id(&output_name, current).output;
+
+        let mut candidates = self
+            .spaces
+            .iter()
+            .filter(|(id, space)| id.output == output && (!space.is_empty() || id.idx == current))
+            .map(|(id, _)| id.idx)
+            .collect::<Vec<_>>();
+        if self.dynamic {
+            let trailing = candidates.iter().max().map(|idx| idx + 1).unwrap_or(1);
+            candidates.push(trailing);
+        }
+        candidates.sort_unstable();
+        candidates.dedup();
+
+        if forward {
+            candidates.into_iter().find(|idx| *idx > current)
+        } else {
+            candidates.into_iter().rev().find(|idx| *idx < current)
+        }
+    }
+
+    fn id<N: AsRef<str>>(&self, output: N, idx: u32) -> WorkspaceId {
+        WorkspaceId {
+            output: if self.per_output {
+                Some(String::from(output.as_ref()))
             } else {
-                self.spaces
-                    .insert(i, Box::new(super::layout::Floating::new(size)));
-                return i;
+                None
+            },
+            idx,
+        }
+    }
+
+    /// Checks if the workspace is currently shown on any output
+    fn is_visible(&self, id: &WorkspaceId) -> bool {
+        self.outputs.iter().any(|o| {
+            o.userdata().get::<ActiveWorkspace>().unwrap().0.get() == id.idx
+                && id.output.as_ref().map(|name| name == o.name()).unwrap_or(true)
+        })
+    }
+
+    /// Returns the configured name of a workspace or its number
+    pub fn name(&self, idx: u32) -> String {
+        self.names
+            .get(&idx)
+            .cloned()
+            .unwrap_or_else(|| idx.to_string())
+    }
+
+    /// Renames a workspace, an empty name resets it to its number
+    pub fn rename(&mut self, idx: u32, name: &str) {
+        slog_scope::debug!("Renaming workspace {} to {:?}", self.name(idx), name);
+        if name.is_empty() {
+            self.names.remove(&idx);
+        } else {
+            self.names.insert(idx, String::from(name));
+        }
+    }
+
+    /// Resolves a workspace by number or name
+    ///
+    /// Names match completely or by the part after the number,
+    /// e.g. "1:web" is reachable by "1", "1:web" and "web".
+    pub fn idx_by_name(&self, name: &str) -> Option<u32> {
+        if let Ok(idx) = name.parse::<u32>() {
+            return Some(idx);
+        }
+        self.names
+            .iter()
+            .find(|(idx, x)| {
+                *x == name
+                    || x.strip_prefix(&*idx.to_string())
+                        .map(|rest| rest.trim_start_matches(':') == name)
+                        .unwrap_or(false)
+            })
+            .map(|(idx, _)| *idx)
+    }
+
+    fn next_available(&mut self, output: &str, size: Size<i32, Logical>) -> u32 {
+        for i in 1..::std::u32::MAX {
+            let id = self.id(output, i);
+            if self.is_visible(&id) {
+                continue;
+            }
+            if let Some(space) = self.spaces.get_mut(&id) {
+                space.rearrange(&size);
+            } else {
+                let config = self.layout_config(i);
+                self.spaces.insert(id, super::layout::new_layout(size, self.insets, &config));
             }
+            return i;
         }
         0
     }
 
+    /// Identifies the set of currently connected outputs
+    fn arrangement_key(&self) -> String {
+        let mut names = self.outputs.iter().map(|o| o.name()).collect::<Vec<_>>();
+        names.sort_unstable();
+        names.join(",")
+    }
+
     pub fn arrange(&mut self) {
-        // Recalculate the outputs location
-        // TODO: handle vertical stacking monitors later
+        // Recalculate the outputs location,
+        // configured ones first, then those remembered for this set of outputs
+        let saved = self.arrangements.get(&self.arrangement_key()).cloned().unwrap_or_default();
+        let mut unplaced = Vec::new();
         let mut output_x = 0;
         for output in self.outputs.iter_mut() {
+            let location = output
+                .preferred_location()
+                .or_else(|| saved.get(output.name()).map(|&location| location.into()));
+            match location {
+                Some(location) => {
+                    output.set_location(location);
+                    output_x = output_x.max(location.x + output.size().w);
+                }
+                None => unplaced.push(output),
+            }
+        }
+        // the rest is lined up left to right, next to the others to avoid overlaps
+        for output in unplaced {
             output.set_location((output_x, 0).into());
             output_x += output.size().w;
         }
     }
 
+    /// Places an output at the configured location
+    pub fn set_preferred_location(&mut self, name: &str, location: Option<Point<i32, Logical>>) {
+        if let Some(output) = self.output_by_name(name) {
+            output.set_preferred_location(location);
+            self.arrange();
+        }
+    }
+
+    /// Moves an output and remembers its location for the current set of outputs
+    pub fn move_output(&mut self, name: &str, location: Point<i32, Logical>) {
+        if self.output_by_name(name).is_none() {
+            slog_scope::debug!("Unknown output: {}", name);
+            return;
+        }
+        let key = self.arrangement_key();
+        let arrangement = self.arrangements.entry(key).or_default();
+        // keep the others where they are
+        for output in self.outputs.iter() {
+            arrangement.insert(String::from(output.name()), output.location().into());
+        }
+        arrangement.insert(String::from(name), location.into());
+        save_arrangements(&self.arrangements);
+
+        self.set_preferred_location(name, Some(location));
+    }
+
     pub fn width(&self) -> i32 {
         self.outputs.iter().map(|x| x.size().w).sum()
     }
@@ -101,11 +362,24 @@ impl Workspaces {
             mode,
         );
         let logical_size = output.geometry().size;
-        let workspace = self.next_available(logical_size);
+        let assigned = self.assigned_workspace(output.name());
+        let workspace = match assigned {
+            Some(idx) => {
+                let insets = self.insets;
+                let config = self.layout_config(idx);
+                let id = self.id(output.name(), idx);
+                self.spaces
+                    .entry(id)
+                    .or_insert_with(|| super::layout::new_layout(logical_size, insets, &config))
+                    .rearrange(&logical_size);
+                idx
+            }
+            None => self.next_available(output.name(), logical_size),
+        };
         slog_scope::info!("New output: {:?}", output);
         slog_scope::debug!(
             "Attaching workspace {} to output {}",
-            workspace,
+            self.name(workspace),
             output.name()
         );
         output
@@ -113,6 +387,33 @@ impl Workspaces {
             .insert_if_missing(|| ActiveWorkspace::new(workspace));
         self.outputs.push(output);
 
+        // the assigned workspace migrates back from the output it was shown on
+        if let Some(idx) = assigned {
+            let others = self
+                .outputs
+                .iter()
+                .take(self.outputs.len() - 1)
+                .filter(|o| o.userdata().get::<ActiveWorkspace>().unwrap().0.get() == idx)
+                .map(|o| (String::from(o.name()), o.size()))
+                .collect::<Vec<_>>();
+            for (name, size) in others {
+                let replacement = self.next_available(&name, size);
+                slog_scope::debug!(
+                    "Workspace {} moves back to its output, {} takes its place on {}",
+                    self.name(idx),
+                    self.name(replacement),
+                    name
+                );
+                self.output_by_name(&name)
+                    .unwrap()
+                    .userdata()
+                    .get::<ActiveWorkspace>()
+                    .unwrap()
+                    .0
+                    .set(replacement);
+            }
+        }
+
         // We call arrange here albeit the output is only appended and
         // this would not affect windows, but arrange could re-organize
         // outputs from a configuration.
@@ -121,21 +422,92 @@ impl Workspaces {
         self.outputs.last().unwrap()
     }
 
+    /// Applies a new mode and optionally scale to an output and lays out its contents again
+    pub fn update_output_mode(&mut self, name: &str, mode: Mode, scale: Option<f32>) {
+        let output = match self.output_by_name(name) {
+            Some(output) => output,
+            None => return,
+        };
+        output.set_mode(mode);
+        if let Some(scale) = scale {
+            output.set_scale(scale);
+        }
+        let size = output.size();
+        if let Some(space) = self.space_by_output_name(name) {
+            space.rearrange(&size);
+        }
+        // the new size might overlap other outputs
+        self.arrange();
+    }
+
     pub fn retain_outputs<F>(&mut self, f: F)
     where
         F: Fn(&Output) -> bool,
     {
-        for output in self.outputs.iter().filter(|o| !f(*o)) {
-            let workspace = output.userdata().get::<ActiveWorkspace>().unwrap().0.get();
-            if self.spaces.get(&workspace).unwrap().is_empty() {
-                slog_scope::debug!("Destroying empty workspace: {}", workspace);
-                self.spaces.remove(&workspace);
+        let removed = self
+            .outputs
+            .iter()
+            .filter(|o| !f(*o))
+            .map(|o| {
+                let workspace = o.userdata().get::<ActiveWorkspace>().unwrap().0.get();
+                self.id(o.name(), workspace)
+            })
+            .collect::<Vec<_>>();
+        for id in removed {
+            if self.spaces.get(&id).map(|space| space.is_empty()).unwrap_or(false) {
+                slog_scope::debug!("Destroying empty workspace: {}", self.name(id.idx));
+                self.spaces.remove(&id);
             }
         }
         self.outputs.retain(f);
+        self.migrate_orphaned();
         self.arrange();
     }
 
+    /// Moves populated workspaces of outputs, that are gone, onto the first remaining output
+    ///
+    /// Only necessary if every output has its own workspaces,
+    /// global ones are reachable from any output.
+    fn migrate_orphaned(&mut self) {
+        if !self.per_output {
+            return;
+        }
+        let (target, size) = match self.outputs.first() {
+            Some(output) => (String::from(output.name()), output.size()),
+            None => return,
+        };
+        let orphaned = self
+            .spaces
+            .keys()
+            .filter(|id| {
+                id.output
+                    .as_ref()
+                    .map(|name| !self.outputs.iter().any(|o| o.name() == name))
+                    .unwrap_or(false)
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        for id in orphaned {
+            let mut space = self.spaces.remove(&id).unwrap();
+            if space.is_empty() {
+                continue;
+            }
+            let idx = (1..::std::u32::MAX)
+                .find(|idx| !self.spaces.contains_key(&self.id(&target, *idx)))
+                .unwrap();
+            slog_scope::debug!(
+                "Migrating workspace {} of {} to workspace {} of {}",
+                id.idx,
+                id.output.as_ref().unwrap(),
+                idx,
+                target
+            );
+            space.rearrange(&size);
+            let id = self.id(&target, idx);
+            self.spaces.insert(id, space);
+        }
+    }
+
     pub fn remove_output_by_name(&mut self, name: &str) {
         self.retain_outputs(|o| o.name() != name);
     }
@@ -144,6 +516,25 @@ impl Workspaces {
         self.outputs.len()
     }
 
+    pub fn outputs(&self) -> impl Iterator<Item = &Output> {
+        self.outputs.iter()
+    }
+
+    /// Returns the number of every existing workspace, the output showing it, if any, and its layout
+    pub fn workspaces(&self) -> impl Iterator<Item = (u32, Option<&str>, &dyn Layout)> {
+        self.spaces.iter().map(move |(id, space)| {
+            let output = self
+                .outputs
+                .iter()
+                .find(|o| {
+                    o.userdata().get::<ActiveWorkspace>().unwrap().0.get() == id.idx
+                        && id.output.as_ref().map(|name| name == o.name()).unwrap_or(true)
+                })
+                .map(|o| o.name());
+            (id.idx, output, &**space)
+        })
+    }
+
     pub fn toplevel_by_surface(&mut self, surface: &WlSurface) -> Option<Kind> {
         for (_, space) in self.spaces.iter_mut() {
             if let Some(window) = space
@@ -156,7 +547,7 @@ impl Workspaces {
         None
     }
 
-    pub fn idx_by_output_name<N: AsRef<str>>(&self, name: N) -> Option<u8> {
+    pub fn idx_by_output_name<N: AsRef<str>>(&self, name: N) -> Option<u32> {
         self.outputs
             .iter()
             .find(|o| o.name() == name.as_ref())
@@ -173,14 +564,42 @@ impl Workspaces {
     where
         N: AsRef<str>,
     {
-        let active = self.idx_by_output_name(name);
+        let active = self.idx_by_output_name(&name);
         if let Some(a) = active {
-            self.spaces.get_mut(&a)
+            let id = self.id(name, a);
+            self.spaces.get_mut(&id)
         } else {
             None
         }
     }
 
+    /// Returns the workspace shown on an output and the one it replaced, while transitioning between both
+    pub fn visible_spaces<N>(&self, name: N, config: &Animations) -> Option<(&dyn Layout, Option<Switch<'_>>)>
+    where
+        N: AsRef<str>,
+    {
+        let idx = self.idx_by_output_name(&name)?;
+        let space = &**self.spaces.get(&self.id(&name, idx))?;
+        let switch = self
+            .switches
+            .get(name.as_ref())
+            .filter(|_| config.enabled && config.workspace > 0)
+            .and_then(|(previous, started)| {
+                let elapsed = started.elapsed().as_millis() as f32 / config.workspace as f32;
+                if elapsed >= 1.0 || previous.idx == idx {
+                    return None;
+                }
+                // the previous workspace might have been destroyed or moved on to another output
+                let previous_space = self.spaces.get(previous).filter(|_| !self.is_visible(previous))?;
+                Some(Switch {
+                    previous: &**previous_space,
+                    progress: config.easing.apply(elapsed),
+                    forward: idx > previous.idx,
+                })
+            });
+        Some((space, switch))
+    }
+
     pub fn space_by_seat(&mut self, seat: &Seat) -> Option<&mut Box<dyn Layout>> {
         if let Some(name) = seat.user_data().get::<ActiveOutput>() {
             let exists = self.space_by_output_name(&*name.0.borrow()).is_some();
@@ -203,10 +622,35 @@ impl Workspaces {
         None
     }
 
-    pub fn space_by_idx(&mut self, idx: u8) -> &mut Box<dyn Layout> {
+    /// Returns the workspace with the given number, relative to `output`
+    /// if every output has its own set of workspaces
+    pub fn space_by_idx<N: AsRef<str>>(&mut self, output: N, idx: u32) -> &mut Box<dyn Layout> {
+        let insets = self.insets;
+        let config = self.layout_config(idx);
+        let id = self.id(output, idx);
         self.spaces
-            .entry(idx)
-            .or_insert(Box::new(super::layout::Floating::new((0, 0))))
+            .entry(id)
+            .or_insert_with(|| super::layout::new_layout((0, 0), insets, &config))
+    }
+
+    /// Moves a window to the workspace with the given number, of the output it is on
+    /// if every output has its own set of workspaces
+    pub fn move_toplevel(&mut self, toplevel: &Kind, idx: u32) {
+        let id = match self
+            .spaces
+            .iter()
+            .find(|(_, space)| space.windows().any(|k| &k == toplevel))
+            .map(|(id, _)| id.clone())
+        {
+            Some(id) => id,
+            None => return,
+        };
+        if id.idx == idx {
+            return;
+        }
+        self.spaces.get_mut(&id).unwrap().remove_toplevel(toplevel.clone());
+        let output = id.output.unwrap_or_default();
+        self.space_by_idx(output, idx).new_toplevel(toplevel.clone());
     }
 
     pub fn output<F>(&mut self, f: F) -> Option<&mut Output>
@@ -227,22 +671,34 @@ impl Workspaces {
         self.output(|o| o.name() == name.as_ref())
     }
 
-    pub fn switch_workspace(&mut self, seat: &Seat, idx: u8) {
+    pub fn switch_workspace(&mut self, seat: &Seat, idx: u32) {
         let output_name = &seat.user_data().get::<ActiveOutput>().unwrap().0;
-        let current_idx = self.idx_by_output_name(&*output_name.borrow()).unwrap();
+        let mut current_idx = self.idx_by_output_name(&*output_name.borrow()).unwrap();
+        let mut current_id = self.id(&*output_name.borrow(), current_idx);
+        let per_output = self.per_output;
         if current_idx != idx {
-            if let Some(output) =
-                self.output(|o| o.userdata().get::<ActiveWorkspace>().unwrap().0.get() == idx)
+            // with per-output workspaces, the workspace is never shown elsewhere
+            if let Some(name) = self
+                .output(|o| {
+                    !per_output && o.userdata().get::<ActiveWorkspace>().unwrap().0.get() == idx
+                })
+                .map(|o| String::from(o.name()))
             {
-                *output_name.borrow_mut() = String::from(output.name());
-                if let Some(ptr) = seat.get_pointer() {
-                    let (w, h) = output.size().into();
-                    ptr.unset_grab();
-                    ptr.motion((w as f64 / 2.0, h as f64 / 2.0).into(), None, 0.into(), 0);
-                }
+                self.focus_output(seat, &name);
             } else {
+                // assigned workspaces replace the workspace of their output instead
+                if let Some(preferred) = self.preferred_output(idx) {
+                    if preferred != *output_name.borrow() {
+                        self.focus_output(seat, &preferred);
+                        current_idx = self.idx_by_output_name(&preferred).unwrap();
+                        current_id = self.id(&preferred, current_idx);
+                    }
+                }
+                let name = self.name(idx);
+                self.switches
+                    .insert(output_name.borrow().clone(), (current_id.clone(), Instant::now()));
                 let output = self.output_by_name(&*output_name.borrow()).unwrap();
-                slog_scope::debug!("Attaching workspace {} to output {}", idx, output.name());
+                slog_scope::debug!("Attaching workspace {} to output {}", name, output.name());
                 output
                     .userdata()
                     .get::<ActiveWorkspace>()
@@ -250,15 +706,62 @@ impl Workspaces {
                     .0
                     .set(idx);
                 let size = output.size();
-                let _ = self
-                    .spaces
-                    .entry(idx)
-                    .or_insert(Box::new(super::layout::Floating::new(size)));
+                let insets = self.insets;
+                let config = self.layout_config(idx);
+                let id = self.id(&*output_name.borrow(), idx);
+                self.spaces
+                    .entry(id)
+                    .or_insert_with(|| super::layout::new_layout(size, insets, &config))
+                    .rearrange(&size);
+            }
+        }
+        if let Some(space) = self.space_by_seat(seat) {
+            if let Some(focused) = space.focused_window() {
+                space.set_urgent(&focused, false);
             }
         }
-        if self.space_by_idx(current_idx).is_empty() && self.output(|o| o.userdata().get::<ActiveWorkspace>().unwrap().0.get() == current_idx).is_none() { 
-            slog_scope::debug!("Destroying empty workspace: {}", current_idx);
-            self.spaces.remove(&current_idx);
+        if self.spaces.get(&current_id).map(|space| space.is_empty()).unwrap_or(false)
+            && !self.is_visible(&current_id)
+        {
+            slog_scope::debug!("Destroying empty workspace: {}", self.name(current_idx));
+            self.spaces.remove(&current_id);
         }
     }
 }
+
+/// File output arrangements are persisted in
+fn arrangements_path() -> Option<PathBuf> {
+    xdg::BaseDirectories::with_prefix("fireplace")
+        .ok()?
+        .place_data_file("outputs.yaml")
+        .ok()
+}
+
+fn load_arrangements() -> HashMap<String, HashMap<String, (i32, i32)>> {
+    arrangements_path()
+        .filter(|path| path.exists())
+        .and_then(|path| match std::fs::File::open(&path)
+            .map_err(|err| err.to_string())
+            .and_then(|file| serde_yaml::from_reader(file).map_err(|err| err.to_string()))
+        {
+            Ok(arrangements) => Some(arrangements),
+            Err(err) => {
+                slog_scope::warn!("Failed to load output arrangements from {}: {}", path.display(), err);
+                None
+            }
+        })
+        .unwrap_or_default()
+}
+
+fn save_arrangements(arrangements: &HashMap<String, HashMap<String, (i32, i32)>>) {
+    let path = match arrangements_path() {
+        Some(path) => path,
+        None => return,
+    };
+    let result = std::fs::File::create(&path)
+        .map_err(|err| err.to_string())
+        .and_then(|file| serde_yaml::to_writer(file, arrangements).map_err(|err| err.to_string()));
+    if let Err(err) = result {
+        slog_scope::warn!("Failed to save output arrangements to {}: {}", path.display(), err);
+    }
+}
@@ -1,38 +1,63 @@
 use crate::{
-    backend::udev::RenderSurface,
-    config::Config,
+    backend::{damage::DamageTracker, render::RenderCache, screencast::Screencasting, screenshot::Screenshot, stats::FrameStats, udev::{FrameState, HardwareCursor, QueuedFrame, TextureCache, Hdr, RenderMode, RenderSurface, SessionFd}},
+    config::{ColorFilter, Config},
+    handler::{gesture::Swipe, DndIcon, MediaControl, Message, Osd},
+    ipc::{DbusService, I3Ipc, NotificationDaemon},
+    lock::{Lock, Locker},
     shell::{window::PopupKind, workspace::Workspaces},
+    wayland::{
+        wl_drm::WlDrm,
+        wl_eglstream_controller::WlEglstreamController,
+        wl_eglstream_display::WlEglstreamDisplay,
+        wp_linux_drm_syncobj_manager_v1::WpLinuxDrmSyncobjManagerV1,
+        SyncobjDevice,
+    },
 };
 use smithay::{
-    backend::renderer::gles2::{Gles2Renderer, Gles2Texture},
+    backend::{
+        drm::DrmDevice,
+        session::Signal,
+        egl::{EGLContext, EGLDisplay},
+        renderer::gles2::{Gles2Renderer, Gles2Texture},
+    },
     reexports::{
-        drm::control::crtc,
-        calloop::{RegistrationToken, timer::TimerHandle},
+        drm::control::{connector, crtc},
+        input,
+        calloop::{Dispatcher, LoopHandle, RegistrationToken, timer::TimerHandle},
         nix::sys::stat::dev_t,
-        wayland_server::Display,
+        wayland_protocols::unstable::linux_dmabuf::v1::server::zwp_linux_dmabuf_v1::ZwpLinuxDmabufV1,
+        wayland_server::{Display, Global},
     },
     wayland::{
-        data_device::{default_action_chooser, init_data_device},
+        data_device::{default_action_chooser, init_data_device, DataDeviceEvent},
         output::xdg::init_xdg_output_manager,
         seat::{Keysym, Seat},
         shell::xdg::ShellState as XdgShellState,
         shm::init_shm_global,
+        tablet_manager::init_tablet_manager_global,
     },
     utils::{
         Size, Physical,
-        signaling::SignalToken
+        signaling::{SignalToken, Signaler},
     },
 };
 use std::{
     cell::RefCell,
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     ffi::OsString,
+    fs::File,
+    path::PathBuf,
     rc::Rc,
     sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
 
 pub struct Fireplace {
     pub config: Config,
+    /// Configuration as read from the file, values set over ipc are applied to it
+    pub config_source: serde_yaml::Value,
+    /// File the configuration was read from, watched for changes
+    pub config_path: Option<PathBuf>,
     pub display: Rc<RefCell<Display>>,
     pub socket_name: OsString,
     pub start_time: std::time::Instant,
@@ -45,8 +70,49 @@ pub struct Fireplace {
 
     // input
     pub seats: Vec<Seat>,
+    /// Seat of every input device by its id, assigned when the device is added
+    pub device_seats: HashMap<String, Seat>,
+    /// Libinput devices, to apply changes of their configuration
+    pub input_devices: Vec<input::Device>,
     pub last_active_seat: Seat,
-    pub suppressed_keys: Vec<Keysym>,
+    pub swipe: Option<Swipe>,
+    pub lid_closed: bool,
+    pub tablet_mode: bool,
+    pub last_input: std::time::Instant,
+
+    // outputs
+    pub night_light: bool,
+    /// Filter of all outputs, `None` while color filters are off
+    pub color_filter: Option<ColorFilter>,
+    pub disabled_outputs: HashSet<String>,
+    pub clamshell_outputs: Vec<String>,
+    pub screenshots: Vec<Screenshot>,
+    pub screencast: Option<Screencasting>,
+    pub i3_ipc: Option<I3Ipc>,
+    pub dbus: Option<DbusService>,
+    /// Bumped by anything changing what the outputs show, see `Fireplace::damage`
+    pub damage: u64,
+    /// Frame statistics are drawn onto every output
+    pub debug_overlay: bool,
+    /// Repainted areas are tinted and windows outlined, see `draw_debug_damage`
+    pub debug_damage: bool,
+    /// Shown on top of every output until it expires
+    pub message: Option<Message>,
+    /// Level like the volume, shown on every output for a moment
+    pub osd: Option<Osd>,
+    pub media: Option<MediaControl>,
+    /// Popups of the built-in notification daemon, shown on the active output
+    pub notifications: Option<NotificationDaemon>,
+    /// Set while the session is locked, nothing but the lock is shown
+    pub lock: Option<Lock>,
+    pub locker: Option<Locker>,
+    /// Keeps logind from handling the power keys fireplace handles
+    pub power_inhibitor: Option<File>,
+    /// Key asking to be pressed again to power off or suspend, and since when
+    pub power_prompt: Option<(Keysym, Instant)>,
+    pub frame_stats: HashMap<String, FrameStats>,
+    /// Panics caught by subsystem, see `Fireplace::guard`
+    pub panics: HashMap<&'static str, usize>,
 
     // backend
     pub tokens: Vec<RegistrationToken>,
@@ -55,41 +121,106 @@ pub struct Fireplace {
 
 pub struct BackendData {
     pub _restart_token: SignalToken,
+    pub drm: Dispatcher<'static, DrmDevice<SessionFd>, Fireplace>,
     pub drm_token: RegistrationToken,
     pub socket_token: RegistrationToken,
     pub surfaces: HashMap<crtc::Handle, SurfaceData>,
+    pub fd: SessionFd,
+    pub mode: RenderMode,
+    pub egl_display: EGLDisplay,
+    pub egl_context: EGLContext,
+    /// Only gpus rendering on their own share buffers with clients
+    pub wl_drm_global: Option<Global<WlDrm>>,
+    pub dmabuf_global: Global<ZwpLinuxDmabufV1>,
+    pub eglstream_globals: Option<(Global<WlEglstreamDisplay>, Global<WlEglstreamController>)>,
+    /// Explicit synchronization, if the driver supports timeline syncobjs
+    pub syncobj_global: Option<(Global<WpLinuxDrmSyncobjManagerV1>, Rc<SyncobjDevice>)>,
     pub pointer: crate::backend::udev::Cursor,
-    pub pointer_images: Vec<(xcursor::parser::Image, Gles2Texture)>,
+    /// Textures of the cursor theme by image
+    pub pointer_images: TextureCache<xcursor::parser::Image, Gles2Texture>,
+    pub cache: RenderCache<Gles2Texture>,
     //fps_texture: Gles2Texture,
     pub renderer: Gles2Renderer,
     pub driver: Option<String>,
+    pub handle: LoopHandle<'static, Fireplace>,
+    pub signaler: Signaler<Signal>,
 }
 
 pub struct SurfaceData {
     pub output: String,
+    pub identifier: String,
+    pub mirror: Option<String>,
+    pub connector: connector::Handle,
+    pub powered: bool,
+    pub temperature: u32,
+    /// Color pipeline of outputs in hdr mode
+    pub hdr: Option<Hdr>,
     pub size: Size<i32, Physical>,
     pub surface: RenderSurface,
+    /// Cursor plane of the crtc, `None` if unsupported
+    pub cursor: Option<HardwareCursor>,
     //fps: fps_ticker::Fps,
     pub render_timer: TimerHandle<(dev_t, crtc::Handle)>,
+    pub render_token: RegistrationToken,
+    /// Duration of a frame at the refresh rate of the mode
+    pub refresh: Duration,
+    /// Frames are scheduled relative to the last vblank
+    pub last_vblank: Option<Instant>,
+    pub frame: FrameState,
+    /// Frame submitted last, until its vblank
+    pub queued: Option<QueuedFrame>,
+    /// Value of `Fireplace::damage` when the last frame was rendered
+    pub rendered: u64,
+    /// Parts of the buffers out of date, to only redraw those
+    pub tracker: DamageTracker,
 }
 
 impl Fireplace {
-    pub fn new(config: Config, display: Display, socket_name: OsString) -> Self {
+    pub fn new(
+        config: Config,
+        config_source: serde_yaml::Value,
+        config_path: Option<PathBuf>,
+        display: Display,
+        socket_name: OsString,
+    ) -> Self {
         let display = Rc::new(RefCell::new(display));
 
         init_shm_global(&mut (*display).borrow_mut(), vec![], None);
-        let shell = crate::shell::init_shell(display.clone());
+        let shell = crate::shell::init_shell(display.clone(), &config);
         init_xdg_output_manager(&mut display.borrow_mut(), None);
+        init_tablet_manager_global(&mut display.borrow_mut());
         let initial_seat = crate::handler::add_seat(&mut *display.borrow_mut(), "seat-1".into());
+        let mut seats = vec![initial_seat.clone()];
+        for name in config.seats.iter().filter(|name| *name != "seat-1") {
+            seats.push(crate::handler::add_seat(&mut *display.borrow_mut(), name.clone()));
+        }
+        let dnd_seats = seats.clone();
         init_data_device(
             &mut display.borrow_mut(),
-            |_dnd_event| { /* TODO */ },
+            move |event| match event {
+                DataDeviceEvent::DnDStarted { icon, .. } => {
+                    // the dragging seat still holds the implicit grab of its button press
+                    let seat = dnd_seats
+                        .iter()
+                        .find(|seat| seat.get_pointer().map(|ptr| ptr.is_grabbed()).unwrap_or(false))
+                        .unwrap_or(&dnd_seats[0]);
+                    *seat.user_data().get::<DndIcon>().unwrap().0.borrow_mut() = icon;
+                }
+                DataDeviceEvent::DnDDropped => {
+                    for seat in dnd_seats.iter() {
+                        *seat.user_data().get::<DndIcon>().unwrap().0.borrow_mut() = None;
+                    }
+                }
+                _ => {}
+            },
             default_action_chooser,
             None,
         );
 
         Fireplace {
             config,
+            config_source,
+            config_path,
             display,
             socket_name,
             start_time: std::time::Instant::now(),
@@ -97,9 +228,35 @@ impl Fireplace {
             xdg_state: shell.xdg_state,
             workspaces: shell.workspaces,
             popups: shell.popups,
-            seats: vec![initial_seat.clone()],
+            seats,
+            device_seats: HashMap::new(),
+            input_devices: Vec::new(),
             last_active_seat: initial_seat,
-            suppressed_keys: Vec::new(),
+            swipe: None,
+            lid_closed: false,
+            tablet_mode: false,
+            last_input: std::time::Instant::now(),
+            night_light: config.night_light.enabled,
+            color_filter: Some(config.color_filter.filter).filter(|_| config.color_filter.enabled),
+            disabled_outputs: HashSet::new(),
+            clamshell_outputs: Vec::new(),
+            screenshots: Vec::new(),
+            screencast: None,
+            i3_ipc: None,
+            dbus: None,
+            damage: 0,
+            debug_overlay: false,
+            debug_damage: std::env::var_os("FIREPLACE_DEBUG_DAMAGE").is_some(),
+            message: None,
+            osd: None,
+            media: None,
+            notifications: None,
+            lock: None,
+            locker: None,
+            power_inhibitor: None,
+            power_prompt: None,
+            frame_stats: HashMap::new(),
+            panics: HashMap::new(),
             tokens: Vec::new(),
             udev: HashMap::new(),
         }
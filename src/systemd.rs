@@ -0,0 +1,140 @@
+//! # Integration with systemd as service manager
+//!
+//! Fireplace may be run as a `Type=notify` user service, optionally socket activated,
+//! and may put the programs it spawns into scopes of their own.
+
+use anyhow::Result;
+use smithay::reexports::{
+    nix::{
+        sys::socket::{sendto, socket, AddressFamily, MsgFlags, SockAddr, SockFlag, SockType, UnixAddr},
+        unistd::{close, getpid},
+    },
+    wayland_server::Display,
+};
+use std::{
+    ffi::OsString,
+    path::Path,
+    os::unix::{
+        io::{FromRawFd, IntoRawFd},
+        net::UnixListener,
+    },
+};
+use zvariant::Value;
+
+/// First file descriptor passed by socket activation
+const LISTEN_FDS_START: i32 = 3;
+
+/// Sends a state like `READY=1` to the service manager, if started by one
+pub fn notify(state: &str) {
+    let path = match std::env::var("NOTIFY_SOCKET") {
+        Ok(path) => path,
+        Err(_) => return,
+    };
+    if let Err(err) = send_notification(&path, state) {
+        slog_scope::warn!("Failed to notify the service manager about {}: {}", state, err);
+    }
+}
+
+fn send_notification(path: &str, state: &str) -> Result<()> {
+    let addr = match path.strip_prefix('@') {
+        Some(name) => UnixAddr::new_abstract(name.as_bytes())?,
+        None => UnixAddr::new(path)?,
+    };
+    let fd = socket(AddressFamily::Unix, SockType::Datagram, SockFlag::SOCK_CLOEXEC, None)?;
+    let result = sendto(fd, state.as_bytes(), &SockAddr::Unix(addr), MsgFlags::empty());
+    let _ = close(fd);
+    result?;
+    Ok(())
+}
+
+/// Serves the wayland socket passed by socket activation, returning its name
+///
+/// Only a single listening unix socket is accepted, anything else leaves the activation unused.
+pub fn add_activated_socket(display: &mut Display) -> Option<OsString> {
+    let pid = std::env::var("LISTEN_PID").ok()?.parse::<i32>().ok()?;
+    let fds = std::env::var("LISTEN_FDS").ok()?.parse::<i32>().ok()?;
+    // not meant for us, but for a process we spawn
+    if pid != getpid().as_raw() {
+        return None;
+    }
+    std::env::remove_var("LISTEN_PID");
+    std::env::remove_var("LISTEN_FDS");
+    std::env::remove_var("LISTEN_FDNAMES");
+    if fds != 1 {
+        slog_scope::warn!("Expected a single activated socket, got {}", fds);
+        return None;
+    }
+
+    let listener = unsafe { UnixListener::from_raw_fd(LISTEN_FDS_START) };
+    let name = listener
+        .local_addr()
+        .ok()
+        .and_then(|addr| addr.as_pathname().and_then(|path| path.file_name()).map(OsString::from));
+    let name = match name {
+        Some(name) => name,
+        None => {
+            slog_scope::warn!("Activated socket is no unix socket bound to a path");
+            return None;
+        }
+    };
+    match unsafe { display.add_socket_fd(listener.into_raw_fd()) } {
+        Ok(()) => Some(name),
+        Err(err) => {
+            slog_scope::warn!("Failed to use the activated socket: {}", err);
+            None
+        }
+    }
+}
+
+/// Makes the variables of the session known to the systemd user instance and d-bus activated services
+pub fn import_environment(variables: &[(&str, String)]) -> Result<()> {
+    let connection = zbus::Connection::new_session()?;
+    let assignments = variables
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect::<Vec<_>>();
+    connection.call_method(
+        Some("org.freedesktop.systemd1"),
+        "/org/freedesktop/systemd1",
+        Some("org.freedesktop.systemd1.Manager"),
+        "SetEnvironment",
+        &(assignments,),
+    )?;
+    let environment = variables.iter().map(|(name, value)| (*name, value.as_str())).collect();
+    zbus::fdo::DBusProxy::new(&connection)?.update_activation_environment(environment)?;
+    Ok(())
+}
+
+/// Checks if systemd is the init system, otherwise there is no user instance to manage scopes
+pub fn is_running() -> bool {
+    Path::new("/run/systemd/system").exists()
+}
+
+/// Moves a spawned process into a transient scope of the user instance, named after the program
+///
+/// Children forked before the scope is created stay behind in the scope of fireplace.
+pub fn start_scope(program: &str, pid: u32) -> Result<()> {
+    let unit = format!("app-fireplace-{}-{}.scope", escape_unit_name(program), pid);
+    let connection = zbus::Connection::new_session()?;
+    let properties: Vec<(&str, Value<'_>)> = vec![
+        ("PIDs", Value::from(vec![pid])),
+        // failed scopes are not kept around to be inspected
+        ("CollectMode", Value::from("inactive-or-failed")),
+    ];
+    let auxiliary: Vec<(&str, Vec<(&str, Value<'_>)>)> = Vec::new();
+    connection.call_method(
+        Some("org.freedesktop.systemd1"),
+        "/org/freedesktop/systemd1",
+        Some("org.freedesktop.systemd1.Manager"),
+        "StartTransientUnit",
+        &(unit.as_str(), "fail", properties, auxiliary),
+    )?;
+    Ok(())
+}
+
+/// Replaces characters not allowed in unit names, `-` separates the parts of the name
+fn escape_unit_name(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == '.' { c } else { '_' })
+        .collect()
+}
@@ -1,5 +1,9 @@
 mod drm;
 mod eglstream;
+mod session_lock;
+mod syncobj;
 
 pub use self::drm::*;
-pub use self::eglstream::*;
\ No newline at end of file
+pub use self::eglstream::*;
+pub use self::session_lock::*;
+pub use self::syncobj::*;
\ No newline at end of file
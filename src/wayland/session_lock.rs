@@ -0,0 +1,191 @@
+// Re-export only the actual code, and then only use this re-export
+// The `generated` module below is just some boilerplate to properly isolate stuff
+// and avoid exposing internal details.
+//
+// You can use all the types from my_protocol as if they went from `wayland_client::protocol`.
+pub use generated::server::{ext_session_lock_manager_v1, ext_session_lock_surface_v1, ext_session_lock_v1};
+
+mod generated {
+    // The generated code tends to trigger a lot of warnings
+    // so we isolate it into a very permissive module
+    #![allow(dead_code,non_camel_case_types,unused_unsafe,unused_variables)]
+    #![allow(non_upper_case_globals,non_snake_case,unused_imports)]
+
+    pub mod server {
+        use smithay::reexports::{wayland_commons, wayland_server};
+
+        // These imports are used by the generated code
+        pub(crate) use wayland_server::{Main, AnonymousObject, Resource, ResourceMap};
+        pub(crate) use wayland_commons::map::{Object, ObjectMetadata};
+        pub(crate) use wayland_commons::{Interface, MessageGroup};
+        pub(crate) use wayland_commons::wire::{Argument, MessageDesc, ArgumentType, Message};
+        pub(crate) use wayland_commons::smallvec;
+        pub(crate) use wayland_server::sys;
+        pub(crate) use wayland_server::protocol::{wl_output, wl_surface};
+        include!(concat!(env!("OUT_DIR"), "/ext_session_lock.rs"));
+    }
+}
+
+use smithay::{
+    reexports::wayland_server::{
+        protocol::{wl_output::WlOutput, wl_surface::WlSurface},
+        DispatchData, Display, Filter, Global, Main,
+    },
+    utils::{Logical, Size},
+    wayland::{compositor::give_role, SERIAL_COUNTER as SCOUNTER},
+};
+
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+};
+
+use self::{
+    ext_session_lock_manager_v1::ExtSessionLockManagerV1, ext_session_lock_surface_v1::ExtSessionLockSurfaceV1,
+    ext_session_lock_v1::ExtSessionLockV1,
+};
+
+/// Role of surfaces shown while the session is locked
+const LOCK_SURFACE_ROLE: &str = "ext_session_lock_surface_v1";
+
+/// Lock of a client asking to lock the session
+#[derive(Clone)]
+pub struct SessionLock {
+    object: ExtSessionLockV1,
+    locked: Rc<Cell<bool>>,
+}
+
+impl PartialEq for SessionLock {
+    fn eq(&self, other: &SessionLock) -> bool {
+        self.object.as_ref().equals(other.object.as_ref())
+    }
+}
+
+impl SessionLock {
+    pub fn alive(&self) -> bool {
+        self.object.as_ref().is_alive()
+    }
+
+    /// Tells the client it is responsible for the locked session now
+    pub fn locked(&self) {
+        self.locked.set(true);
+        self.object.locked();
+    }
+
+    /// Tells the client its lock is not used (anymore)
+    pub fn finished(&self) {
+        self.object.finished();
+    }
+}
+
+/// Surface of a lock client covering a single output
+#[derive(Clone)]
+pub struct LockSurface {
+    object: ExtSessionLockSurfaceV1,
+    surface: WlSurface,
+}
+
+impl LockSurface {
+    pub fn alive(&self) -> bool {
+        self.object.as_ref().is_alive() && self.surface.as_ref().is_alive()
+    }
+
+    pub fn surface(&self) -> &WlSurface {
+        &self.surface
+    }
+
+    /// Asks the client to cover an output of the given size
+    pub fn configure(&self, size: Size<i32, Logical>) {
+        self.object
+            .configure(SCOUNTER.next_serial().into(), size.w.max(0) as u32, size.h.max(0) as u32);
+    }
+}
+
+pub enum SessionLockEvent {
+    /// A client asks to lock the session
+    Lock(SessionLock),
+    /// The client of the lock authenticated the user
+    Unlock(SessionLock),
+    /// A client created the surface it shows on an output
+    NewSurface {
+        lock: SessionLock,
+        surface: LockSurface,
+        output: WlOutput,
+    },
+}
+
+pub fn init_session_lock_global<F>(display: &mut Display, callback: F) -> Global<ExtSessionLockManagerV1>
+where
+    F: FnMut(SessionLockEvent, DispatchData) + 'static,
+{
+    let callback: Rc<RefCell<dyn FnMut(SessionLockEvent, DispatchData)>> = Rc::new(RefCell::new(callback));
+    let global = Filter::new(move |(manager, _version): (Main<ExtSessionLockManagerV1>, u32), _, _| {
+        let callback = callback.clone();
+        manager.quick_assign(move |_, req, ddata| {
+            use ext_session_lock_manager_v1::Request;
+            match req {
+                Request::Lock { id } => {
+                    let lock = SessionLock {
+                        object: (*id).clone(),
+                        locked: Rc::new(Cell::new(false)),
+                    };
+                    assign_lock(id, lock.clone(), callback.clone());
+                    (&mut *callback.borrow_mut())(SessionLockEvent::Lock(lock), ddata);
+                }
+                Request::Destroy => {}
+            }
+        });
+    });
+    display.create_global(1, global)
+}
+
+fn assign_lock(
+    id: Main<ExtSessionLockV1>,
+    lock: SessionLock,
+    callback: Rc<RefCell<dyn FnMut(SessionLockEvent, DispatchData)>>,
+) {
+    id.quick_assign(move |object, req, ddata| {
+        use ext_session_lock_v1::{Error, Request};
+        match req {
+            Request::GetLockSurface { id, surface, output } => {
+                if give_role(&surface, LOCK_SURFACE_ROLE).is_err() {
+                    object
+                        .as_ref()
+                        .post_error(Error::Role.to_raw(), String::from("The surface already has a role"));
+                    return;
+                }
+                id.quick_assign(|_, _, _| {});
+                let surface = LockSurface {
+                    object: (*id).clone(),
+                    surface,
+                };
+                (&mut *callback.borrow_mut())(
+                    SessionLockEvent::NewSurface {
+                        lock: lock.clone(),
+                        surface,
+                        output,
+                    },
+                    ddata,
+                );
+            }
+            Request::Destroy => {
+                if lock.locked.get() {
+                    object.as_ref().post_error(
+                        Error::InvalidDestroy.to_raw(),
+                        String::from("The session is locked, use unlock_and_destroy"),
+                    );
+                }
+            }
+            Request::UnlockAndDestroy => {
+                if !lock.locked.get() {
+                    object.as_ref().post_error(
+                        Error::InvalidUnlock.to_raw(),
+                        String::from("The session was never locked by this object"),
+                    );
+                    return;
+                }
+                (&mut *callback.borrow_mut())(SessionLockEvent::Unlock(lock.clone()), ddata);
+            }
+        }
+    });
+}
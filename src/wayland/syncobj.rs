@@ -0,0 +1,512 @@
+// Re-export only the actual code, and then only use this re-export
+// The `generated` module below is just some boilerplate to properly isolate stuff
+// and avoid exposing internal details.
+//
+// You can use all the types from my_protocol as if they went from `wayland_client::protocol`.
+pub use generated::server::{
+    wp_linux_drm_syncobj_manager_v1, wp_linux_drm_syncobj_surface_v1, wp_linux_drm_syncobj_timeline_v1,
+};
+
+mod generated {
+    // The generated code tends to trigger a lot of warnings
+    // so we isolate it into a very permissive module
+    #![allow(dead_code,non_camel_case_types,unused_unsafe,unused_variables)]
+    #![allow(non_upper_case_globals,non_snake_case,unused_imports)]
+
+    pub mod server {
+        use smithay::reexports::{wayland_commons, wayland_server};
+
+        // These imports are used by the generated code
+        pub(crate) use wayland_server::{Main, AnonymousObject, Resource, ResourceMap};
+        pub(crate) use wayland_commons::map::{Object, ObjectMetadata};
+        pub(crate) use wayland_commons::{Interface, MessageGroup};
+        pub(crate) use wayland_commons::wire::{Argument, MessageDesc, ArgumentType, Message};
+        pub(crate) use wayland_commons::smallvec;
+        pub(crate) use wayland_server::sys;
+        pub(crate) use wayland_server::protocol::wl_surface;
+        include!(concat!(env!("OUT_DIR"), "/wp_linux_drm_syncobj.rs"));
+    }
+}
+
+use smithay::{
+    backend::allocator::dmabuf::Dmabuf,
+    reexports::{
+        nix::{self, libc, unistd::close},
+        wayland_server::{
+            protocol::{wl_buffer::WlBuffer, wl_surface::WlSurface},
+            Client, Display, Filter, Global, Main,
+        },
+    },
+    wayland::compositor::{with_states, BufferAssignment, Cacheable, SurfaceAttributes, SurfaceData},
+};
+
+use std::{
+    cell::RefCell,
+    fs::{File, OpenOptions},
+    io,
+    os::unix::io::{AsRawFd, RawFd},
+    path::PathBuf,
+    rc::Rc,
+    time::Duration,
+};
+
+use self::{
+    wp_linux_drm_syncobj_manager_v1::WpLinuxDrmSyncobjManagerV1,
+    wp_linux_drm_syncobj_surface_v1::WpLinuxDrmSyncobjSurfaceV1,
+};
+use crate::backend::egl;
+
+/// Longest time to wait for clients to submit the work of an acquire point
+const SUBMIT_TIMEOUT: Duration = Duration::from_millis(4);
+
+const DRM_CAP_SYNCOBJ_TIMELINE: u64 = 0x14;
+const DRM_SYNCOBJ_HANDLE_TO_FD_FLAGS_EXPORT_SYNC_FILE: u32 = 1;
+const DRM_SYNCOBJ_WAIT_FLAGS_WAIT_FOR_SUBMIT: u32 = 1 << 1;
+
+/// Structs and requests of `drm.h`, not covered by the drm crate
+mod ioctl {
+    use smithay::reexports::nix;
+
+    #[repr(C)]
+    pub struct DrmGetCap {
+        pub capability: u64,
+        pub value: u64,
+    }
+
+    #[repr(C)]
+    pub struct DrmSyncobjCreate {
+        pub handle: u32,
+        pub flags: u32,
+    }
+
+    #[repr(C)]
+    pub struct DrmSyncobjDestroy {
+        pub handle: u32,
+        pub pad: u32,
+    }
+
+    #[repr(C)]
+    pub struct DrmSyncobjHandle {
+        pub handle: u32,
+        pub flags: u32,
+        pub fd: i32,
+        pub pad: u32,
+    }
+
+    #[repr(C)]
+    pub struct DrmSyncobjTransfer {
+        pub src_handle: u32,
+        pub dst_handle: u32,
+        pub src_point: u64,
+        pub dst_point: u64,
+        pub flags: u32,
+        pub pad: u32,
+    }
+
+    #[repr(C)]
+    pub struct DrmSyncobjTimelineWait {
+        pub handles: u64,
+        pub points: u64,
+        pub timeout_nsec: i64,
+        pub count_handles: u32,
+        pub flags: u32,
+        pub first_signaled: u32,
+        pub pad: u32,
+    }
+
+    #[repr(C)]
+    pub struct DrmSyncobjTimelineArray {
+        pub handles: u64,
+        pub points: u64,
+        pub count_handles: u32,
+        pub flags: u32,
+    }
+
+    nix::ioctl_readwrite!(get_cap, b'd', 0x0c, DrmGetCap);
+    nix::ioctl_readwrite!(syncobj_create, b'd', 0xBF, DrmSyncobjCreate);
+    nix::ioctl_readwrite!(syncobj_destroy, b'd', 0xC0, DrmSyncobjDestroy);
+    nix::ioctl_readwrite!(syncobj_handle_to_fd, b'd', 0xC1, DrmSyncobjHandle);
+    nix::ioctl_readwrite!(syncobj_fd_to_handle, b'd', 0xC2, DrmSyncobjHandle);
+    nix::ioctl_readwrite!(syncobj_timeline_wait, b'd', 0xCA, DrmSyncobjTimelineWait);
+    nix::ioctl_readwrite!(syncobj_transfer, b'd', 0xCC, DrmSyncobjTransfer);
+    nix::ioctl_readwrite!(syncobj_timeline_signal, b'd', 0xCD, DrmSyncobjTimelineArray);
+}
+
+fn to_io(err: nix::Error) -> io::Error {
+    io::Error::from_raw_os_error(err.as_errno().map(|errno| errno as i32).unwrap_or(libc::EINVAL))
+}
+
+/// Render node timelines are imported into, shared by all timelines of a gpu
+pub struct SyncobjDevice {
+    node: File,
+    /// Release points of buffers no longer used, signalled once the frames using them are done
+    released: RefCell<Vec<(SyncPoint, WlBuffer)>>,
+}
+
+impl SyncobjDevice {
+    /// Signals the release points of buffers released since the last call
+    ///
+    /// Called once a frame was presented, frames still using the buffers were queued before.
+    pub fn signal_released(&self) {
+        for (point, buffer) in self.released.borrow_mut().drain(..) {
+            if let Err(err) = point.signal() {
+                slog_scope::debug!("Failed to signal release point: {}", err);
+            }
+            buffer.release();
+        }
+    }
+}
+
+/// A DRM syncobj timeline imported by a client
+pub struct Timeline {
+    device: Rc<SyncobjDevice>,
+    handle: u32,
+}
+
+impl Drop for Timeline {
+    fn drop(&mut self) {
+        let mut destroy = ioctl::DrmSyncobjDestroy {
+            handle: self.handle,
+            pad: 0,
+        };
+        let _ = unsafe { ioctl::syncobj_destroy(self.device.node.as_raw_fd(), &mut destroy) };
+    }
+}
+
+/// A point on a timeline of a client
+#[derive(Clone)]
+pub struct SyncPoint {
+    timeline: Rc<Timeline>,
+    point: u64,
+}
+
+impl std::fmt::Debug for SyncPoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SyncPoint")
+            .field("handle", &self.timeline.handle)
+            .field("point", &self.point)
+            .finish()
+    }
+}
+
+impl SyncPoint {
+    fn fd(&self) -> RawFd {
+        self.timeline.device.node.as_raw_fd()
+    }
+
+    /// Waits for the point to be signalled, `flags` are `DRM_SYNCOBJ_WAIT_FLAGS_*`
+    fn wait(&self, timeout: Duration, flags: u32) -> io::Result<()> {
+        let handle = self.timeline.handle;
+        let point = self.point;
+        let deadline = if timeout == Duration::ZERO {
+            0
+        } else {
+            let now = nix::time::clock_gettime(nix::time::ClockId::CLOCK_MONOTONIC).map_err(to_io)?;
+            let now = Duration::new(now.tv_sec() as u64, now.tv_nsec() as u32);
+            (now + timeout).as_nanos() as i64
+        };
+        let mut wait = ioctl::DrmSyncobjTimelineWait {
+            handles: &handle as *const u32 as u64,
+            points: &point as *const u64 as u64,
+            timeout_nsec: deadline,
+            count_handles: 1,
+            flags,
+            first_signaled: 0,
+            pad: 0,
+        };
+        unsafe { ioctl::syncobj_timeline_wait(self.fd(), &mut wait) }
+            .map(|_| ())
+            .map_err(to_io)
+    }
+
+    pub fn is_signaled(&self) -> bool {
+        self.wait(Duration::ZERO, 0).is_ok()
+    }
+
+    /// Exports the fence of the point as a sync file, waiting a little for the client to submit it
+    pub fn export(&self) -> io::Result<RawFd> {
+        if self.wait(Duration::ZERO, 0).is_err() {
+            // the fence of a point only exists once its work is submitted
+            let _ = self.wait(SUBMIT_TIMEOUT, DRM_SYNCOBJ_WAIT_FLAGS_WAIT_FOR_SUBMIT);
+        }
+        let fd = self.fd();
+        let mut create = ioctl::DrmSyncobjCreate { handle: 0, flags: 0 };
+        unsafe { ioctl::syncobj_create(fd, &mut create) }.map_err(to_io)?;
+        let mut transfer = ioctl::DrmSyncobjTransfer {
+            src_handle: self.timeline.handle,
+            dst_handle: create.handle,
+            src_point: self.point,
+            dst_point: 0,
+            flags: 0,
+            pad: 0,
+        };
+        let mut export = ioctl::DrmSyncobjHandle {
+            handle: create.handle,
+            flags: DRM_SYNCOBJ_HANDLE_TO_FD_FLAGS_EXPORT_SYNC_FILE,
+            fd: -1,
+            pad: 0,
+        };
+        let result = unsafe { ioctl::syncobj_transfer(fd, &mut transfer) }
+            .and_then(|_| unsafe { ioctl::syncobj_handle_to_fd(fd, &mut export) })
+            .map(|_| export.fd)
+            .map_err(to_io);
+        let mut destroy = ioctl::DrmSyncobjDestroy {
+            handle: create.handle,
+            pad: 0,
+        };
+        let _ = unsafe { ioctl::syncobj_destroy(fd, &mut destroy) };
+        result
+    }
+
+    pub fn signal(&self) -> io::Result<()> {
+        let handle = self.timeline.handle;
+        let point = self.point;
+        let mut signal = ioctl::DrmSyncobjTimelineArray {
+            handles: &handle as *const u32 as u64,
+            points: &point as *const u64 as u64,
+            count_handles: 1,
+            flags: 0,
+        };
+        unsafe { ioctl::syncobj_timeline_signal(self.fd(), &mut signal) }
+            .map(|_| ())
+            .map_err(to_io)
+    }
+
+    /// Signals the point together with releasing the buffer, once the frames using it are done
+    pub fn release(self, buffer: WlBuffer) {
+        let device = self.timeline.device.clone();
+        device.released.borrow_mut().push((self, buffer));
+    }
+
+    /// Makes the gpu of the current egl context wait for the point before executing further commands
+    ///
+    /// Falls back to waiting on the cpu, if the point can not be turned into a native fence.
+    pub fn gpu_wait(&self) {
+        if self.is_signaled() {
+            return;
+        }
+        let fd = match self.export() {
+            Ok(fd) => fd,
+            Err(err) => {
+                slog_scope::debug!("Failed to export acquire point, waiting on the cpu: {}", err);
+                let _ = self.wait(SUBMIT_TIMEOUT, DRM_SYNCOBJ_WAIT_FLAGS_WAIT_FOR_SUBMIT);
+                return;
+            }
+        };
+        unsafe {
+            let display = egl::GetCurrentDisplay();
+            let attributes = [
+                egl::SYNC_NATIVE_FENCE_FD_ANDROID as egl::types::EGLint,
+                fd,
+                egl::NONE as egl::types::EGLint,
+            ];
+            let sync = egl::CreateSyncKHR(display, egl::SYNC_NATIVE_FENCE_ANDROID, attributes.as_ptr());
+            if sync.is_null() {
+                // egl only takes ownership of the fd on success
+                let _ = close(fd);
+                let _ = self.wait(SUBMIT_TIMEOUT, DRM_SYNCOBJ_WAIT_FLAGS_WAIT_FOR_SUBMIT);
+                return;
+            }
+            egl::WaitSyncKHR(display, sync, 0);
+            egl::DestroySyncKHR(display, sync);
+        }
+    }
+}
+
+/// Sync points set for the next commit of a surface
+#[derive(Default)]
+pub struct SyncobjCachedState {
+    acquire: Option<SyncPoint>,
+    release: Option<SyncPoint>,
+}
+
+impl Cacheable for SyncobjCachedState {
+    fn commit(&mut self) -> Self {
+        SyncobjCachedState {
+            acquire: self.acquire.take(),
+            release: self.release.take(),
+        }
+    }
+
+    fn merge_into(self, into: &mut Self) {
+        if self.acquire.is_some() || self.release.is_some() {
+            *into = self;
+        }
+    }
+}
+
+/// The syncobj surface of a wl_surface, there may only be one at a time
+#[derive(Default)]
+struct SyncobjSurface(RefCell<Option<WpLinuxDrmSyncobjSurfaceV1>>);
+
+/// Takes the sync points of the buffer committed to a surface
+///
+/// Has to be called on commit, before the buffer is taken from the `SurfaceAttributes`.
+/// Clients using the protocol wrongly are disconnected.
+pub fn take_sync_points(states: &SurfaceData) -> Option<(SyncPoint, SyncPoint)> {
+    use wp_linux_drm_syncobj_surface_v1::Error;
+
+    let object = states.data_map.get::<SyncobjSurface>()?.0.borrow().clone()?;
+    let (acquire, release) = {
+        let mut cached = states.cached_state.current::<SyncobjCachedState>();
+        (cached.acquire.take(), cached.release.take())
+    };
+    let attributes = states.cached_state.current::<SurfaceAttributes>();
+    let buffer = match attributes.buffer.as_ref() {
+        Some(BufferAssignment::NewBuffer { buffer, .. }) => buffer,
+        _ if acquire.is_none() && release.is_none() => return None,
+        _ => {
+            post_error(&object, Error::NoBuffer, "No buffer attached");
+            return None;
+        }
+    };
+
+    if buffer.as_ref().user_data().get::<Dmabuf>().is_none() {
+        post_error(&object, Error::UnsupportedBuffer, "Only dmabufs support explicit synchronization");
+        return None;
+    }
+    let (acquire, release) = match (acquire, release) {
+        (Some(acquire), Some(release)) => (acquire, release),
+        (None, _) => {
+            post_error(&object, Error::NoAcquirePoint, "No acquire point set");
+            return None;
+        }
+        (_, None) => {
+            post_error(&object, Error::NoReleasePoint, "No release point set");
+            return None;
+        }
+    };
+    if Rc::ptr_eq(&acquire.timeline, &release.timeline) && acquire.point >= release.point {
+        post_error(&object, Error::ConflictingPoints, "The release point has to follow the acquire point");
+        return None;
+    }
+    Some((acquire, release))
+}
+
+fn post_error(object: &WpLinuxDrmSyncobjSurfaceV1, error: wp_linux_drm_syncobj_surface_v1::Error, message: &str) {
+    object.as_ref().post_error(error.to_raw(), String::from(message));
+}
+
+/// Checks if the kernel driver of a render node supports timeline syncobjs
+fn supports_timelines(node: &File) -> bool {
+    let mut cap = ioctl::DrmGetCap {
+        capability: DRM_CAP_SYNCOBJ_TIMELINE,
+        value: 0,
+    };
+    unsafe { ioctl::get_cap(node.as_raw_fd(), &mut cap) }.is_ok() && cap.value != 0
+}
+
+pub fn init_syncobj_global<F>(
+    display: &mut Display,
+    render_node: PathBuf,
+    client_filter: F,
+) -> anyhow::Result<(Global<WpLinuxDrmSyncobjManagerV1>, Rc<SyncobjDevice>)>
+where
+    F: FnMut(Client) -> bool + 'static,
+{
+    let node = OpenOptions::new().read(true).write(true).open(&render_node)?;
+    if !supports_timelines(&node) {
+        anyhow::bail!("{} does not support timeline syncobjs", render_node.display());
+    }
+    egl::load_with(|sym| unsafe { smithay::backend::egl::get_proc_address(sym) });
+    let device = Rc::new(SyncobjDevice {
+        node,
+        released: RefCell::new(Vec::new()),
+    });
+
+    let global_device = device.clone();
+    let global = Filter::new(move |(manager, _version): (Main<WpLinuxDrmSyncobjManagerV1>, u32), _, _| {
+        let device = global_device.clone();
+        manager.quick_assign(move |manager, req, _| {
+            use wp_linux_drm_syncobj_manager_v1::{Error, Request};
+            match req {
+                Request::GetSurface { id, surface } => get_surface(&manager, id, surface),
+                Request::ImportTimeline { id, fd } => {
+                    let mut import = ioctl::DrmSyncobjHandle {
+                        handle: 0,
+                        flags: 0,
+                        fd,
+                        pad: 0,
+                    };
+                    let result = unsafe { ioctl::syncobj_fd_to_handle(device.node.as_raw_fd(), &mut import) };
+                    let _ = close(fd);
+                    if let Err(err) = result {
+                        manager.as_ref().post_error(
+                            Error::InvalidTimeline.to_raw(),
+                            format!("Failed to import timeline: {}", err),
+                        );
+                        return;
+                    }
+                    id.as_ref().user_data().set(|| {
+                        Rc::new(Timeline {
+                            device: device.clone(),
+                            handle: import.handle,
+                        })
+                    });
+                    id.quick_assign(|_, _, _| {});
+                }
+                Request::Destroy => {}
+            }
+        });
+    });
+    Ok((display.create_global_with_filter(1, global, client_filter), device))
+}
+
+fn get_surface(manager: &Main<WpLinuxDrmSyncobjManagerV1>, id: Main<WpLinuxDrmSyncobjSurfaceV1>, surface: WlSurface) {
+    let exists = with_states(&surface, |states| {
+        states.data_map.insert_if_missing(SyncobjSurface::default);
+        let object = states.data_map.get::<SyncobjSurface>().unwrap();
+        let exists = object.0.borrow().as_ref().map(|object| object.as_ref().is_alive()).unwrap_or(false);
+        if !exists {
+            *object.0.borrow_mut() = Some((*id).clone());
+        }
+        exists
+    })
+    .unwrap_or(true);
+    if exists {
+        manager.as_ref().post_error(
+            wp_linux_drm_syncobj_manager_v1::Error::SurfaceExists.to_raw(),
+            String::from("The surface already has a syncobj surface"),
+        );
+        return;
+    }
+
+    id.quick_assign(move |object, req, _| {
+        use wp_linux_drm_syncobj_surface_v1::{Error, Request};
+        if !surface.as_ref().is_alive() {
+            if !matches!(req, Request::Destroy) {
+                post_error(&object, Error::NoSurface, "The surface was destroyed");
+            }
+            return;
+        }
+        let (timeline, point_hi, point_lo, acquire) = match req {
+            Request::SetAcquirePoint { timeline, point_hi, point_lo } => (timeline, point_hi, point_lo, true),
+            Request::SetReleasePoint { timeline, point_hi, point_lo } => (timeline, point_hi, point_lo, false),
+            Request::Destroy => {
+                let _ = with_states(&surface, |states| {
+                    if let Some(object) = states.data_map.get::<SyncobjSurface>() {
+                        *object.0.borrow_mut() = None;
+                    }
+                    *states.cached_state.pending::<SyncobjCachedState>() = SyncobjCachedState::default();
+                });
+                return;
+            }
+        };
+        let timeline = match timeline.as_ref().user_data().get::<Rc<Timeline>>() {
+            Some(timeline) => timeline.clone(),
+            None => return,
+        };
+        let point = SyncPoint {
+            timeline,
+            point: (point_hi as u64) << 32 | point_lo as u64,
+        };
+        let _ = with_states(&surface, |states| {
+            let mut pending = states.cached_state.pending::<SyncobjCachedState>();
+            if acquire {
+                pending.acquire = Some(point);
+            } else {
+                pending.release = Some(point);
+            }
+        });
+    });
+}